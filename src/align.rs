@@ -0,0 +1,123 @@
+//! Alignment utilities.
+//!
+//! All functions take alignments as `u64` and check that they are a power of two, as required
+//! by hal. Unchecked variants panic on invalid alignment or overflow, checked variants return
+//! `None` instead.
+
+/// Check if `value` is a multiple of `alignment`.
+///
+/// ### Panics
+///
+/// Panics if `alignment` is not a power of two.
+#[inline]
+pub fn is_aligned(value: u64, alignment: u64) -> bool {
+    assert_power_of_two(alignment);
+    value & (alignment - 1) == 0
+}
+
+/// Round `value` up to a multiple of `alignment`.
+///
+/// ### Panics
+///
+/// Panics if `alignment` is not a power of two or if the result overflows.
+#[inline]
+pub fn align_up(value: u64, alignment: u64) -> u64 {
+    assert_power_of_two(alignment);
+    checked_align_up(value, alignment).expect("Aligned value overflows")
+}
+
+/// Round `value` down to a multiple of `alignment`.
+///
+/// ### Panics
+///
+/// Panics if `alignment` is not a power of two.
+#[inline]
+pub fn align_down(value: u64, alignment: u64) -> u64 {
+    assert_power_of_two(alignment);
+    value & !(alignment - 1)
+}
+
+/// Get the number of bytes `value` must be increased by to become a multiple of `alignment`.
+///
+/// ### Panics
+///
+/// Panics if `alignment` is not a power of two or if the aligned value overflows.
+#[inline]
+pub fn align_offset(value: u64, alignment: u64) -> u64 {
+    align_up(value, alignment) - value
+}
+
+/// Round `value` up to a multiple of `alignment`.
+///
+/// Returns `None` if `alignment` is not a power of two or if the result overflows.
+#[inline]
+pub fn checked_align_up(value: u64, alignment: u64) -> Option<u64> {
+    if !alignment.is_power_of_two() {
+        return None;
+    }
+    value
+        .checked_add(alignment - 1)
+        .map(|value| value & !(alignment - 1))
+}
+
+/// Round `value` down to a multiple of `alignment`.
+///
+/// Returns `None` if `alignment` is not a power of two.
+#[inline]
+pub fn checked_align_down(value: u64, alignment: u64) -> Option<u64> {
+    if alignment.is_power_of_two() {
+        Some(value & !(alignment - 1))
+    } else {
+        None
+    }
+}
+
+/// Get the number of bytes `value` must be increased by to become a multiple of `alignment`.
+///
+/// Returns `None` if `alignment` is not a power of two or if the aligned value overflows.
+#[inline]
+pub fn checked_align_offset(value: u64, alignment: u64) -> Option<u64> {
+    checked_align_up(value, alignment).map(|aligned| aligned - value)
+}
+
+#[inline]
+fn assert_power_of_two(alignment: u64) {
+    assert!(
+        alignment.is_power_of_two(),
+        "Alignment {} is not a power of two",
+        alignment
+    );
+}
+
+#[test]
+fn test_align() {
+    assert_eq!(align_up(0, 256), 0);
+    assert_eq!(align_up(1, 256), 256);
+    assert_eq!(align_up(256, 256), 256);
+    assert_eq!(align_up(257, 1), 257);
+    assert_eq!(align_down(255, 256), 0);
+    assert_eq!(align_down(513, 256), 512);
+    assert_eq!(align_offset(100, 64), 28);
+    assert!(is_aligned(0, 4096));
+    assert!(is_aligned(8192, 4096));
+    assert!(!is_aligned(8191, 4096));
+}
+
+#[test]
+fn test_checked_align() {
+    assert_eq!(checked_align_up(1, 256), Some(256));
+    assert_eq!(checked_align_up(!0 - 10, 256), None);
+    assert_eq!(checked_align_up(!0, 1), Some(!0));
+    assert_eq!(checked_align_up(100, 96), None);
+    assert_eq!(checked_align_up(100, 0), None);
+    assert_eq!(checked_align_down(513, 256), Some(512));
+    assert_eq!(checked_align_down(513, 0), None);
+    assert_eq!(checked_align_offset(100, 64), Some(28));
+    assert_eq!(checked_align_offset(!0, 2), None);
+}
+
+#[test]
+#[should_panic]
+fn test_align_non_power_of_two() {
+    align_up(100, 96);
+}
@@ -1,14 +1,24 @@
 use std::any::Any;
+use std::cmp::{max, min};
 use std::collections::VecDeque;
 use std::fmt::Debug;
-use std::mem::replace;
+use std::mem::{replace, size_of};
 use std::ops::Range;
 
 use gfx_hal::memory::Requirements;
 use gfx_hal::{Backend, MemoryTypeId};
 
+use align::{align_up, checked_align_offset};
 use block::{Block, RawBlock};
-use {alignment_shift, MemoryAllocator, MemoryError, MemorySubAllocator};
+use invariant::InvariantViolation;
+use label::Pool;
+use misuse::{MisuseHandler, MisusePolicy};
+use owner::Owner;
+use stats::{ChunkInfo, ChunkStats};
+use {
+    check_requirements, checked_round_up, InvalidRequirements, MemoryAllocator, MemoryError,
+    MemorySubAllocator,
+};
 
 /// Sub-allocator that can be used for short-lived objects.
 ///
@@ -18,6 +28,9 @@ use {alignment_shift, MemoryAllocator, MemoryError, MemorySubAllocator};
 ///
 /// This allocator can be used to allocate blocks of any size.
 ///
+/// The memory type is chosen by the caller, so requirements are expected to allow it.
+/// This is only checked with debug assertions.
+///
 /// ### Type parameters:
 ///
 /// - `T`: type of blocks this allocator sub-allocates from.
@@ -25,9 +38,17 @@ use {alignment_shift, MemoryAllocator, MemoryError, MemorySubAllocator};
 pub struct ArenaAllocator<T> {
     id: MemoryTypeId,
     chunk_size: u64,
+    growth: Option<ArenaGrowth>,
+    rounding: ArenaRounding,
+    next_chunk_size: u64,
+    /// Chunks are at most this big, unless a single block is bigger
+    chunk_limit: u64,
+    min_alignment: u64,
     freed: u64,
     hot: Option<ArenaNode<T>>,
     nodes: VecDeque<ArenaNode<T>>,
+    owner: Owner,
+    misuse: MisuseHandler,
 }
 
 impl<T> ArenaAllocator<T> {
@@ -42,9 +63,16 @@ impl<T> ArenaAllocator<T> {
         ArenaAllocator {
             id,
             chunk_size,
+            growth: None,
+            rounding: ArenaRounding::ChunkSize,
+            next_chunk_size: chunk_size,
+            chunk_limit: !0,
+            min_alignment: 1,
             freed: 0,
             hot: None,
             nodes: VecDeque::new(),
+            owner: Owner::new(),
+            misuse: MisuseHandler::default(),
         }
     }
 
@@ -69,51 +97,369 @@ impl<T> ArenaAllocator<T> {
         self.chunk_size
     }
 
+    /// Set geometric growth of chunk sizes.
+    /// `None` makes all chunks `chunk_size` bytes, which is the default.
+    pub fn set_growth(&mut self, growth: Option<ArenaGrowth>) {
+        self.growth = growth;
+        self.next_chunk_size = self.chunk_size;
+    }
+
+    /// Get geometric growth of chunk sizes.
+    pub fn growth(&self) -> Option<ArenaGrowth> {
+        self.growth
+    }
+
+    /// Set how chunks are sized for blocks bigger than the next chunk.
+    /// Defaults to `ArenaRounding::ChunkSize`.
+    pub fn set_rounding(&mut self, rounding: ArenaRounding) {
+        self.rounding = rounding;
+    }
+
+    /// Get how chunks are sized for blocks bigger than the next chunk.
+    pub fn rounding(&self) -> ArenaRounding {
+        self.rounding
+    }
+
+    /// Round `chunk_size` up to a multiple of `granularity`, e.g. a 2 MiB large page.
+    ///
+    /// All chunks are allocated in increments of `chunk_size`, so their sizes are multiples
+    /// of `granularity` too.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `granularity` is not a power of two.
+    pub fn set_chunk_granularity(&mut self, granularity: u64) {
+        self.chunk_size = align_up(self.chunk_size, granularity);
+        self.next_chunk_size = max(align_up(self.next_chunk_size, granularity), self.chunk_size);
+    }
+
+    /// Make chunks at most `limit` bytes, taking precedence over `chunk_size` and growth.
+    ///
+    /// Blocks bigger than `limit` still get a chunk of their exact size. Defaults to no limit.
+    pub fn set_chunk_limit(&mut self, limit: u64) {
+        self.chunk_limit = limit;
+    }
+
+    /// Get the maximum size of chunks.
+    pub fn chunk_limit(&self) -> u64 {
+        self.chunk_limit
+    }
+
+    /// Get the minimum alignment of blocks allocated by this allocator.
+    pub fn min_alignment(&self) -> u64 {
+        self.min_alignment
+    }
+
+    /// Set the minimum alignment of blocks allocated by this allocator.
+    ///
+    /// All blocks will be aligned to at least `alignment` bytes, even if the requirements allow
+    /// a smaller alignment. E.g. a pool of uniform buffers bound at dynamic offsets can use
+    /// `minUniformBufferOffsetAlignment` here.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `alignment` is not a power of two.
+    pub fn set_min_alignment(&mut self, alignment: u64) {
+        assert!(alignment.is_power_of_two());
+        self.min_alignment = alignment;
+    }
+
     /// Retrieves the block backing an allocation.
-    pub fn underlying_block<M>(&self, block: &ArenaBlock<M>) -> &T {
-        let index = (block.1 - self.freed) as usize;
+    ///
+    /// ### Panics
+    ///
+    /// Panics if the block wasn't allocated by this allocator or was already freed,
+    /// see `owns`.
+    pub fn underlying_block<M>(&self, block: &ArenaBlock<M>) -> &T
+    where
+        M: Debug + Any,
+        T: Block<Memory = M>,
+    {
+        self.owner.check(block.2, "arena");
+        match self.node_of(block).unwrap_or_else(|| foreign_block(block)) {
+            None => &self.hot.as_ref().unwrap().block,
+            Some(index) => &self.nodes[index].block,
+        }
+    }
+
+    /// Check if a block could have been allocated by this allocator and its chunk is alive.
+    ///
+    /// Blocks carry the id of the chunk they were allocated from. The id must belong to a chunk
+    /// that wasn't freed yet, and the chunk must contain the block. With debug assertions or
+    /// the `checks` feature, blocks also carry the id of the allocator instance, which must
+    /// match.
+    pub fn owns<M>(&self, block: &ArenaBlock<M>) -> bool
+    where
+        M: Debug + Any,
+        T: Block<Memory = M>,
+    {
+        self.owner == block.2 && self.node_of(block).is_some()
+    }
+
+    /// Get the id stamped into blocks of this allocator.
+    pub(crate) fn owner(&self) -> Owner {
+        self.owner
+    }
+
+    /// Set what happens when a block is freed into this allocator that can't have been
+    /// allocated by it, or more bytes are freed from a chunk than were allocated.
+    ///
+    /// Defaults to `MisusePolicy::Panic`.
+    pub fn set_misuse_policy(&mut self, policy: MisusePolicy) {
+        self.misuse.set_policy(policy);
+    }
+
+    pub(crate) fn set_misuse_handler(&mut self, handler: MisuseHandler) {
+        self.misuse = handler;
+    }
+
+    /// Describe what is wrong with freeing a block, if anything.
+    fn misuse_of<M>(&self, block: &ArenaBlock<M>) -> Option<String>
+    where
+        M: Debug + Any,
+        T: Block<Memory = M>,
+    {
+        if let Some(message) = self.owner.mismatch(block.2, "arena") {
+            return Some(message);
+        }
+        let node = match self.node_of(block) {
+            None => return Some(foreign_message(block)),
+            Some(None) => self.hot.as_ref().unwrap(),
+            Some(Some(index)) => &self.nodes[index],
+        };
+        if node.freed + block.size() > node.used - node.wasted {
+            Some(format!(
+                "Block {:?} of chunk {} is freed, but more bytes were already freed from the \
+                 chunk than allocated",
+                block.range(),
+                block.1
+            ))
+        } else {
+            None
+        }
+    }
 
-        if self.nodes.len() == index {
-            &self.hot.as_ref().unwrap().block
+    /// Find the position of the node a block was allocated from.
+    ///
+    /// Returns `Some(None)` for the hot node and `None` if no live node contains the block.
+    fn node_of<M>(&self, block: &ArenaBlock<M>) -> Option<Option<usize>>
+    where
+        M: Debug + Any,
+        T: Block<Memory = M>,
+    {
+        let index = block.1.checked_sub(self.freed)? as usize;
+        let (node, position) = if index == self.nodes.len() {
+            (self.hot.as_ref()?, None)
         } else {
-            &self.nodes[index].block
+            (self.nodes.get(index)?, Some(index))
+        };
+        if node.block.contains(block) {
+            Some(position)
+        } else {
+            None
         }
     }
 
-    /// Get the total size of all blocks allocated by this allocator.
+    /// Allocate a block from the current chunk, never allocating a new one.
+    ///
+    /// Fails with `MemoryError::WouldGrow` if the block doesn't fit into the current chunk.
+    pub fn try_alloc<M>(&mut self, reqs: Requirements) -> Result<ArenaBlock<M>, MemoryError>
+    where
+        M: Debug + Any,
+        T: Block<Memory = M>,
+    {
+        check_requirements(&reqs)?;
+        debug_assert_ne!((1 << self.id.0) & reqs.type_mask, 0);
+        let reqs = Requirements {
+            alignment: max(reqs.alignment, self.min_alignment),
+            ..reqs
+        };
+        self.alloc_hot(reqs).ok_or(MemoryError::WouldGrow)
+    }
+
+    fn alloc_hot<M>(&mut self, reqs: Requirements) -> Option<ArenaBlock<M>>
+    where
+        M: Debug + Any,
+        T: Block<Memory = M>,
+    {
+        let index = self.freed + self.nodes.len() as u64;
+        let block = self.hot.as_mut()?.alloc(reqs)?;
+        Some(ArenaBlock(block, index, self.owner))
+    }
+
+    /// Get the total size of blocks in use, as requested.
+    ///
+    /// Alignment padding is accounted in `wasted` instead.
     pub fn used(&self) -> u64 {
-        self.nodes.iter().map(|node| node.used - node.freed).sum()
+        self.nodes
+            .iter()
+            .chain(self.hot.as_ref())
+            .map(ArenaNode::live)
+            .sum()
     }
 
-    /// Get the total size of all chunks allocated by this allocator.
+    /// Get the total size of all chunks held by this allocator, including the current one.
     pub fn allocated(&self) -> u64
     where
         T: Block,
     {
-        self.nodes.iter().map(|node| node.block.size()).sum()
+        self.nodes
+            .iter()
+            .chain(self.hot.as_ref())
+            .map(|node| node.block.size())
+            .sum()
+    }
+
+    /// Get the number of chunks held by this allocator, including the current one.
+    pub fn chunk_count(&self) -> usize {
+        self.nodes.len() + self.hot.is_some() as usize
+    }
+
+    /// Get the number of bytes of host memory used by the bookkeeping of this allocator.
+    ///
+    /// Counts the queue of chunks still in use, not the allocator itself.
+    pub fn host_memory(&self) -> usize {
+        self.nodes.capacity() * size_of::<ArenaNode<T>>()
+    }
+
+    /// Get the number of bytes that can't be used for allocations.
+    ///
+    /// This includes padding inserted to satisfy alignment and the unused tails of chunks that
+    /// were replaced by a newer chunk.
+    pub fn wasted(&self) -> u64
+    where
+        T: Block,
+    {
+        let retired: u64 = self
+            .nodes
+            .iter()
+            .map(|node| node.wasted + node.block.size() - node.used)
+            .sum();
+        retired + self.hot.as_ref().map(|node| node.wasted).unwrap_or(0)
+    }
+
+    /// Get statistics of all chunks allocated by this allocator.
+    pub fn chunk_stats<'a>(&'a self) -> impl Iterator<Item = ChunkStats> + 'a {
+        self.nodes
+            .iter()
+            .chain(self.hot.as_ref())
+            .map(|node| node.stats)
     }
 
-    unsafe fn cleanup<B, A>(&mut self, owner: &mut A, device: &B::Device)
+    /// Get the current state of all chunks allocated by this allocator.
+    pub fn chunks<'a>(&'a self) -> impl Iterator<Item = ChunkInfo> + 'a {
+        self.nodes
+            .iter()
+            .chain(self.hot.as_ref())
+            .map(|node| ChunkInfo::new(Pool::Arena, &node.stats, node.live()))
+    }
+
+    /// Audit internal consistency: no chunk is used beyond its size and no more bytes are freed
+    /// or wasted in a chunk than were used.
+    pub fn check_invariants(&self) -> Result<(), InvariantViolation>
+    where
+        T: Block,
+    {
+        let nodes = self.nodes.iter().chain(self.hot.as_ref());
+        for (index, node) in (self.freed..).zip(nodes) {
+            let message = if node.used > node.block.size() {
+                format!("{} bytes used of {} bytes", node.used, node.block.size())
+            } else if node.freed + node.wasted > node.used {
+                format!(
+                    "{} bytes freed and {} bytes wasted of {} bytes used",
+                    node.freed, node.wasted, node.used
+                )
+            } else {
+                continue;
+            };
+            return Err(InvariantViolation::new(
+                format!("type {} arena", self.id.0),
+                format!("chunk {}: {}", index, message),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Free the current chunk if no blocks are allocated from it, returning it to the underlying
+    /// allocator. Other chunks are freed as soon as they are not used.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `owner`: allocator that was used to allocate the chunks
+    /// - `device`: device the chunks were allocated from
+    pub unsafe fn trim<B, A>(&mut self, owner: &mut A, device: &B::Device)
+    where
+        B: Backend,
+        T: Block<Memory = B::Memory>,
+        A: MemoryAllocator<B, Block = T>,
+    {
+        self.trim_with(|chunk| owner.free(device, chunk));
+    }
+
+    /// Pass the current chunk to `free` if no blocks are allocated from it.
+    fn trim_with<F>(&mut self, free: F)
+    where
+        F: FnOnce(T),
+    {
+        if let Some(hot) = self.hot.take() {
+            if hot.is_used() {
+                self.hot = Some(hot);
+            } else {
+                free(hot.block);
+            }
+        }
+    }
+
+    /// Dispose of this allocator, freeing all chunks even if blocks are still in use.
+    ///
+    /// See `RootAllocator::dispose_forced`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `owner`: allocator that was used to allocate the chunks
+    /// - `device`: device the chunks were allocated from, must be idle
+    pub unsafe fn dispose_forced<B, A>(mut self, owner: &mut A, device: &B::Device)
     where
         B: Backend,
         T: Block<Memory = B::Memory>,
         A: MemoryAllocator<B, Block = T>,
     {
+        for node in self.nodes.drain(..).chain(self.hot.take()) {
+            owner.free(device, node.block);
+        }
+    }
+
+    /// Free a block, passing chunks that are no longer used to `free`.
+    ///
+    /// Chunks are freed in order, the unused chunk at the front either replaces a current chunk
+    /// that is still in use or is freed.
+    fn release_with<M, F>(&mut self, block: ArenaBlock<M>, mut free: F)
+    where
+        M: Debug + Any,
+        T: Block<Memory = M>,
+        F: FnMut(T),
+    {
+        if let Some(message) = self.misuse_of(&block) {
+            self.misuse.report(self.id, message);
+            unsafe { block.0.dispose() }
+            return;
+        }
+        let index = match self.node_of(&block).expect("Checked by misuse_of") {
+            None => return self.hot.as_mut().unwrap().free(block.0),
+            Some(index) => index,
+        };
+        self.nodes[index].free(block.0);
         while self
             .nodes
             .front()
             .map(|node| !node.is_used())
             .unwrap_or(false)
         {
-            if let Some(node) = self.nodes.pop_front() {
-                if let Some(ref mut hot) = self.hot {
-                    if hot.is_used() {
-                        self.nodes.push_back(replace(hot, node));
-                    } else {
-                        // No need to replace.
-                        node.dispose(owner, device).unwrap();
-                    }
-                }
+            let node = self.nodes.pop_front().unwrap();
+            match self.hot {
+                Some(ref mut hot) if hot.is_used() => self.nodes.push_back(replace(hot, node)),
+                // No need to replace.
+                _ => free(node.block),
             }
             self.freed += 1;
         }
@@ -131,14 +477,31 @@ impl<T> ArenaAllocator<T> {
         T: Block<Memory = B::Memory>,
         A: MemoryAllocator<B, Block = T>,
     {
-        let size = ((reqs.size - 1) / self.chunk_size + 1) * self.chunk_size;
+        let size = match self.rounding {
+            ArenaRounding::ChunkSize => checked_round_up(reqs.size, self.chunk_size),
+            ArenaRounding::PowerOfTwo => reqs.size.checked_next_power_of_two(),
+            ArenaRounding::Exact => Some(reqs.size),
+        }
+        .ok_or(InvalidRequirements::Overflow {
+            size: reqs.size,
+            alignment: reqs.alignment,
+        })?;
+        let size = max(size, self.next_chunk_size);
+        let size = min(size, max(self.chunk_limit, reqs.size));
         let arena_requirements = Requirements {
             type_mask: 1 << self.id.0,
             size,
             alignment: reqs.alignment,
         };
         let arena_block = owner.alloc(device, request, arena_requirements)?;
-        Ok(ArenaNode::new(arena_block))
+        if let Some(growth) = self.growth {
+            let next = (self.next_chunk_size as f64 * growth.factor as f64) as u64;
+            let next = max(next, self.chunk_size);
+            let next = checked_round_up(next, self.chunk_size).unwrap_or(!0);
+            self.next_chunk_size = min(next, growth.max_chunk_size);
+        }
+        let stats = ChunkStats::new(self.id, arena_block.memory(), arena_block.range());
+        Ok(ArenaNode::new(arena_block, stats))
     }
 }
 
@@ -158,16 +521,15 @@ where
         request: O::Request,
         reqs: Requirements,
     ) -> Result<ArenaBlock<B::Memory>, MemoryError> {
-        if (1 << self.id.0) & reqs.type_mask == 0 {
-            return Err(MemoryError::NoCompatibleMemoryType);
-        }
-        let index = self.freed + self.nodes.len() as u64;
-        if let Some(ref mut hot) = self.hot.as_mut() {
-            match hot.alloc(reqs) {
-                Some(block) => return Ok(ArenaBlock(block, index)),
-                None => {}
-            }
+        check_requirements(&reqs)?;
+        debug_assert_ne!((1 << self.id.0) & reqs.type_mask, 0);
+        let reqs = Requirements {
+            alignment: max(reqs.alignment, self.min_alignment),
+            ..reqs
         };
+        if let Some(block) = self.alloc_hot(reqs) {
+            return Ok(block);
+        }
 
         let mut node = self.allocate_node(owner, device, request, reqs)?;
         let block = node.alloc(reqs).unwrap();
@@ -178,23 +540,11 @@ where
             }
         };
         let index = self.freed + self.nodes.len() as u64;
-        Ok(ArenaBlock(block, index))
+        Ok(ArenaBlock(block, index, self.owner))
     }
 
     unsafe fn free(&mut self, owner: &mut O, device: &B::Device, block: ArenaBlock<B::Memory>) {
-        let ArenaBlock(block, index) = block;
-        let index = (index - self.freed) as usize;
-
-        match self.nodes.len() {
-            len if len == index => {
-                self.hot.as_mut().unwrap().free(block);
-            }
-            len if len > index => {
-                self.nodes[index].free(block);
-                self.cleanup(owner, device);
-            }
-            _ => unreachable!(),
-        }
+        self.release_with(block, |chunk| owner.free(device, chunk));
     }
 
     unsafe fn dispose(mut self, owner: &mut O, device: &B::Device) -> Result<(), Self> {
@@ -209,18 +559,60 @@ where
     }
 }
 
+/// Geometric growth of chunk sizes of `ArenaAllocator`.
+///
+/// Each new chunk is `factor` times bigger than the previous one, rounded up to a multiple of
+/// `chunk_size`, until `max_chunk_size` is reached. Workloads with steadily growing data need
+/// fewer chunks before stabilizing.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ArenaGrowth {
+    /// Factor between sizes of consecutive chunks.
+    pub factor: f32,
+    /// Maximum size of chunks allocated because of growth.
+    pub max_chunk_size: u64,
+}
+
+/// How `ArenaAllocator` sizes chunks for blocks that don't fit into a chunk of the usual size.
+///
+/// Chunks are never smaller than the usual size, and what remains of a chunk after the block
+/// serves the blocks allocated after it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ArenaRounding {
+    /// Round up to a multiple of `chunk_size`, so all chunks stay multiples of it, e.g. of a
+    /// large page. This is the default.
+    ChunkSize,
+
+    /// Round up to the next power of two, leaving room for more big blocks.
+    PowerOfTwo,
+
+    /// Use the size of the block, wasting no memory when big blocks are rare.
+    Exact,
+}
+
+impl Default for ArenaRounding {
+    fn default() -> Self {
+        ArenaRounding::ChunkSize
+    }
+}
+
 #[derive(Debug)]
 struct ArenaNode<T> {
     used: u64,
     freed: u64,
+    wasted: u64,
+    stats: ChunkStats,
     block: T,
 }
 
 impl<T> ArenaNode<T> {
-    fn new(block: T) -> Self {
+    fn new(block: T, stats: ChunkStats) -> Self {
         ArenaNode {
             used: 0,
             freed: 0,
+            wasted: 0,
+            stats,
             block,
         }
     }
@@ -231,15 +623,18 @@ impl<T> ArenaNode<T> {
         T: Block<Memory = M>,
     {
         let offset = self.block.range().start + self.used;
-        let total_size = reqs.size + alignment_shift(reqs.alignment, offset);
+        let shift = checked_align_offset(offset, reqs.alignment)?;
+        let total_size = reqs.size.checked_add(shift)?;
 
         if self.block.size() - self.used < total_size {
             None
         } else {
             self.used += total_size;
+            self.wasted += shift;
+            self.stats.alloc();
             Some(RawBlock::new(
                 self.block.memory(),
-                offset..total_size + offset,
+                offset + shift..offset + total_size,
             ))
         }
     }
@@ -251,11 +646,17 @@ impl<T> ArenaNode<T> {
     {
         assert!(self.block.contains(&block));
         self.freed += block.size();
+        self.stats.free();
         unsafe { block.dispose() }
     }
 
+    /// Get the total size of blocks in use, excluding alignment padding.
+    fn live(&self) -> u64 {
+        self.used - self.wasted - self.freed
+    }
+
     fn is_used(&self) -> bool {
-        self.freed != self.used
+        self.live() != 0
     }
 
     unsafe fn dispose<B, A>(self, owner: &mut A, device: &B::Device) -> Result<(), Self>
@@ -273,9 +674,30 @@ impl<T> ArenaNode<T> {
     }
 }
 
+/// Report a block that doesn't belong to any live chunk of an allocator.
+fn foreign_block<M>(block: &ArenaBlock<M>) -> !
+where
+    M: Debug + Any,
+{
+    panic!("{}", foreign_message(block))
+}
+
+/// Describe a block that doesn't belong to any live chunk of an allocator.
+fn foreign_message<M>(block: &ArenaBlock<M>) -> String
+where
+    M: Debug + Any,
+{
+    format!(
+        "Block {:?} of chunk {} wasn't allocated by this arena allocator or its chunk was already \
+         freed",
+        block.range(),
+        block.1
+    )
+}
+
 /// `Block` type returned by `ArenaAllocator`.
 #[derive(Debug)]
-pub struct ArenaBlock<M>(pub(crate) RawBlock<M>, pub(crate) u64);
+pub struct ArenaBlock<M>(pub(crate) RawBlock<M>, pub(crate) u64, pub(crate) Owner);
 
 impl<M> Block for ArenaBlock<M>
 where
@@ -302,3 +724,174 @@ fn test_send_sync() {
         foo::<ArenaAllocator<M>>()
     }
 }
+
+#[test]
+fn test_node_alignment() {
+    let memory = 0u8;
+    let chunk = RawBlock::new(&memory, 64..1088);
+    let stats = ChunkStats::new(MemoryTypeId(0), &memory, chunk.range());
+    let mut node = ArenaNode::new(chunk, stats);
+    let reqs = |size, alignment| Requirements {
+        size,
+        alignment,
+        type_mask: !0,
+    };
+
+    let a = node.alloc::<u8>(reqs(10, 1)).unwrap();
+    assert_eq!(a.range(), 64..74);
+    let b = node.alloc::<u8>(reqs(100, 256)).unwrap();
+    assert_eq!(b.range(), 256..356);
+    let c = node.alloc::<u8>(reqs(3, 16)).unwrap();
+    assert_eq!(c.range(), 368..371);
+    let d = node.alloc::<u8>(reqs(64, 64)).unwrap();
+    assert_eq!(d.range(), 384..448);
+    assert!(node.alloc::<u8>(reqs(512, 1024)).is_none());
+    assert_eq!(node.wasted, 182 + 12 + 13);
+    assert_eq!(node.live(), 177);
+
+    for block in [a, b, c, d] {
+        node.free(block);
+    }
+    assert!(!node.is_used());
+    unsafe { node.block.dispose() }
+}
+
+#[test]
+fn test_owns() {
+    let memory = 0u8;
+    let other = 0u8;
+    let chunk = RawBlock::new(&memory, 0..1024);
+    let stats = ChunkStats::new(MemoryTypeId(0), &memory, chunk.range());
+    let mut allocator = ArenaAllocator::new(MemoryTypeId(0), 1024);
+    allocator.hot = Some(ArenaNode::new(chunk, stats));
+    let reqs = Requirements {
+        size: 16,
+        alignment: 1,
+        type_mask: !0,
+    };
+
+    let block = allocator.try_alloc::<u8>(reqs).unwrap();
+    assert!(allocator.owns(&block));
+    assert_eq!(allocator.underlying_block(&block).range(), 0..1024);
+
+    let stale = ArenaBlock(RawBlock::new(&memory, 0..16), 1, allocator.owner);
+    assert!(!allocator.owns(&stale));
+    let foreign = ArenaBlock(RawBlock::new(&other, 0..16), 0, allocator.owner);
+    assert!(!allocator.owns(&foreign));
+
+    let mut hot = allocator.hot.take().unwrap();
+    for block in vec![block, stale, foreign] {
+        if hot.block.contains(&block) && block.1 == 0 {
+            hot.free(block.0);
+        } else {
+            unsafe { block.0.dispose() }
+        }
+    }
+    assert!(!hot.is_used());
+    unsafe { hot.block.dispose() }
+}
+
+#[test]
+fn test_min_alignment() {
+    let memory = 0u8;
+    let chunk = RawBlock::new(&memory, 0..1024);
+    let stats = ChunkStats::new(MemoryTypeId(0), &memory, chunk.range());
+    let mut allocator = ArenaAllocator::new(MemoryTypeId(0), 1024);
+    allocator.set_min_alignment(256);
+    allocator.hot = Some(ArenaNode::new(chunk, stats));
+    let reqs = Requirements {
+        size: 10,
+        alignment: 4,
+        type_mask: !0,
+    };
+
+    let a = allocator.try_alloc::<u8>(reqs).unwrap();
+    let b = allocator.try_alloc::<u8>(reqs).unwrap();
+    assert_eq!(a.range(), 0..10);
+    assert_eq!(b.range(), 256..266);
+    assert_eq!(allocator.wasted(), 246);
+
+    let mut hot = allocator.hot.take().unwrap();
+    hot.free(a.0);
+    hot.free(b.0);
+    unsafe { hot.block.dispose() }
+}
+
+#[test]
+fn test_wasted() {
+    let memory = 0u8;
+    let mut allocator = ArenaAllocator::new(MemoryTypeId(0), 1024);
+    let reqs = |size, alignment| Requirements {
+        size,
+        alignment,
+        type_mask: !0,
+    };
+    let chunk = RawBlock::new(&memory, 0..1024);
+    let stats = ChunkStats::new(MemoryTypeId(0), &memory, chunk.range());
+    allocator.hot = Some(ArenaNode::new(chunk, stats));
+
+    let a = allocator.try_alloc::<u8>(reqs(100, 1)).unwrap();
+    let b = allocator.try_alloc::<u8>(reqs(100, 256)).unwrap();
+    assert_eq!(b.range(), 256..356);
+    assert_eq!(allocator.wasted(), 156);
+    assert_eq!(allocator.used(), 200);
+
+    // The unused tail of a retired chunk can't be used anymore
+    let retired = allocator.hot.take().unwrap();
+    allocator.nodes.push_back(retired);
+    let chunk = RawBlock::new(&memory, 1024..2048);
+    let stats = ChunkStats::new(MemoryTypeId(0), &memory, chunk.range());
+    allocator.hot = Some(ArenaNode::new(chunk, stats));
+    assert_eq!(allocator.wasted(), 156 + 1024 - 356);
+
+    let mut retired = allocator.nodes.pop_front().unwrap();
+    retired.free(a.0);
+    retired.free(b.0);
+    unsafe {
+        retired.block.dispose();
+        allocator.hot.take().unwrap().block.dispose();
+    }
+}
+
+#[test]
+fn test_trim() {
+    let memory = 0u8;
+    let mut allocator = ArenaAllocator::new(MemoryTypeId(0), 1024);
+    let reqs = Requirements {
+        size: 16,
+        alignment: 1,
+        type_mask: !0,
+    };
+    let chunk = RawBlock::new(&memory, 0..1024);
+    let stats = ChunkStats::new(MemoryTypeId(0), &memory, chunk.range());
+    allocator.hot = Some(ArenaNode::new(chunk, stats));
+    let block = allocator.try_alloc::<u8>(reqs).unwrap();
+
+    // Retire the chunk in use, as `alloc` does when a block doesn't fit
+    let chunk = RawBlock::new(&memory, 1024..2048);
+    let stats = ChunkStats::new(MemoryTypeId(0), &memory, chunk.range());
+    let retired = allocator.hot.replace(ArenaNode::new(chunk, stats)).unwrap();
+    allocator.nodes.push_back(retired);
+
+    let mut freed = Vec::new();
+    allocator.trim_with(|chunk| freed.push(chunk));
+    assert!(allocator.hot.is_none());
+    assert_eq!(freed[0].range(), 1024..2048);
+
+    // Without a current chunk the retired chunk is freed along with its last block
+    allocator.release_with(block, |chunk| freed.push(chunk));
+    assert_eq!(freed[1].range(), 0..1024);
+    assert_eq!(allocator.chunk_count(), 0);
+    assert!(allocator.check_invariants().is_ok());
+    for chunk in freed {
+        unsafe { chunk.dispose() }
+    }
+}
+
+#[test]
+fn test_block_size() {
+    assert_eq!(
+        size_of::<ArenaBlock<()>>(),
+        size_of::<RawBlock<()>>() + 8 + size_of::<Owner>()
+    );
+}
@@ -1,8 +1,13 @@
 use std::any::Any;
 use std::fmt::Debug;
+use std::mem::ManuallyDrop;
 use std::ops::Range;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::u32;
 
-use relevant::Relevant;
+use guard::Relevant;
 
 /// Trait for types that represent a block (`Range`) of `Memory`.
 pub trait Block: Send + Sync + Debug {
@@ -56,6 +61,8 @@ pub struct RawBlock<M> {
     memory: *const M,
 }
 
+// `memory` is only dereferenced by `memory`, and allocators only point it at hal memory
+// objects, which are `Send` and `Sync`.
 unsafe impl<M> Send for RawBlock<M> {}
 
 unsafe impl<M> Sync for RawBlock<M> {}
@@ -78,16 +85,39 @@ impl<M> RawBlock<M> {
         }
     }
 
-    #[doc(hidden)]
+    /// Construct a tagged block for a range of a memory object.
+    ///
+    /// This allows allocators outside of this crate to produce `RawBlock`s and wrap them in their
+    /// own block types, the same way this crate's sub-allocators do.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `memory`: memory object the block is allocated from
+    /// - `range`: range of the `memory` used by the block
+    ///
+    /// ### Safety
+    ///
+    /// The block keeps a pointer to `memory` without borrowing it. The caller must ensure that
+    /// `memory` stays at the same address and is not freed until the block is disposed.
+    /// The block must be disposed with `dispose` by the allocator that created it.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `range.start > range.end`.
+    pub unsafe fn from_raw_parts(memory: &M, range: Range<u64>) -> Self {
+        Self::new(memory, range)
+    }
+
+    /// Change the size of this block, keeping its offset.
+    pub(crate) fn resize(&mut self, size: u64) {
+        self.range.end = self.range.start + size;
+    }
+
     /// Dispose of this block.
     ///
     /// This is unsafe because the caller must ensure that the memory of the block is not used
     /// again. This will typically entail dropping some kind of resource (`Buffer` or `Image` to
     /// give some examples) that occupy this memory.
-    ///
-    /// ### Returns
-    ///
-    /// Tag value of the block
     pub unsafe fn dispose(self) {
         self.relevant.dispose();
     }
@@ -111,6 +141,101 @@ where
     }
 }
 
+/// Block of memory with 32-bit offset and size, for pools of memory objects below 4 GiB.
+///
+/// Takes 16 bytes instead of the 24 of a `RawBlock`, which adds up when blocks are stored per
+/// resource, e.g. in ECS component storages. Blocks are compacted with `compact` and turned back
+/// into `RawBlock`s with `expand` to be returned to their allocator. Whether all blocks of a
+/// pool fit is known when the pool is created, see `fits_pool`.
+///
+/// Like a `RawBlock`, a `CompactBlock` must never be silently dropped.
+///
+/// ### Type parameters:
+///
+/// - `M`: hal memory type.
+#[derive(Debug)]
+pub struct CompactBlock<M> {
+    relevant: Relevant,
+    memory: *const M,
+    offset: u32,
+    size: u32,
+}
+
+// Same as `RawBlock`.
+unsafe impl<M> Send for CompactBlock<M> {}
+
+unsafe impl<M> Sync for CompactBlock<M> {}
+
+impl<M> CompactBlock<M> {
+    /// Largest size of memory objects whose blocks always fit into a `CompactBlock`.
+    pub const MAX_POOL_SIZE: u64 = u32::MAX as u64;
+
+    /// Check if all blocks of a pool can be compacted.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `max_size`: size of the largest memory object of the pool, e.g.
+    ///               `ChunkedAllocator::max_chunk_size` for chunked allocators that own their
+    ///               chunks
+    pub fn fits_pool(max_size: u64) -> bool {
+        max_size <= Self::MAX_POOL_SIZE
+    }
+
+    /// Compact a block.
+    ///
+    /// ### Returns
+    ///
+    /// Returns the block unchanged if it ends beyond `MAX_POOL_SIZE`.
+    pub fn compact(block: RawBlock<M>) -> Result<Self, RawBlock<M>> {
+        if block.range.end > Self::MAX_POOL_SIZE {
+            return Err(block);
+        }
+        let RawBlock {
+            relevant,
+            range,
+            memory,
+        } = block;
+        Ok(CompactBlock {
+            relevant,
+            memory,
+            offset: range.start as u32,
+            size: (range.end - range.start) as u32,
+        })
+    }
+
+    /// Turn this block back into a `RawBlock`, e.g. to return it to its allocator.
+    pub fn expand(self) -> RawBlock<M> {
+        let range = self.range_u64();
+        RawBlock {
+            relevant: self.relevant,
+            range,
+            memory: self.memory,
+        }
+    }
+
+    fn range_u64(&self) -> Range<u64> {
+        self.offset as u64..self.offset as u64 + self.size as u64
+    }
+}
+
+impl<M> Block for CompactBlock<M>
+where
+    M: Debug + Any,
+{
+    type Memory = M;
+
+    #[inline]
+    fn memory(&self) -> &M {
+        // Has to be valid, like the memory of the `RawBlock` it was compacted from
+        unsafe { &*self.memory }
+    }
+
+    #[inline]
+    fn range(&self) -> Range<u64> {
+        self.range_u64()
+    }
+}
+
 impl<T, Y> Block for (T, Y)
 where
     T: Block,
@@ -128,3 +253,191 @@ where
         self.0.range()
     }
 }
+
+/// Block of memory shared by several resources.
+///
+/// Each `SharedBlock` refers to the range occupied by a single resource, while the underlying
+/// block is kept alive until the last `SharedBlock` referring to it is consumed.
+///
+/// ### Type parameters:
+///
+/// - `T`: type of the underlying block.
+#[derive(Debug)]
+pub struct SharedBlock<T> {
+    shared: Arc<Shared<T>>,
+    range: Range<u64>,
+}
+
+/// Underlying block of `SharedBlock`s, with the number of them not consumed yet.
+#[derive(Debug)]
+struct Shared<T> {
+    block: ManuallyDrop<T>,
+    handles: AtomicUsize,
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        // The last consumed shared block took the block, unless some were dropped instead
+        if *self.handles.get_mut() != 0 {
+            unsafe { ManuallyDrop::drop(&mut self.block) }
+        }
+    }
+}
+
+impl<T> SharedBlock<T>
+where
+    T: Block,
+{
+    /// Split a block into shared blocks.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `block`: block to share
+    /// - `ranges`: offsets and sizes of resource ranges relative to the start of the block
+    ///
+    /// ### Panics
+    ///
+    /// Panics if any range doesn't fit into the block.
+    ///
+    /// ### Returns
+    ///
+    /// Shared blocks in the order of `ranges`, or `block` back if `ranges` is empty, since no
+    /// shared block would be left to return it to its allocator.
+    pub fn split<I>(block: T, ranges: I) -> Result<Vec<Self>, T>
+    where
+        I: IntoIterator<Item = (u64, u64)>,
+    {
+        let shared = Arc::new(Shared {
+            block: ManuallyDrop::new(block),
+            handles: AtomicUsize::new(0),
+        });
+        let (start, block_size) = (shared.block.range().start, shared.block.size());
+        let blocks: Vec<Self> = ranges
+            .into_iter()
+            .map(|(offset, size)| {
+                assert!(offset
+                    .checked_add(size)
+                    .map_or(false, |end| end <= block_size));
+                SharedBlock {
+                    shared: shared.clone(),
+                    range: start + offset..start + offset + size,
+                }
+            })
+            .collect();
+        if blocks.is_empty() {
+            // No handles are counted, so dropping `shared` leaves the block alone
+            Err(unsafe { ptr::read(&*shared.block) })
+        } else {
+            shared.handles.store(blocks.len(), Ordering::Release);
+            Ok(blocks)
+        }
+    }
+
+    /// Get the underlying block.
+    pub fn whole(&self) -> &T {
+        &self.shared.block
+    }
+
+    /// Get number of shared blocks referring to the same underlying block and not consumed yet.
+    pub fn shared_count(&self) -> usize {
+        self.shared.handles.load(Ordering::Acquire)
+    }
+
+    /// Consume this shared block.
+    ///
+    /// Shared blocks may be consumed concurrently, exactly one of them returns the block.
+    ///
+    /// ### Returns
+    ///
+    /// Underlying block if this was the last shared block referring to it.
+    pub fn into_inner(self) -> Option<T> {
+        if self.shared.handles.fetch_sub(1, Ordering::AcqRel) == 1 {
+            // Other shared blocks are consumed and don't access the block anymore, and with no
+            // handles counted, dropping `shared` leaves it alone
+            Some(unsafe { ptr::read(&*self.shared.block) })
+        } else {
+            None
+        }
+    }
+}
+
+impl<T> Block for SharedBlock<T>
+where
+    T: Block,
+{
+    type Memory = T::Memory;
+
+    #[inline(always)]
+    fn memory(&self) -> &T::Memory {
+        self.shared.block.memory()
+    }
+
+    #[inline(always)]
+    fn range(&self) -> Range<u64> {
+        self.range.clone()
+    }
+}
+
+#[test]
+fn test_compact_block() {
+    use std::mem::size_of;
+    assert_eq!(size_of::<CompactBlock<()>>(), 16);
+    assert!(CompactBlock::<()>::fits_pool(1 << 31));
+    assert!(!CompactBlock::<()>::fits_pool(1 << 32));
+
+    let memory = ();
+    let block = CompactBlock::compact(RawBlock::new(&memory, 256..1024)).unwrap();
+    assert_eq!(block.range(), 256..1024);
+    let block = block.expand();
+    assert_eq!(block.range(), 256..1024);
+    unsafe { block.dispose() }
+
+    let block =
+        CompactBlock::compact(RawBlock::new(&memory, 1 << 32..(1 << 32) + 256)).unwrap_err();
+    unsafe { block.dispose() }
+}
+
+#[test]
+fn test_shared_block_split() {
+    let memory = ();
+    let block = RawBlock::new(&memory, 256..1024);
+    let mut blocks = SharedBlock::split(block, vec![(0, 100), (512, 256)]).unwrap();
+    assert_eq!(blocks[0].range(), 256..356);
+    assert_eq!(blocks[1].range(), 768..1024);
+    assert_eq!(blocks[0].shared_count(), 2);
+    assert!(blocks.pop().unwrap().into_inner().is_none());
+    let block = blocks.pop().unwrap().into_inner().unwrap();
+
+    // Nothing to share, the block is handed back instead of being dropped
+    let block = SharedBlock::split(block, None).unwrap_err();
+    assert_eq!(block.range(), 256..1024);
+    unsafe { block.dispose() }
+}
+
+#[test]
+#[should_panic]
+fn test_shared_block_split_overflow() {
+    let memory = ();
+    let block = RawBlock::new(&memory, 0..1024);
+    let _ = SharedBlock::split(block, vec![(512, !0)]);
+}
+
+#[test]
+fn test_shared_block_concurrent_into_inner() {
+    use std::thread;
+    let memory = ();
+    for _ in 0..100 {
+        let block = RawBlock::new(&memory, 0..1024);
+        let blocks = SharedBlock::split(block, vec![(0, 512), (512, 512)]).unwrap();
+        let threads: Vec<_> = blocks
+            .into_iter()
+            .map(|block| thread::spawn(move || block.into_inner()))
+            .collect();
+        let mut inner: Vec<_> = threads
+            .into_iter()
+            .filter_map(|thread| thread.join().unwrap())
+            .collect();
+        assert_eq!(inner.len(), 1);
+        unsafe { inner.pop().unwrap().dispose() }
+    }
+}
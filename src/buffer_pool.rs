@@ -0,0 +1,378 @@
+use std::fmt::{self, Debug};
+use std::iter::once;
+use std::ops::Range;
+
+use gfx_hal::buffer::Usage as BufferUsage;
+use gfx_hal::{Backend, Device};
+
+use align::align_up;
+use block::Block;
+use factory::{FactoryError, ResourceInfo};
+use guard::Relevant;
+use MemoryAllocator;
+
+/// Pool of small buffers sub-allocated from big shared buffers.
+///
+/// Instead of creating a hal `Buffer` for each allocation, this pool creates buffers of
+/// `buffer_size` bytes, binds them to memory from the underlying allocator and hands out ranges
+/// of those buffers. Fewer buffer objects means fewer binds and device objects.
+///
+/// All buffers of a pool share the same usage and are allocated with the same request, so a
+/// separate pool should be used for each usage class and kind of memory.
+///
+/// ### Type parameters:
+///
+/// - `B`: hal `Backend`
+/// - `A`: allocator used to allocate memory for the shared buffers
+pub struct BufferPool<B: Backend, A: MemoryAllocator<B>> {
+    usage: BufferUsage,
+    request: A::Request,
+    buffer_size: u64,
+    buffers: Vec<Option<PoolBuffer<B, A::Block>>>,
+}
+
+impl<B, A> Debug for BufferPool<B, A>
+where
+    B: Backend,
+    A: MemoryAllocator<B>,
+    A::Request: Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("BufferPool")
+            .field("usage", &self.usage)
+            .field("request", &self.request)
+            .field("buffer_size", &self.buffer_size)
+            .field("buffers", &self.buffers)
+            .finish()
+    }
+}
+
+impl<B, A> BufferPool<B, A>
+where
+    B: Backend,
+    A: MemoryAllocator<B>,
+    A::Request: Clone,
+{
+    /// Create a new buffer pool.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `usage`: hal buffer `Usage` of the shared buffers
+    /// - `request`: information needed by the `MemoryAllocator` to allocate memory for the
+    ///              shared buffers
+    /// - `buffer_size`: minimum size of the shared buffers in bytes
+    pub fn new(usage: BufferUsage, request: A::Request, buffer_size: u64) -> Self {
+        BufferPool {
+            usage,
+            request,
+            buffer_size,
+            buffers: Vec::new(),
+        }
+    }
+
+    /// Get usage of the shared buffers.
+    pub fn usage(&self) -> BufferUsage {
+        self.usage
+    }
+
+    /// Get the total size of all ranges allocated from this pool.
+    pub fn used(&self) -> u64 {
+        self.buffers
+            .iter()
+            .filter_map(Option::as_ref)
+            .map(|buffer| buffer.used)
+            .sum()
+    }
+
+    /// Get the total size of all shared buffers.
+    pub fn allocated(&self) -> u64 {
+        self.buffers
+            .iter()
+            .filter_map(Option::as_ref)
+            .map(|buffer| buffer.size)
+            .sum()
+    }
+
+    /// Check if any of the ranges allocated from this pool are still in use.
+    pub fn is_used(&self) -> bool {
+        self.used() != 0
+    }
+
+    /// Allocate a range of a shared buffer.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `allocator`: allocator used to allocate memory for new shared buffers, must always be
+    ///                the same for an instance of the pool
+    /// - `device`: device to create shared buffers on
+    /// - `size`: size of the range in bytes
+    /// - `alignment`: alignment of the range offset within the shared buffer, e.g.
+    ///                `minUniformBufferOffsetAlignment` for uniform buffers
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `alignment` is not a power of two.
+    pub unsafe fn alloc(
+        &mut self,
+        allocator: &mut A,
+        device: &B::Device,
+        size: u64,
+        alignment: u64,
+    ) -> Result<PooledBuffer, FactoryError> {
+        for (index, buffer) in self.buffers.iter_mut().enumerate() {
+            if let Some(ref mut buffer) = *buffer {
+                if let Some(range) = buffer.alloc(size, alignment) {
+                    return Ok(PooledBuffer::new(index, range));
+                }
+            }
+        }
+
+        let buffer_size = if size > self.buffer_size {
+            size
+        } else {
+            self.buffer_size
+        };
+        let mut buffer = self.create_buffer(allocator, device, buffer_size)?;
+        let range = buffer.alloc(size, alignment).expect("Buffer is big enough");
+        let index = match self.buffers.iter().position(Option::is_none) {
+            Some(index) => {
+                self.buffers[index] = Some(buffer);
+                index
+            }
+            None => {
+                self.buffers.push(Some(buffer));
+                self.buffers.len() - 1
+            }
+        };
+        Ok(PooledBuffer::new(index, range))
+    }
+
+    /// Free a range of a shared buffer.
+    ///
+    /// The shared buffer is destroyed and its memory is freed when it has no ranges in use.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `allocator`: allocator used to allocate memory for the shared buffers
+    /// - `device`: device the shared buffers were created on
+    /// - `buffer`: range to free
+    pub unsafe fn free(&mut self, allocator: &mut A, device: &B::Device, buffer: PooledBuffer) {
+        let PooledBuffer {
+            relevant,
+            index,
+            range,
+        } = buffer;
+        relevant.dispose();
+        let empty = {
+            let shared = self.buffers[index].as_mut().expect("Buffer is alive");
+            shared.free(range);
+            shared.used == 0
+        };
+        if empty {
+            let shared = self.buffers[index].take().unwrap();
+            device.destroy_buffer(shared.raw);
+            allocator.free(device, shared.block);
+        }
+    }
+
+    /// Get the shared buffer a range was allocated from.
+    pub fn buffer(&self, buffer: &PooledBuffer) -> &B::Buffer {
+        &self.buffers[buffer.index]
+            .as_ref()
+            .expect("Buffer is alive")
+            .raw
+    }
+
+    /// Get the memory block bound to the shared buffer a range was allocated from.
+    pub fn block(&self, buffer: &PooledBuffer) -> &A::Block {
+        &self.buffers[buffer.index]
+            .as_ref()
+            .expect("Buffer is alive")
+            .block
+    }
+
+    /// Attempt to dispose of this pool.
+    ///
+    /// ### Returns
+    ///
+    /// If the pool contains ranges that are still in use, this will return `Err(self)`.
+    pub unsafe fn dispose(mut self, allocator: &mut A, device: &B::Device) -> Result<(), Self> {
+        if self.is_used() {
+            Err(self)
+        } else {
+            for shared in self.buffers.drain(..).flatten() {
+                device.destroy_buffer(shared.raw);
+                allocator.free(device, shared.block);
+            }
+            Ok(())
+        }
+    }
+
+    unsafe fn create_buffer(
+        &mut self,
+        allocator: &mut A,
+        device: &B::Device,
+        size: u64,
+    ) -> Result<PoolBuffer<B, A::Block>, FactoryError> {
+        let usage = self.usage;
+        let resource = || ResourceInfo::Buffer { size, usage };
+        let mut raw = device.create_buffer(size, usage).map_err(|error| {
+            FactoryError::BufferCreationError {
+                error,
+                resource: resource(),
+            }
+        })?;
+        let reqs = device.get_buffer_requirements(&raw);
+        let block = match allocator.alloc(device, self.request.clone(), reqs) {
+            Ok(block) => block,
+            Err(error) => {
+                device.destroy_buffer(raw);
+                return Err(FactoryError::MemoryError {
+                    error,
+                    resource: resource(),
+                });
+            }
+        };
+        if let Err(error) = device.bind_buffer_memory(block.memory(), block.range().start, &mut raw)
+        {
+            device.destroy_buffer(raw);
+            allocator.free(device, block);
+            return Err(FactoryError::BindError {
+                error,
+                resource: resource(),
+            });
+        }
+        Ok(PoolBuffer {
+            raw,
+            block,
+            size,
+            used: 0,
+            free: once(0..size).collect(),
+        })
+    }
+}
+
+/// Big buffer shared between pooled buffers.
+#[derive(Debug)]
+struct PoolBuffer<B: Backend, T> {
+    raw: B::Buffer,
+    block: T,
+    size: u64,
+    used: u64,
+    free: Vec<Range<u64>>,
+}
+
+impl<B, T> PoolBuffer<B, T>
+where
+    B: Backend,
+{
+    fn alloc(&mut self, size: u64, alignment: u64) -> Option<Range<u64>> {
+        let range = first_fit(&mut self.free, size, alignment)?;
+        self.used += size;
+        Some(range)
+    }
+
+    fn free(&mut self, range: Range<u64>) {
+        self.used -= range.end - range.start;
+        release(&mut self.free, range);
+    }
+}
+
+/// Take first free range that can fit aligned `size` bytes.
+fn first_fit(free: &mut Vec<Range<u64>>, size: u64, alignment: u64) -> Option<Range<u64>> {
+    let (index, start) = free.iter().enumerate().find_map(|(index, range)| {
+        let start = align_up(range.start, alignment);
+        if start + size <= range.end {
+            Some((index, start))
+        } else {
+            None
+        }
+    })?;
+    let range = free.remove(index);
+    if start + size < range.end {
+        free.insert(index, start + size..range.end);
+    }
+    if range.start < start {
+        free.insert(index, range.start..start);
+    }
+    Some(start..start + size)
+}
+
+/// Return range to the sorted free list, merging it with adjacent free ranges.
+fn release(free: &mut Vec<Range<u64>>, range: Range<u64>) {
+    let index = free
+        .iter()
+        .position(|free| free.start > range.start)
+        .unwrap_or(free.len());
+    let mut range = range;
+    if index < free.len() && free[index].start == range.end {
+        range.end = free.remove(index).end;
+    }
+    if index > 0 && free[index - 1].end == range.start {
+        free[index - 1].end = range.end;
+    } else {
+        free.insert(index, range);
+    }
+}
+
+/// Range of a shared buffer allocated by `BufferPool`.
+///
+/// A `PooledBuffer` must never be silently dropped, that will result in a panic.
+/// It must be freed by returning it to the same pool it came from.
+#[derive(Debug)]
+pub struct PooledBuffer {
+    relevant: Relevant,
+    index: usize,
+    range: Range<u64>,
+}
+
+impl PooledBuffer {
+    fn new(index: usize, range: Range<u64>) -> Self {
+        PooledBuffer {
+            relevant: Relevant,
+            index,
+            range,
+        }
+    }
+
+    /// Offset of this range in the shared buffer.
+    pub fn offset(&self) -> u64 {
+        self.range.start
+    }
+
+    /// Size of this range in bytes.
+    pub fn size(&self) -> u64 {
+        self.range.end - self.range.start
+    }
+
+    /// Range of the shared buffer.
+    pub fn range(&self) -> Range<u64> {
+        self.range.clone()
+    }
+}
+
+#[test]
+fn test_free_list() {
+    let mut free = once(0..1024).collect();
+    assert_eq!(first_fit(&mut free, 10, 1), Some(0..10));
+    assert_eq!(first_fit(&mut free, 10, 256), Some(256..266));
+    assert_eq!(free, [10..256, 266..1024]);
+    assert_eq!(first_fit(&mut free, 2048, 1), None);
+    release(&mut free, 0..10);
+    assert_eq!(free, [0..256, 266..1024]);
+    release(&mut free, 256..266);
+    assert_eq!(free.len(), 1);
+    assert_eq!(free[0], 0..1024);
+}
+
+#[test]
+#[allow(dead_code)]
+fn test_send_sync() {
+    fn foo<T: Send + Sync>() {}
+    fn bar<B: Backend, A: MemoryAllocator<B> + Send + Sync>()
+    where
+        A::Request: Send + Sync,
+    {
+        foo::<BufferPool<B, A>>()
+    }
+}
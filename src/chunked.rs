@@ -1,23 +1,91 @@
 use std::any::Any;
 use std::cmp::{max, min};
-use std::collections::VecDeque;
 use std::fmt::Debug;
+use std::mem::size_of;
 use std::ops::Range;
 
 use gfx_hal::memory::Requirements;
 use gfx_hal::{Backend, MemoryTypeId};
+use smallvec::{Array, SmallVec};
 
+use align::is_aligned;
 use block::{Block, RawBlock};
-use {alignment_shift, MemoryAllocator, MemoryError, MemorySubAllocator};
+use invariant::InvariantViolation;
+use label::Pool;
+use misuse::{MisuseHandler, MisusePolicy};
+use owner::Owner;
+use stats::{ChunkInfo, ChunkStats};
+use {check_requirements, MemoryAllocator, MemoryError, MemorySubAllocator};
 
-/// Chunks are super-allocator blocks,
-/// which are then divided into smaller 'blocks'
+/// Chunk (big block from super-allocator) divided into smaller 'blocks'.
 #[derive(Debug)]
-struct FreeBlock {
-    /// Index of chunk (big block from super-allocator)
-    chunk_index: usize,
-    /// Block index inside the chunk
-    block_index: u64,
+struct Chunk<T> {
+    block: T,
+    stats: ChunkStats,
+    /// Bit for each block, set if the block is free. Chunks of up to 128 blocks keep it inline
+    free: SmallVec<[u64; 2]>,
+    /// Number of free blocks
+    free_count: usize,
+    /// Number of blocks in the chunk
+    blocks: usize,
+    /// Chunk is in the list of chunks with free blocks
+    listed: bool,
+    /// Most recently freed block
+    recent: Option<u64>,
+}
+
+impl<T> Chunk<T> {
+    fn new(block: T, stats: ChunkStats, blocks: usize) -> Self {
+        let mut free = SmallVec::from_elem(!0u64, blocks / 64);
+        let tail = blocks % 64;
+        if tail != 0 {
+            free.push((1 << tail) - 1);
+        }
+        Chunk {
+            block,
+            stats,
+            free,
+            free_count: blocks,
+            blocks,
+            listed: false,
+            recent: None,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.free_count == self.blocks
+    }
+
+    fn host_memory(&self) -> usize {
+        spilled_size(&self.free)
+    }
+
+    /// Take the first free block, or the most recently freed one if `recycle` is set.
+    fn take(&mut self, recycle: bool) -> Option<u64> {
+        let recent = self.recent.take().filter(|_| recycle);
+        let (word, bit) = match recent {
+            Some(block_index) => ((block_index / 64) as usize, (block_index % 64) as u32),
+            None => {
+                let word = self.free.iter().position(|&word| word != 0)?;
+                (word, self.free[word].trailing_zeros())
+            }
+        };
+        debug_assert_ne!(self.free[word] & (1 << bit), 0);
+        self.free[word] &= !(1 << bit);
+        self.free_count -= 1;
+        self.stats.alloc();
+        Some(word as u64 * 64 + bit as u64)
+    }
+
+    /// Return a block.
+    fn put(&mut self, block_index: u64) {
+        let (word, bit) = ((block_index / 64) as usize, block_index % 64);
+        assert_eq!(self.free[word] & (1 << bit), 0, "Block is freed twice");
+        self.free[word] |= 1 << bit;
+        self.free_count += 1;
+        self.recent = Some(block_index);
+        self.stats.free();
+    }
 }
 
 #[derive(Debug)]
@@ -27,10 +95,26 @@ struct ChunkedNode<T> {
     chunk_size: u64,
     /// Size of small blocks
     block_size: u64,
-    /// List of free blocks
-    free: VecDeque<FreeBlock>,
-    /// List of allocated chunks
-    chunks: Vec<T>,
+    /// List of allocated chunks. Freed chunks leave empty slots
+    chunks: Vec<Option<Chunk<T>>>,
+    /// Indices of chunks with free blocks, inline while there are few of them
+    available: SmallVec<[usize; 4]>,
+    /// Bytes of allocated blocks not covered by requested sizes
+    wasted: u64,
+    /// Number of blocks in all chunks
+    count: usize,
+    /// Number of free blocks in all chunks
+    free: usize,
+    /// Total size of all chunks
+    allocated: u64,
+    /// Accept chunks smaller than `chunk_size` when the owner runs out of memory
+    partial: bool,
+    /// Prefer recently used blocks and chunks
+    recycle: bool,
+    /// Index of the chunk a block was most recently allocated from or freed to
+    recent: Option<usize>,
+    /// Id stamped into blocks
+    owner: Owner,
 }
 
 impl<T> ChunkedNode<T> {
@@ -39,19 +123,22 @@ impl<T> ChunkedNode<T> {
             id,
             chunk_size,
             block_size,
-            free: VecDeque::new(),
             chunks: Vec::new(),
+            available: SmallVec::new(),
+            wasted: 0,
+            count: 0,
+            free: 0,
+            allocated: 0,
+            partial: false,
+            recycle: false,
+            recent: None,
+            owner: Owner::new(),
         }
     }
 
     fn is_used(&self) -> bool {
         // All blocks are free
-        self.count() != self.free.len()
-    }
-
-    fn count(&self) -> usize {
-        // Blocks count is chunk count multiplied by blocks per chunk
-        self.chunks.len() * self.blocks_per_chunk()
+        self.count != self.free
     }
 
     fn blocks_per_chunk(&self) -> usize {
@@ -59,12 +146,206 @@ impl<T> ChunkedNode<T> {
         (self.chunk_size / self.block_size) as usize
     }
 
+    /// Get the number of blocks in a chunk, which can be smaller than `chunk_size`.
+    fn blocks_in(&self, chunk: &T) -> usize
+    where
+        T: Block,
+    {
+        min(
+            (chunk.size() / self.block_size) as usize,
+            self.blocks_per_chunk(),
+        )
+    }
+
     fn used(&self) -> u64 {
-        (self.count() - self.free.len()) as u64 * self.block_size
+        (self.count - self.free) as u64 * self.block_size
     }
 
     fn allocated(&self) -> u64 {
-        self.chunks.len() as u64 * self.chunk_size
+        self.allocated
+    }
+
+    fn chunk_count(&self) -> usize {
+        self.chunks.iter().filter(|chunk| chunk.is_some()).count()
+    }
+
+    fn host_memory(&self) -> usize {
+        self.chunks.capacity() * size_of::<Option<Chunk<T>>>()
+            + spilled_size(&self.available)
+            + self
+                .chunks
+                .iter()
+                .flatten()
+                .map(Chunk::host_memory)
+                .sum::<usize>()
+    }
+
+    /// Remove a chunk from the list along with its free blocks.
+    fn remove_chunk(&mut self, chunk_index: usize) -> (T, ChunkStats)
+    where
+        T: Block,
+    {
+        let chunk = self.chunks[chunk_index].take().expect("Chunk is alive");
+        self.count -= chunk.blocks;
+        self.free -= chunk.free_count;
+        self.allocated -= chunk.block.size();
+        if chunk.listed {
+            self.available.retain(|&index| index != chunk_index);
+        }
+        if self.recent == Some(chunk_index) {
+            self.recent = None;
+        }
+        (chunk.block, chunk.stats)
+    }
+
+    /// Describe what is wrong with returning a block of a live chunk, if anything.
+    fn misuse_of<M>(&self, block: &ChunkedBlock<M>) -> Option<String>
+    where
+        M: Debug + Any,
+        T: Block<Memory = M>,
+    {
+        let chunk = self.chunks[block.1].as_ref().expect("Chunk is alive");
+        let offset = block.range().start - chunk.block.range().start;
+        let block_index = offset / self.block_size;
+        if offset % self.block_size != 0 || block_index >= chunk.blocks as u64 {
+            Some(format!(
+                "Block {:?} of chunk {} isn't one of the blocks of its chunk",
+                block.range(),
+                block.1
+            ))
+        } else if chunk.free[(block_index / 64) as usize] & (1 << (block_index % 64)) != 0 {
+            Some(format!(
+                "Block {:?} of chunk {} is freed twice",
+                block.range(),
+                block.1
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Get the block of a live chunk.
+    fn chunk(&self, index: usize) -> Option<&T> {
+        self.chunks
+            .get(index)
+            .and_then(Option::as_ref)
+            .map(|chunk| &chunk.block)
+    }
+
+    fn chunk_stats<'a>(&'a self) -> impl Iterator<Item = ChunkStats> + 'a {
+        self.chunks.iter().flatten().map(|chunk| chunk.stats)
+    }
+
+    fn chunk_infos<'a>(&'a self) -> impl Iterator<Item = ChunkInfo> + 'a {
+        self.chunks.iter().flatten().map(move |chunk| {
+            let used = (chunk.blocks - chunk.free_count) as u64 * self.block_size;
+            ChunkInfo::new(Pool::Chunked, &chunk.stats, used)
+        })
+    }
+
+    /// Check that counters, free bitmaps and the list of chunks with free blocks agree.
+    fn check_invariants(&self) -> Result<(), String>
+    where
+        T: Block,
+    {
+        let (mut count, mut free, mut allocated, mut listed) = (0, 0, 0, 0);
+        for (index, chunk) in self.chunks.iter().enumerate() {
+            let chunk = match *chunk {
+                Some(ref chunk) => chunk,
+                None => continue,
+            };
+            if chunk.blocks != self.blocks_in(&chunk.block) {
+                return Err(format!(
+                    "chunk {} of {} bytes counts {} blocks of {} bytes",
+                    index,
+                    chunk.block.size(),
+                    chunk.blocks,
+                    self.block_size
+                ));
+            }
+            let bits: usize = chunk
+                .free
+                .iter()
+                .map(|word| word.count_ones() as usize)
+                .sum();
+            let tail = chunk.blocks % 64;
+            let tail_bits = match chunk.free.last() {
+                Some(&word) if tail != 0 => word >> tail,
+                _ => 0,
+            };
+            if chunk.free.len() != (chunk.blocks + 63) / 64 || tail_bits != 0 {
+                return Err(format!(
+                    "free bitmap of chunk {} doesn't fit its blocks",
+                    index
+                ));
+            }
+            if bits != chunk.free_count {
+                return Err(format!(
+                    "chunk {} has {} free blocks in its bitmap but counts {}",
+                    index, bits, chunk.free_count
+                ));
+            }
+            if let Some(recent) = chunk.recent {
+                if chunk.free[(recent / 64) as usize] & (1 << (recent % 64)) == 0 {
+                    return Err(format!(
+                        "recently freed block {} of chunk {} is in use",
+                        recent, index
+                    ));
+                }
+            }
+            let entries = self.available.iter().filter(|&&i| i == index).count();
+            if chunk.listed != (chunk.free_count != 0) || entries != chunk.listed as usize {
+                return Err(format!(
+                    "chunk {} with {} free blocks is listed {} times as available",
+                    index, chunk.free_count, entries
+                ));
+            }
+            count += chunk.blocks;
+            free += chunk.free_count;
+            allocated += chunk.block.size();
+            listed += chunk.listed as usize;
+        }
+        if listed != self.available.len() {
+            return Err(format!(
+                "{} chunks are listed as available instead of {}",
+                self.available.len(),
+                listed
+            ));
+        }
+        if (count, free, allocated) != (self.count, self.free, self.allocated) {
+            return Err(format!(
+                "chunks hold {} blocks, {} free, in {} bytes but {} blocks, {} free, in {} \
+                 bytes are counted",
+                count, free, allocated, self.count, self.free, self.allocated
+            ));
+        }
+        if self.wasted > self.used() {
+            return Err(format!(
+                "{} bytes are wasted but only {} bytes are used",
+                self.wasted,
+                self.used()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Free all chunks with no blocks in use.
+    unsafe fn trim<B, A>(&mut self, owner: &mut A, device: &B::Device)
+    where
+        B: Backend,
+        T: Block<Memory = B::Memory>,
+        A: MemoryAllocator<B, Block = T>,
+    {
+        for chunk_index in 0..self.chunks.len() {
+            let empty = match self.chunks[chunk_index] {
+                Some(ref chunk) => chunk.is_empty(),
+                None => false,
+            };
+            if empty {
+                let (chunk, _) = self.remove_chunk(chunk_index);
+                owner.free(device, chunk);
+            }
+        }
     }
 
     unsafe fn grow<B, A>(
@@ -77,32 +358,91 @@ impl<T> ChunkedNode<T> {
         B: Backend,
         T: Block<Memory = B::Memory>,
         A: MemoryAllocator<B, Block = T>,
+        A::Request: Clone,
     {
-        let reqs = Requirements {
+        let mut reqs = Requirements {
             type_mask: 1 << self.id.0,
             size: self.chunk_size,
             alignment: self.block_size,
         };
-        // Get a new chunk
-        let chunk = owner.alloc(device, request, reqs)?;
-        assert_eq!(0, alignment_shift(reqs.alignment, chunk.range().start));
-        assert!(chunk.size() >= self.chunk_size);
+        // Get a new chunk, or a smaller one if allowed and the owner is out of memory
+        let chunk = loop {
+            match owner.alloc(device, request.clone(), reqs) {
+                Ok(chunk) => break chunk,
+                Err(MemoryError::OutOfMemory) if self.partial && reqs.size > self.block_size => {
+                    reqs.size = max(reqs.size / 2 / self.block_size, 1) * self.block_size;
+                }
+                Err(err) => return Err(err),
+            }
+        };
+        let stats = ChunkStats::new(self.id, chunk.memory(), chunk.range());
+        self.insert_chunk(chunk, stats);
+        Ok(())
+    }
+
+    /// Add a chunk with all of its blocks free.
+    fn insert_chunk(&mut self, chunk: T, stats: ChunkStats)
+    where
+        T: Block,
+    {
+        assert!(is_aligned(chunk.range().start, self.block_size));
+        assert!(chunk.size() >= self.block_size);
 
-        let blocks_per_chunk = self.blocks_per_chunk();
+        let blocks = self.blocks_in(&chunk);
+        self.count += blocks;
+        self.free += blocks;
+        self.allocated += chunk.size();
 
-        // `len()` will return the next index to use
-        let chunk_index = self.chunks.len();
+        // Reuse an empty slot or `len()` which will be the next index to use
+        let chunk_index = self
+            .chunks
+            .iter()
+            .position(Option::is_none)
+            .unwrap_or(self.chunks.len());
 
-        // Fill the free list with new blocks
-        self.free.extend((0..blocks_per_chunk).map(|i| FreeBlock {
-            chunk_index,
-            block_index: i as u64,
-        }));
+        let mut chunk = Chunk::new(chunk, stats, blocks);
+        chunk.listed = true;
+        self.available.push(chunk_index);
 
         // Place the new chunk in the list
-        self.chunks.push(chunk);
+        if chunk_index == self.chunks.len() {
+            self.chunks.push(Some(chunk));
+        } else {
+            self.chunks[chunk_index] = Some(chunk);
+        }
+    }
 
-        Ok(())
+    /// Remove a chunk with no blocks in use that is aligned to `alignment`.
+    fn take_free_chunk(&mut self, alignment: u64) -> Option<(T, ChunkStats)>
+    where
+        T: Block,
+    {
+        let chunk_size = self.chunk_size;
+        let chunk_index = self.chunks.iter().position(|chunk| match *chunk {
+            Some(ref chunk) => {
+                chunk.block.size() >= chunk_size
+                    && chunk.is_empty()
+                    && is_aligned(chunk.block.range().start, alignment)
+            }
+            None => false,
+        })?;
+        Some(self.remove_chunk(chunk_index))
+    }
+
+    fn try_alloc<M>(&mut self, reqs: Requirements) -> Option<ChunkedBlock<M>>
+    where
+        M: Debug + Any,
+        T: Block<Memory = M>,
+    {
+        let block = self.alloc_no_grow()?;
+
+        // Check that block meets the requirements.
+        assert!(block.size() >= reqs.size);
+        assert!(is_aligned(block.range().start, reqs.alignment));
+
+        // Remember the requested size to account rounding overhead
+        self.wasted += block.size() - reqs.size;
+        Some(ChunkedBlock(block.0, block.1, reqs.size, self.owner))
     }
 
     fn alloc_no_grow<M>(&mut self) -> Option<ChunkedBlock<M>>
@@ -110,19 +450,90 @@ impl<T> ChunkedNode<T> {
         M: Debug + Any,
         T: Block<Memory = M>,
     {
-        // Find a free block
-        self.free.pop_front().map(|free_block| {
-            // Memory offset is block index times block size
-            // plus chunk memory offset
-            let offset = free_block.block_index * self.block_size
-                + self.chunks[free_block.chunk_index].range().start;
-            let block = RawBlock::new(
-                self.chunks[free_block.chunk_index].memory(),
-                offset..self.block_size + offset,
-            );
-            // Remember what chunk the block came from
-            ChunkedBlock(block, free_block.chunk_index)
-        })
+        // Find a chunk with free blocks
+        let position = self.pick_available()?;
+        let chunk_index = self.available[position];
+        let recycle = self.recycle;
+        let chunk = self.chunks[chunk_index].as_mut().expect("Chunk is alive");
+        let block_index = chunk.take(recycle).expect("Listed chunk has free blocks");
+        if chunk.free_count == 0 {
+            chunk.listed = false;
+            self.available.remove(position);
+        }
+        self.free -= 1;
+        if recycle {
+            self.recent = Some(chunk_index);
+        }
+
+        // Memory offset is block index times block size
+        // plus chunk memory offset
+        let offset = block_index * self.block_size + chunk.block.range().start;
+        let block = RawBlock::new(chunk.block.memory(), offset..self.block_size + offset);
+        // Remember what chunk the block came from
+        Some(ChunkedBlock(
+            block,
+            chunk_index,
+            self.block_size,
+            self.owner,
+        ))
+    }
+
+    /// Get the position in `available` of the chunk to allocate the next block from.
+    ///
+    /// Without recycling this is the last listed chunk. With recycling the most recently touched
+    /// chunk is preferred, then the last listed chunk that has blocks in use, so fresh chunks are
+    /// only opened when partially full ones are exhausted.
+    fn pick_available(&self) -> Option<usize> {
+        let last = self.available.len().checked_sub(1)?;
+        if !self.recycle {
+            return Some(last);
+        }
+        let chunks = &self.chunks;
+        let is_partial = |index: usize| {
+            chunks[index]
+                .as_ref()
+                .map_or(false, |chunk| !chunk.is_empty())
+        };
+        self.recent
+            .and_then(|recent| self.available.iter().rposition(|&index| index == recent))
+            .or_else(|| self.available.iter().rposition(|&index| is_partial(index)))
+            .or(Some(last))
+    }
+
+    /// Return a block to its chunk.
+    fn release<M>(&mut self, block: ChunkedBlock<M>)
+    where
+        M: Debug + Any,
+        T: Block<Memory = M>,
+    {
+        assert_eq!(block.range().start % self.block_size, 0);
+        assert_eq!(block.size(), self.block_size);
+        let offset = block.range().start;
+        let block_memory: *const M = block.memory();
+
+        // Dispose block retreiving chunk index
+        let chunk_index = {
+            unsafe { block.0.dispose() };
+            self.wasted -= self.block_size - block.2;
+            block.1
+        };
+
+        let block_size = self.block_size;
+        let chunk = self.chunks[chunk_index].as_mut().expect("Chunk is alive");
+
+        // Confirm the chunk index
+        assert!(::std::ptr::eq(chunk.block.memory(), block_memory));
+
+        // Calculate the block index inside the chunk
+        chunk.put((offset - chunk.block.range().start) / block_size);
+        if !chunk.listed {
+            chunk.listed = true;
+            self.available.push(chunk_index);
+        }
+        self.free += 1;
+        if self.recycle {
+            self.recent = Some(chunk_index);
+        }
     }
 }
 
@@ -131,6 +542,7 @@ where
     B: Backend,
     T: Block<Memory = B::Memory>,
     O: MemoryAllocator<B, Block = T>,
+    O::Request: Clone,
 {
     type Request = O::Request;
     type Block = ChunkedBlock<B::Memory>;
@@ -142,61 +554,26 @@ where
         request: O::Request,
         reqs: Requirements,
     ) -> Result<ChunkedBlock<B::Memory>, MemoryError> {
-        // Check memory type
-        if (1 << self.id.0) & reqs.type_mask == 0 {
-            return Err(MemoryError::NoCompatibleMemoryType);
-        }
-
-        // Try to allocate a block
-        let block = match self.alloc_no_grow() {
-            Some(block) => block,
-            None => {
-                // Grow from super-allocator
-                self.grow(owner, device, request)?;
-                self.alloc_no_grow().expect("Just growed")
-            }
-        };
+        // Memory type is checked by the caller
+        debug_assert_ne!((1 << self.id.0) & reqs.type_mask, 0);
 
-        // Check that block meets the requirements.
-        assert!(block.size() >= reqs.size);
-        assert_eq!(block.range().start & (reqs.alignment - 1), 0);
-        Ok(block)
+        if self.available.is_empty() {
+            // Grow from super-allocator
+            self.grow(owner, device, request)?;
+        }
+        Ok(self.try_alloc(reqs).expect("Just growed"))
     }
 
     unsafe fn free(&mut self, _owner: &mut O, _device: &B::Device, block: ChunkedBlock<B::Memory>) {
-        assert_eq!(block.range().start % self.block_size, 0);
-        assert_eq!(block.size(), self.block_size);
-        let offset = block.range().start;
-        let block_memory: *const B::Memory = block.memory();
-
-        // Dispose block retreiving chunk index
-        let chunk_index = {
-            block.0.dispose();
-            block.1
-        };
-
-        // Confirm the chunk index
-        assert!(::std::ptr::eq(
-            self.chunks[chunk_index].memory(),
-            block_memory
-        ));
-
-        // Calculate the block index inside the chunk
-        let block_index = (offset - self.chunks[chunk_index].range().start) / self.block_size;
-
-        // Push the block back into the 'free blocks' list
-        self.free.push_front(FreeBlock {
-            block_index,
-            chunk_index,
-        });
+        self.release(block);
     }
 
     unsafe fn dispose(mut self, owner: &mut O, device: &B::Device) -> Result<(), Self> {
         if self.is_used() {
             Err(self)
         } else {
-            for chunk in self.chunks.drain(..) {
-                owner.free(device, chunk);
+            for chunk in self.chunks.drain(..).flatten() {
+                owner.free(device, chunk.block);
             }
             Ok(())
         }
@@ -212,6 +589,9 @@ where
 ///
 /// This allocator can only allocate memory `max_chunk_size` bytes in size or less.
 ///
+/// The memory type is chosen by the caller, so requirements are expected to allow it.
+/// This is only checked with debug assertions.
+///
 /// ### Type parameters:
 ///
 /// - `T`: type of bigger blocks this allocator sub-allocates from.
@@ -221,7 +601,17 @@ pub struct ChunkedAllocator<T> {
     blocks_per_chunk: usize,
     min_block_size: u64,
     max_chunk_size: u64,
-    nodes: Vec<ChunkedNode<T>>,
+    min_alignment: u64,
+    /// Chunk sizes are raised to at least this
+    chunk_granularity: u64,
+    /// Chunk sizes are lowered to at most this, down to a single block
+    chunk_limit: u64,
+    partial_chunks: bool,
+    recycle: bool,
+    /// Nodes of size classes, created when first used
+    nodes: Vec<Option<ChunkedNode<T>>>,
+    owner: Owner,
+    misuse: MisuseHandler,
 }
 
 impl<T> ChunkedAllocator<T> {
@@ -253,14 +643,31 @@ impl<T> ChunkedAllocator<T> {
             blocks_per_chunk,
             min_block_size,
             max_chunk_size,
+            min_alignment: 1,
+            chunk_granularity: 1,
+            chunk_limit: !0,
+            partial_chunks: false,
+            recycle: false,
             nodes: Vec::new(),
+            owner: Owner::new(),
+            misuse: MisuseHandler::default(),
+        }
+    }
+
+    /// Create an empty allocator with the same configuration and its own id.
+    pub(crate) fn new_like(&self) -> Self {
+        ChunkedAllocator {
+            nodes: Vec::new(),
+            owner: Owner::new(),
+            misuse: self.misuse.clone(),
+            ..*self
         }
     }
 
     /// Check if any of the blocks allocated by this allocator are still in use.
     /// If this function returns `false`, the allocator can be `dispose`d.
     pub fn is_used(&self) -> bool {
-        self.nodes.iter().any(ChunkedNode::is_used)
+        self.nodes().any(ChunkedNode::is_used)
     }
 
     /// Get memory type of the allocator
@@ -283,20 +690,403 @@ impl<T> ChunkedAllocator<T> {
         self.blocks_per_chunk
     }
 
+    /// Get the size of blocks used to satisfy requests of `size` bytes aligned to `alignment`.
+    ///
+    /// Blocks are rounded up to a power of two multiple of `min_block_size`, so
+    /// `size_class_for(size, alignment) - size` bytes are wasted by such an allocation.
+    /// Returns `None` if such requests can't be satisfied by this allocator.
+    pub fn size_class_for(&self, size: u64, alignment: u64) -> Option<u64> {
+        let size = max(size, max(alignment, self.min_alignment));
+        if size == 0 || size > self.max_chunk_size {
+            None
+        } else {
+            Some(self.block_size(self.pick_node(size)))
+        }
+    }
+
+    /// Get the minimum alignment of blocks allocated by this allocator.
+    pub fn min_alignment(&self) -> u64 {
+        self.min_alignment
+    }
+
+    /// Set the minimum alignment of blocks allocated by this allocator.
+    ///
+    /// All blocks will be aligned to at least `alignment` bytes, even if the requirements allow
+    /// a smaller alignment. Since blocks are aligned to their size this also acts as a lower
+    /// bound of the block size.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `alignment` is not a power of two or is larger than `max_chunk_size`.
+    pub fn set_min_alignment(&mut self, alignment: u64) {
+        assert!(alignment.is_power_of_two());
+        assert!(alignment <= self.max_chunk_size);
+        self.min_alignment = alignment;
+    }
+
+    /// Allow chunks smaller than the chunk size of a size class.
+    ///
+    /// When the underlying allocator runs out of memory, smaller chunks with fewer blocks are
+    /// requested instead of failing right away, so the last scraps of a nearly full heap remain
+    /// usable. Disabled by default.
+    pub fn set_partial_chunks(&mut self, partial: bool) {
+        self.partial_chunks = partial;
+        for node in self.nodes.iter_mut().flatten() {
+            node.partial = partial;
+        }
+    }
+
+    /// Check if chunks smaller than the chunk size of a size class are allowed.
+    pub fn partial_chunks(&self) -> bool {
+        self.partial_chunks
+    }
+
+    /// Make chunks at least `granularity` bytes, up to `max_chunk_size`.
+    ///
+    /// Small size classes get more blocks per chunk, so that every chunk is a multiple of
+    /// e.g. a 2 MiB large page, which helps the page management of some drivers.
+    /// Only affects size classes not used yet. Defaults to 1.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `granularity` is not a power of two.
+    pub fn set_chunk_granularity(&mut self, granularity: u64) {
+        assert!(granularity.is_power_of_two());
+        self.chunk_granularity = granularity;
+    }
+
+    /// Get the minimum size of chunks.
+    pub fn chunk_granularity(&self) -> u64 {
+        self.chunk_granularity
+    }
+
+    /// Make chunks at most `limit` bytes, taking precedence over `set_chunk_granularity`.
+    ///
+    /// Size classes whose chunks would be bigger get fewer blocks per chunk, down to a single
+    /// block per chunk. Only affects size classes not used yet. Defaults to no limit.
+    pub fn set_chunk_limit(&mut self, limit: u64) {
+        self.chunk_limit = limit;
+    }
+
+    /// Get the maximum size of chunks of size classes with more than one block per chunk.
+    pub fn chunk_limit(&self) -> u64 {
+        self.chunk_limit
+    }
+
+    /// Prefer recently freed blocks and partially full chunks.
+    ///
+    /// When enabled, blocks are allocated from the chunk that was most recently allocated from
+    /// or freed to, reusing its most recently freed block first, and chunks with blocks in use
+    /// are filled before fresh ones. This keeps live blocks packed in fewer chunks, which leaves
+    /// more chunks for `trim` and touches memory that is likely still in cache.
+    /// Disabled by default.
+    pub fn set_recycle(&mut self, recycle: bool) {
+        self.recycle = recycle;
+        for node in self.nodes.iter_mut().flatten() {
+            node.recycle = recycle;
+            if !recycle {
+                node.recent = None;
+            }
+        }
+    }
+
+    /// Check if recently freed blocks and partially full chunks are preferred.
+    pub fn recycle(&self) -> bool {
+        self.recycle
+    }
+
     /// Retrieves the block backing an allocation.
-    pub fn underlying_block<M: Debug + Any>(&self, block: &ChunkedBlock<M>) -> &T {
-        let index = self.pick_node(block.size());
-        &self.nodes[index as usize].chunks[block.1]
+    ///
+    /// ### Panics
+    ///
+    /// Panics if the block wasn't allocated by this allocator or its chunk was already freed,
+    /// see `owns`.
+    pub fn underlying_block<M>(&self, block: &ChunkedBlock<M>) -> &T
+    where
+        M: Debug + Any,
+        T: Block<Memory = M>,
+    {
+        self.owner.check(block.3, "chunked");
+        let index = self.node_of(block).unwrap_or_else(|| foreign_block(block));
+        self.node(index).chunk(block.1).expect("Checked by node_of")
+    }
+
+    /// Check if a block could have been allocated by this allocator and its chunk is alive.
+    ///
+    /// The size class is recovered from the size of the block, which is always exactly the
+    /// block size of its class regardless of the requested size and alignment. The chunk the
+    /// block refers to must be alive and contain the block. With debug assertions or the
+    /// `checks` feature, blocks also carry the id of the allocator instance, which must match.
+    pub fn owns<M>(&self, block: &ChunkedBlock<M>) -> bool
+    where
+        M: Debug + Any,
+        T: Block<Memory = M>,
+    {
+        self.owner == block.3 && self.node_of(block).is_some()
+    }
+
+    /// Get the id stamped into blocks of this allocator.
+    pub(crate) fn owner(&self) -> Owner {
+        self.owner
+    }
+
+    /// Set what happens when a block is freed into this allocator that can't have been
+    /// allocated by it or is freed twice.
+    ///
+    /// Defaults to `MisusePolicy::Panic`.
+    pub fn set_misuse_policy(&mut self, policy: MisusePolicy) {
+        self.misuse.set_policy(policy);
+    }
+
+    pub(crate) fn set_misuse_handler(&mut self, handler: MisuseHandler) {
+        self.misuse = handler;
+    }
+
+    /// Describe what is wrong with freeing a block, if anything.
+    fn misuse_of<M>(&self, block: &ChunkedBlock<M>) -> Option<String>
+    where
+        M: Debug + Any,
+        T: Block<Memory = M>,
+    {
+        if let Some(message) = self.owner.mismatch(block.3, "chunked") {
+            return Some(message);
+        }
+        match self.node_of(block) {
+            Some(index) => self.node(index).misuse_of(block),
+            None => Some(foreign_message(block)),
+        }
+    }
+
+    /// Find the index of the node a block was allocated from.
+    fn node_of<M>(&self, block: &ChunkedBlock<M>) -> Option<u8>
+    where
+        M: Debug + Any,
+        T: Block<Memory = M>,
+    {
+        let size = block.size();
+        if size == 0 || size > self.max_chunk_size || size % self.min_block_size != 0 {
+            return None;
+        }
+        let index = self.pick_node(size);
+        let node = self.nodes.get(index as usize)?.as_ref()?;
+        if node.block_size != size {
+            return None;
+        }
+        match node.chunk(block.1) {
+            Some(chunk) if chunk.contains(block) => Some(index),
+            _ => None,
+        }
+    }
+
+    /// Allocate a block from free blocks of existing chunks, never allocating a new chunk.
+    ///
+    /// Fails with `MemoryError::WouldGrow` if there is no suitable free block.
+    pub fn try_alloc<M>(&mut self, reqs: Requirements) -> Result<ChunkedBlock<M>, MemoryError>
+    where
+        M: Debug + Any,
+        T: Block<Memory = M>,
+    {
+        let (index, reqs) = self.prepare(reqs)?;
+        self.node_mut(index)
+            .try_alloc(reqs)
+            .ok_or(MemoryError::WouldGrow)
     }
 
-    /// Get the total size of all blocks allocated by this allocator.
+    /// Allocate a block, adding a chunk made by `new_chunk` if no chunk has a free block.
+    ///
+    /// `new_chunk` gets the size of the chunk. Used to simulate allocators without a device.
+    pub(crate) fn alloc_with<M, F>(
+        &mut self,
+        reqs: Requirements,
+        new_chunk: F,
+    ) -> Result<ChunkedBlock<M>, MemoryError>
+    where
+        M: Debug + Any,
+        T: Block<Memory = M>,
+        F: FnOnce(u64) -> T,
+    {
+        let (index, reqs) = self.prepare(reqs)?;
+        let node = self.node_mut(index);
+        if node.available.is_empty() {
+            let chunk = new_chunk(node.chunk_size);
+            let stats = ChunkStats::new(node.id, chunk.memory(), chunk.range());
+            node.insert_chunk(chunk, stats);
+        }
+        Ok(node.try_alloc(reqs).expect("Just grew"))
+    }
+
+    /// Return a block allocated with `alloc_with`.
+    pub(crate) fn release<M>(&mut self, block: ChunkedBlock<M>)
+    where
+        M: Debug + Any,
+        T: Block<Memory = M>,
+    {
+        let index = self
+            .node_of(&block)
+            .unwrap_or_else(|| foreign_block(&block));
+        self.node_mut(index).release(block);
+    }
+
+    /// Take all chunks of this allocator, which must have no blocks in use.
+    pub(crate) fn into_chunks(self) -> impl Iterator<Item = T> {
+        assert!(!self.is_used());
+        self.nodes
+            .into_iter()
+            .flatten()
+            .flat_map(|node| node.chunks.into_iter().flatten().map(|chunk| chunk.block))
+    }
+
+    /// Pick the node for a block and make sure it has free blocks if any chunk can be reused.
+    fn prepare(&mut self, reqs: Requirements) -> Result<(u8, Requirements), MemoryError>
+    where
+        T: Block,
+    {
+        check_requirements(&reqs)?;
+        let reqs = Requirements {
+            alignment: max(reqs.alignment, self.min_alignment),
+            ..reqs
+        };
+        if max(reqs.size, reqs.alignment) > self.max_chunk_size {
+            return Err(MemoryError::OutOfMemory);
+        }
+        let index = self.pick_node(max(reqs.size, reqs.alignment));
+        self.grow(index);
+        if self.node(index).available.is_empty() {
+            self.reuse_chunk(index);
+        }
+        Ok((index, reqs))
+    }
+
+    /// Get the total size of blocks in use, as requested.
+    ///
+    /// Rounding of blocks up to their size class is accounted in `wasted` instead.
     pub fn used(&self) -> u64 {
-        self.nodes.iter().map(|node| node.used()).sum()
+        self.nodes().map(|node| node.used() - node.wasted).sum()
     }
 
-    /// Get the total size of all chunks allocated by this allocator.
+    /// Get the total size of all chunks held by this allocator, including unused ones.
     pub fn allocated(&self) -> u64 {
-        self.nodes.iter().map(|node| node.allocated()).sum()
+        self.nodes().map(ChunkedNode::allocated).sum()
+    }
+
+    /// Get the number of chunks held by this allocator, including unused ones.
+    pub fn chunk_count(&self) -> usize {
+        self.nodes().map(ChunkedNode::chunk_count).sum()
+    }
+
+    /// Get the number of bytes of host memory used by the bookkeeping of this allocator.
+    ///
+    /// Counts the lists of size classes and chunks and the free bitmaps of chunks, not the
+    /// allocator itself.
+    pub fn host_memory(&self) -> usize {
+        self.nodes.capacity() * size_of::<Option<ChunkedNode<T>>>()
+            + self.nodes().map(ChunkedNode::host_memory).sum::<usize>()
+    }
+
+    /// Get the number of bytes that can't be used for allocations.
+    ///
+    /// This is the overhead of rounding requested sizes up to the block size.
+    pub fn wasted(&self) -> u64 {
+        self.nodes().map(|node| node.wasted).sum()
+    }
+
+    /// Get statistics of all chunks allocated by this allocator.
+    pub fn chunk_stats<'a>(&'a self) -> impl Iterator<Item = ChunkStats> + 'a {
+        self.nodes().flat_map(ChunkedNode::chunk_stats)
+    }
+
+    /// Get the current state of all chunks allocated by this allocator.
+    pub fn chunks<'a>(&'a self) -> impl Iterator<Item = ChunkInfo> + 'a {
+        self.nodes().flat_map(ChunkedNode::chunk_infos)
+    }
+
+    /// Audit internal consistency: block counts match free bitmaps of chunks, chunks with free
+    /// blocks are listed exactly once, and size classes match their chunks.
+    pub fn check_invariants(&self) -> Result<(), InvariantViolation>
+    where
+        T: Block,
+    {
+        let allocator = format!("type {} chunked", self.id.0);
+        for (index, node) in self.nodes.iter().enumerate() {
+            let node = match *node {
+                Some(ref node) => node,
+                None => continue,
+            };
+            if node.block_size != self.block_size(index as u8) {
+                return Err(InvariantViolation::new(
+                    allocator,
+                    format!(
+                        "size class {} has blocks of {} bytes instead of {}",
+                        index,
+                        node.block_size,
+                        self.block_size(index as u8)
+                    ),
+                ));
+            }
+            node.check_invariants().map_err(|message| {
+                InvariantViolation::new(&allocator, format!("size class {}: {}", index, message))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Free all chunks with no blocks in use, returning them to the underlying allocator.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `owner`: allocator that was used to allocate the chunks
+    /// - `device`: device the chunks were allocated from
+    pub unsafe fn trim<B, A>(&mut self, owner: &mut A, device: &B::Device)
+    where
+        B: Backend,
+        T: Block<Memory = B::Memory>,
+        A: MemoryAllocator<B, Block = T>,
+    {
+        for node in self.nodes.iter_mut().flatten() {
+            node.trim(owner, device);
+        }
+    }
+
+    /// Dispose of this allocator, freeing all chunks even if blocks are still in use.
+    ///
+    /// See `RootAllocator::dispose_forced`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `owner`: allocator that was used to allocate the chunks
+    /// - `device`: device the chunks were allocated from, must be idle
+    pub unsafe fn dispose_forced<B, A>(mut self, owner: &mut A, device: &B::Device)
+    where
+        B: Backend,
+        T: Block<Memory = B::Memory>,
+        A: MemoryAllocator<B, Block = T>,
+    {
+        for node in self.nodes.drain(..).flatten() {
+            for chunk in node.chunks.into_iter().flatten() {
+                owner.free(device, chunk.block);
+            }
+        }
+    }
+
+    fn nodes(&self) -> impl Iterator<Item = &ChunkedNode<T>> {
+        self.nodes.iter().flatten()
+    }
+
+    fn node(&self, index: u8) -> &ChunkedNode<T> {
+        self.nodes[index as usize]
+            .as_ref()
+            .expect("Node is created")
+    }
+
+    fn node_mut(&mut self, index: u8) -> &mut ChunkedNode<T> {
+        self.nodes[index as usize]
+            .as_mut()
+            .expect("Node is created")
+    }
+
+    /// Get the size of the blocks allocated for requests of `size` bytes.
+    pub(crate) fn block_size_for(&self, size: u64) -> u64 {
+        self.block_size(self.pick_node(size))
     }
 
     fn block_size(&self, index: u8) -> u64 {
@@ -304,33 +1094,69 @@ impl<T> ChunkedAllocator<T> {
     }
 
     fn chunk_size(&self, index: u8) -> u64 {
-        min(
-            self.block_size(index) * self.blocks_per_chunk as u64,
+        let block_size = self.block_size(index);
+        let size = min(
+            max(
+                block_size.saturating_mul(self.blocks_per_chunk as u64),
+                self.chunk_granularity,
+            ),
             self.max_chunk_size,
-        )
+        );
+        // Whole blocks only, so chunks keep being multiples of the block size
+        let limit = max(self.chunk_limit / block_size * block_size, block_size);
+        min(size, limit)
     }
 
     fn pick_node(&self, size: u64) -> u8 {
         // blocks can't be larger than max_chunk_size
         debug_assert!(size <= self.max_chunk_size);
-        let bits = ::std::mem::size_of::<usize>() * 8;
         assert_ne!(size, 0);
-        let node = (bits - ((size - 1) / self.min_block_size).leading_zeros() as usize) as u8;
+        let node = (64 - ((size - 1) / self.min_block_size).leading_zeros()) as u8;
         debug_assert!(size <= self.block_size(node));
         debug_assert!(node == 0 || size > self.block_size(node - 1));
         node
     }
 
+    /// Move a chunk with no blocks in use from a node with the same chunk size to node `index`.
+    ///
+    /// Size classes bigger than `max_chunk_size / blocks_per_chunk` all use chunks of
+    /// `max_chunk_size` bytes, so free memory can flow between them without going through the
+    /// underlying allocator.
+    fn reuse_chunk(&mut self, index: u8)
+    where
+        T: Block,
+    {
+        let (chunk_size, block_size) = {
+            let node = self.node(index);
+            (node.chunk_size, node.block_size)
+        };
+        let chunk = self
+            .nodes
+            .iter_mut()
+            .enumerate()
+            .filter(|&(other, _)| other != index as usize)
+            .filter_map(|(_, node)| node.as_mut())
+            .filter(|node| node.chunk_size == chunk_size)
+            .filter_map(|node| node.take_free_chunk(block_size))
+            .next();
+        if let Some((chunk, stats)) = chunk {
+            self.node_mut(index).insert_chunk(chunk, stats);
+        }
+    }
+
+    /// Create the node of size class `index` if it doesn't exist yet.
     fn grow(&mut self, index: u8) {
         assert!(self.chunk_size(index) <= self.max_chunk_size);
-        let len = self.nodes.len() as u8;
-        let id = self.id;
-
-        let range = len..index + 1;
-        self.nodes.reserve(range.len());
-        for index in range {
-            let node = ChunkedNode::new(id, self.chunk_size(index), self.block_size(index));
-            self.nodes.push(node);
+        if self.nodes.len() <= index as usize {
+            self.nodes.resize_with(index as usize + 1, || None);
+        }
+        if self.nodes[index as usize].is_none() {
+            let mut node =
+                ChunkedNode::new(self.id, self.chunk_size(index), self.block_size(index));
+            node.partial = self.partial_chunks;
+            node.recycle = self.recycle;
+            node.owner = self.owner;
+            self.nodes[index as usize] = Some(node);
         }
     }
 }
@@ -340,6 +1166,7 @@ where
     B: Backend,
     T: Block<Memory = B::Memory>,
     O: MemoryAllocator<B, Block = T>,
+    O::Request: Clone,
 {
     type Request = O::Request;
     type Block = ChunkedBlock<B::Memory>;
@@ -351,24 +1178,25 @@ where
         request: O::Request,
         reqs: Requirements,
     ) -> Result<ChunkedBlock<B::Memory>, MemoryError> {
-        if max(reqs.size, reqs.alignment) > self.max_chunk_size {
-            return Err(MemoryError::OutOfMemory);
-        }
-        let index = self.pick_node(max(reqs.size, reqs.alignment));
-        self.grow(index);
-        self.nodes[index as usize].alloc(owner, device, request, reqs)
+        let (index, reqs) = self.prepare(reqs)?;
+        self.node_mut(index).alloc(owner, device, request, reqs)
     }
 
     unsafe fn free(&mut self, owner: &mut O, device: &B::Device, block: ChunkedBlock<B::Memory>) {
-        let index = self.pick_node(block.size());
-        self.nodes[index as usize].free(owner, device, block);
+        if let Some(message) = self.misuse_of(&block) {
+            self.misuse.report(self.id, message);
+            block.0.dispose();
+            return;
+        }
+        let index = self.node_of(&block).expect("Checked by misuse_of");
+        self.node_mut(index).free(owner, device, block);
     }
 
     unsafe fn dispose(mut self, owner: &mut O, device: &B::Device) -> Result<(), Self> {
         if self.is_used() {
             Err(self)
         } else {
-            for node in self.nodes.drain(..) {
+            for node in self.nodes.drain(..).flatten() {
                 node.dispose(owner, device).unwrap();
             }
             Ok(())
@@ -377,8 +1205,46 @@ where
 }
 
 /// `Block` type returned by `ChunkedAllocator`.
+///
+/// Holds the index of the chunk it was allocated from, the originally requested size and the
+/// id of the allocator.
 #[derive(Debug)]
-pub struct ChunkedBlock<M>(pub(crate) RawBlock<M>, pub(crate) usize);
+pub struct ChunkedBlock<M>(
+    pub(crate) RawBlock<M>,
+    pub(crate) usize,
+    pub(crate) u64,
+    pub(crate) Owner,
+);
+
+/// Get the size of the heap allocation of a small vector, zero while it is inline.
+fn spilled_size<A: Array>(vec: &SmallVec<A>) -> usize {
+    if vec.spilled() {
+        vec.capacity() * size_of::<A::Item>()
+    } else {
+        0
+    }
+}
+
+/// Report a block that doesn't belong to any live chunk of an allocator.
+fn foreign_block<M>(block: &ChunkedBlock<M>) -> !
+where
+    M: Debug + Any,
+{
+    panic!("{}", foreign_message(block))
+}
+
+/// Describe a block that doesn't belong to any live chunk of an allocator.
+fn foreign_message<M>(block: &ChunkedBlock<M>) -> String
+where
+    M: Debug + Any,
+{
+    format!(
+        "Block {:?} of chunk {} wasn't allocated by this chunked allocator or its chunk was \
+         already freed",
+        block.range(),
+        block.1
+    )
+}
 
 impl<M> Block for ChunkedBlock<M>
 where
@@ -405,3 +1271,347 @@ fn test_send_sync() {
         foo::<ChunkedAllocator<M>>()
     }
 }
+
+#[test]
+fn test_pick_node() {
+    let allocator = ChunkedAllocator::<RawBlock<()>>::new(MemoryTypeId(0), 4, 256, 1 << 20);
+    let table = [
+        (1, 0),
+        (255, 0),
+        (256, 0),
+        (257, 1),
+        (512, 1),
+        (513, 2),
+        (1024, 2),
+        (1025, 3),
+        ((1 << 19) + 1, 12),
+        (1 << 20, 12),
+    ];
+    for &(size, node) in &table {
+        assert_eq!(allocator.pick_node(size), node, "size {}", size);
+    }
+}
+
+#[test]
+fn test_pick_node_exhaustive() {
+    for &min_block_size in &[1, 4, 64, 256] {
+        let allocator =
+            ChunkedAllocator::<RawBlock<()>>::new(MemoryTypeId(0), 8, min_block_size, 1 << 14);
+        for size in 1..=allocator.max_chunk_size() {
+            let node = allocator.pick_node(size);
+            assert!(size <= allocator.block_size(node));
+            assert!(node == 0 || size > allocator.block_size(node - 1));
+            assert!(allocator.chunk_size(node) >= allocator.block_size(node));
+        }
+    }
+}
+
+#[test]
+fn test_size_class_for() {
+    let mut allocator = ChunkedAllocator::<RawBlock<()>>::new(MemoryTypeId(0), 4, 256, 1 << 20);
+    assert_eq!(allocator.size_class_for(0, 1), Some(256));
+    assert_eq!(allocator.size_class_for(100, 1), Some(256));
+    assert_eq!(allocator.size_class_for(100, 4096), Some(4096));
+    assert_eq!(allocator.size_class_for(3000, 1), Some(4096));
+    assert_eq!(allocator.size_class_for(1 << 20, 1), Some(1 << 20));
+    assert_eq!(allocator.size_class_for((1 << 20) + 1, 1), None);
+    assert_eq!(allocator.size_class_for(1, 1 << 21), None);
+    allocator.set_min_alignment(1024);
+    assert_eq!(allocator.size_class_for(100, 1), Some(1024));
+}
+
+#[test]
+fn test_reuse_chunk() {
+    let memory = ();
+    let mut allocator = ChunkedAllocator::<RawBlock<()>>::new(MemoryTypeId(0), 4, 256, 1024);
+    allocator.grow(0);
+    allocator.grow(2);
+    assert!(allocator.nodes[1].is_none());
+    let chunk = RawBlock::new(&memory, 1024..2048);
+    let stats = ChunkStats::new(MemoryTypeId(0), &memory, chunk.range());
+    allocator.node_mut(0).insert_chunk(chunk, stats);
+    assert_eq!(allocator.node(0).free, 4);
+
+    allocator.reuse_chunk(2);
+    assert_eq!(allocator.node(0).allocated(), 0);
+    assert_eq!(allocator.node(0).free, 0);
+    assert_eq!(allocator.node(2).allocated(), 1024);
+    assert_eq!(allocator.node(2).free, 1);
+
+    // Chunks in use stay where they are
+    let block = allocator.node_mut(2).alloc_no_grow::<()>().unwrap();
+    allocator.grow(1);
+    allocator.reuse_chunk(1);
+    assert_eq!(allocator.node(1).allocated(), 0);
+    allocator.node_mut(2).release(block);
+    unsafe { allocator.node_mut(2).remove_chunk(0).0.dispose() }
+}
+
+#[test]
+fn test_partial_chunk() {
+    let memory = ();
+    let mut node = ChunkedNode::<RawBlock<()>>::new(MemoryTypeId(0), 1024, 256);
+    let chunk = RawBlock::new(&memory, 0..512);
+    let stats = ChunkStats::new(MemoryTypeId(0), &memory, chunk.range());
+    node.insert_chunk(chunk, stats);
+    assert_eq!(node.allocated(), 512);
+    assert_eq!(node.free, 2);
+
+    // Partial chunks are not moved to other size classes
+    assert!(node.take_free_chunk(256).is_none());
+
+    let block = node.alloc_no_grow::<()>().unwrap();
+    assert!(node.is_used());
+    assert_eq!(node.used(), 256);
+    node.release(block);
+    assert!(!node.is_used());
+    unsafe { node.remove_chunk(0).0.dispose() }
+    assert_eq!(node.allocated(), 0);
+}
+
+#[test]
+fn test_try_alloc() {
+    let memory = ();
+    let reqs = Requirements {
+        size: 200,
+        alignment: 4,
+        type_mask: 1,
+    };
+    let mut allocator = ChunkedAllocator::<RawBlock<()>>::new(MemoryTypeId(0), 4, 256, 1024);
+    match allocator.try_alloc::<()>(reqs) {
+        Err(MemoryError::WouldGrow) => {}
+        other => panic!("Unexpected {:?}", other),
+    }
+
+    let chunk = RawBlock::new(&memory, 0..1024);
+    let stats = ChunkStats::new(MemoryTypeId(0), &memory, chunk.range());
+    allocator.node_mut(0).insert_chunk(chunk, stats);
+    let block = allocator.try_alloc::<()>(reqs).unwrap();
+    assert_eq!(block.size(), 256);
+    assert_eq!(allocator.wasted(), 56);
+    assert_eq!(allocator.used(), 200);
+    assert_eq!(allocator.allocated(), 1024);
+    assert_eq!(allocator.chunk_count(), 1);
+    assert!(allocator.host_memory() >= size_of::<Option<ChunkedNode<RawBlock<()>>>>() + 8);
+    let info = allocator.chunks().next().unwrap();
+    assert_eq!(
+        (info.pool, info.size, info.used, info.blocks),
+        (Pool::Chunked, 1024, 256, 1)
+    );
+    allocator.node_mut(0).release(block);
+    unsafe { allocator.node_mut(0).remove_chunk(0).0.dispose() }
+}
+
+#[test]
+fn test_free_bitmap() {
+    let memory = ();
+    let mut node = ChunkedNode::<RawBlock<()>>::new(MemoryTypeId(0), 100 * 256, 256);
+    let chunk = RawBlock::new(&memory, 0..100 * 256);
+    let stats = ChunkStats::new(MemoryTypeId(0), &memory, chunk.range());
+    node.insert_chunk(chunk, stats);
+    assert_eq!(node.free, 100);
+
+    let blocks: Vec<ChunkedBlock<()>> = (0..100).map(|_| node.alloc_no_grow().unwrap()).collect();
+    assert!(node.available.is_empty());
+    assert!(node.alloc_no_grow::<()>().is_none());
+    assert_eq!(blocks[70].range(), 70 * 256..71 * 256);
+
+    // Freed blocks are found again through the chunk's bitmap
+    for block in blocks {
+        node.release(block);
+    }
+    assert_eq!(&node.available[..], &[0]);
+    assert!(!node.is_used());
+    let block = node.alloc_no_grow::<()>().unwrap();
+    assert_eq!(block.range(), 0..256);
+    node.release(block);
+    unsafe { node.remove_chunk(0).0.dispose() }
+}
+
+#[test]
+fn test_recycle() {
+    let memory = ();
+    let mut node = ChunkedNode::<RawBlock<()>>::new(MemoryTypeId(0), 1024, 256);
+    node.recycle = true;
+    let chunk = RawBlock::new(&memory, 0..1024);
+    let stats = ChunkStats::new(MemoryTypeId(0), &memory, chunk.range());
+    node.insert_chunk(chunk, stats);
+
+    // The most recently freed block is reused first
+    let mut blocks: Vec<ChunkedBlock<()>> = (0..3).map(|_| node.alloc_no_grow().unwrap()).collect();
+    node.release(blocks.remove(2));
+    node.release(blocks.remove(0));
+    blocks.push(node.alloc_no_grow().unwrap());
+    assert_eq!(blocks[1].range(), 0..256);
+    blocks.push(node.alloc_no_grow().unwrap());
+    assert_eq!(blocks[2].range(), 512..768);
+
+    // Partially full chunks are filled before fresh ones
+    let chunk = RawBlock::new(&memory, 1024..2048);
+    let stats = ChunkStats::new(MemoryTypeId(0), &memory, chunk.range());
+    node.insert_chunk(chunk, stats);
+    node.recent = None;
+    blocks.push(node.alloc_no_grow().unwrap());
+    assert_eq!(blocks[3].1, 0);
+    blocks.push(node.alloc_no_grow().unwrap());
+    assert_eq!(blocks[4].1, 1);
+
+    for block in blocks {
+        node.release(block);
+    }
+    unsafe {
+        node.remove_chunk(0).0.dispose();
+        node.remove_chunk(1).0.dispose();
+    }
+}
+
+#[test]
+fn test_owns() {
+    let memory = ();
+    let reqs = Requirements {
+        size: 100,
+        alignment: 512,
+        type_mask: 1,
+    };
+    let mut allocator = ChunkedAllocator::<RawBlock<()>>::new(MemoryTypeId(0), 4, 256, 2048);
+    allocator.grow(1);
+    let chunk = RawBlock::new(&memory, 0..2048);
+    let stats = ChunkStats::new(MemoryTypeId(0), &memory, chunk.range());
+    allocator.node_mut(1).insert_chunk(chunk, stats);
+
+    // Alignment larger than size picks the node by alignment
+    let block = allocator.try_alloc::<()>(reqs).unwrap();
+    assert_eq!(block.size(), 512);
+    assert!(allocator.owns(&block));
+    assert_eq!(allocator.underlying_block(&block).range(), 0..2048);
+
+    let stale = ChunkedBlock(RawBlock::new(&memory, 0..512), 1, 100, allocator.owner);
+    assert!(!allocator.owns(&stale));
+    unsafe { stale.0.dispose() }
+    let odd = ChunkedBlock(RawBlock::new(&memory, 0..300), 0, 100, allocator.owner);
+    assert!(!allocator.owns(&odd));
+    unsafe { odd.0.dispose() }
+
+    allocator.node_mut(1).release(block);
+    unsafe { allocator.node_mut(1).remove_chunk(0).0.dispose() }
+}
+
+#[test]
+fn test_chunk_granularity() {
+    let mut allocator = ChunkedAllocator::<RawBlock<()>>::new(MemoryTypeId(0), 4, 256, 1 << 20);
+    assert_eq!(allocator.chunk_size(0), 1024);
+    allocator.set_chunk_granularity(1 << 16);
+    assert_eq!(allocator.chunk_size(0), 1 << 16);
+    allocator.set_chunk_granularity(1 << 21);
+    assert_eq!(allocator.chunk_size(0), 1 << 20);
+}
+
+#[test]
+fn test_chunk_limit() {
+    let mut allocator = ChunkedAllocator::<RawBlock<()>>::new(MemoryTypeId(0), 4, 256, 1 << 20);
+    allocator.set_chunk_limit(3000);
+    assert_eq!(allocator.chunk_size(0), 1024);
+    assert_eq!(allocator.chunk_size(2), 2048);
+    assert_eq!(allocator.chunk_size(4), 4096);
+    allocator.set_chunk_granularity(1 << 16);
+    assert_eq!(allocator.chunk_size(0), 2816);
+}
+
+#[test]
+fn test_check_invariants() {
+    let memory = ();
+    let mut node = ChunkedNode::<RawBlock<()>>::new(MemoryTypeId(0), 100 * 256, 256);
+    let chunk = RawBlock::new(&memory, 0..100 * 256);
+    let stats = ChunkStats::new(MemoryTypeId(0), &memory, chunk.range());
+    node.insert_chunk(chunk, stats);
+    let block = node.alloc_no_grow::<()>().unwrap();
+    assert_eq!(node.check_invariants(), Ok(()));
+
+    // Drift between the counters and the bitmap is caught
+    node.free += 1;
+    assert!(node.check_invariants().is_err());
+    node.free -= 1;
+    node.chunks[0].as_mut().unwrap().free[1] |= 1 << 40;
+    assert!(node.check_invariants().is_err());
+    node.chunks[0].as_mut().unwrap().free[1] &= !(1 << 40);
+    assert_eq!(node.check_invariants(), Ok(()));
+
+    node.release(block);
+    unsafe { node.remove_chunk(0).0.dispose() }
+}
+
+#[test]
+fn test_misuse_of() {
+    let memory = ();
+    let mut allocator = ChunkedAllocator::<RawBlock<()>>::new(MemoryTypeId(0), 4, 256, 2048);
+    allocator.grow(0);
+    let chunk = RawBlock::new(&memory, 0..1024);
+    let stats = ChunkStats::new(MemoryTypeId(0), &memory, chunk.range());
+    allocator.node_mut(0).insert_chunk(chunk, stats);
+
+    let block = allocator.node_mut(0).alloc_no_grow::<()>().unwrap();
+    assert_eq!(allocator.misuse_of(&block), None);
+    let range = block.range();
+    allocator.node_mut(0).release(block);
+
+    // The block is found again, but it is free already
+    let twice = ChunkedBlock(RawBlock::new(&memory, range), 0, 256, allocator.owner);
+    assert!(allocator.misuse_of(&twice).unwrap().contains("freed twice"));
+    unsafe { twice.0.dispose() }
+    let foreign = ChunkedBlock(RawBlock::new(&memory, 0..256), 3, 256, allocator.owner);
+    assert!(allocator.misuse_of(&foreign).is_some());
+    unsafe { foreign.0.dispose() }
+
+    unsafe { allocator.node_mut(0).remove_chunk(0).0.dispose() }
+}
+
+#[test]
+fn test_min_alignment() {
+    let memory = ();
+    let reqs = Requirements {
+        size: 100,
+        alignment: 4,
+        type_mask: 1,
+    };
+    let mut allocator = ChunkedAllocator::<RawBlock<()>>::new(MemoryTypeId(0), 4, 256, 1 << 20);
+    allocator.set_min_alignment(1024);
+    let a = allocator
+        .alloc_with::<(), _>(reqs, |size| RawBlock::new(&memory, 0..size))
+        .unwrap();
+    let b = allocator
+        .alloc_with::<(), _>(reqs, |size| RawBlock::new(&memory, 0..size))
+        .unwrap();
+    assert_eq!(a.range(), 0..1024);
+    assert_eq!(b.range(), 1024..2048);
+
+    allocator.release(a);
+    allocator.release(b);
+    for chunk in allocator.into_chunks() {
+        unsafe { chunk.dispose() }
+    }
+}
+
+#[test]
+fn test_inline_bitmap() {
+    let memory = ();
+    let chunk = RawBlock::new(&memory, 0..1024);
+    let stats = ChunkStats::new(MemoryTypeId(0), &memory, chunk.range());
+    let chunk = Chunk::new(chunk, stats, 128);
+    assert_eq!(chunk.host_memory(), 0);
+    unsafe { chunk.block.dispose() }
+
+    let chunk = RawBlock::new(&memory, 0..1024);
+    let stats = ChunkStats::new(MemoryTypeId(0), &memory, chunk.range());
+    let chunk = Chunk::new(chunk, stats, 129);
+    assert_eq!(chunk.free.len(), 3);
+    assert!(chunk.host_memory() >= 3 * size_of::<u64>());
+    unsafe { chunk.block.dispose() }
+}
+
+#[test]
+fn test_block_size() {
+    assert_eq!(
+        size_of::<ChunkedBlock<()>>(),
+        size_of::<RawBlock<()>>() + 16 + size_of::<Owner>()
+    );
+}
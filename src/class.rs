@@ -0,0 +1,88 @@
+use gfx_hal::memory::Properties;
+use gfx_hal::MemoryTypeId;
+
+/// Practical category of a memory type derived from its properties.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MemoryClass {
+    /// Device local memory that can't be mapped.
+    /// Best for resources accessed only by the device.
+    DeviceOnly,
+
+    /// Device local memory that can be mapped, as found on integrated GPUs or in the
+    /// host-visible window of discrete GPUs' memory.
+    Unified,
+
+    /// Mappable uncached host memory. Best for staging data written by the host.
+    Upload,
+
+    /// Mappable cached host memory. Best for data read back by the host.
+    Readback,
+
+    /// Memory that is neither device local nor mappable.
+    Other,
+}
+
+impl MemoryClass {
+    /// Classify memory type with given properties.
+    pub fn of(properties: Properties) -> Self {
+        let device_local = properties.contains(Properties::DEVICE_LOCAL);
+        let mappable = properties.contains(Properties::CPU_VISIBLE);
+        match (device_local, mappable) {
+            (true, false) => MemoryClass::DeviceOnly,
+            (true, true) => MemoryClass::Unified,
+            (false, true) if properties.contains(Properties::CPU_CACHED) => MemoryClass::Readback,
+            (false, true) => MemoryClass::Upload,
+            (false, false) => MemoryClass::Other,
+        }
+    }
+
+    /// Check if memory of this class is device local.
+    pub fn is_device_local(&self) -> bool {
+        matches!(*self, MemoryClass::DeviceOnly | MemoryClass::Unified)
+    }
+
+    /// Check if memory of this class can be mapped.
+    pub fn is_mappable(&self) -> bool {
+        matches!(
+            *self,
+            MemoryClass::Unified | MemoryClass::Upload | MemoryClass::Readback
+        )
+    }
+}
+
+/// Description of a memory type, see `SmartAllocator::memory_types`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryTypeInfo {
+    /// Id of the memory type.
+    pub id: MemoryTypeId,
+    /// Properties of the memory type.
+    pub properties: Properties,
+    /// Index of the heap the memory type allocates from.
+    pub heap_index: usize,
+    /// Size of the heap in bytes, as reported by the device.
+    pub heap_size: u64,
+    /// Class of the memory type derived from its properties.
+    pub class: MemoryClass,
+}
+
+#[test]
+fn test_classify() {
+    assert_eq!(
+        MemoryClass::of(Properties::DEVICE_LOCAL),
+        MemoryClass::DeviceOnly
+    );
+    assert_eq!(
+        MemoryClass::of(Properties::DEVICE_LOCAL | Properties::CPU_VISIBLE | Properties::COHERENT),
+        MemoryClass::Unified
+    );
+    assert_eq!(
+        MemoryClass::of(Properties::CPU_VISIBLE | Properties::COHERENT),
+        MemoryClass::Upload
+    );
+    assert_eq!(
+        MemoryClass::of(Properties::CPU_VISIBLE | Properties::CPU_CACHED),
+        MemoryClass::Readback
+    );
+    assert_eq!(MemoryClass::of(Properties::empty()), MemoryClass::Other);
+    assert!(MemoryClass::Unified.is_device_local() && MemoryClass::Unified.is_mappable());
+}
@@ -1,30 +1,62 @@
 use std::any::Any;
+use std::cmp::{max, min};
+use std::collections::BTreeMap;
 use std::fmt::Debug;
+use std::iter::once;
+use std::mem::size_of;
 use std::ops::Range;
 
 use gfx_hal::memory::Requirements;
-use gfx_hal::{Backend, MemoryTypeId};
+use gfx_hal::{Backend, Limits, MemoryTypeId};
 
-use arena::{ArenaAllocator, ArenaBlock};
+use align::{align_up, checked_align_up};
+use arena::{ArenaAllocator, ArenaBlock, ArenaGrowth, ArenaRounding};
 use block::{Block, RawBlock};
 use chunked::{ChunkedAllocator, ChunkedBlock};
+use invariant::InvariantViolation;
+use label::{BlockLocation, Labeler, MemoryLabel, Pool};
+#[cfg(feature = "block-age")]
+use metadata::AgedBlock;
+use metadata::{BlockId, BlockMetadata, MetadataSlab};
+use misuse::{Misuse, MisuseHandler, MisusePolicy};
+use owner::Owner;
+use request::{Group, Lifetime, Request};
 use root::RootAllocator;
-use {MemoryAllocator, MemoryError, MemorySubAllocator};
+use stats::{ChunkInfo, ChunkStats, MemoryTypeConfig, SizeHistogram};
+use tuning::{validate_parameters, Slack, DEDICATED_THRESHOLD_DIVISOR, MAX_NON_COHERENT_ATOM_SIZE};
+use {
+    check_requirements, checked_round_up, InvalidRequirements, MemoryAllocator, MemoryError,
+    MemorySubAllocator,
+};
 
 /// Controls what sub allocator is used for an allocation by `CombinedAllocator`
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Type {
     /// For short-lived objects, such as staging buffers.
     ShortLived,
 
     /// General purpose.
     General,
+
+    /// General purpose, always given its own memory object regardless of size.
+    /// For memory that is exported or shared with other APIs.
+    Dedicated,
+
+    /// General purpose, never given its own memory object regardless of size.
+    /// Bounds the number of memory objects, but fails with `OutOfMemory` for blocks larger
+    /// than the maximum chunk size.
+    SubAllocated,
 }
 
 /// Allocator with support for both short-lived and long-lived allocations.
 ///
 /// This allocator allocates blocks using either an `ArenaAllocator` or a `ChunkedAllocator`
-/// depending on which kind of allocation is requested.
+/// depending on which kind of allocation is requested. `Type::General` blocks are placed by
+/// their `Lifetime` hint when the request has one, see `Lifetime`. Blocks of requests with a
+/// group are kept in chunks of their own, see `Request::in_group`, and so are blocks read by
+/// the host if `set_segregate_readback` is enabled.
+///
+/// All blocks come from a single memory type, so required properties of requests are ignored.
 ///
 /// ### Type parameters:
 ///
@@ -36,9 +68,42 @@ where
 {
     root: RootAllocator<B>,
     root_used: u64,
+    /// Bytes dedicated blocks were rounded up by to large pages
+    root_wasted: u64,
     arenas: ArenaAllocator<RawBlock<B::Memory>>,
     chunks: ChunkedAllocator<RawBlock<B::Memory>>,
+    /// Chunks of blocks with `Lifetime::Static`
+    statics: ChunkedAllocator<RawBlock<B::Memory>>,
+    /// Chunks of blocks of each group, see `Request::in_group`
+    groups: BTreeMap<Group, ChunkedAllocator<RawBlock<B::Memory>>>,
+    /// Chunks of blocks read by the host, see `set_segregate_readback`
+    readbacks: ChunkedAllocator<RawBlock<B::Memory>>,
+    segregate_readback: bool,
+    /// `nonCoherentAtomSize` readback blocks are padded to, see `set_readback_atom`
+    readback_atom: Option<u64>,
     allocations: usize,
+    dedicated_threshold: u64,
+    large_page: Option<u64>,
+    slack: Option<Slack>,
+    /// Whether blocks are tracked in `live`, see `set_tracking`
+    tracking: bool,
+    /// Live blocks keyed by memory object address and offset
+    live: BTreeMap<(usize, u64), Live>,
+    metadata: MetadataSlab,
+    histogram: SizeHistogram,
+    /// Number of frames started so far
+    #[cfg(feature = "block-age")]
+    frame: u64,
+    misuse: MisuseHandler,
+    /// Id stamped into blocks allocated from `root` directly
+    owner: Owner,
+}
+
+/// Live block in `CombinedAllocator::live`.
+#[derive(Clone, Copy, Debug)]
+struct Live {
+    size: u64,
+    metadata: BlockId,
 }
 
 impl<B> CombinedAllocator<B>
@@ -54,6 +119,10 @@ where
     /// - `blocks_per_chunk`: see `ChunkedAllocator`
     /// - `min_block_size`: see `ChunkedAllocator`
     /// - `max_chunk_size`: see `ChunkedAllocator`
+    ///
+    /// ### Panics
+    ///
+    /// Panics if the parameters are invalid, see `TuningProfile::validate`.
     pub fn new(
         memory_type_id: MemoryTypeId,
         arena_chunk_size: u64,
@@ -61,9 +130,18 @@ where
         min_block_size: u64,
         max_chunk_size: u64,
     ) -> Self {
+        if let Err(err) = validate_parameters(
+            arena_chunk_size,
+            blocks_per_chunk,
+            min_block_size,
+            max_chunk_size,
+        ) {
+            panic!("Invalid allocator configuration: {}", err);
+        }
         CombinedAllocator {
             root: RootAllocator::new(memory_type_id),
             root_used: 0,
+            root_wasted: 0,
             arenas: ArenaAllocator::new(memory_type_id, arena_chunk_size),
             chunks: ChunkedAllocator::new(
                 memory_type_id,
@@ -71,7 +149,213 @@ where
                 min_block_size,
                 max_chunk_size,
             ),
+            statics: ChunkedAllocator::new(
+                memory_type_id,
+                blocks_per_chunk,
+                min_block_size,
+                max_chunk_size,
+            ),
+            groups: BTreeMap::new(),
+            readbacks: ChunkedAllocator::new(
+                memory_type_id,
+                blocks_per_chunk,
+                min_block_size,
+                max_chunk_size,
+            ),
+            segregate_readback: false,
+            readback_atom: Some(MAX_NON_COHERENT_ATOM_SIZE),
             allocations: 0,
+            dedicated_threshold: max_chunk_size / DEDICATED_THRESHOLD_DIVISOR,
+            large_page: None,
+            slack: None,
+            tracking: false,
+            live: BTreeMap::new(),
+            metadata: MetadataSlab::new(),
+            histogram: SizeHistogram::default(),
+            #[cfg(feature = "block-age")]
+            frame: 0,
+            misuse: MisuseHandler::default(),
+            owner: Owner::new(),
+        }
+    }
+
+    /// Create a combined allocator with chunk sizes and block alignment adjusted to device limits.
+    ///
+    /// Chunk sizes are rounded up to a multiple of `bufferImageGranularity` (or
+    /// `optimalBufferCopyOffsetAlignment` if larger), and all blocks are aligned to it. This way
+    /// linear and non-linear resources can be placed next to each other and any block can be
+    /// used as a copy source or destination. Readback blocks are padded to
+    /// `nonCoherentAtomSize`, see `set_readback_atom`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `memory_type_id`: ID of the memory type this allocator allocates from.
+    /// - `limits`: limits of the device the memory will be allocated from
+    /// - `arena_chunk_size`: see `ArenaAllocator`
+    /// - `blocks_per_chunk`: see `ChunkedAllocator`
+    /// - `min_block_size`: see `ChunkedAllocator`
+    /// - `max_chunk_size`: see `ChunkedAllocator`
+    pub fn with_limits(
+        memory_type_id: MemoryTypeId,
+        limits: &Limits,
+        arena_chunk_size: u64,
+        blocks_per_chunk: usize,
+        min_block_size: u64,
+        max_chunk_size: u64,
+    ) -> Self {
+        let granularity = max(
+            limits.buffer_image_granularity,
+            limits.optimal_buffer_copy_offset_alignment,
+        )
+        .max(1)
+        .next_power_of_two();
+        let mut allocator = Self::new(
+            memory_type_id,
+            align_up(arena_chunk_size, granularity),
+            blocks_per_chunk,
+            min_block_size,
+            max(max_chunk_size, granularity),
+        );
+        allocator.set_min_alignment(granularity);
+        allocator.set_readback_atom(Some(
+            (limits.non_coherent_atom_size as u64)
+                .max(1)
+                .next_power_of_two(),
+        ));
+        allocator
+    }
+
+    /// Set the size above which `Type::General` blocks get their own memory object.
+    /// Defaults to half of `max_chunk_size`.
+    ///
+    /// Blocks larger than `max_chunk_size` always get their own memory object.
+    pub fn set_dedicated_threshold(&mut self, threshold: u64) {
+        self.dedicated_threshold = threshold;
+    }
+
+    /// Get the size above which `Type::General` blocks get their own memory object.
+    pub fn dedicated_threshold(&self) -> u64 {
+        self.dedicated_threshold
+    }
+
+    /// Get the parameters this allocator actually uses, including defaults and adjustments
+    /// to device limits.
+    pub fn config(&self) -> MemoryTypeConfig {
+        MemoryTypeConfig {
+            memory_type: self.memory_type(),
+            arena_chunk_size: self.arenas.chunk_size(),
+            arena_growth: self.arenas.growth(),
+            arena_rounding: self.arenas.rounding(),
+            blocks_per_chunk: self.chunks.blocks_per_chunk(),
+            min_block_size: self.chunks.min_block_size(),
+            max_chunk_size: self.chunks.max_chunk_size(),
+            dedicated_threshold: self.dedicated_threshold,
+            min_alignment: self.min_alignment(),
+            partial_chunks: self.chunks.partial_chunks(),
+            recycle: self.chunks.recycle(),
+            large_page: self.large_page,
+            max_allocation: self.root.max_allocation(),
+        }
+    }
+
+    /// Align memory objects to large pages of `size` bytes, e.g. 2 MiB.
+    ///
+    /// Arena chunks are rounded up to multiples of the page size, chunked allocators make chunks
+    /// at least one page big, and blocks with their own memory object of at least a page are
+    /// rounded up to whole pages. This helps the internal page management of some drivers at
+    /// the cost of some memory. Only affects chunks and size classes allocated afterwards.
+    /// `None` stops rounding blocks with their own memory object, which is the default.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `size` is not a power of two.
+    pub fn set_large_page(&mut self, size: Option<u64>) {
+        if let Some(size) = size {
+            assert!(size.is_power_of_two());
+            self.arenas.set_chunk_granularity(size);
+            let granularity = min(size, self.chunks.max_chunk_size());
+            for allocator in self.chunked_mut().1 {
+                allocator.set_chunk_granularity(granularity);
+            }
+        }
+        self.large_page = size;
+    }
+
+    /// Get the size of large pages memory objects are aligned to.
+    pub fn large_page(&self) -> Option<u64> {
+        self.large_page
+    }
+
+    /// Limit the size of single memory objects allocated from the device.
+    ///
+    /// Chunks that would be bigger are split into smaller ones, and blocks bigger than `max`
+    /// fail early with `MemoryError::TooLarge`, instead of hitting driver-specific failures.
+    /// Chunks of size classes already in use keep their size. `None` removes the limit,
+    /// which is the default.
+    pub fn set_max_allocation(&mut self, max: Option<u64>) {
+        let limit = max.unwrap_or(!0);
+        self.arenas.set_chunk_limit(limit);
+        for allocator in self.chunked_mut().1 {
+            allocator.set_chunk_limit(limit);
+        }
+        self.root.set_max_allocation(max);
+    }
+
+    /// Get the maximum size of single memory objects.
+    pub fn max_allocation(&self) -> Option<u64> {
+        self.root.max_allocation()
+    }
+
+    /// Set a callback to label memory objects right after they are allocated.
+    ///
+    /// See `RootAllocator::set_labeler`.
+    pub fn set_labeler<F>(&mut self, callback: F)
+    where
+        F: Fn(&B::Memory, &MemoryLabel) + Send + Sync + 'static,
+    {
+        self.root.set_labeler(callback);
+    }
+
+    pub(crate) fn set_shared_labeler(&mut self, labeler: Labeler<B::Memory>) {
+        self.root.set_shared_labeler(labeler);
+    }
+
+    /// Set what happens when a block is freed into this allocator that isn't live in it,
+    /// e.g. because it was already freed or came from another allocator.
+    ///
+    /// With `MisusePolicy::Report` such blocks are leaked instead of corrupting the allocator,
+    /// and the misuse is passed to the callback set with `set_misuse_callback`.
+    /// Defaults to `MisusePolicy::Panic`.
+    pub fn set_misuse_policy(&mut self, policy: MisusePolicy) {
+        self.misuse.set_policy(policy);
+        self.share_misuse_handler();
+    }
+
+    /// Get what happens when this allocator is misused.
+    pub fn misuse_policy(&self) -> MisusePolicy {
+        self.misuse.policy()
+    }
+
+    /// Set a callback receiving misuse reported with `MisusePolicy::Report`, e.g. to log it.
+    pub fn set_misuse_callback<F>(&mut self, callback: F)
+    where
+        F: Fn(&Misuse) + Send + Sync + 'static,
+    {
+        self.misuse.set_callback(callback);
+        self.share_misuse_handler();
+    }
+
+    pub(crate) fn set_misuse_handler(&mut self, handler: MisuseHandler) {
+        self.misuse = handler;
+        self.share_misuse_handler();
+    }
+
+    fn share_misuse_handler(&mut self) {
+        self.root.set_misuse_handler(self.misuse.clone());
+        self.arenas.set_misuse_handler(self.misuse.clone());
+        let misuse = self.misuse.clone();
+        for allocator in self.chunked_mut().1 {
+            allocator.set_misuse_handler(misuse.clone());
         }
     }
 
@@ -80,14 +364,622 @@ where
         self.root.memory_type()
     }
 
-    /// Get the total size of all blocks allocated by this allocator.
+    /// Set geometric growth of arena chunk sizes.
+    ///
+    /// See `ArenaAllocator::set_growth`.
+    pub fn set_arena_growth(&mut self, growth: Option<ArenaGrowth>) {
+        self.arenas.set_growth(growth);
+    }
+
+    /// Set how arena chunks are sized for blocks bigger than the next chunk.
+    ///
+    /// See `ArenaAllocator::set_rounding`.
+    pub fn set_arena_rounding(&mut self, rounding: ArenaRounding) {
+        self.arenas.set_rounding(rounding);
+    }
+
+    /// Reserve extra space behind each block so it can grow with `try_grow_in_place`.
+    ///
+    /// Blocks are handed out with the requested size, and `capacity` tells how far they can
+    /// grow. `None` reserves no extra space, which is the default, and blocks keep the size
+    /// given by the sub-allocator.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if the slack is invalid, see `Slack::validate`.
+    pub fn set_slack(&mut self, slack: Option<Slack>) {
+        if let Some(Err(err)) = slack.map(|slack| slack.validate()) {
+            panic!("Invalid slack: {}", err);
+        }
+        self.slack = slack;
+    }
+
+    /// Get the extra space reserved behind blocks, see `set_slack`.
+    pub fn slack(&self) -> Option<Slack> {
+        self.slack
+    }
+
+    /// Track live blocks in a map with their metadata.
+    ///
+    /// Tracking enables `metadata`, `set_tag`, `set_user_data`, `find_block_containing` and
+    /// `blocks_older_than`, and reports blocks freed twice as misuse. It inserts every block into
+    /// a map and reads the clock on each allocation, so it is disabled by default.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if blocks are allocated.
+    pub fn set_tracking(&mut self, tracking: bool) {
+        assert_eq!(
+            self.allocations, 0,
+            "Tracking can't change while blocks are allocated"
+        );
+        self.tracking = tracking;
+    }
+
+    /// Check if live blocks are tracked, see `set_tracking`.
+    pub fn tracking(&self) -> bool {
+        self.tracking
+    }
+
+    /// Keep blocks read by the host in chunks of their own, apart from blocks written by it.
+    ///
+    /// Flushing and invalidating ranges of non-coherent memory works on whole atoms, so
+    /// upload and readback blocks sharing a chunk can clobber each other's data at their
+    /// borders. With segregation, blocks of requests marked with `Request::for_readback` are
+    /// allocated from `readbacks`, including short-lived ones, unless they are grouped or get
+    /// their own memory object. Disabled by default.
+    pub fn set_segregate_readback(&mut self, segregate: bool) {
+        self.segregate_readback = segregate;
+    }
+
+    /// Check if blocks read by the host are kept apart, see `set_segregate_readback`.
+    pub fn segregate_readback(&self) -> bool {
+        self.segregate_readback
+    }
+
+    /// Pad blocks of requests marked with `Request::for_readback` to whole atoms of `size`
+    /// bytes, the `nonCoherentAtomSize` of the device.
+    ///
+    /// Invalidating a mapped range of non-coherent memory affects whole atoms, so padded
+    /// readback blocks can be invalidated without touching the data of neighboring blocks.
+    /// Blocks are aligned to the atom size and their size is rounded up to a multiple of it.
+    /// `new` pads to `MAX_NON_COHERENT_ATOM_SIZE`, which suits any device, while `with_limits`
+    /// uses the atom size of the device limits. `None` disables padding.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `size` is not a power of two.
+    pub fn set_readback_atom(&mut self, size: Option<u64>) {
+        if let Some(size) = size {
+            assert!(size.is_power_of_two());
+        }
+        self.readback_atom = size;
+    }
+
+    /// Get the atom size readback blocks are padded to, see `set_readback_atom`.
+    pub fn readback_atom(&self) -> Option<u64> {
+        self.readback_atom
+    }
+
+    /// Pad requirements of readback requests to whole atoms, see `set_readback_atom`.
+    ///
+    /// ### Errors
+    ///
+    /// `InvalidRequirements::Overflow` if the padded size doesn't fit in `u64`.
+    fn with_padding(
+        &self,
+        request: Request,
+        reqs: Requirements,
+    ) -> Result<Requirements, MemoryError> {
+        match self.readback_atom {
+            Some(atom) if request.is_readback() => padded(reqs, atom).ok_or_else(|| {
+                InvalidRequirements::Overflow {
+                    size: reqs.size,
+                    alignment: max(reqs.alignment, atom),
+                }
+                .into()
+            }),
+            _ => Ok(reqs),
+        }
+    }
+
+    /// Check if a block of `request` belongs into `readbacks`, unless it is grouped or
+    /// dedicated.
+    pub(crate) fn segregates(&self, request: Request) -> bool {
+        self.segregate_readback && request.is_readback()
+    }
+
+    /// Get the sub-allocator type for a request, see `placement`.
+    ///
+    /// Segregated short-lived blocks are placed as `Type::General` to keep them out of arenas.
+    fn placement(&self, request: Request) -> Type {
+        match placement(request) {
+            Type::ShortLived if self.segregates(request) => Type::General,
+            ty => ty,
+        }
+    }
+
+    /// Get the minimum alignment of blocks allocated by this allocator.
+    pub fn min_alignment(&self) -> u64 {
+        self.chunks.min_alignment()
+    }
+
+    /// Set the minimum alignment of blocks allocated by this allocator.
+    ///
+    /// See `ArenaAllocator::set_min_alignment` and `ChunkedAllocator::set_min_alignment`.
+    /// Dedicated allocations always start at offset zero, so they are aligned to anything.
+    pub fn set_min_alignment(&mut self, alignment: u64) {
+        self.arenas.set_min_alignment(alignment);
+        for allocator in self.chunked_mut().1 {
+            allocator.set_min_alignment(alignment);
+        }
+    }
+
+    /// Allow chunks smaller than the chunk size when memory is nearly exhausted.
+    ///
+    /// See `ChunkedAllocator::set_partial_chunks`.
+    pub fn set_partial_chunks(&mut self, partial: bool) {
+        for allocator in self.chunked_mut().1 {
+            allocator.set_partial_chunks(partial);
+        }
+    }
+
+    /// Prefer recently freed blocks and partially full chunks.
+    ///
+    /// See `ChunkedAllocator::set_recycle`.
+    pub fn set_recycle(&mut self, recycle: bool) {
+        for allocator in self.chunked_mut().1 {
+            allocator.set_recycle(recycle);
+        }
+    }
+
+    /// Get statistics of all chunks sub-allocated by this allocator.
+    ///
+    /// Chunk statistics include labels of the memory objects the chunks were allocated from.
+    pub fn chunk_stats<'a>(&'a self) -> impl Iterator<Item = ChunkStats> + 'a {
+        self.arenas
+            .chunk_stats()
+            .chain(self.chunked().flat_map(ChunkedAllocator::chunk_stats))
+            .map(move |mut stats| {
+                stats.label = self.root.label_at(stats.memory);
+                stats
+            })
+    }
+
+    /// Get the current state of all chunks sub-allocated by this allocator.
+    ///
+    /// Dedicated memory objects are not chunks and are not included.
+    pub fn chunks<'a>(&'a self) -> impl Iterator<Item = ChunkInfo> + 'a {
+        self.arenas
+            .chunks()
+            .chain(self.chunks.chunks())
+            .chain(self.statics.chunks())
+            .chain(self.readbacks.chunks())
+            .chain(self.groups.iter().flat_map(|(&group, allocator)| {
+                allocator.chunks().map(move |info| ChunkInfo {
+                    group: Some(group),
+                    ..info
+                })
+            }))
+            .map(move |mut info| {
+                info.label = self.root.label_at(info.memory);
+                info
+            })
+    }
+
+    /// Get the histogram of requested sizes and alignments of blocks allocated so far.
+    pub fn size_histogram(&self) -> &SizeHistogram {
+        &self.histogram
+    }
+
+    /// Forget the blocks counted in the size histogram.
+    pub fn clear_size_histogram(&mut self) {
+        self.histogram.clear();
+    }
+
+    /// Get the location of a block allocated by this allocator.
+    ///
+    /// Returns `None` if the block wasn't allocated by this allocator.
+    pub fn locate<T>(&self, block: &T) -> Option<BlockLocation>
+    where
+        T: Block<Memory = B::Memory>,
+    {
+        self.root.label(block.memory()).map(|label| BlockLocation {
+            label,
+            offset: block.range().start,
+            size: block.size(),
+        })
+    }
+
+    /// Free all cached chunks with no blocks in use.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device the memory was allocated from
+    pub unsafe fn trim(&mut self, device: &B::Device) {
+        self.arenas.trim(&mut self.root, device);
+        let (root, chunked) = self.chunked_mut();
+        for allocator in chunked {
+            allocator.trim(root, device);
+        }
+    }
+
+    /// Find the live block covering `offset` in a memory object allocated by this allocator.
+    ///
+    /// Useful to map a memory range reported by a device fault back to an allocation.
+    /// The pool of the returned location's label tells how the block was allocated.
+    /// Only tracked blocks are found, see `set_tracking`.
+    ///
+    /// Returns `None` if no live block covers the offset.
+    pub fn find_block_containing(&self, memory: &B::Memory, offset: u64) -> Option<BlockLocation> {
+        let address = memory as *const B::Memory as usize;
+        let (&(_, start), live) = self
+            .live
+            .range((address, 0)..=(address, offset))
+            .next_back()?;
+        if offset - start < live.size {
+            self.root.label_at(address).map(|label| BlockLocation {
+                label,
+                offset: start,
+                size: live.size,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Start a new frame, making live blocks one frame older.
+    #[cfg(feature = "block-age")]
+    pub fn begin_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    /// Get live blocks allocated at least `frames` frames ago, optionally only those tagged
+    /// with `tag`.
+    ///
+    /// Blocks of systems that should only keep them for a few frames showing up here are
+    /// likely leaked. Only tracked blocks are listed, see `set_tracking`.
+    #[cfg(feature = "block-age")]
+    pub fn blocks_older_than<'a>(
+        &'a self,
+        frames: u64,
+        tag: Option<&'a str>,
+    ) -> impl Iterator<Item = AgedBlock> + 'a {
+        self.live
+            .iter()
+            .filter_map(move |(&(address, offset), live)| {
+                let metadata = *self.metadata.get(live.metadata)?;
+                let age = age_of(&metadata, self.frame, frames, tag)?;
+                let label = self.root.label_at(address)?;
+                Some(AgedBlock {
+                    location: BlockLocation {
+                        label,
+                        offset,
+                        size: live.size,
+                    },
+                    frames: age,
+                    metadata,
+                })
+            })
+    }
+
+    /// Get the metadata of a live block allocated by this allocator.
+    ///
+    /// Returns `None` if the block wasn't allocated by this allocator, was freed or isn't
+    /// tracked, see `set_tracking`.
+    pub fn metadata<T>(&self, block: &T) -> Option<&BlockMetadata>
+    where
+        T: Block<Memory = B::Memory>,
+    {
+        let live = self.live.get(&live_key(block))?;
+        self.metadata.get(live.metadata)
+    }
+
+    /// Get the size a live block can grow to in place, including the slack reserved for it.
+    ///
+    /// Returns `None` if the block wasn't allocated by this allocator.
+    pub fn capacity(&self, block: &CombinedBlock<B::Memory>) -> Option<u64> {
+        self.capacity_of(block.size(), &block.1)
+    }
+
+    /// Get the capacity of a block of `size` bytes with `tag`, unless it was allocated by
+    /// another allocator.
+    pub(crate) fn capacity_of(&self, size: u64, tag: &CombinedTag) -> Option<u64> {
+        if self.owner_mismatch(tag).is_some() {
+            return None;
+        }
+        Some(match *tag {
+            CombinedTag::Arena(_, capacity, _) => capacity,
+            CombinedTag::Chunked(_, size, _) => self.chunks.block_size_for(size),
+            CombinedTag::Static(_, size, _) => self.statics.block_size_for(size),
+            CombinedTag::Readback(_, size, _) => self.readbacks.block_size_for(size),
+            CombinedTag::Grouped(_, group, size, _) => {
+                self.groups[&Group(group)].block_size_for(size)
+            }
+            CombinedTag::Root(capacity, _, _) => capacity,
+        })
+    }
+
+    /// Change the size of a live block without moving it, if it has the capacity.
+    ///
+    /// Resources bound to the block must be recreated to use the new size. Live blocks are
+    /// accounted by their capacity, so usage counted by this allocator doesn't change.
+    ///
+    /// ### Returns
+    ///
+    /// Returns `false`, leaving the block untouched, if `size` exceeds its capacity or the
+    /// block wasn't allocated by this allocator.
+    pub fn try_grow_in_place(&mut self, block: &mut CombinedBlock<B::Memory>, size: u64) -> bool {
+        match self.capacity(block) {
+            Some(capacity) if size <= capacity => {
+                block.0.resize(size);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Name what a live block is used for, e.g. for leak reports and debugging.
+    ///
+    /// Does nothing if the block wasn't allocated by this allocator or isn't tracked.
+    pub fn set_tag<T>(&mut self, block: &T, tag: &'static str)
+    where
+        T: Block<Memory = B::Memory>,
+    {
+        if let Some(metadata) = self.metadata_mut(block) {
+            metadata.tag = Some(tag);
+        }
+    }
+
+    /// Attach an arbitrary value to a live block.
+    ///
+    /// Does nothing if the block wasn't allocated by this allocator or isn't tracked.
+    pub fn set_user_data<T>(&mut self, block: &T, user_data: u64)
+    where
+        T: Block<Memory = B::Memory>,
+    {
+        if let Some(metadata) = self.metadata_mut(block) {
+            metadata.user_data = user_data;
+        }
+    }
+
+    fn metadata_mut<T>(&mut self, block: &T) -> Option<&mut BlockMetadata>
+    where
+        T: Block<Memory = B::Memory>,
+    {
+        let live = self.live.get(&live_key(block))?;
+        self.metadata.get_mut(live.metadata)
+    }
+
+    /// Add the slack to the size of requirements.
+    fn with_slack(&self, reqs: Requirements) -> Requirements {
+        match self.slack {
+            Some(slack) => Requirements {
+                size: min(
+                    slack.apply(reqs.size),
+                    self.root.max_allocation().unwrap_or(!0),
+                ),
+                ..reqs
+            },
+            None => reqs,
+        }
+    }
+
+    /// Count a block that was just allocated for `reqs`, and track it if enabled.
+    ///
+    /// With slack, the block is shrunk to the requested size. Its full size is kept as its
+    /// capacity, in the tag of arena blocks and derived from the size class of chunked ones.
+    fn track(&mut self, block: &mut CombinedBlock<B::Memory>, reqs: &Requirements) {
+        self.allocations += 1;
+        self.histogram.record(reqs);
+        if self.tracking {
+            let live = Live {
+                size: block.size(),
+                metadata: self.metadata.insert(BlockMetadata {
+                    #[cfg(feature = "block-age")]
+                    frame: self.frame,
+                    ..BlockMetadata::new()
+                }),
+            };
+            self.live.insert(live_key(block), live);
+        }
+        if self.slack.is_some() {
+            block.0.resize(reqs.size);
+        }
+    }
+
+    /// Get the number of blocks allocated by this allocator and not yet freed.
+    pub fn allocations(&self) -> usize {
+        self.allocations
+    }
+
+    /// Get the number of memory objects allocated from the device so far, including freed ones.
+    pub fn device_allocations(&self) -> u64 {
+        self.root.device_allocations()
+    }
+
+    /// Get the total size of blocks in use, as requested.
+    ///
+    /// Sum of `dedicated_used` and `used` of `arenas`, `chunks`, `statics`, `readbacks` and
+    /// groups.
     pub fn used(&self) -> u64 {
-        self.root_used + self.arenas.used() + self.chunks.used()
+        self.root_used
+            + self.arenas.used()
+            + self.chunked().map(ChunkedAllocator::used).sum::<u64>()
     }
 
-    /// Get the total size of all chunks allocated by this allocator.
+    /// Get the total size of memory held by this allocator.
+    ///
+    /// Sum of `dedicated_used` and `allocated` of `arenas`, `chunks`, `statics`, `readbacks` and
+    /// groups.
     pub fn allocated(&self) -> u64 {
-        self.root_used + self.arenas.allocated() + self.chunks.allocated()
+        self.root_used
+            + self.arenas.allocated()
+            + self.chunked().map(ChunkedAllocator::allocated).sum::<u64>()
+    }
+
+    /// Audit internal consistency of this allocator and its sub-allocators.
+    ///
+    /// Checks that the number and sizes of live blocks match what the sub-allocators count,
+    /// and that memory allocated from the device matches the chunks and dedicated blocks held.
+    /// See `ArenaAllocator::check_invariants` and `ChunkedAllocator::check_invariants`.
+    ///
+    /// Sizes of live blocks are only compared with tracking enabled, see `set_tracking`.
+    pub fn check_invariants(&self) -> Result<(), InvariantViolation> {
+        self.arenas.check_invariants()?;
+        for allocator in self.chunked() {
+            allocator.check_invariants()?;
+        }
+        let allocator = format!("type {} combined", self.memory_type().0);
+        if self.tracking {
+            if self.allocations != self.live.len() {
+                return Err(InvariantViolation::new(
+                    allocator,
+                    format!(
+                        "{} blocks are counted but {} are live",
+                        self.allocations,
+                        self.live.len()
+                    ),
+                ));
+            }
+            let live = self.live.values().map(|live| live.size).sum::<u64>();
+            let blocks = self.live_size();
+            if live != blocks {
+                return Err(InvariantViolation::new(
+                    allocator,
+                    format!(
+                        "live blocks sum to {} bytes but sub-allocators count {}",
+                        live, blocks
+                    ),
+                ));
+            }
+        }
+        if self.root.used() != self.allocated() {
+            return Err(InvariantViolation::new(
+                allocator,
+                format!(
+                    "{} bytes are allocated from the device but {} bytes are held",
+                    self.root.used(),
+                    self.allocated()
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Get the total size of live blocks, including rounding to block sizes, as counted by the
+    /// sub-allocators.
+    pub(crate) fn live_size(&self) -> u64 {
+        self.root_used
+            + self.arenas.used()
+            + self
+                .chunked()
+                .map(|allocator| allocator.used() + allocator.wasted())
+                .sum::<u64>()
+    }
+
+    /// Get the chunked allocators serving general, static, readback and grouped blocks.
+    fn chunked<'a>(
+        &'a self,
+    ) -> impl Iterator<Item = &'a ChunkedAllocator<RawBlock<B::Memory>>> + 'a {
+        once(&self.chunks)
+            .chain(once(&self.statics))
+            .chain(once(&self.readbacks))
+            .chain(self.groups.values())
+    }
+
+    /// Get the root allocator and mutable chunked allocators, see `chunked`.
+    ///
+    /// The root allocator is borrowed alongside, for chunks to be freed into it.
+    fn chunked_mut<'a>(
+        &'a mut self,
+    ) -> (
+        &'a mut RootAllocator<B>,
+        impl Iterator<Item = &'a mut ChunkedAllocator<RawBlock<B::Memory>>> + 'a,
+    ) {
+        let chunked = once(&mut self.chunks)
+            .chain(once(&mut self.statics))
+            .chain(once(&mut self.readbacks))
+            .chain(self.groups.values_mut());
+        (&mut self.root, chunked)
+    }
+
+    /// Dispose of the chunked allocator of `group` once none of its blocks are in use.
+    ///
+    /// Groups are created on first allocation, so they don't pile up as they come and go.
+    unsafe fn drop_unused_group(&mut self, device: &B::Device, group: Group) {
+        if self
+            .groups
+            .get(&group)
+            .map_or(false, |chunks| !chunks.is_used())
+        {
+            let chunks = self.groups.remove(&group).unwrap();
+            chunks.dispose(&mut self.root, device).unwrap();
+        }
+    }
+
+    /// Get the arena allocator serving `Type::ShortLived` blocks.
+    pub fn arenas(&self) -> &ArenaAllocator<RawBlock<B::Memory>> {
+        &self.arenas
+    }
+
+    /// Get the chunked allocator serving small general purpose blocks.
+    pub fn chunks(&self) -> &ChunkedAllocator<RawBlock<B::Memory>> {
+        &self.chunks
+    }
+
+    /// Get the chunked allocator serving small blocks with `Lifetime::Static`.
+    pub fn statics(&self) -> &ChunkedAllocator<RawBlock<B::Memory>> {
+        &self.statics
+    }
+
+    /// Get the chunked allocator serving small blocks read by the host, see
+    /// `set_segregate_readback`.
+    pub fn readbacks(&self) -> &ChunkedAllocator<RawBlock<B::Memory>> {
+        &self.readbacks
+    }
+
+    /// Get the chunked allocator serving small blocks of a group, if any were allocated.
+    pub fn group(&self, group: Group) -> Option<&ChunkedAllocator<RawBlock<B::Memory>>> {
+        self.groups.get(&group)
+    }
+
+    /// Get the total size of blocks with their own memory object.
+    pub fn dedicated_used(&self) -> u64 {
+        self.root_used
+    }
+
+    /// Get the number of blocks with their own memory object.
+    pub fn dedicated_count(&self) -> usize {
+        self.root.count_pool(Pool::Dedicated)
+    }
+
+    /// Get the number of bytes that can't be used for allocations.
+    ///
+    /// Includes the rounding of dedicated blocks up to large pages, see `set_large_page`.
+    /// See `ArenaAllocator::wasted` and `ChunkedAllocator::wasted`.
+    pub fn wasted(&self) -> u64 {
+        self.root_wasted
+            + self.arenas.wasted()
+            + self.chunked().map(ChunkedAllocator::wasted).sum::<u64>()
+    }
+
+    /// Get the number of bytes of host memory used by the bookkeeping of this allocator.
+    ///
+    /// Sum of `host_memory` of the root allocator and of `arenas`, `chunks`, `statics`,
+    /// `readbacks` and groups, plus live blocks, their metadata and the size histogram. Sizes
+    /// of maps are estimated from their number of entries. The allocator itself is not counted.
+    pub fn host_memory(&self) -> usize {
+        self.root.host_memory()
+            + self.arenas.host_memory()
+            + self.groups.len() * size_of::<(Group, ChunkedAllocator<RawBlock<B::Memory>>)>()
+            + self
+                .chunked()
+                .map(ChunkedAllocator::host_memory)
+                .sum::<usize>()
+            + self.live.len() * size_of::<((usize, u64), Live)>()
+            + self.metadata.host_memory()
+            + self.histogram.host_memory()
     }
 }
 
@@ -95,60 +987,101 @@ impl<B> MemoryAllocator<B> for CombinedAllocator<B>
 where
     B: Backend,
 {
-    type Request = Type;
+    type Request = Request;
     type Block = CombinedBlock<B::Memory>;
 
     unsafe fn alloc(
         &mut self,
         device: &B::Device,
-        request: Type,
+        request: Request,
         reqs: Requirements,
     ) -> Result<CombinedBlock<B::Memory>, MemoryError> {
-        let block = match request {
-            Type::ShortLived => self
-                .arenas
-                .alloc(&mut self.root, device, (), reqs)
-                .map(|ArenaBlock(block, tag)| CombinedBlock(block, CombinedTag::Arena(tag)))?,
-            Type::General => {
-                if reqs.size > self.chunks.max_chunk_size() / 2 {
-                    let block = self
-                        .root
-                        .alloc(device, (), reqs)
-                        .map(|block| CombinedBlock(block, CombinedTag::Root))?;
+        check_requirements(&reqs)?;
+        // Sub-allocators rely on the memory type being checked here
+        if (1 << self.memory_type().0) & reqs.type_mask == 0 {
+            return Err(MemoryError::NoCompatibleMemoryType);
+        }
+        let reqs = self.with_padding(request, reqs)?;
+        let max_allocation = self.root.max_allocation().unwrap_or(!0);
+        if reqs.size > max_allocation {
+            return Err(MemoryError::TooLarge {
+                size: reqs.size,
+                max: max_allocation,
+            });
+        }
+        let requested = reqs;
+        let reqs = self.with_slack(reqs);
+        let mut block = match self.placement(request) {
+            Type::ShortLived => {
+                self.root.set_pool(Pool::Arena);
+                self.arenas.alloc(&mut self.root, device, (), reqs).map(
+                    |ArenaBlock(block, tag, owner)| {
+                        let capacity = block.size();
+                        CombinedBlock(block, CombinedTag::Arena(tag, capacity, owner))
+                    },
+                )?
+            }
+            ty => {
+                if self.dedicated(ty, reqs) {
+                    self.root.set_pool(Pool::Dedicated);
+                    let size = reqs.size;
+                    let reqs = rounded_to_page(reqs, self.large_page, max_allocation);
+                    let owner = self.owner;
+                    let block = self.root.alloc(device, (), reqs).map(|block| {
+                        let capacity = block.size();
+                        CombinedBlock(block, CombinedTag::Root(capacity, capacity - size, owner))
+                    })?;
                     self.root_used += block.size();
+                    self.root_wasted += block.size() - size;
                     block
+                } else if let Some(group) = request.group() {
+                    self.root.set_pool(Pool::Chunked);
+                    let chunks = &self.chunks;
+                    self.groups
+                        .entry(group)
+                        .or_insert_with(|| chunks.new_like())
+                        .alloc(&mut self.root, device, (), reqs)
+                        .map(|ChunkedBlock(block, tag, size, owner)| {
+                            CombinedBlock(
+                                block,
+                                CombinedTag::Grouped(tag as u32, group.0, size, owner),
+                            )
+                        })?
+                } else if self.segregates(request) {
+                    self.root.set_pool(Pool::Chunked);
+                    self.readbacks.alloc(&mut self.root, device, (), reqs).map(
+                        |ChunkedBlock(block, tag, size, owner)| {
+                            CombinedBlock(block, CombinedTag::Readback(tag as u32, size, owner))
+                        },
+                    )?
+                } else if request.expected_lifetime() == Lifetime::Static {
+                    self.root.set_pool(Pool::Chunked);
+                    self.statics.alloc(&mut self.root, device, (), reqs).map(
+                        |ChunkedBlock(block, tag, size, owner)| {
+                            CombinedBlock(block, CombinedTag::Static(tag as u32, size, owner))
+                        },
+                    )?
                 } else {
+                    self.root.set_pool(Pool::Chunked);
                     self.chunks.alloc(&mut self.root, device, (), reqs).map(
-                        |ChunkedBlock(block, tag)| CombinedBlock(block, CombinedTag::Chunked(tag)),
+                        |ChunkedBlock(block, tag, size, owner)| {
+                            CombinedBlock(block, CombinedTag::Chunked(tag as u32, size, owner))
+                        },
                     )?
                 }
             }
         };
-        self.allocations += 1;
+        self.track(&mut block, &requested);
         Ok(block)
     }
 
     unsafe fn free(&mut self, device: &B::Device, block: CombinedBlock<B::Memory>) {
-        match block.1 {
-            CombinedTag::Arena(tag) => {
-                self.arenas
-                    .free(&mut self.root, device, ArenaBlock(block.0, tag))
-            }
-            CombinedTag::Chunked(tag) => {
-                self.chunks
-                    .free(&mut self.root, device, ChunkedBlock(block.0, tag))
-            }
-            CombinedTag::Root => {
-                self.root_used -= block.size();
-                self.root.free(device, block.0)
-            }
-        }
-        self.allocations -= 1;
+        self.free_checked(device, block);
     }
 
     fn is_used(&self) -> bool {
         if self.allocations == 0 {
-            debug_assert!(!self.arenas.is_used() && !self.chunks.is_used());
+            debug_assert!(!self.arenas.is_used() && !self.chunked().any(ChunkedAllocator::is_used));
             false
         } else {
             true
@@ -161,20 +1094,320 @@ where
         }
         self.arenas.dispose(&mut self.root, device).unwrap();
         self.chunks.dispose(&mut self.root, device).unwrap();
+        self.statics.dispose(&mut self.root, device).unwrap();
+        self.readbacks.dispose(&mut self.root, device).unwrap();
+        for (_, group) in self.groups {
+            group.dispose(&mut self.root, device).unwrap();
+        }
         self.root.dispose(device).unwrap();
         Ok(())
     }
 }
 
+impl<B> CombinedAllocator<B>
+where
+    B: Backend,
+{
+    /// Attempt to dispose of this allocator without panicking.
+    ///
+    /// Unlike `dispose` this never panics if the state of sub-allocators is inconsistent,
+    /// e.g. because blocks were leaked.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: must be the same device all allocations have been made against
+    ///
+    /// ### Returns
+    ///
+    /// If the allocator still holds any memory, this will return `Err(self)`.
+    #[allow(clippy::result_large_err)]
+    pub unsafe fn try_dispose(mut self, device: &B::Device) -> Result<(), Self> {
+        if self.is_used() || self.arenas.is_used() || self.chunked().any(ChunkedAllocator::is_used)
+        {
+            return Err(self);
+        }
+        self.trim(device);
+        if self.root.is_used() {
+            return Err(self);
+        }
+        match self.root.dispose(device) {
+            Ok(()) => Ok(()),
+            Err(root) => Err(CombinedAllocator { root, ..self }),
+        }
+    }
+
+    /// Dispose of this allocator, freeing all memory even if blocks are still in use.
+    ///
+    /// See `RootAllocator::dispose_forced`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: must be the same device all allocations have been made against.
+    ///             The device must be idle.
+    pub unsafe fn dispose_forced(mut self, device: &B::Device) {
+        self.arenas.dispose_forced(&mut self.root, device);
+        self.chunks.dispose_forced(&mut self.root, device);
+        self.statics.dispose_forced(&mut self.root, device);
+        self.readbacks.dispose_forced(&mut self.root, device);
+        for (_, group) in self.groups {
+            group.dispose_forced(&mut self.root, device);
+        }
+        self.root.dispose_forced(device);
+    }
+
+    /// Allocate a block from memory already allocated from the device.
+    ///
+    /// Like `alloc`, but fails with `MemoryError::WouldGrow` instead of allocating memory from
+    /// the device. Blocks that would get their own memory object always fail.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `request`: type of the block, required properties are not checked
+    /// - `reqs`: the requirements the memory block must meet
+    pub fn try_alloc(
+        &mut self,
+        request: Request,
+        reqs: Requirements,
+    ) -> Result<CombinedBlock<B::Memory>, MemoryError> {
+        check_requirements(&reqs)?;
+        if (1 << self.memory_type().0) & reqs.type_mask == 0 {
+            return Err(MemoryError::NoCompatibleMemoryType);
+        }
+        let reqs = self.with_padding(request, reqs)?;
+        let requested = reqs;
+        let reqs = self.with_slack(reqs);
+        let mut block = match self.placement(request) {
+            Type::ShortLived => {
+                self.arenas
+                    .try_alloc(reqs)
+                    .map(|ArenaBlock(block, tag, owner)| {
+                        let capacity = block.size();
+                        CombinedBlock(block, CombinedTag::Arena(tag, capacity, owner))
+                    })?
+            }
+            ty @ Type::General | ty @ Type::SubAllocated if !self.dedicated(ty, reqs) => {
+                if let Some(group) = request.group() {
+                    self.groups
+                        .get_mut(&group)
+                        .ok_or(MemoryError::WouldGrow)?
+                        .try_alloc(reqs)
+                        .map(|ChunkedBlock(block, tag, size, owner)| {
+                            CombinedBlock(
+                                block,
+                                CombinedTag::Grouped(tag as u32, group.0, size, owner),
+                            )
+                        })?
+                } else if self.segregates(request) {
+                    self.readbacks.try_alloc(reqs).map(
+                        |ChunkedBlock(block, tag, size, owner)| {
+                            CombinedBlock(block, CombinedTag::Readback(tag as u32, size, owner))
+                        },
+                    )?
+                } else if request.expected_lifetime() == Lifetime::Static {
+                    self.statics
+                        .try_alloc(reqs)
+                        .map(|ChunkedBlock(block, tag, size, owner)| {
+                            CombinedBlock(block, CombinedTag::Static(tag as u32, size, owner))
+                        })?
+                } else {
+                    self.chunks
+                        .try_alloc(reqs)
+                        .map(|ChunkedBlock(block, tag, size, owner)| {
+                            CombinedBlock(block, CombinedTag::Chunked(tag as u32, size, owner))
+                        })?
+                }
+            }
+            _ => return Err(MemoryError::WouldGrow),
+        };
+        self.track(&mut block, &requested);
+        Ok(block)
+    }
+
+    /// Check if a block of `Type::General` family should get its own memory object.
+    fn dedicated(&self, request: Type, reqs: Requirements) -> bool {
+        match request {
+            Type::Dedicated => true,
+            Type::SubAllocated => false,
+            _ => reqs.size > min(self.dedicated_threshold, self.chunks.max_chunk_size()),
+        }
+    }
+
+    /// Free a block, unless it isn't live in this allocator.
+    ///
+    /// ### Returns
+    ///
+    /// `false` if the block was reported as misuse and leaked instead.
+    pub(crate) unsafe fn free_checked(
+        &mut self,
+        device: &B::Device,
+        mut block: CombinedBlock<B::Memory>,
+    ) -> bool {
+        if let Some(message) = self.owner_mismatch(&block.1) {
+            self.misuse.report(self.memory_type(), message);
+            block.0.dispose();
+            return false;
+        }
+        if self.tracking {
+            match self.live.remove(&live_key(&block)) {
+                Some(live) => {
+                    self.metadata.remove(live.metadata);
+                }
+                None => {
+                    self.misuse.report(
+                        self.memory_type(),
+                        format!(
+                            "Block {:?} isn't live in this combined allocator, it was already \
+                             freed or allocated by another allocator",
+                            block.range()
+                        ),
+                    );
+                    block.0.dispose();
+                    return false;
+                }
+            }
+        }
+        let capacity = self
+            .capacity_of(block.size(), &block.1)
+            .expect("Owner was checked");
+        block.0.resize(capacity);
+        match block.1 {
+            CombinedTag::Arena(tag, _, owner) => {
+                self.arenas
+                    .free(&mut self.root, device, ArenaBlock(block.0, tag, owner))
+            }
+            CombinedTag::Chunked(tag, size, owner) => self.chunks.free(
+                &mut self.root,
+                device,
+                ChunkedBlock(block.0, tag as usize, size, owner),
+            ),
+            CombinedTag::Static(tag, size, owner) => self.statics.free(
+                &mut self.root,
+                device,
+                ChunkedBlock(block.0, tag as usize, size, owner),
+            ),
+            CombinedTag::Readback(tag, size, owner) => self.readbacks.free(
+                &mut self.root,
+                device,
+                ChunkedBlock(block.0, tag as usize, size, owner),
+            ),
+            CombinedTag::Grouped(tag, group, size, owner) => {
+                let group = Group(group);
+                self.groups.get_mut(&group).expect("Group is alive").free(
+                    &mut self.root,
+                    device,
+                    ChunkedBlock(block.0, tag as usize, size, owner),
+                );
+                self.drop_unused_group(device, group);
+            }
+            CombinedTag::Root(_, rounding, _) => {
+                self.root_used -= block.size();
+                self.root_wasted -= rounding;
+                self.root.free(device, block.0)
+            }
+        }
+        self.allocations -= 1;
+        true
+    }
+
+    /// Describe the mismatch if a block with `tag` was allocated by another allocator.
+    fn owner_mismatch(&self, tag: &CombinedTag) -> Option<String> {
+        match *tag {
+            CombinedTag::Arena(_, _, owner) => self.arenas.owner().mismatch(owner, "arena"),
+            CombinedTag::Chunked(_, _, owner) => self.chunks.owner().mismatch(owner, "chunked"),
+            CombinedTag::Static(_, _, owner) => self.statics.owner().mismatch(owner, "chunked"),
+            CombinedTag::Readback(_, _, owner) => self.readbacks.owner().mismatch(owner, "chunked"),
+            CombinedTag::Grouped(_, group, _, owner) => match self.groups.get(&Group(group)) {
+                Some(chunks) => chunks.owner().mismatch(owner, "chunked"),
+                None => Some(format!(
+                    "Block of group {} that has no blocks was freed",
+                    group
+                )),
+            },
+            CombinedTag::Root(_, _, owner) => self.owner.mismatch(owner, "combined"),
+        }
+    }
+
+    /// Check if this allocator holds any memory allocated from the device.
+    pub(crate) fn holds_memory(&self) -> bool {
+        self.root.is_used()
+    }
+}
+
+/// Key of a live block in `CombinedAllocator::live`.
+fn live_key<T: Block>(block: &T) -> (usize, u64) {
+    (
+        block.memory() as *const T::Memory as usize,
+        block.range().start,
+    )
+}
+
+/// Get the age of a block in frame `frame`, unless it's younger than `frames` or not tagged
+/// with `tag`.
+#[cfg(feature = "block-age")]
+fn age_of(metadata: &BlockMetadata, frame: u64, frames: u64, tag: Option<&str>) -> Option<u64> {
+    let age = frame - metadata.frame;
+    if age < frames || tag.map_or(false, |tag| metadata.tag != Some(tag)) {
+        None
+    } else {
+        Some(age)
+    }
+}
+
 /// `Block` type returned by `CombinedAllocator`.
 #[derive(Debug)]
 pub struct CombinedBlock<M>(pub(crate) RawBlock<M>, pub(crate) CombinedTag);
 
+/// Tag telling which sub-allocator a block came from.
+///
+/// Holds everything needed to free the block, so freeing doesn't depend on blocks being tracked:
+/// the arena chunk index and the capacity of arena blocks, the chunk index, group and requested
+/// size of chunked blocks, or the capacity of dedicated ones and their rounding to large pages.
+/// Capacities are fixed when the block is allocated, so they don't change when the block is
+/// resized. Chunk indices are stored as `u32`, since the number of memory objects a device can
+/// allocate is far below that. The `Owner` stamped by the sub-allocator, or by the combined
+/// allocator for blocks of its root, takes another word when checks are enabled.
 #[derive(Debug)]
 pub(crate) enum CombinedTag {
-    Arena(u64),
-    Chunked(usize),
-    Root,
+    Arena(u64, u64, Owner),
+    Chunked(u32, u64, Owner),
+    Static(u32, u64, Owner),
+    Readback(u32, u64, Owner),
+    Grouped(u32, u32, u64, Owner),
+    Root(u64, u64, Owner),
+}
+
+/// Get the sub-allocator type for a request, taking the lifetime hint of
+/// `Type::General` requests into account.
+fn placement(request: Request) -> Type {
+    match request.ty() {
+        Type::General if request.expected_lifetime().is_short() => Type::ShortLived,
+        ty => ty,
+    }
+}
+
+/// Round the size of `reqs` of dedicated blocks up to a multiple of `page`, unless the block is
+/// smaller than a page or rounding would exceed `max_allocation`.
+fn rounded_to_page(reqs: Requirements, page: Option<u64>, max_allocation: u64) -> Requirements {
+    match page {
+        Some(page) if reqs.size >= page => Requirements {
+            size: checked_round_up(reqs.size, page)
+                .filter(|&size| size <= max_allocation)
+                .unwrap_or(reqs.size),
+            ..reqs
+        },
+        _ => reqs,
+    }
+}
+
+/// Align `reqs` to `atom` bytes and round their size up to a multiple of it.
+///
+/// Returns `None` if the rounded size overflows.
+fn padded(reqs: Requirements, atom: u64) -> Option<Requirements> {
+    Some(Requirements {
+        size: checked_align_up(reqs.size, atom)?,
+        alignment: max(reqs.alignment, atom),
+        ..reqs
+    })
 }
 
 impl<M> Block for CombinedBlock<M>
@@ -202,3 +1435,231 @@ fn test_send_sync() {
         foo::<CombinedAllocator<B>>()
     }
 }
+
+#[test]
+fn test_block_size() {
+    use std::mem::size_of;
+    assert_eq!(size_of::<CombinedTag>(), 24 + size_of::<Owner>());
+    assert_eq!(
+        size_of::<CombinedBlock<()>>(),
+        24 + size_of::<CombinedTag>()
+    );
+}
+
+#[test]
+fn test_placement() {
+    let general = Request::general();
+    assert_eq!(placement(general), Type::General);
+    assert_eq!(
+        placement(general.lifetime(Lifetime::PerFrame)),
+        Type::ShortLived
+    );
+    assert_eq!(placement(general.lifetime(Lifetime::Static)), Type::General);
+    assert_eq!(
+        placement(Request::dedicated().lifetime(Lifetime::PerPass)),
+        Type::Dedicated
+    );
+}
+
+#[test]
+fn test_padded() {
+    let reqs = Requirements {
+        size: 100,
+        alignment: 16,
+        type_mask: !0,
+    };
+    let padded_reqs = padded(reqs, 64).unwrap();
+    assert_eq!(padded_reqs.size, 128);
+    assert_eq!(padded_reqs.alignment, 64);
+    assert_eq!(padded(padded_reqs, 64).unwrap().size, 128);
+    assert_eq!(padded(reqs, 8).unwrap().alignment, 16);
+    let huge = Requirements {
+        size: !0 - 10,
+        ..reqs
+    };
+    assert!(padded(huge, 64).is_none());
+}
+
+#[test]
+fn test_with_limits() {
+    use gfx_backend_empty::{Backend as Empty, Device};
+    let limits = Limits {
+        buffer_image_granularity: 1024,
+        optimal_buffer_copy_offset_alignment: 256,
+        ..Limits::default()
+    };
+    let allocator =
+        CombinedAllocator::<Empty>::with_limits(MemoryTypeId(0), &limits, 1000, 8, 32, 512);
+    assert_eq!(allocator.min_alignment(), 1024);
+    assert_eq!(allocator.arenas.chunk_size(), 1024);
+    assert_eq!(allocator.chunks.max_chunk_size(), 1024);
+    assert!(unsafe { allocator.dispose(&Device) }.is_ok());
+}
+
+#[test]
+#[cfg(any(debug_assertions, feature = "checks"))]
+fn test_owner_mismatch() {
+    use gfx_backend_empty::{Backend as Empty, Device};
+    let first = CombinedAllocator::<Empty>::new(MemoryTypeId(0), 1024, 8, 32, 512);
+    let second = CombinedAllocator::<Empty>::new(MemoryTypeId(0), 1024, 8, 32, 512);
+
+    let root = CombinedTag::Root(1024, 0, first.owner);
+    assert!(first.owner_mismatch(&root).is_none());
+    assert!(second.owner_mismatch(&root).is_some());
+    let chunked = CombinedTag::Chunked(0, 256, first.chunks.owner());
+    assert!(first.owner_mismatch(&chunked).is_none());
+    assert!(second.owner_mismatch(&chunked).is_some());
+    // Chunked blocks of another sub-allocator of the same combined allocator
+    let statics = CombinedTag::Static(0, 256, first.chunks.owner());
+    assert!(first.owner_mismatch(&statics).is_some());
+
+    assert!(unsafe { first.dispose(&Device) }.is_ok());
+    assert!(unsafe { second.dispose(&Device) }.is_ok());
+}
+
+#[test]
+fn test_capacity() {
+    use gfx_backend_empty::{Backend as Empty, Device};
+    let allocator = CombinedAllocator::<Empty>::new(MemoryTypeId(0), 1024, 8, 32, 512);
+    assert!(!allocator.tracking());
+
+    // Capacity is known from the tag alone, without tracking
+    let arena = CombinedTag::Arena(0, 256, allocator.arenas.owner());
+    assert_eq!(allocator.capacity_of(156, &arena), Some(256));
+    assert_eq!(allocator.capacity_of(256, &arena), Some(256));
+    let chunked = CombinedTag::Chunked(0, 200, allocator.chunks.owner());
+    assert_eq!(allocator.capacity_of(150, &chunked), Some(256));
+    let root = CombinedTag::Root(4096, 0, allocator.owner);
+    assert_eq!(allocator.capacity_of(4000, &root), Some(4096));
+    if cfg!(any(debug_assertions, feature = "checks")) {
+        let foreign = CombinedTag::Chunked(0, 200, allocator.arenas.owner());
+        assert_eq!(allocator.capacity_of(150, &foreign), None);
+    }
+
+    assert!(unsafe { allocator.dispose(&Device) }.is_ok());
+}
+
+#[test]
+#[cfg(feature = "block-age")]
+fn test_block_age() {
+    use gfx_backend_empty::{Backend as Empty, Device};
+    let mut allocator = CombinedAllocator::<Empty>::new(MemoryTypeId(0), 1024, 8, 32, 512);
+    allocator.begin_frame();
+    allocator.begin_frame();
+    assert_eq!(allocator.frame, 2);
+    assert_eq!(allocator.blocks_older_than(0, None).count(), 0);
+
+    let metadata = BlockMetadata {
+        tag: Some("shadow map"),
+        frame: 3,
+        ..BlockMetadata::new()
+    };
+    assert_eq!(age_of(&metadata, 5, 2, None), Some(2));
+    assert_eq!(age_of(&metadata, 5, 3, None), None);
+    assert_eq!(age_of(&metadata, 5, 0, Some("shadow map")), Some(2));
+    assert_eq!(age_of(&metadata, 5, 0, Some("particles")), None);
+
+    assert!(unsafe { allocator.dispose(&Device) }.is_ok());
+}
+
+#[test]
+fn test_chunked() {
+    use gfx_backend_empty::{Backend as Empty, Device};
+    let mut allocator = CombinedAllocator::<Empty>::new(MemoryTypeId(0), 1024, 8, 32, 512);
+    assert_eq!(allocator.chunked().count(), 3);
+    let group = allocator.chunks.new_like();
+    allocator.groups.insert(Group(1), group);
+    assert_eq!(allocator.chunked().count(), 4);
+
+    // Settings reach chunked allocators of groups too
+    allocator.set_min_alignment(64);
+    allocator.set_recycle(true);
+    let group = allocator.group(Group(1)).unwrap();
+    assert_eq!(group.min_alignment(), 64);
+    assert!(group.recycle());
+    assert!(allocator
+        .chunked()
+        .all(|chunks| chunks.min_alignment() == 64));
+
+    // Groups without blocks are dropped
+    unsafe { allocator.drop_unused_group(&Device, Group(1)) };
+    assert!(allocator.group(Group(1)).is_none());
+    assert_eq!(allocator.chunked().count(), 3);
+    assert!(unsafe { allocator.dispose(&Device) }.is_ok());
+}
+
+#[test]
+fn test_readback_atom() {
+    use gfx_backend_empty::{Backend as Empty, Device};
+    let mut allocator = CombinedAllocator::<Empty>::new(MemoryTypeId(0), 1024, 8, 32, 512);
+    assert_eq!(allocator.readback_atom(), Some(MAX_NON_COHERENT_ATOM_SIZE));
+    let reqs = Requirements {
+        size: 100,
+        alignment: 16,
+        type_mask: !0,
+    };
+    let readback = Request::general().for_readback();
+    assert_eq!(allocator.with_padding(readback, reqs).unwrap().size, 256);
+    assert_eq!(
+        allocator
+            .with_padding(Request::general(), reqs)
+            .unwrap()
+            .size,
+        100
+    );
+    let huge = Requirements {
+        size: !0 - 10,
+        ..reqs
+    };
+    match allocator.with_padding(readback, huge) {
+        Err(MemoryError::InvalidRequirements(InvalidRequirements::Overflow {
+            size,
+            alignment,
+        })) => {
+            assert_eq!(size, !0 - 10);
+            assert_eq!(alignment, MAX_NON_COHERENT_ATOM_SIZE);
+        }
+        r => panic!("Unexpected result {:?}", r),
+    }
+    assert!(allocator.with_padding(Request::general(), huge).is_ok());
+
+    let limits = Limits {
+        non_coherent_atom_size: 64,
+        ..Limits::default()
+    };
+    let limited =
+        CombinedAllocator::<Empty>::with_limits(MemoryTypeId(0), &limits, 1024, 8, 32, 512);
+    assert_eq!(limited.readback_atom(), Some(64));
+
+    allocator.set_readback_atom(None);
+    assert_eq!(allocator.with_padding(readback, reqs).unwrap().size, 100);
+    assert!(unsafe { allocator.dispose(&Device) }.is_ok());
+    assert!(unsafe { limited.dispose(&Device) }.is_ok());
+}
+
+#[test]
+fn test_large_page_rounding() {
+    use gfx_backend_empty::{Backend as Empty, Device};
+    let reqs = Requirements {
+        size: 3 << 20,
+        alignment: 256,
+        type_mask: !0,
+    };
+    let page = Some(2 << 20);
+    assert_eq!(rounded_to_page(reqs, page, !0).size, 4 << 20);
+    assert_eq!(rounded_to_page(reqs, page, 3 << 20).size, 3 << 20);
+    assert_eq!(rounded_to_page(reqs, None, !0).size, 3 << 20);
+    let small = Requirements {
+        size: 1 << 20,
+        ..reqs
+    };
+    assert_eq!(rounded_to_page(small, page, !0).size, 1 << 20);
+
+    // Rounding of live dedicated blocks is wasted
+    let mut allocator = CombinedAllocator::<Empty>::new(MemoryTypeId(0), 1024, 8, 32, 512);
+    assert_eq!(allocator.wasted(), 0);
+    allocator.root_wasted = 1 << 20;
+    assert_eq!(allocator.wasted(), 1 << 20);
+    allocator.root_wasted = 0;
+    assert!(unsafe { allocator.dispose(&Device) }.is_ok());
+}
@@ -0,0 +1,187 @@
+use gfx_hal::adapter::AdapterInfo;
+use gfx_hal::MemoryProperties;
+
+use arena::ArenaGrowth;
+use domain::Domain;
+use misuse::MisusePolicy;
+use smart::{HeapSelection, RetryPolicy, SpillPolicy, TrimPolicy};
+use tuning::{ConfigError, DedicatedThreshold, Slack, TuningProfile};
+
+/// Complete configuration of a `SmartAllocator`, kept apart from any allocator state.
+///
+/// The same configuration can be shared between tools and the game, serialized with the
+/// `serialize` feature, and used with `SmartAllocator::from_config` to create allocators for
+/// multiple devices. Optional settings default to what the allocator uses when they are not set.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SmartAllocatorConfig {
+    /// Parameters of the allocators of each memory type.
+    pub profile: TuningProfile,
+    /// Minimum alignment of blocks, on top of the alignment required by device limits.
+    /// See `SmartAllocator::set_min_alignment`.
+    pub min_alignment: Option<u64>,
+    /// Dedicated threshold replacing the one of the profile.
+    /// See `SmartAllocator::set_dedicated_threshold_rule`.
+    pub dedicated_threshold: Option<DedicatedThreshold>,
+    /// See `SmartAllocator::set_arena_growth`.
+    pub arena_growth: Option<ArenaGrowth>,
+    /// See `SmartAllocator::set_partial_chunks`.
+    pub partial_chunks: bool,
+    /// See `SmartAllocator::set_recycle`.
+    pub recycle: bool,
+    /// See `SmartAllocator::set_slack`.
+    pub slack: Option<Slack>,
+    /// See `SmartAllocator::set_tracking`.
+    pub tracking: bool,
+    /// See `SmartAllocator::set_segregate_readback`.
+    pub segregate_readback: bool,
+    /// Sizes of large pages memory objects of heaps are aligned to, by heap index.
+    /// See `SmartAllocator::set_large_page`.
+    pub large_pages: Vec<(usize, u64)>,
+    /// Maximum sizes of single memory objects of heaps, by heap index.
+    /// See `SmartAllocator::set_max_allocation`.
+    pub max_allocations: Vec<(usize, u64)>,
+    /// Sizes heaps pretend to have, by heap index, to simulate devices with less memory.
+    /// See `SmartAllocator::set_heap_cap`.
+    pub heap_caps: Vec<(usize, u64)>,
+    /// Budgets of domains. See `SmartAllocator::set_domain_budget`.
+    pub domain_budgets: Vec<(Domain, u64)>,
+    /// See `SmartAllocator::set_spill_policy`.
+    pub spill: Option<SpillPolicy>,
+    /// See `SmartAllocator::set_heap_selection`.
+    pub heap_selection: HeapSelection,
+    /// See `SmartAllocator::set_trim_policy`.
+    pub trim: Option<TrimPolicy>,
+    /// See `SmartAllocator::set_retry_policy`.
+    pub retry: Option<RetryPolicy>,
+    /// See `SmartAllocator::set_frame_budget`.
+    pub frame_budget: Option<u64>,
+    /// See `SmartAllocator::set_misuse_policy`.
+    pub misuse: MisusePolicy,
+}
+
+impl SmartAllocatorConfig {
+    /// Create a configuration from a profile, leaving everything else at defaults.
+    pub fn new(profile: TuningProfile) -> Self {
+        SmartAllocatorConfig {
+            profile,
+            min_alignment: None,
+            dedicated_threshold: None,
+            arena_growth: None,
+            partial_chunks: false,
+            recycle: false,
+            slack: None,
+            tracking: false,
+            segregate_readback: false,
+            large_pages: Vec::new(),
+            max_allocations: Vec::new(),
+            heap_caps: Vec::new(),
+            domain_budgets: Vec::new(),
+            spill: None,
+            heap_selection: HeapSelection::LeastUsed,
+            trim: None,
+            retry: None,
+            frame_budget: None,
+            misuse: MisusePolicy::Panic,
+        }
+    }
+
+    /// Create a configuration with the preset for an adapter.
+    ///
+    /// See `TuningProfile::for_adapter`.
+    pub fn for_adapter(info: &AdapterInfo) -> Self {
+        Self::new(TuningProfile::for_adapter(info))
+    }
+
+    /// Check that allocators can be created from this configuration.
+    ///
+    /// Heap indices depend on the device and are checked by `validate_for`.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.profile.validate()?;
+        if let Some(rule) = self.dedicated_threshold {
+            rule.validate()?;
+        }
+        if let Some(slack) = self.slack {
+            slack.validate()?;
+        }
+        if let Some(alignment) = self.min_alignment {
+            if !alignment.is_power_of_two() {
+                return Err(ConfigError::MinAlignment(alignment));
+            }
+        }
+        if let Some(&(_, size)) = self
+            .large_pages
+            .iter()
+            .find(|&&(_, size)| !size.is_power_of_two())
+        {
+            return Err(ConfigError::LargePage(size));
+        }
+        Ok(())
+    }
+
+    /// Check that allocators can be created from this configuration for a device with
+    /// `memory_properties`.
+    ///
+    /// See `validate`, and checks that heap indices of per-heap settings are in range.
+    pub fn validate_for(&self, memory_properties: &MemoryProperties) -> Result<(), ConfigError> {
+        self.validate()?;
+        let heaps = memory_properties.memory_heaps.len();
+        match self
+            .large_pages
+            .iter()
+            .chain(&self.max_allocations)
+            .chain(&self.heap_caps)
+            .find(|&&(heap_index, _)| heap_index >= heaps)
+        {
+            Some(&(heap_index, _)) => Err(ConfigError::HeapIndex(heap_index)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for SmartAllocatorConfig {
+    fn default() -> Self {
+        Self::new(TuningProfile::default())
+    }
+}
+
+impl From<TuningProfile> for SmartAllocatorConfig {
+    fn from(profile: TuningProfile) -> Self {
+        Self::new(profile)
+    }
+}
+
+#[test]
+fn test_config() {
+    let config = SmartAllocatorConfig::from(TuningProfile::mobile());
+    assert_eq!(config.profile, TuningProfile::mobile());
+    assert_eq!(config.heap_selection, HeapSelection::LeastUsed);
+    assert!(config.validate().is_ok());
+
+    let mut invalid = config.clone();
+    invalid.profile.blocks_per_chunk = 0;
+    assert!(invalid.validate().is_err());
+
+    let mut invalid = config.clone();
+    invalid.min_alignment = Some(96);
+    assert_eq!(invalid.validate(), Err(ConfigError::MinAlignment(96)));
+
+    let mut invalid = config.clone();
+    invalid.large_pages = vec![(0, 1 << 21), (1, 3 << 20)];
+    assert_eq!(invalid.validate(), Err(ConfigError::LargePage(3 << 20)));
+
+    // Heap indices are only known to be valid for a device
+    let properties = MemoryProperties {
+        memory_types: Vec::new(),
+        memory_heaps: vec![1 << 30],
+    };
+    let mut invalid = config;
+    invalid.heap_caps = vec![(1, 1 << 28)];
+    assert!(invalid.validate().is_ok());
+    assert_eq!(
+        invalid.validate_for(&properties),
+        Err(ConfigError::HeapIndex(1))
+    );
+    invalid.heap_caps = vec![(0, 1 << 28)];
+    assert!(invalid.validate_for(&properties).is_ok());
+}
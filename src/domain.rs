@@ -0,0 +1,131 @@
+/// Part of an application memory is accounted to, e.g. engine, world, streaming or editor.
+///
+/// Domains are passed with requests and are one level above tags: tags only name blocks,
+/// while domains have budgets enforced by `SmartAllocator` and usage tracked separately.
+/// Applications define their own domains:
+///
+/// ```rust
+/// # extern crate gfx_memory;
+/// # use gfx_memory::{Domain, Request};
+/// # fn main() {
+/// const STREAMING: Domain = Domain(2);
+///
+/// let request = Request::general().device_local().in_domain(STREAMING);
+/// assert_eq!(request.domain(), STREAMING);
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Domain(pub u16);
+
+impl Domain {
+    /// Domain of requests that don't specify one.
+    pub const DEFAULT: Domain = Domain(0);
+}
+
+/// Budget and usage of a domain, see `SmartAllocator::domain_stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DomainStats {
+    /// Maximum total size of live blocks of the domain.
+    pub budget: Option<u64>,
+    /// Total size of live blocks of the domain.
+    pub used: u64,
+    /// Number of live blocks of the domain.
+    pub blocks: u64,
+    /// Highest total size of live blocks of the domain so far.
+    pub peak: u64,
+}
+
+impl DomainStats {
+    /// Check if a block of `size` bytes fits into the budget.
+    pub fn fits(&self, size: u64) -> bool {
+        self.budget
+            .map_or(true, |budget| self.used.saturating_add(size) <= budget)
+    }
+}
+
+/// Stats of all domains used so far, indexed by domain.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Domains {
+    stats: Vec<DomainStats>,
+}
+
+impl Domains {
+    pub(crate) fn get(&self, domain: Domain) -> DomainStats {
+        self.stats
+            .get(domain.0 as usize)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn get_mut(&mut self, domain: Domain) -> &mut DomainStats {
+        let index = domain.0 as usize;
+        if index >= self.stats.len() {
+            self.stats.resize(index + 1, DomainStats::default());
+        }
+        &mut self.stats[index]
+    }
+
+    pub(crate) fn host_memory(&self) -> usize {
+        self.stats.capacity() * ::std::mem::size_of::<DomainStats>()
+    }
+
+    pub(crate) fn set_budget(&mut self, domain: Domain, budget: Option<u64>) {
+        self.get_mut(domain).budget = budget;
+    }
+
+    pub(crate) fn alloc(&mut self, domain: Domain, size: u64) {
+        let stats = self.get_mut(domain);
+        stats.used += size;
+        stats.blocks += 1;
+        stats.peak = ::std::cmp::max(stats.peak, stats.used);
+    }
+
+    /// Account a live block of the domain growing or shrinking from `old` to `new` bytes.
+    pub(crate) fn resize(&mut self, domain: Domain, old: u64, new: u64) {
+        let stats = self.get_mut(domain);
+        stats.used = (stats.used + new).saturating_sub(old);
+        stats.peak = ::std::cmp::max(stats.peak, stats.used);
+    }
+
+    pub(crate) fn free(&mut self, domain: Domain, size: u64) {
+        let stats = self.get_mut(domain);
+        debug_assert!(stats.used >= size && stats.blocks > 0);
+        stats.used = stats.used.saturating_sub(size);
+        stats.blocks = stats.blocks.saturating_sub(1);
+    }
+
+    /// Iterate over domains that were ever used or given a budget.
+    pub(crate) fn iter<'a>(&'a self) -> impl Iterator<Item = (Domain, DomainStats)> + 'a {
+        self.stats
+            .iter()
+            .enumerate()
+            .filter(|&(_, stats)| *stats != DomainStats::default())
+            .map(|(index, stats)| (Domain(index as u16), *stats))
+    }
+}
+
+#[test]
+fn test_domains() {
+    let world = Domain(1);
+    let mut domains = Domains::default();
+    domains.set_budget(world, Some(1024));
+    assert!(domains.get(world).fits(1024));
+    domains.alloc(world, 768);
+    assert!(!domains.get(world).fits(512));
+    domains.free(world, 768);
+    assert_eq!(
+        domains.get(world),
+        DomainStats {
+            budget: Some(1024),
+            used: 0,
+            blocks: 0,
+            peak: 768,
+        }
+    );
+    assert_eq!(domains.get(Domain(7)), DomainStats::default());
+    assert_eq!(
+        domains.iter().map(|(domain, _)| domain).collect::<Vec<_>>(),
+        vec![world]
+    );
+}
@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+
+use gfx_hal::Backend;
+
+use MemoryAllocator;
+
+/// Generation of resources that are released together, e.g. the generation of a swapchain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Epoch(pub u64);
+
+/// Key of a value stored in `Epochs`.
+///
+/// A key must not be used after its value is removed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct EpochKey {
+    epoch: Epoch,
+    index: usize,
+}
+
+impl EpochKey {
+    /// Get the epoch of the value.
+    pub fn epoch(&self) -> Epoch {
+        self.epoch
+    }
+}
+
+/// Values, such as blocks or factory items, grouped by the epoch they belong to.
+///
+/// When the swapchain is recreated, everything created for the old one can be released at once
+/// with `drain_epoch` or `free_epoch`, and `epochs` reports what is still alive, so transient
+/// attachments aren't leaked by resize storms.
+///
+/// ### Type parameters:
+///
+/// - `T`: type of the values
+#[derive(Debug)]
+pub struct Epochs<T> {
+    groups: BTreeMap<Epoch, Group<T>>,
+}
+
+#[derive(Debug)]
+struct Group<T> {
+    values: Vec<Option<T>>,
+    live: usize,
+}
+
+impl<T> Default for Epochs<T> {
+    fn default() -> Self {
+        Epochs {
+            groups: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T> Epochs<T> {
+    /// Create an empty collection.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a value to an epoch.
+    pub fn insert(&mut self, epoch: Epoch, value: T) -> EpochKey {
+        let group = self.groups.entry(epoch).or_insert_with(|| Group {
+            values: Vec::new(),
+            live: 0,
+        });
+        group.values.push(Some(value));
+        group.live += 1;
+        EpochKey {
+            epoch,
+            index: group.values.len() - 1,
+        }
+    }
+
+    /// Get a value.
+    pub fn get(&self, key: EpochKey) -> Option<&T> {
+        self.groups
+            .get(&key.epoch)
+            .and_then(|group| group.values.get(key.index))
+            .and_then(Option::as_ref)
+    }
+
+    /// Remove a single value, e.g. when it is released before the end of its epoch.
+    pub fn remove(&mut self, key: EpochKey) -> Option<T> {
+        let value = {
+            let group = self.groups.get_mut(&key.epoch)?;
+            let value = group.values.get_mut(key.index)?.take()?;
+            group.live -= 1;
+            value
+        };
+        if self.count(key.epoch) == 0 {
+            self.groups.remove(&key.epoch);
+        }
+        Some(value)
+    }
+
+    /// Get the number of values alive in an epoch.
+    pub fn count(&self, epoch: Epoch) -> usize {
+        self.groups.get(&epoch).map_or(0, |group| group.live)
+    }
+
+    /// Get the total number of values.
+    pub fn len(&self) -> usize {
+        self.groups.values().map(|group| group.live).sum()
+    }
+
+    /// Check if there are no values.
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Get epochs with values alive in them, along with the number of values, oldest first.
+    pub fn epochs<'a>(&'a self) -> impl Iterator<Item = (Epoch, usize)> + 'a {
+        self.groups
+            .iter()
+            .map(|(&epoch, group)| (epoch, group.live))
+    }
+
+    /// Remove all values of an epoch.
+    pub fn drain_epoch(&mut self, epoch: Epoch) -> Vec<T> {
+        match self.groups.remove(&epoch) {
+            Some(group) => group.values.into_iter().flatten().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Remove all values of epochs older than `epoch`.
+    pub fn drain_before(&mut self, epoch: Epoch) -> Vec<T> {
+        let newer = self.groups.split_off(&epoch);
+        let older = ::std::mem::replace(&mut self.groups, newer);
+        older
+            .into_iter()
+            .flat_map(|(_, group)| group.values.into_iter().flatten())
+            .collect()
+    }
+
+    /// Free all blocks of an epoch.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `allocator`: allocator the blocks were allocated from
+    /// - `device`: device the blocks were allocated from
+    /// - `epoch`: epoch to release
+    ///
+    /// ### Returns
+    ///
+    /// Number of blocks freed.
+    pub unsafe fn free_epoch<B, A>(
+        &mut self,
+        allocator: &mut A,
+        device: &B::Device,
+        epoch: Epoch,
+    ) -> usize
+    where
+        B: Backend,
+        A: MemoryAllocator<B, Block = T>,
+    {
+        let blocks = self.drain_epoch(epoch);
+        let count = blocks.len();
+        for block in blocks {
+            allocator.free(device, block);
+        }
+        count
+    }
+}
+
+#[test]
+fn test_epochs() {
+    let mut epochs = Epochs::new();
+    let first = epochs.insert(Epoch(1), 10);
+    epochs.insert(Epoch(1), 11);
+    let second = epochs.insert(Epoch(2), 20);
+    epochs.insert(Epoch(3), 30);
+    assert_eq!(epochs.len(), 4);
+    assert_eq!(epochs.get(second), Some(&20));
+
+    assert_eq!(epochs.remove(first), Some(10));
+    assert_eq!(epochs.remove(first), None);
+    assert_eq!(
+        epochs.epochs().collect::<Vec<_>>(),
+        vec![(Epoch(1), 1), (Epoch(2), 1), (Epoch(3), 1)]
+    );
+
+    assert_eq!(epochs.remove(second), Some(20));
+    assert_eq!(epochs.count(Epoch(2)), 0);
+    assert_eq!(epochs.drain_before(Epoch(3)), vec![11]);
+    assert_eq!(epochs.drain_epoch(Epoch(3)), vec![30]);
+    assert!(epochs.is_empty());
+}
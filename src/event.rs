@@ -0,0 +1,64 @@
+use std::fmt::{self, Debug};
+
+use gfx_hal::memory::Properties;
+use gfx_hal::MemoryTypeId;
+
+/// Notable events reported by allocators.
+///
+/// Events are delivered synchronously to the callback set with `SmartAllocator::set_listener`
+/// or `Uploader::set_listener`.
+#[derive(Clone, Debug)]
+pub enum Event {
+    /// Device local memory is under pressure and an allocation was placed into memory with
+    /// fallback properties instead.
+    Spilled {
+        /// Properties requested for the allocation.
+        requested: Properties,
+        /// Memory type the allocation was placed into.
+        memory_type: MemoryTypeId,
+        /// Size of the allocation in bytes.
+        size: u64,
+    },
+
+    /// A submitted batch of an `Uploader` is still pending after the number of cleanups set
+    /// with `Uploader::set_watchdog`, so its staging buffers are not freed. Usually a symptom
+    /// of a fence that never signals, e.g. because the submission was lost.
+    StuckFrees {
+        /// Number of staging buffers waiting to be freed.
+        buffers: usize,
+        /// Total size of the staging buffers in bytes.
+        size: u64,
+        /// Number of cleanups the batch has been pending for.
+        cleanups: u32,
+    },
+}
+
+type Callback = Box<dyn Fn(&Event) + Send + Sync>;
+
+/// Optional callback receiving `Event`s.
+#[derive(Default)]
+pub(crate) struct Listener(Option<Callback>);
+
+impl Listener {
+    pub(crate) fn set<F>(&mut self, callback: F)
+    where
+        F: Fn(&Event) + Send + Sync + 'static,
+    {
+        self.0 = Some(Box::new(callback));
+    }
+
+    pub(crate) fn emit(&self, event: Event) {
+        if let Some(ref callback) = self.0 {
+            callback(&event);
+        }
+    }
+}
+
+impl Debug for Listener {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(_) => fmt.write_str("Listener(Some(..))"),
+            None => fmt.write_str("Listener(None)"),
+        }
+    }
+}
@@ -1,18 +1,28 @@
 use std::borrow::{Borrow, BorrowMut};
-use std::fmt::Debug;
+use std::error::Error;
+use std::fmt::{self, Debug};
+use std::iter::once;
 use std::ops::Range;
 
-use gfx_hal::buffer::{CreationError as BufferCreationError, Usage as BufferUsage};
+use gfx_hal::buffer::{
+    CreationError as BufferCreationError, State as BufferState, Usage as BufferUsage,
+};
+use gfx_hal::command::{BufferCopy, CommandBuffer, Level as CommandLevel, Shot};
 use gfx_hal::device::BindError;
 use gfx_hal::format::Format;
 use gfx_hal::image::{
-    CreationError as ImageCreationError, Kind, Level, Tiling, Usage as ImageUsage, ViewCapabilities,
+    CreationError as ImageCreationError, Kind, Level, State as ImageState, SubresourceRange,
+    Tiling, Usage as ImageUsage, ViewCapabilities,
 };
+use gfx_hal::memory::{Barrier, Requirements};
+use gfx_hal::queue::{QueueFamilyId, Supports, Transfer};
 use gfx_hal::{Backend, Device};
 
-use block::Block;
+use align::is_aligned;
+use block::{Block, SharedBlock};
+use transaction::Transaction;
 
-use {MemoryAllocator, MemoryError};
+use {layout_requirements, merge_requirements, MemoryAllocator, MemoryError};
 
 /// Factory trait used to create buffers and images and manage the memory for them.
 ///
@@ -92,6 +102,16 @@ pub trait Factory<B: Backend> {
     /// - `device`: device the image was created on
     /// - `image`: the image to destroy
     unsafe fn destroy_image(&mut self, device: &B::Device, image: Self::Image);
+
+    /// Start a transaction creating several resources together.
+    ///
+    /// See `Transaction`.
+    fn transaction() -> Transaction<B, Self>
+    where
+        Self: Sized,
+    {
+        Transaction::new()
+    }
 }
 
 /// Memory resource produced by the blanket `MemoryAllocator` as `Factory` implementation.
@@ -104,6 +124,8 @@ pub trait Factory<B: Backend> {
 pub struct Item<I, T> {
     raw: I,
     block: T,
+    family: Option<QueueFamilyId>,
+    info: ResourceInfo,
 }
 
 impl<I, T> Item<I, T> {
@@ -111,13 +133,113 @@ impl<I, T> Item<I, T> {
     pub fn raw(&self) -> &I {
         &self.raw
     }
+
+    /// Get the description the item was created with.
+    pub fn info(&self) -> &ResourceInfo {
+        &self.info
+    }
 }
 
 impl<I, T> Item<I, T> {
+    /// Replace the block of the item, e.g. to tag it with where it was allocated.
+    pub(crate) fn map_block<U, F>(self, f: F) -> Item<I, U>
+    where
+        F: FnOnce(T) -> U,
+    {
+        Item {
+            raw: self.raw,
+            block: f(self.block),
+            family: self.family,
+            info: self.info,
+        }
+    }
+
     /// Get block of the item.
     pub fn block(&self) -> &T {
         &self.block
     }
+
+    /// Get the queue family owning the item, if recorded.
+    ///
+    /// Items are created without an owner, which is fine for resources only used by one queue
+    /// family or created with concurrent sharing.
+    pub fn family(&self) -> Option<QueueFamilyId> {
+        self.family
+    }
+
+    /// Record the queue family owning the item.
+    pub fn set_family(&mut self, family: Option<QueueFamilyId>) {
+        self.family = family;
+    }
+
+    /// Record that ownership of the item moves to the `dst` queue family.
+    ///
+    /// ### Returns
+    ///
+    /// Source and destination families for the ownership transfer barriers, or `None` if no
+    /// transfer is needed because the item had no owner or is already owned by `dst`.
+    pub fn transfer_families(&mut self, dst: QueueFamilyId) -> Option<Range<QueueFamilyId>> {
+        let families = match self.family {
+            Some(src) if src != dst => Some(src..dst),
+            _ => None,
+        };
+        self.family = Some(dst);
+        families
+    }
+
+    /// Move ownership of a buffer to the `dst` queue family.
+    ///
+    /// The returned barrier must be recorded both on a queue of the source family (release)
+    /// and on a queue of `dst` (acquire).
+    ///
+    /// ### Parameters:
+    ///
+    /// - `dst`: queue family that will use the buffer
+    /// - `states`: access of the buffer before and after the transfer
+    pub fn transfer_buffer<'a, B>(
+        &'a mut self,
+        dst: QueueFamilyId,
+        states: Range<BufferState>,
+    ) -> Barrier<'a, B>
+    where
+        B: Backend<Buffer = I>,
+    {
+        let families = self.transfer_families(dst);
+        Barrier::Buffer {
+            states,
+            target: &self.raw,
+            families,
+            range: None..None,
+        }
+    }
+
+    /// Move ownership of an image to the `dst` queue family.
+    ///
+    /// The returned barrier must be recorded both on a queue of the source family (release)
+    /// and on a queue of `dst` (acquire).
+    ///
+    /// ### Parameters:
+    ///
+    /// - `dst`: queue family that will use the image
+    /// - `states`: access and layout of the image before and after the transfer
+    /// - `range`: subresources of the image the barrier applies to
+    pub fn transfer_image<'a, B>(
+        &'a mut self,
+        dst: QueueFamilyId,
+        states: Range<ImageState>,
+        range: SubresourceRange,
+    ) -> Barrier<'a, B>
+    where
+        B: Backend<Image = I>,
+    {
+        let families = self.transfer_families(dst);
+        Barrier::Image {
+            states,
+            target: &self.raw,
+            families,
+            range,
+        }
+    }
 }
 
 impl<I, T> Borrow<I> for Item<I, T> {
@@ -148,51 +270,169 @@ where
     }
 }
 
+/// Description of an image to create.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageInfo {
+    /// `Kind` of texture storage to allocate
+    pub kind: Kind,
+    /// Mipmap level
+    pub level: Level,
+    /// Texture format
+    pub format: Format,
+    /// Image tiling
+    pub tiling: Tiling,
+    /// hal image usage
+    pub usage: ImageUsage,
+    /// View capabilities
+    pub view_caps: ViewCapabilities,
+}
+
+/// Description of the resource a `FactoryError` occurred for.
+#[derive(Clone, Debug)]
+pub enum ResourceInfo {
+    /// A single buffer.
+    Buffer {
+        /// Size of the buffer in bytes
+        size: u64,
+        /// hal buffer usage
+        usage: BufferUsage,
+    },
+
+    /// A single image.
+    Image(ImageInfo),
+
+    /// Several resources sharing one block of memory.
+    Shared {
+        /// Number of resources
+        count: usize,
+        /// Size of the shared block in bytes
+        size: u64,
+    },
+}
+
+impl fmt::Display for ResourceInfo {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ResourceInfo::Buffer { size, usage } => {
+                write!(fmt, "buffer of {} bytes with usage {:?}", size, usage)
+            }
+            ResourceInfo::Image(ref info) => write!(
+                fmt,
+                "image {:?} with format {:?} and usage {:?}",
+                info.kind, info.format, info.usage
+            ),
+            ResourceInfo::Shared { count, size } => {
+                write!(fmt, "{} resources sharing {} bytes", count, size)
+            }
+        }
+    }
+}
+
 /// Possible errors that may be returned from the blanket `MemoryAllocator` as `Factory`
 /// implementation.
-#[derive(Debug, Clone, Fail)]
+///
+/// Each variant carries the description of the resource that failed.
+#[derive(Debug, Clone)]
 pub enum FactoryError {
     /// Memory error.
-    #[fail(display = "Memory error")]
-    MemoryError(#[cause] MemoryError),
+    MemoryError {
+        /// Underlying error
+        error: MemoryError,
+        /// Resource memory was allocated for
+        resource: ResourceInfo,
+    },
 
     /// Binding of a buffer or image object failed.
-    #[fail(display = "Bind error")]
-    BindError(#[cause] BindError),
+    BindError {
+        /// Underlying error
+        error: BindError,
+        /// Resource memory was bound to
+        resource: ResourceInfo,
+    },
 
     /// Buffer creation error.
-    #[fail(display = "Failed to create buffer")]
-    BufferCreationError(#[cause] BufferCreationError),
+    BufferCreationError {
+        /// Underlying error
+        error: BufferCreationError,
+        /// Buffer that was created
+        resource: ResourceInfo,
+    },
 
     /// Image creation error.
-    #[fail(display = "Failed to create image")]
-    ImageCreationError(#[cause] ImageCreationError),
+    ImageCreationError {
+        /// Underlying error
+        error: ImageCreationError,
+        /// Image that was created
+        resource: ResourceInfo,
+    },
+}
+
+impl FactoryError {
+    /// Get the description of the resource the error occurred for.
+    pub fn resource(&self) -> &ResourceInfo {
+        match *self {
+            FactoryError::MemoryError { ref resource, .. }
+            | FactoryError::BindError { ref resource, .. }
+            | FactoryError::BufferCreationError { ref resource, .. }
+            | FactoryError::ImageCreationError { ref resource, .. } => resource,
+        }
+    }
+}
+
+impl fmt::Display for FactoryError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FactoryError::MemoryError { ref resource, .. } => {
+                write!(fmt, "Failed to allocate memory for {}", resource)
+            }
+            FactoryError::BindError { ref resource, .. } => {
+                write!(fmt, "Failed to bind memory to {}", resource)
+            }
+            FactoryError::BufferCreationError { ref resource, .. }
+            | FactoryError::ImageCreationError { ref resource, .. } => {
+                write!(fmt, "Failed to create {}", resource)
+            }
+        }
+    }
 }
 
-impl From<MemoryError> for FactoryError {
-    fn from(error: MemoryError) -> Self {
-        FactoryError::MemoryError(error)
+impl Error for FactoryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            FactoryError::MemoryError { ref error, .. } => Some(error),
+            FactoryError::BindError { ref error, .. } => Some(HalError::wrap(error)),
+            FactoryError::BufferCreationError { ref error, .. } => Some(HalError::wrap(error)),
+            FactoryError::ImageCreationError { ref error, .. } => Some(HalError::wrap(error)),
+        }
     }
 }
 
-impl From<BufferCreationError> for FactoryError {
-    fn from(error: BufferCreationError) -> Self {
-        FactoryError::BufferCreationError(error)
+/// hal errors only implement `Display` and `Debug`.
+/// This wrapper exposes them as `std::error::Error` sources.
+#[repr(transparent)]
+pub(crate) struct HalError<E>(E);
+
+impl<E> HalError<E> {
+    pub(crate) fn wrap(error: &E) -> &Self {
+        // `HalError<E>` is a transparent wrapper over `E`.
+        unsafe { &*(error as *const E as *const Self) }
     }
 }
 
-impl From<ImageCreationError> for FactoryError {
-    fn from(error: ImageCreationError) -> Self {
-        FactoryError::ImageCreationError(error)
+impl<E: Debug> Debug for HalError<E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(fmt)
     }
 }
 
-impl From<BindError> for FactoryError {
-    fn from(error: BindError) -> Self {
-        FactoryError::BindError(error)
+impl<E: fmt::Display> fmt::Display for HalError<E> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(fmt)
     }
 }
 
+impl<E: Debug + fmt::Display> Error for HalError<E> {}
+
 impl<B, A> Factory<B> for A
 where
     B: Backend,
@@ -211,11 +451,39 @@ where
         size: u64,
         usage: BufferUsage,
     ) -> Result<Item<B::Buffer, A::Block>, FactoryError> {
-        let mut buf = device.create_buffer(size, usage)?;
+        let resource = || ResourceInfo::Buffer { size, usage };
+        let mut buf = device.create_buffer(size, usage).map_err(|error| {
+            FactoryError::BufferCreationError {
+                error,
+                resource: resource(),
+            }
+        })?;
         let reqs = device.get_buffer_requirements(&buf);
-        let block = self.alloc(device, request, reqs)?;
-        device.bind_buffer_memory(block.memory(), block.range().start, &mut buf)?;
-        Ok(Item { raw: buf, block })
+        let block = match self.alloc(device, request, reqs) {
+            Ok(block) => block,
+            Err(error) => {
+                device.destroy_buffer(buf);
+                return Err(FactoryError::MemoryError {
+                    error,
+                    resource: resource(),
+                });
+            }
+        };
+        if let Err(error) = device.bind_buffer_memory(block.memory(), block.range().start, &mut buf)
+        {
+            device.destroy_buffer(buf);
+            self.free(device, block);
+            return Err(FactoryError::BindError {
+                error,
+                resource: resource(),
+            });
+        }
+        Ok(Item {
+            raw: buf,
+            block,
+            family: None,
+            info: resource(),
+        })
     }
 
     unsafe fn create_image(
@@ -229,11 +497,48 @@ where
         usage: ImageUsage,
         view_caps: ViewCapabilities,
     ) -> Result<Item<B::Image, A::Block>, FactoryError> {
-        let mut img = device.create_image(kind, level, format, tiling, usage, view_caps)?;
+        let resource = || {
+            ResourceInfo::Image(ImageInfo {
+                kind,
+                level,
+                format,
+                tiling,
+                usage,
+                view_caps,
+            })
+        };
+        let mut img = device
+            .create_image(kind, level, format, tiling, usage, view_caps)
+            .map_err(|error| FactoryError::ImageCreationError {
+                error,
+                resource: resource(),
+            })?;
         let reqs = device.get_image_requirements(&img);
-        let block = self.alloc(device, request, reqs)?;
-        device.bind_image_memory(block.memory(), block.range().start, &mut img)?;
-        Ok(Item { raw: img, block })
+        let block = match self.alloc(device, request, reqs) {
+            Ok(block) => block,
+            Err(error) => {
+                device.destroy_image(img);
+                return Err(FactoryError::MemoryError {
+                    error,
+                    resource: resource(),
+                });
+            }
+        };
+        if let Err(error) = device.bind_image_memory(block.memory(), block.range().start, &mut img)
+        {
+            device.destroy_image(img);
+            self.free(device, block);
+            return Err(FactoryError::BindError {
+                error,
+                resource: resource(),
+            });
+        }
+        Ok(Item {
+            raw: img,
+            block,
+            family: None,
+            info: resource(),
+        })
     }
 
     unsafe fn destroy_buffer(&mut self, device: &B::Device, buffer: Self::Buffer) {
@@ -246,3 +551,619 @@ where
         self.free(device, image.block);
     }
 }
+
+/// Factory extension for recreating resources created by the factory in other memory.
+///
+/// Kept apart from `Factory`, so factories only have to create and destroy resources.
+///
+/// ### Type parameters:
+///
+/// - `B`: hal `Backend`
+pub trait RecreateFactory<B: Backend>: Factory<B> {
+    /// Type of memory blocks resources are bound to.
+    type Block: Block<Memory = B::Memory>;
+
+    /// Create a copy of a buffer created by this factory, with the same size and usage.
+    ///
+    /// Allocates and binds memory for the new buffer and records copying of the contents of
+    /// `buffer` into `command`, e.g. for duplicating resources in editors.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device the buffer was created on
+    /// - `request`: information needed by the `MemoryAllocator` to allocate a block of memory for
+    ///              the new buffer
+    /// - `buffer`: the buffer to copy
+    /// - `command`: command buffer to record the copy into
+    ///
+    /// ### Safety
+    ///
+    /// `buffer` must have `TRANSFER_SRC` and `TRANSFER_DST` usage, and must not be written to
+    /// or destroyed before the copy completes.
+    unsafe fn clone_buffer<C, S, L>(
+        &mut self,
+        device: &B::Device,
+        request: Self::BufferRequest,
+        buffer: &Self::Buffer,
+        command: &mut CommandBuffer<B, C, S, L>,
+    ) -> Result<Self::Buffer, Self::Error>
+    where
+        C: Supports<Transfer>,
+        S: Shot,
+        L: CommandLevel;
+
+    /// Recreate a buffer bound to another block, e.g. when defragmentation moves its memory.
+    ///
+    /// The new buffer has the size and usage of `buffer` and no queue family owner. `buffer`
+    /// is left untouched, so its contents can be copied to the new buffer, after which it is
+    /// destroyed as usual, freeing its old block.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device the buffer was created on
+    /// - `buffer`: the buffer to recreate
+    /// - `block`: block to bind the new buffer to, e.g. the destination of a
+    ///            `SmartAllocator::migrate`
+    ///
+    /// ### Returns
+    ///
+    /// If the buffer can't be created or bound, `block` is returned along with the error.
+    ///
+    /// ### Safety
+    ///
+    /// `block` must satisfy the memory requirements of the buffer, which holds for blocks
+    /// allocated with the requirements `buffer` was allocated with.
+    unsafe fn rebind_buffer(
+        &mut self,
+        device: &B::Device,
+        buffer: &Self::Buffer,
+        block: Self::Block,
+    ) -> Result<Self::Buffer, (Self::Block, Self::Error)>;
+
+    /// Recreate an image bound to another block, e.g. when defragmentation moves its memory.
+    ///
+    /// Like `rebind_buffer`, but for images. The new image is in the `Undefined` layout.
+    ///
+    /// ### Safety
+    ///
+    /// `block` must satisfy the memory requirements of the image, which holds for blocks
+    /// allocated with the requirements `image` was allocated with.
+    unsafe fn rebind_image(
+        &mut self,
+        device: &B::Device,
+        image: &Self::Image,
+        block: Self::Block,
+    ) -> Result<Self::Image, (Self::Block, Self::Error)>;
+}
+
+impl<B, A> RecreateFactory<B> for A
+where
+    B: Backend,
+    A: MemoryAllocator<B>,
+{
+    type Block = A::Block;
+
+    unsafe fn clone_buffer<C, S, L>(
+        &mut self,
+        device: &B::Device,
+        request: A::Request,
+        buffer: &Item<B::Buffer, A::Block>,
+        command: &mut CommandBuffer<B, C, S, L>,
+    ) -> Result<Item<B::Buffer, A::Block>, FactoryError>
+    where
+        C: Supports<Transfer>,
+        S: Shot,
+        L: CommandLevel,
+    {
+        let (size, usage) = match buffer.info {
+            ResourceInfo::Buffer { size, usage } => (size, usage),
+            _ => unreachable!("Buffers are created with buffer info"),
+        };
+        let clone = self.create_buffer(device, request, size, usage)?;
+        command.copy_buffer(
+            &buffer.raw,
+            &clone.raw,
+            once(BufferCopy {
+                src: 0,
+                dst: 0,
+                size,
+            }),
+        );
+        Ok(clone)
+    }
+
+    unsafe fn rebind_buffer(
+        &mut self,
+        device: &B::Device,
+        buffer: &Item<B::Buffer, A::Block>,
+        block: A::Block,
+    ) -> Result<Item<B::Buffer, A::Block>, (A::Block, FactoryError)> {
+        let (size, usage) = match buffer.info {
+            ResourceInfo::Buffer { size, usage } => (size, usage),
+            _ => unreachable!("Buffers are created with buffer info"),
+        };
+        let buf = match device.create_buffer(size, usage) {
+            Ok(buf) => buf,
+            Err(error) => {
+                return Err((
+                    block,
+                    FactoryError::BufferCreationError {
+                        error,
+                        resource: buffer.info.clone(),
+                    },
+                ))
+            }
+        };
+        bind_buffer(device, buf, block, buffer.info.clone())
+    }
+
+    unsafe fn rebind_image(
+        &mut self,
+        device: &B::Device,
+        image: &Item<B::Image, A::Block>,
+        block: A::Block,
+    ) -> Result<Item<B::Image, A::Block>, (A::Block, FactoryError)> {
+        let info = match image.info {
+            ResourceInfo::Image(info) => info,
+            _ => unreachable!("Images are created with image info"),
+        };
+        let img = match device.create_image(
+            info.kind,
+            info.level,
+            info.format,
+            info.tiling,
+            info.usage,
+            info.view_caps,
+        ) {
+            Ok(img) => img,
+            Err(error) => {
+                return Err((
+                    block,
+                    FactoryError::ImageCreationError {
+                        error,
+                        resource: image.info.clone(),
+                    },
+                ))
+            }
+        };
+        bind_image(device, img, block, image.info.clone())
+    }
+}
+
+/// Factory extension for creating several resources bound to a single block of memory.
+///
+/// The block is returned to the allocator only after the last resource sharing it is destroyed.
+///
+/// ### Type parameters:
+///
+/// - `B`: hal `Backend`
+pub trait SharedFactory<B: Backend>: Factory<B> {
+    /// Type of buffers sharing memory this factory produce.
+    /// The user can borrow the raw buffer.
+    type SharedBuffer: BorrowMut<B::Buffer> + Block<Memory = B::Memory>;
+
+    /// Type of images sharing memory this factory produce.
+    /// The user can borrow the raw image.
+    type SharedImage: BorrowMut<B::Image> + Block<Memory = B::Memory>;
+
+    /// Create buffers with the specified sizes and usages sharing one block of memory.
+    /// Nothing is allocated if `buffers` is empty.
+    ///
+    /// ### Parameters
+    ///
+    /// - `device`: device to create the buffers on
+    /// - `request`: information needed by the `MemoryAllocator` to allocate a block of memory for
+    ///              the buffers
+    /// - `buffers`: size in bytes and hal buffer `Usage` of each buffer
+    unsafe fn create_shared_buffers(
+        &mut self,
+        device: &B::Device,
+        request: Self::BufferRequest,
+        buffers: &[(u64, BufferUsage)],
+    ) -> Result<Vec<Self::SharedBuffer>, Self::Error>;
+
+    /// Create images sharing one block of memory.
+    /// Nothing is allocated if `images` is empty.
+    ///
+    /// ### Parameters
+    ///
+    /// - `device`: device to create the images on
+    /// - `request`: information needed by the `MemoryAllocator` to allocate a block of memory for
+    ///              the images
+    /// - `images`: description of each image
+    unsafe fn create_shared_images(
+        &mut self,
+        device: &B::Device,
+        request: Self::ImageRequest,
+        images: &[ImageInfo],
+    ) -> Result<Vec<Self::SharedImage>, Self::Error>;
+
+    /// Destroy a buffer created by `create_shared_buffers`.
+    /// The memory is freed once no other resource uses it.
+    unsafe fn destroy_shared_buffer(&mut self, device: &B::Device, buffer: Self::SharedBuffer);
+
+    /// Destroy an image created by `create_shared_images`.
+    /// The memory is freed once no other resource uses it.
+    unsafe fn destroy_shared_image(&mut self, device: &B::Device, image: Self::SharedImage);
+}
+
+impl<B, A> SharedFactory<B> for A
+where
+    B: Backend,
+    A: MemoryAllocator<B>,
+{
+    type SharedBuffer = Item<B::Buffer, SharedBlock<A::Block>>;
+    type SharedImage = Item<B::Image, SharedBlock<A::Block>>;
+
+    unsafe fn create_shared_buffers(
+        &mut self,
+        device: &B::Device,
+        request: A::Request,
+        buffers: &[(u64, BufferUsage)],
+    ) -> Result<Vec<Self::SharedBuffer>, FactoryError> {
+        let mut raws = Vec::with_capacity(buffers.len());
+        for &(size, usage) in buffers {
+            match device.create_buffer(size, usage) {
+                Ok(buf) => raws.push(buf),
+                Err(error) => {
+                    for buf in raws {
+                        device.destroy_buffer(buf);
+                    }
+                    return Err(FactoryError::BufferCreationError {
+                        error,
+                        resource: ResourceInfo::Buffer { size, usage },
+                    });
+                }
+            }
+        }
+        let infos = buffers
+            .iter()
+            .map(|&(size, usage)| ResourceInfo::Buffer { size, usage })
+            .collect();
+        let reqs = raws
+            .iter()
+            .map(|buf| device.get_buffer_requirements(buf))
+            .collect::<Vec<_>>();
+        bind_shared(
+            self,
+            device,
+            request,
+            raws,
+            infos,
+            &reqs,
+            |device, block, offset, buf| device.bind_buffer_memory(block.memory(), offset, buf),
+            |device, buf| device.destroy_buffer(buf),
+        )
+    }
+
+    unsafe fn create_shared_images(
+        &mut self,
+        device: &B::Device,
+        request: A::Request,
+        images: &[ImageInfo],
+    ) -> Result<Vec<Self::SharedImage>, FactoryError> {
+        let mut raws = Vec::with_capacity(images.len());
+        for info in images {
+            match device.create_image(
+                info.kind,
+                info.level,
+                info.format,
+                info.tiling,
+                info.usage,
+                info.view_caps,
+            ) {
+                Ok(img) => raws.push(img),
+                Err(error) => {
+                    for img in raws {
+                        device.destroy_image(img);
+                    }
+                    return Err(FactoryError::ImageCreationError {
+                        error,
+                        resource: ResourceInfo::Image(*info),
+                    });
+                }
+            }
+        }
+        let infos = images
+            .iter()
+            .map(|info| ResourceInfo::Image(*info))
+            .collect();
+        let reqs = raws
+            .iter()
+            .map(|img| device.get_image_requirements(img))
+            .collect::<Vec<_>>();
+        bind_shared(
+            self,
+            device,
+            request,
+            raws,
+            infos,
+            &reqs,
+            |device, block, offset, img| device.bind_image_memory(block.memory(), offset, img),
+            |device, img| device.destroy_image(img),
+        )
+    }
+
+    unsafe fn destroy_shared_buffer(&mut self, device: &B::Device, buffer: Self::SharedBuffer) {
+        device.destroy_buffer(buffer.raw);
+        if let Some(block) = buffer.block.into_inner() {
+            self.free(device, block);
+        }
+    }
+
+    unsafe fn destroy_shared_image(&mut self, device: &B::Device, image: Self::SharedImage) {
+        device.destroy_image(image.raw);
+        if let Some(block) = image.block.into_inner() {
+            self.free(device, block);
+        }
+    }
+}
+
+type SharedItems<I, T> = Vec<Item<I, SharedBlock<T>>>;
+
+/// Allocate one block for all resources and bind them to it.
+/// Resources are destroyed if allocation or binding fails.
+unsafe fn bind_shared<B, A, I, F, D>(
+    allocator: &mut A,
+    device: &B::Device,
+    request: A::Request,
+    raws: Vec<I>,
+    infos: Vec<ResourceInfo>,
+    reqs: &[Requirements],
+    bind: F,
+    destroy: D,
+) -> Result<SharedItems<I, A::Block>, FactoryError>
+where
+    B: Backend,
+    A: MemoryAllocator<B>,
+    F: Fn(&B::Device, &A::Block, u64, &mut I) -> Result<(), BindError>,
+    D: Fn(&B::Device, I),
+{
+    if raws.is_empty() {
+        return Ok(Vec::new());
+    }
+    let merged = merge_requirements(reqs);
+    let resource = || ResourceInfo::Shared {
+        count: reqs.len(),
+        size: merged.size,
+    };
+    let block = match allocator.alloc(device, request, merged) {
+        Ok(block) => block,
+        Err(error) => {
+            for raw in raws {
+                destroy(device, raw);
+            }
+            return Err(FactoryError::MemoryError {
+                error,
+                resource: resource(),
+            });
+        }
+    };
+    let offsets = layout_requirements(reqs);
+    let mut raws = raws;
+    let mut result = Ok(());
+    for (raw, &offset) in raws.iter_mut().zip(&offsets) {
+        result = bind(device, &block, block.range().start + offset, raw);
+        if result.is_err() {
+            break;
+        }
+    }
+    if let Err(error) = result {
+        for raw in raws {
+            destroy(device, raw);
+        }
+        allocator.free(device, block);
+        return Err(FactoryError::BindError {
+            error,
+            resource: resource(),
+        });
+    }
+    let blocks = SharedBlock::split(
+        block,
+        offsets.into_iter().zip(reqs.iter().map(|reqs| reqs.size)),
+    )
+    .unwrap_or_else(|_| unreachable!("There is at least one resource"));
+    Ok(raws
+        .into_iter()
+        .zip(blocks)
+        .zip(infos)
+        .map(|((raw, block), info)| Item {
+            raw,
+            block,
+            family: None,
+            info,
+        })
+        .collect())
+}
+
+/// Bind `block` to a buffer created without memory.
+/// The buffer is destroyed and `block` returned if binding fails.
+pub(crate) unsafe fn bind_buffer<B, T>(
+    device: &B::Device,
+    mut buf: B::Buffer,
+    block: T,
+    info: ResourceInfo,
+) -> Result<Item<B::Buffer, T>, (T, FactoryError)>
+where
+    B: Backend,
+    T: Block<Memory = B::Memory>,
+{
+    debug_assert!({
+        let reqs = device.get_buffer_requirements(&buf);
+        reqs.size <= block.size() && is_aligned(block.range().start, reqs.alignment)
+    });
+    if let Err(error) = device.bind_buffer_memory(block.memory(), block.range().start, &mut buf) {
+        device.destroy_buffer(buf);
+        return Err((
+            block,
+            FactoryError::BindError {
+                error,
+                resource: info,
+            },
+        ));
+    }
+    Ok(Item {
+        raw: buf,
+        block,
+        family: None,
+        info,
+    })
+}
+
+/// Bind `block` to an image created without memory.
+/// The image is destroyed and `block` returned if binding fails.
+pub(crate) unsafe fn bind_image<B, T>(
+    device: &B::Device,
+    mut img: B::Image,
+    block: T,
+    info: ResourceInfo,
+) -> Result<Item<B::Image, T>, (T, FactoryError)>
+where
+    B: Backend,
+    T: Block<Memory = B::Memory>,
+{
+    debug_assert!({
+        let reqs = device.get_image_requirements(&img);
+        reqs.size <= block.size() && is_aligned(block.range().start, reqs.alignment)
+    });
+    if let Err(error) = device.bind_image_memory(block.memory(), block.range().start, &mut img) {
+        device.destroy_image(img);
+        return Err((
+            block,
+            FactoryError::BindError {
+                error,
+                resource: info,
+            },
+        ));
+    }
+    Ok(Item {
+        raw: img,
+        block,
+        family: None,
+        info,
+    })
+}
+
+#[test]
+fn test_error_source() {
+    let usage = BufferUsage::VERTEX;
+    let error = FactoryError::BufferCreationError {
+        error: BufferCreationError::UnsupportedUsage { usage },
+        resource: ResourceInfo::Buffer { size: 256, usage },
+    };
+    assert_eq!(
+        error.to_string(),
+        "Failed to create buffer of 256 bytes with usage VERTEX"
+    );
+    assert_eq!(
+        error.source().unwrap().to_string(),
+        "Buffer usage unsupported (VERTEX)."
+    );
+
+    let error = FactoryError::MemoryError {
+        error: MemoryError::OutOfMemory,
+        resource: ResourceInfo::Shared {
+            count: 2,
+            size: 512,
+        },
+    };
+    assert_eq!(error.source().unwrap().to_string(), "Out of memory");
+}
+
+#[test]
+fn test_transfer_families() {
+    let mut item = Item {
+        raw: (),
+        block: (),
+        family: None,
+        info: ResourceInfo::Buffer {
+            size: 256,
+            usage: BufferUsage::VERTEX,
+        },
+    };
+    assert_eq!(item.transfer_families(QueueFamilyId(1)), None);
+    assert_eq!(item.family(), Some(QueueFamilyId(1)));
+    assert_eq!(item.transfer_families(QueueFamilyId(1)), None);
+    assert_eq!(
+        item.transfer_families(QueueFamilyId(0)),
+        Some(QueueFamilyId(1)..QueueFamilyId(0))
+    );
+    assert_eq!(item.family(), Some(QueueFamilyId(0)));
+}
+
+#[test]
+fn test_factory_impl() {
+    use block::RawBlock;
+    use gfx_backend_empty::Backend as Empty;
+    type Buffer = Item<<Empty as Backend>::Buffer, RawBlock<<Empty as Backend>::Memory>>;
+    type Image = Item<<Empty as Backend>::Image, RawBlock<<Empty as Backend>::Memory>>;
+
+    // Factories only have to create and destroy resources, this one is always out of memory
+    struct Minimal;
+    impl Factory<Empty> for Minimal {
+        type Buffer = Buffer;
+        type Image = Image;
+        type BufferRequest = ();
+        type ImageRequest = ();
+        type Error = FactoryError;
+
+        unsafe fn create_buffer(
+            &mut self,
+            _: &<Empty as Backend>::Device,
+            _: (),
+            size: u64,
+            usage: BufferUsage,
+        ) -> Result<Buffer, FactoryError> {
+            Err(FactoryError::MemoryError {
+                error: MemoryError::OutOfMemory,
+                resource: ResourceInfo::Buffer { size, usage },
+            })
+        }
+
+        unsafe fn create_image(
+            &mut self,
+            _: &<Empty as Backend>::Device,
+            _: (),
+            kind: Kind,
+            level: Level,
+            format: Format,
+            tiling: Tiling,
+            usage: ImageUsage,
+            view_caps: ViewCapabilities,
+        ) -> Result<Image, FactoryError> {
+            Err(FactoryError::MemoryError {
+                error: MemoryError::OutOfMemory,
+                resource: ResourceInfo::Image(ImageInfo {
+                    kind,
+                    level,
+                    format,
+                    tiling,
+                    usage,
+                    view_caps,
+                }),
+            })
+        }
+
+        unsafe fn destroy_buffer(&mut self, _: &<Empty as Backend>::Device, _: Buffer) {}
+
+        unsafe fn destroy_image(&mut self, _: &<Empty as Backend>::Device, _: Image) {}
+    }
+
+    let device = ::gfx_backend_empty::Device;
+    let buffer = unsafe { Minimal.create_buffer(&device, (), 256, BufferUsage::VERTEX) };
+    match buffer {
+        Err(FactoryError::MemoryError {
+            resource: ResourceInfo::Buffer { size: 256, .. },
+            ..
+        }) => {}
+        r => panic!("Unexpected result {:?}", r.map(|_| ())),
+    }
+
+    // Allocators recreate resources of their blanket factory
+    fn recreate<B: Backend, F: RecreateFactory<B>>() {}
+    #[allow(dead_code)]
+    fn allocators<B: Backend>() {
+        recreate::<B, ::SmartAllocator<B>>();
+        recreate::<B, ::CombinedAllocator<B>>();
+    }
+}
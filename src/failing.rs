@@ -0,0 +1,160 @@
+use gfx_hal::memory::Requirements;
+use gfx_hal::Backend;
+
+use {MemoryAllocator, MemoryError};
+
+/// Allocator that fails allocations on purpose, for testing.
+///
+/// Wraps another allocator and can be programmed to fail the Nth allocation or all allocations
+/// above a size, so that handling of out of memory errors can be tested deterministically
+/// without exhausting a real device. All other allocations, frees and disposal are forwarded
+/// to the wrapped allocator.
+///
+/// ### Type parameters:
+///
+/// - `A`: wrapped allocator
+#[derive(Debug)]
+pub struct FailingAllocator<A> {
+    allocator: A,
+    attempts: u64,
+    failures: u64,
+    fail_nth: Option<u64>,
+    fail_above: Option<u64>,
+    error: MemoryError,
+}
+
+impl<A> FailingAllocator<A> {
+    /// Wrap an allocator. No allocations fail until programmed to.
+    pub fn new(allocator: A) -> Self {
+        FailingAllocator {
+            allocator,
+            attempts: 0,
+            failures: 0,
+            fail_nth: None,
+            fail_above: None,
+            error: MemoryError::OutOfMemory,
+        }
+    }
+
+    /// Fail the `n`th allocation, counting from 1 and including allocations already made.
+    /// `None` stops failing it.
+    pub fn set_fail_nth(&mut self, n: Option<u64>) {
+        self.fail_nth = n;
+    }
+
+    /// Fail all allocations bigger than `size` bytes. `None` stops failing them.
+    pub fn set_fail_above(&mut self, size: Option<u64>) {
+        self.fail_above = size;
+    }
+
+    /// Set the error failed allocations return. Defaults to `MemoryError::OutOfMemory`.
+    pub fn set_error(&mut self, error: MemoryError) {
+        self.error = error;
+    }
+
+    /// Get the number of allocations attempted so far, including failed ones.
+    pub fn attempts(&self) -> u64 {
+        self.attempts
+    }
+
+    /// Get the number of allocations failed on purpose so far.
+    pub fn failures(&self) -> u64 {
+        self.failures
+    }
+
+    /// Get the wrapped allocator.
+    pub fn inner(&self) -> &A {
+        &self.allocator
+    }
+
+    /// Get the wrapped allocator mutably.
+    pub fn inner_mut(&mut self) -> &mut A {
+        &mut self.allocator
+    }
+
+    /// Unwrap the allocator.
+    pub fn into_inner(self) -> A {
+        self.allocator
+    }
+
+    /// Count an allocation attempt and check if it should fail.
+    fn fail(&mut self, size: u64) -> Result<(), MemoryError> {
+        self.attempts += 1;
+        let nth = self.fail_nth == Some(self.attempts);
+        let above = self.fail_above.map_or(false, |max| size > max);
+        if nth || above {
+            self.failures += 1;
+            Err(self.error.clone())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<B, A> MemoryAllocator<B> for FailingAllocator<A>
+where
+    B: Backend,
+    A: MemoryAllocator<B>,
+{
+    type Request = A::Request;
+    type Block = A::Block;
+
+    unsafe fn alloc(
+        &mut self,
+        device: &B::Device,
+        request: A::Request,
+        reqs: Requirements,
+    ) -> Result<A::Block, MemoryError> {
+        self.fail(reqs.size)?;
+        self.allocator.alloc(device, request, reqs)
+    }
+
+    unsafe fn free(&mut self, device: &B::Device, block: A::Block) {
+        self.allocator.free(device, block)
+    }
+
+    fn is_used(&self) -> bool {
+        self.allocator.is_used()
+    }
+
+    unsafe fn dispose(self, device: &B::Device) -> Result<(), Self> {
+        let FailingAllocator {
+            allocator,
+            attempts,
+            failures,
+            fail_nth,
+            fail_above,
+            error,
+        } = self;
+        allocator
+            .dispose(device)
+            .map_err(|allocator| FailingAllocator {
+                allocator,
+                attempts,
+                failures,
+                fail_nth,
+                fail_above,
+                error,
+            })
+    }
+}
+
+#[test]
+fn test_fail() {
+    let mut allocator = FailingAllocator::new(());
+    allocator.set_fail_nth(Some(2));
+    allocator.set_fail_above(Some(1024));
+    assert!(allocator.fail(16).is_ok());
+    match allocator.fail(16) {
+        Err(MemoryError::OutOfMemory) => {}
+        other => panic!("Expected out of memory, got {:?}", other),
+    }
+    assert!(allocator.fail(1024).is_ok());
+    allocator.set_error(MemoryError::TooManyObjects);
+    match allocator.fail(1025) {
+        Err(MemoryError::TooManyObjects) => {}
+        other => panic!("Expected too many objects, got {:?}", other),
+    }
+    assert_eq!(allocator.attempts(), 4);
+    assert_eq!(allocator.failures(), 2);
+}
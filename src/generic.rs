@@ -0,0 +1,421 @@
+use std::fmt::{self, Debug};
+use std::ops::Range;
+
+use gfx_hal::memory::{Properties, Requirements};
+use gfx_hal::{Backend, MemoryProperties, MemoryType, MemoryTypeId};
+
+use arena::ArenaAllocator;
+use block::{Block, RawBlock};
+use chunked::ChunkedAllocator;
+use label::Pool;
+use root::RootAllocator;
+use {check_requirements, MemoryAllocator, MemoryError, MemorySubAllocator};
+
+/// Allocator owning a sub-allocator along with the root allocator it allocates chunks from.
+///
+/// Adapts any `MemorySubAllocator` of a single memory type into a `MemoryAllocator`.
+///
+/// ### Type parameters:
+///
+/// - `B`: hal `Backend`
+/// - `S`: sub-allocator allocating from the `RootAllocator`
+pub struct OwningAllocator<B: Backend, S> {
+    root: RootAllocator<B>,
+    sub: S,
+    /// Number of live blocks
+    blocks: usize,
+}
+
+impl<B, S> Debug for OwningAllocator<B, S>
+where
+    B: Backend,
+    S: Debug,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("OwningAllocator")
+            .field("root", &self.root)
+            .field("sub", &self.sub)
+            .field("blocks", &self.blocks)
+            .finish()
+    }
+}
+
+impl<B, S> OwningAllocator<B, S>
+where
+    B: Backend,
+{
+    /// Create an allocator from a sub-allocator of memory type `memory_type_id`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `memory_type_id`: memory type chunks are allocated from
+    /// - `pool`: kind of the chunks of `sub`, for labels of their memory objects
+    /// - `sub`: sub-allocator of the same memory type
+    pub fn new(memory_type_id: MemoryTypeId, pool: Pool, sub: S) -> Self {
+        let mut root = RootAllocator::new(memory_type_id);
+        root.set_pool(pool);
+        OwningAllocator {
+            root,
+            sub,
+            blocks: 0,
+        }
+    }
+
+    /// Get the sub-allocator.
+    pub fn sub_allocator(&self) -> &S {
+        &self.sub
+    }
+
+    /// Get the root allocator chunks are allocated from.
+    pub fn root(&self) -> &RootAllocator<B> {
+        &self.root
+    }
+}
+
+impl<B, S> MemoryAllocator<B> for OwningAllocator<B, S>
+where
+    B: Backend,
+    S: MemorySubAllocator<B, RootAllocator<B>> + Debug,
+{
+    type Request = S::Request;
+    type Block = S::Block;
+
+    unsafe fn alloc(
+        &mut self,
+        device: &B::Device,
+        request: S::Request,
+        reqs: Requirements,
+    ) -> Result<S::Block, MemoryError> {
+        let block = self.sub.alloc(&mut self.root, device, request, reqs)?;
+        self.blocks += 1;
+        Ok(block)
+    }
+
+    unsafe fn free(&mut self, device: &B::Device, block: S::Block) {
+        self.blocks -= 1;
+        self.sub.free(&mut self.root, device, block)
+    }
+
+    fn is_used(&self) -> bool {
+        self.blocks != 0
+    }
+
+    unsafe fn dispose(self, device: &B::Device) -> Result<(), Self> {
+        let OwningAllocator {
+            mut root,
+            sub,
+            blocks,
+        } = self;
+        if blocks != 0 {
+            return Err(OwningAllocator { root, sub, blocks });
+        }
+        match sub.dispose(&mut root, device) {
+            Ok(()) => {
+                if root.dispose(device).is_err() {
+                    panic!("Root allocator is used although its sub-allocator was disposed");
+                }
+                Ok(())
+            }
+            Err(sub) => Err(OwningAllocator { root, sub, blocks }),
+        }
+    }
+}
+
+/// Allocator choosing memory types like `SmartAllocator`, with one allocator of type `A` per
+/// memory type.
+///
+/// Requests only name the required memory properties, the type and lifetime of blocks are
+/// given by `A`. See `ArenaSmartAllocator` and `ChunkedSmartAllocator`.
+///
+/// ### Type parameters:
+///
+/// - `A`: allocator of each memory type
+#[derive(Debug)]
+pub struct GenericSmartAllocator<A> {
+    allocators: Vec<(MemoryType, A)>,
+    /// Size and used size of each heap
+    heaps: Vec<(u64, u64)>,
+}
+
+/// Smart allocator placing all blocks in arenas.
+///
+/// For purely transient workloads, e.g. video processing, where every block is freed within
+/// a few frames. See `GenericSmartAllocator::arena`.
+pub type ArenaSmartAllocator<B> =
+    GenericSmartAllocator<OwningAllocator<B, ArenaAllocator<RawBlock<<B as Backend>::Memory>>>>;
+
+/// Smart allocator placing all blocks in chunks.
+///
+/// For purely pooled workloads, e.g. static scenes, where blocks are never freed in bulk.
+/// See `GenericSmartAllocator::chunked`.
+pub type ChunkedSmartAllocator<B> =
+    GenericSmartAllocator<OwningAllocator<B, ChunkedAllocator<RawBlock<<B as Backend>::Memory>>>>;
+
+impl<A> GenericSmartAllocator<A> {
+    /// Create a new generic smart allocator from `MemoryProperties` given by a device.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `memory_properties`: memory properties describing the memory available on a device
+    /// - `allocator`: creates the allocator of each memory type
+    pub fn new<F>(memory_properties: MemoryProperties, mut allocator: F) -> Self
+    where
+        F: FnMut(MemoryTypeId) -> A,
+    {
+        GenericSmartAllocator {
+            allocators: memory_properties
+                .memory_types
+                .into_iter()
+                .enumerate()
+                .map(|(index, memory_type)| (memory_type, allocator(MemoryTypeId(index))))
+                .collect(),
+            heaps: memory_properties
+                .memory_heaps
+                .into_iter()
+                .map(|size| (size, 0))
+                .collect(),
+        }
+    }
+
+    /// Get the allocator of a memory type.
+    pub fn allocator(&self, memory_type: MemoryTypeId) -> Option<&A> {
+        self.allocators
+            .get(memory_type.0)
+            .map(|&(_, ref allocator)| allocator)
+    }
+
+    /// Choose the memory type with the required properties whose heap is least used.
+    fn choose(&self, prop: Properties, reqs: Requirements) -> Result<usize, MemoryError> {
+        check_requirements(&reqs)?;
+        let mut compatible = false;
+        let mut candidate: Option<(usize, f32)> = None;
+        for (index, &(memory_type, _)) in self.allocators.iter().enumerate() {
+            if (1 << index) & reqs.type_mask == 0 || !memory_type.properties.contains(prop) {
+                continue;
+            }
+            compatible = true;
+            let (size, used) = self.heaps[memory_type.heap_index];
+            if size.saturating_sub(used) < reqs.size.saturating_add(reqs.alignment) {
+                continue;
+            }
+            let usage = used as f32 / size as f32;
+            if candidate.map_or(true, |(_, best)| usage < best) {
+                candidate = Some((index, usage));
+            }
+        }
+        match candidate {
+            Some((index, _)) => Ok(index),
+            None if compatible => Err(MemoryError::OutOfMemory),
+            None => Err(MemoryError::NoCompatibleMemoryType),
+        }
+    }
+}
+
+impl<B> ArenaSmartAllocator<B>
+where
+    B: Backend,
+{
+    /// Create a new smart allocator placing all blocks in arenas.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `memory_properties`: memory properties describing the memory available on a device
+    /// - `arena_chunk_size`: see `ArenaAllocator`
+    pub fn arena(memory_properties: MemoryProperties, arena_chunk_size: u64) -> Self {
+        Self::new(memory_properties, |id| {
+            OwningAllocator::new(id, Pool::Arena, ArenaAllocator::new(id, arena_chunk_size))
+        })
+    }
+}
+
+impl<B> ChunkedSmartAllocator<B>
+where
+    B: Backend,
+{
+    /// Create a new smart allocator placing all blocks in chunks.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `memory_properties`: memory properties describing the memory available on a device
+    /// - `blocks_per_chunk`: see `ChunkedAllocator`
+    /// - `min_block_size`: see `ChunkedAllocator`
+    /// - `max_chunk_size`: see `ChunkedAllocator`
+    pub fn chunked(
+        memory_properties: MemoryProperties,
+        blocks_per_chunk: usize,
+        min_block_size: u64,
+        max_chunk_size: u64,
+    ) -> Self {
+        Self::new(memory_properties, |id| {
+            OwningAllocator::new(
+                id,
+                Pool::Chunked,
+                ChunkedAllocator::new(id, blocks_per_chunk, min_block_size, max_chunk_size),
+            )
+        })
+    }
+}
+
+impl<B, A> MemoryAllocator<B> for GenericSmartAllocator<A>
+where
+    B: Backend,
+    A: MemoryAllocator<B, Request = ()>,
+{
+    type Request = Properties;
+    type Block = GenericBlock<A::Block>;
+
+    unsafe fn alloc(
+        &mut self,
+        device: &B::Device,
+        request: Properties,
+        reqs: Requirements,
+    ) -> Result<GenericBlock<A::Block>, MemoryError> {
+        let index = self.choose(request, reqs)?;
+        let block = self.allocators[index].1.alloc(device, (), reqs)?;
+        let heap = &mut self.heaps[self.allocators[index].0.heap_index];
+        heap.1 = heap.1.saturating_add(block.size());
+        Ok(GenericBlock {
+            block,
+            memory_type: index,
+        })
+    }
+
+    unsafe fn free(&mut self, device: &B::Device, block: GenericBlock<A::Block>) {
+        let heap = &mut self.heaps[self.allocators[block.memory_type].0.heap_index];
+        heap.1 = heap.1.saturating_sub(block.block.size());
+        self.allocators[block.memory_type]
+            .1
+            .free(device, block.block)
+    }
+
+    fn is_used(&self) -> bool {
+        self.allocators
+            .iter()
+            .any(|&(_, ref allocator)| allocator.is_used())
+    }
+
+    unsafe fn dispose(mut self, device: &B::Device) -> Result<(), Self> {
+        if MemoryAllocator::<B>::is_used(&self) {
+            return Err(self);
+        }
+        for (_, allocator) in self.allocators.drain(..) {
+            if let Err(_allocator) = allocator.dispose(device) {
+                panic!("Allocator failed to dispose although it isn't used");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Block allocated by a `GenericSmartAllocator`.
+#[derive(Debug)]
+pub struct GenericBlock<T> {
+    block: T,
+    memory_type: usize,
+}
+
+impl<T> GenericBlock<T> {
+    /// Get the memory type the block was allocated from.
+    pub fn memory_type(&self) -> MemoryTypeId {
+        MemoryTypeId(self.memory_type)
+    }
+
+    /// Get the block allocated by the allocator of the memory type.
+    pub fn inner(&self) -> &T {
+        &self.block
+    }
+}
+
+impl<T> Block for GenericBlock<T>
+where
+    T: Block,
+{
+    type Memory = T::Memory;
+
+    #[inline]
+    fn memory(&self) -> &T::Memory {
+        self.block.memory()
+    }
+
+    #[inline]
+    fn range(&self) -> Range<u64> {
+        self.block.range()
+    }
+}
+
+#[test]
+fn test_choose() {
+    let mut allocator = GenericSmartAllocator::new(
+        MemoryProperties {
+            memory_types: vec![
+                MemoryType {
+                    properties: Properties::DEVICE_LOCAL,
+                    heap_index: 0,
+                },
+                MemoryType {
+                    properties: Properties::DEVICE_LOCAL,
+                    heap_index: 1,
+                },
+                MemoryType {
+                    properties: Properties::CPU_VISIBLE,
+                    heap_index: 2,
+                },
+            ],
+            memory_heaps: vec![1024, 1024, 1024],
+        },
+        |_| (),
+    );
+    allocator.heaps[0].1 = 512;
+    let reqs = Requirements {
+        size: 256,
+        alignment: 16,
+        type_mask: !0,
+    };
+
+    // The least used heap is chosen among compatible memory types
+    assert_eq!(allocator.choose(Properties::DEVICE_LOCAL, reqs).unwrap(), 1);
+    let reqs_first = Requirements {
+        type_mask: 0b1,
+        ..reqs
+    };
+    assert_eq!(
+        allocator
+            .choose(Properties::DEVICE_LOCAL, reqs_first)
+            .unwrap(),
+        0
+    );
+    assert_eq!(allocator.choose(Properties::CPU_VISIBLE, reqs).unwrap(), 2);
+    let too_large = Requirements { size: 1024, ..reqs };
+    match allocator.choose(Properties::CPU_VISIBLE, too_large) {
+        Err(MemoryError::OutOfMemory) => {}
+        result => panic!("Unexpected result {:?}", result),
+    }
+    match allocator.choose(Properties::CPU_CACHED, reqs) {
+        Err(MemoryError::NoCompatibleMemoryType) => {}
+        result => panic!("Unexpected result {:?}", result),
+    }
+}
+
+#[test]
+fn test_aliases() {
+    use gfx_backend_empty::{Backend as Empty, Device};
+    let properties = || MemoryProperties {
+        memory_types: vec![MemoryType {
+            properties: Properties::DEVICE_LOCAL,
+            heap_index: 0,
+        }],
+        memory_heaps: vec![1 << 30],
+    };
+    let arena = ArenaSmartAllocator::<Empty>::arena(properties(), 1 << 20);
+    assert!(!MemoryAllocator::<Empty>::is_used(&arena));
+    assert!(unsafe { arena.dispose(&Device) }.is_ok());
+
+    let chunked = ChunkedSmartAllocator::<Empty>::chunked(properties(), 32, 256, 1 << 26);
+    assert_eq!(
+        chunked
+            .allocator(MemoryTypeId(0))
+            .map(|allocator| allocator.sub_allocator().max_chunk_size()),
+        Some(1 << 26)
+    );
+    assert!(unsafe { chunked.dispose(&Device) }.is_ok());
+}
@@ -0,0 +1,28 @@
+//! Guard for values that must not be dropped.
+//!
+//! By default this is `relevant::Relevant`, which panics when dropped. With the `log-leaks`
+//! feature enabled, dropping a guarded value logs an error instead, so applications that
+//! prefer leaking to aborting can continue.
+
+#[cfg(not(feature = "log-leaks"))]
+pub(crate) use relevant::Relevant;
+
+/// Values of this type should not be dropped, but only an error is logged if they are.
+#[cfg(feature = "log-leaks")]
+#[derive(Clone, Debug, PartialOrd, PartialEq, Ord, Eq, Hash)]
+pub(crate) struct Relevant;
+
+#[cfg(feature = "log-leaks")]
+impl Relevant {
+    /// Dispose this value.
+    pub(crate) fn dispose(self) {
+        ::std::mem::forget(self)
+    }
+}
+
+#[cfg(feature = "log-leaks")]
+impl Drop for Relevant {
+    fn drop(&mut self) {
+        error!("Value that must be returned to its allocator was dropped");
+    }
+}
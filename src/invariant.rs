@@ -0,0 +1,44 @@
+use std::error::Error;
+use std::fmt;
+
+/// Internal inconsistency of an allocator found by `check_invariants`.
+///
+/// Allocators never produce these unless there is a bug in this crate or blocks were freed
+/// into the wrong allocator, so they are meant to be reported rather than handled.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InvariantViolation {
+    /// Allocator the inconsistency was found in, e.g. `type 2 chunked` or `heap 1`.
+    pub allocator: String,
+    /// Description of the inconsistency.
+    pub message: String,
+}
+
+impl InvariantViolation {
+    pub(crate) fn new<A, M>(allocator: A, message: M) -> Self
+    where
+        A: fmt::Display,
+        M: Into<String>,
+    {
+        InvariantViolation {
+            allocator: allocator.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}: {}", self.allocator, self.message)
+    }
+}
+
+impl Error for InvariantViolation {}
+
+#[test]
+fn test_display() {
+    let violation = InvariantViolation::new("heap 1", "used 256 bytes but blocks sum to 512");
+    assert_eq!(
+        violation.to_string(),
+        "heap 1: used 256 bytes but blocks sum to 512"
+    );
+}
@@ -0,0 +1,110 @@
+use std::fmt;
+use std::sync::Arc;
+
+use gfx_hal::MemoryTypeId;
+
+/// Kind of allocation a memory object was allocated from the device for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Pool {
+    /// Chunk of an `ArenaAllocator`.
+    Arena,
+    /// Chunk of a `ChunkedAllocator`.
+    Chunked,
+    /// Dedicated allocation of a single block.
+    Dedicated,
+}
+
+/// Description of a memory object allocated from the device.
+///
+/// Passed to the labeling callback so that debuggers and validation layers can show
+/// meaningful names for memory objects.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MemoryLabel {
+    /// Kind of allocation the memory object was allocated for.
+    pub pool: Pool,
+    /// Memory type of the memory object.
+    pub memory_type: MemoryTypeId,
+    /// Sequential number of the memory object within its memory type.
+    pub index: u64,
+}
+
+impl fmt::Display for MemoryLabel {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let pool = match self.pool {
+            Pool::Arena => "arena",
+            Pool::Chunked => "chunked",
+            Pool::Dedicated => "dedicated",
+        };
+        write!(
+            fmt,
+            "gfx-memory/{}/type{}/{}",
+            pool, self.memory_type.0, self.index
+        )
+    }
+}
+
+/// Location of a block in a labeled memory object.
+///
+/// Lets blocks be correlated with memory objects and offsets shown in graphics debugger
+/// captures. Displayed as `<label>+0x<offset>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BlockLocation {
+    /// Label of the memory object the block is in.
+    pub label: MemoryLabel,
+    /// Offset of the block in the memory object.
+    pub offset: u64,
+    /// Size of the block in bytes.
+    pub size: u64,
+}
+
+impl fmt::Display for BlockLocation {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}+{:#x}", self.label, self.offset)
+    }
+}
+
+type Callback<M> = Arc<dyn Fn(&M, &MemoryLabel) + Send + Sync>;
+
+/// Callback labeling memory objects, shared between allocators of all memory types.
+pub(crate) struct Labeler<M>(Callback<M>);
+
+impl<M> Labeler<M> {
+    pub(crate) fn new<F>(callback: F) -> Self
+    where
+        F: Fn(&M, &MemoryLabel) + Send + Sync + 'static,
+    {
+        Labeler(Arc::new(callback))
+    }
+
+    pub(crate) fn label(&self, memory: &M, label: &MemoryLabel) {
+        (self.0)(memory, label)
+    }
+}
+
+impl<M> Clone for Labeler<M> {
+    fn clone(&self) -> Self {
+        Labeler(self.0.clone())
+    }
+}
+
+impl<M> fmt::Debug for Labeler<M> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str("Labeler(..)")
+    }
+}
+
+#[test]
+fn test_display() {
+    let label = MemoryLabel {
+        pool: Pool::Chunked,
+        memory_type: MemoryTypeId(3),
+        index: 12,
+    };
+    assert_eq!(label.to_string(), "gfx-memory/chunked/type3/12");
+    let location = BlockLocation {
+        label,
+        offset: 256,
+        size: 64,
+    };
+    assert_eq!(location.to_string(), "gfx-memory/chunked/type3/12+0x100");
+}
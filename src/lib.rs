@@ -5,28 +5,26 @@
 //! ```rust
 //! extern crate gfx_hal;
 //! extern crate gfx_memory;
-//! extern crate failure;
-//!
-//! use failure::Error;
 //!
 //! use gfx_hal::{Backend, Device};
 //! use gfx_hal::buffer::Usage;
-//! use gfx_hal::memory::Properties;
-//! use gfx_memory::{MemoryAllocator, SmartAllocator, SmartBlock, Type, Block};
+//! use gfx_memory::{MemoryAllocator, Request, SmartAllocator, SmartBlock, Block};
 //!
 //! fn make_vertex_buffer<B: Backend>(device: &B::Device,
 //!                                   allocator: &mut SmartAllocator<B>,
 //!                                   size: u64
-//! ) -> Result<(SmartBlock<B::Memory>, B::Buffer), Error>
+//! ) -> Result<(SmartBlock<B::Memory>, B::Buffer), Box<dyn std::error::Error>>
 //! {
 //!     // Create unbounded buffer object. It has no memory assigned.
-//!     let mut buf = unsafe { device.create_buffer(size, Usage::VERTEX)? };
+//!     let mut buf = unsafe { device.create_buffer(size, Usage::VERTEX) }
+//!         .map_err(|err| err.to_string())?;
 //!     // Ger memory requirements for the buffer.
 //!     let reqs = unsafe { device.get_buffer_requirements(&buf) };
 //!     // Allocate block of device-local memory that satisfy requirements for buffer.
-//!     let block = unsafe { allocator.alloc(device, (Type::General, Properties::DEVICE_LOCAL), reqs)? };
+//!     let block = unsafe { allocator.alloc(device, Request::general().device_local(), reqs)? };
 //!     // Bind memory block to the buffer.
-//!     unsafe { device.bind_buffer_memory(block.memory(), block.range().start, &mut buf)? };
+//!     unsafe { device.bind_buffer_memory(block.memory(), block.range().start, &mut buf) }
+//!         .map_err(|err| err.to_string())?;
 //!     Ok((block, buf))
 //! }
 //!
@@ -38,50 +36,257 @@
 #![deny(unused_imports)]
 #![deny(unused_must_use)]
 
+#[cfg(test)]
+extern crate gfx_backend_empty;
 extern crate gfx_hal;
+#[cfg(feature = "log")]
 #[macro_use]
-extern crate failure;
+extern crate log;
 extern crate relevant;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+extern crate smallvec;
 
-pub use arena::{ArenaAllocator, ArenaBlock};
-pub use block::{Block, RawBlock};
+pub use arena::{ArenaAllocator, ArenaBlock, ArenaGrowth, ArenaRounding};
+pub use block::{Block, CompactBlock, RawBlock, SharedBlock};
+pub use buffer_pool::{BufferPool, PooledBuffer};
 pub use chunked::{ChunkedAllocator, ChunkedBlock};
+pub use class::{MemoryClass, MemoryTypeInfo};
 pub use combined::{CombinedAllocator, CombinedBlock, Type};
-pub use factory::{Factory, FactoryError, Item};
+pub use config::SmartAllocatorConfig;
+pub use domain::{Domain, DomainStats};
+pub use epoch::{Epoch, EpochKey, Epochs};
+pub use event::Event;
+pub use factory::{
+    Factory, FactoryError, ImageInfo, Item, RecreateFactory, ResourceInfo, SharedFactory,
+};
+pub use failing::FailingAllocator;
+pub use generic::{
+    ArenaSmartAllocator, ChunkedSmartAllocator, GenericBlock, GenericSmartAllocator,
+    OwningAllocator,
+};
+pub use invariant::InvariantViolation;
+pub use label::{BlockLocation, MemoryLabel, Pool};
+pub use mapped::MappedBlock;
+pub use metadata::{AgedBlock, BlockMetadata};
+pub use misuse::{Misuse, MisusePolicy};
+pub use readback_heap::ReadbackHeap;
+pub use render_target::RenderTargetCache;
+pub use request::{Group, Lifetime, Request};
 pub use root::RootAllocator;
-pub use smart::{SmartAllocator, SmartBlock};
+pub use sharded::{ShardedAllocator, ShardedBlock};
+pub use simulate::{
+    compare_pools, AllocationTrace, PoolComparison, SimulatedPool, SimulationResult, TraceOp,
+    TraceRecorder,
+};
+pub use smart::{
+    HeapSelection, Migration, Preference, Reservation, RetryPolicy, SmartAllocator, SmartBlock,
+    SpillPolicy, TrimPolicy,
+};
+pub use stats::{
+    AllocationFailure, AllocatorConfig, ChunkInfo, ChunkStats, FrameStats, HeapUsage, Leak,
+    LeakReport, MemoryTypeConfig, SizeHistogram,
+};
+pub use transaction::{Resources, SmartResources, Transaction};
+pub use tuning::{
+    ConfigError, DedicatedThreshold, Slack, TuningProfile, DEDICATED_THRESHOLD_DIVISOR,
+    MAX_NON_COHERENT_ATOM_SIZE,
+};
+pub use upload::{
+    StreamTarget, UploadCoordinator, UploadError, UploadLoader, UploadTarget, Uploader,
+};
+pub use upload_heap::UploadHeap;
 
 use std::cmp::PartialOrd;
-use std::fmt::Debug;
+use std::error::Error;
+use std::fmt::{self, Debug};
 use std::ops::{Add, BitOr, Sub};
 
 use gfx_hal::device::AllocationError;
 use gfx_hal::device::OutOfMemory;
-use gfx_hal::memory::Requirements;
+use gfx_hal::memory::{Properties, Requirements};
 use gfx_hal::Backend;
 
+pub mod align;
 mod arena;
 mod block;
+mod buffer_pool;
 mod chunked;
+mod class;
 mod combined;
+mod config;
+mod domain;
+mod epoch;
+mod event;
 mod factory;
+mod failing;
+mod generic;
+mod guard;
+mod invariant;
+mod label;
+mod mapped;
+mod marker;
+mod metadata;
+mod misuse;
+mod owner;
+mod readback_heap;
+mod render_target;
+mod request;
 mod root;
+mod sharded;
+mod simulate;
 mod smart;
+mod stats;
+mod transaction;
+mod tuning;
+mod upload;
+mod upload_heap;
 
 /// Possible errors that may be returned from allocators.
-#[derive(Clone, Debug, Fail)]
+#[derive(Clone, Debug)]
 pub enum MemoryError {
     /// Allocator doesn't have compatible memory types.
-    #[fail(display = "No compatible memory found")]
     NoCompatibleMemoryType,
 
     /// All compatible memory is exhausted.
-    #[fail(display = "Out of memory")]
     OutOfMemory,
 
     /// Implementations might have a limit on number of allocations
-    #[fail(display = "Can't allocate more objects")]
     TooManyObjects,
+
+    /// Requirements passed to the allocator are invalid.
+    InvalidRequirements(InvalidRequirements),
+
+    /// The allocation can't be served without allocating memory from the device.
+    WouldGrow,
+
+    /// The block doesn't fit into the budget of its domain.
+    OverBudget(Domain),
+
+    /// The block is bigger than the largest memory object allowed for the heap.
+    TooLarge {
+        /// Requested size
+        size: u64,
+        /// Maximum size of a single memory object
+        max: u64,
+    },
+
+    /// No memory type is allowed by the type mask and has the required properties.
+    ///
+    /// Returned by `SmartAllocator` before trying any memory type, with the offending
+    /// requirements and the properties of all memory types for logging.
+    Incompatible(Box<IncompatibleMemory>),
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MemoryError::NoCompatibleMemoryType => fmt.write_str("No compatible memory found"),
+            MemoryError::OutOfMemory => fmt.write_str("Out of memory"),
+            MemoryError::TooManyObjects => fmt.write_str("Can't allocate more objects"),
+            MemoryError::WouldGrow => fmt.write_str("Allocation requires device memory"),
+            MemoryError::OverBudget(domain) => {
+                write!(fmt, "Allocation exceeds the budget of domain {}", domain.0)
+            }
+            MemoryError::TooLarge { size, max } => write!(
+                fmt,
+                "Allocation of {} bytes exceeds the maximum of {} bytes",
+                size, max
+            ),
+            MemoryError::InvalidRequirements(ref invalid) => {
+                write!(fmt, "Invalid memory requirements: {}", invalid)
+            }
+            MemoryError::Incompatible(ref incompatible) => {
+                write!(fmt, "No compatible memory type: {}", incompatible)
+            }
+        }
+    }
+}
+
+impl Error for MemoryError {}
+
+/// Requirements no memory type satisfies, see `MemoryError::Incompatible`.
+///
+/// Printing it with `Display` lists each memory type with whether the type mask allows it and
+/// which required properties it lacks.
+#[derive(Clone, Debug)]
+pub struct IncompatibleMemory {
+    /// Requirements of the block.
+    pub reqs: Requirements,
+    /// Properties required by the request.
+    pub properties: Properties,
+    /// Properties of each memory type, by memory type index.
+    pub memory_types: Vec<Properties>,
+}
+
+impl fmt::Display for IncompatibleMemory {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "{} bytes aligned to {} with type mask {:#b} and properties {:?}",
+            self.reqs.size, self.reqs.alignment, self.reqs.type_mask, self.properties
+        )?;
+        for (index, &properties) in self.memory_types.iter().enumerate() {
+            write!(fmt, "; type {} ({:?}): ", index, properties)?;
+            let allowed = (1u64 << index) & self.reqs.type_mask != 0;
+            let missing = self.properties - properties;
+            match (allowed, missing.is_empty()) {
+                (false, _) => fmt.write_str("not in type mask")?,
+                (true, false) => write!(fmt, "lacks {:?}", missing)?,
+                (true, true) => fmt.write_str("compatible")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reasons for `Requirements` to be rejected by `validate_requirements`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InvalidRequirements {
+    /// Alignment is not a power of two.
+    ///
+    /// Allocators always reject such requirements, since power-of-two alignment is assumed
+    /// throughout and any other alignment would silently produce misaligned blocks.
+    Alignment(u64),
+
+    /// Size is zero.
+    ///
+    /// Some backends report zero size for certain resources. Allocators always reject such
+    /// requirements, since device memory objects can't be empty; such resources need no memory.
+    ZeroSize,
+
+    /// Type mask doesn't allow any memory type.
+    EmptyTypeMask,
+
+    /// Size padded for alignment doesn't fit in `u64`.
+    Overflow {
+        /// Requested size
+        size: u64,
+        /// Requested alignment
+        alignment: u64,
+    },
+}
+
+impl fmt::Display for InvalidRequirements {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InvalidRequirements::Alignment(alignment) => {
+                write!(fmt, "alignment {} is not a power of two", alignment)
+            }
+            InvalidRequirements::ZeroSize => fmt.write_str("size is zero"),
+            InvalidRequirements::EmptyTypeMask => fmt.write_str("type mask is empty"),
+            InvalidRequirements::Overflow { size, alignment } => {
+                write!(fmt, "size {} with alignment {} overflows", size, alignment)
+            }
+        }
+    }
+}
+
+impl From<InvalidRequirements> for MemoryError {
+    fn from(invalid: InvalidRequirements) -> Self {
+        MemoryError::InvalidRequirements(invalid)
+    }
 }
 
 impl From<OutOfMemory> for MemoryError {
@@ -225,15 +430,145 @@ pub trait MemorySubAllocator<B: Backend, O> {
         Self: Sized;
 }
 
+/// Allocators can be used through mutable references.
+///
+/// Disposing a reference doesn't dispose the referenced allocator, which must still be disposed
+/// by its owner. It only fails if blocks allocated from the allocator are still in use.
+impl<'a, B, A> MemoryAllocator<B> for &'a mut A
+where
+    B: Backend,
+    A: MemoryAllocator<B> + ?Sized,
+{
+    type Request = A::Request;
+    type Block = A::Block;
+
+    unsafe fn alloc(
+        &mut self,
+        device: &B::Device,
+        request: A::Request,
+        reqs: Requirements,
+    ) -> Result<A::Block, MemoryError> {
+        (**self).alloc(device, request, reqs)
+    }
+
+    unsafe fn free(&mut self, device: &B::Device, block: A::Block) {
+        (**self).free(device, block)
+    }
+
+    fn is_used(&self) -> bool {
+        (**self).is_used()
+    }
+
+    unsafe fn dispose(self, _device: &B::Device) -> Result<(), Self> {
+        if self.is_used() {
+            Err(self)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<B, A> MemoryAllocator<B> for Box<A>
+where
+    B: Backend,
+    A: MemoryAllocator<B>,
+{
+    type Request = A::Request;
+    type Block = A::Block;
+
+    unsafe fn alloc(
+        &mut self,
+        device: &B::Device,
+        request: A::Request,
+        reqs: Requirements,
+    ) -> Result<A::Block, MemoryError> {
+        (**self).alloc(device, request, reqs)
+    }
+
+    unsafe fn free(&mut self, device: &B::Device, block: A::Block) {
+        (**self).free(device, block)
+    }
+
+    fn is_used(&self) -> bool {
+        (**self).is_used()
+    }
+
+    unsafe fn dispose(self, device: &B::Device) -> Result<(), Self> {
+        (*self).dispose(device).map_err(Box::new)
+    }
+}
+
+/// Sub-allocators can be used through mutable references.
+///
+/// Disposing a reference does nothing, the referenced allocator must still be disposed by its
+/// owner.
+impl<'a, B, O, S> MemorySubAllocator<B, O> for &'a mut S
+where
+    B: Backend,
+    S: MemorySubAllocator<B, O> + ?Sized,
+{
+    type Request = S::Request;
+    type Block = S::Block;
+
+    unsafe fn alloc(
+        &mut self,
+        owner: &mut O,
+        device: &B::Device,
+        request: S::Request,
+        reqs: Requirements,
+    ) -> Result<S::Block, MemoryError> {
+        (**self).alloc(owner, device, request, reqs)
+    }
+
+    unsafe fn free(&mut self, owner: &mut O, device: &B::Device, block: S::Block) {
+        (**self).free(owner, device, block)
+    }
+
+    unsafe fn dispose(self, _owner: &mut O, _device: &B::Device) -> Result<(), Self> {
+        Ok(())
+    }
+}
+
+impl<B, O, S> MemorySubAllocator<B, O> for Box<S>
+where
+    B: Backend,
+    S: MemorySubAllocator<B, O>,
+{
+    type Request = S::Request;
+    type Block = S::Block;
+
+    unsafe fn alloc(
+        &mut self,
+        owner: &mut O,
+        device: &B::Device,
+        request: S::Request,
+        reqs: Requirements,
+    ) -> Result<S::Block, MemoryError> {
+        (**self).alloc(owner, device, request, reqs)
+    }
+
+    unsafe fn free(&mut self, owner: &mut O, device: &B::Device, block: S::Block) {
+        (**self).free(owner, device, block)
+    }
+
+    unsafe fn dispose(self, owner: &mut O, device: &B::Device) -> Result<(), Self> {
+        (*self).dispose(owner, device).map_err(Box::new)
+    }
+}
+
 /// Calculate shift from specified offset required to satisfy alignment.
+#[deprecated(note = "use `align::align_offset` or `align::checked_align_offset`")]
 pub fn alignment_shift<T>(alignment: T, offset: T) -> T
 where
     T: From<u8> + Add<Output = T> + Sub<Output = T> + BitOr<Output = T> + PartialOrd + Copy,
 {
-    shift_for_alignment(alignment, offset) - offset
+    #[allow(deprecated)]
+    let aligned = shift_for_alignment(alignment, offset);
+    aligned - offset
 }
 
 /// Shift from specified offset to fulfill required alignment
+#[deprecated(note = "use `align::align_up` or `align::checked_align_up`")]
 pub fn shift_for_alignment<T>(alignment: T, offset: T) -> T
 where
     T: From<u8> + Add<Output = T> + Sub<Output = T> + BitOr<Output = T> + PartialOrd,
@@ -244,3 +579,260 @@ where
         offset
     }
 }
+
+/// Check that requirements make sense before allocating memory for them.
+///
+/// Allocators perform this check on every allocation in debug builds or with the `checks`
+/// feature enabled, instead of letting the arithmetic misbehave deep inside.
+/// Alignment and size are checked in all builds.
+pub fn validate_requirements(reqs: &Requirements) -> Result<(), InvalidRequirements> {
+    if !reqs.alignment.is_power_of_two() {
+        return Err(InvalidRequirements::Alignment(reqs.alignment));
+    }
+    if reqs.size == 0 {
+        return Err(InvalidRequirements::ZeroSize);
+    }
+    if reqs.type_mask == 0 {
+        return Err(InvalidRequirements::EmptyTypeMask);
+    }
+    if reqs.size.checked_add(reqs.alignment).is_none() {
+        return Err(InvalidRequirements::Overflow {
+            size: reqs.size,
+            alignment: reqs.alignment,
+        });
+    }
+    Ok(())
+}
+
+/// Validate requirements if checks are enabled, and alignment and size unconditionally.
+pub(crate) fn check_requirements(reqs: &Requirements) -> Result<(), MemoryError> {
+    if !reqs.alignment.is_power_of_two() {
+        return Err(InvalidRequirements::Alignment(reqs.alignment).into());
+    }
+    if reqs.size == 0 {
+        return Err(InvalidRequirements::ZeroSize.into());
+    }
+    if cfg!(any(debug_assertions, feature = "checks")) {
+        validate_requirements(reqs)?;
+    }
+    Ok(())
+}
+
+/// Round `value` up to a multiple of `granularity`, returning `None` on overflow or if
+/// `granularity` is zero.
+pub(crate) fn checked_round_up(value: u64, granularity: u64) -> Option<u64> {
+    value
+        .checked_add(granularity.checked_sub(1)?)
+        .map(|value| value / granularity * granularity)
+}
+
+/// Calculate offsets of resources placed one after another in a single block.
+///
+/// Offsets are relative to the start of the block, which must be aligned according to
+/// requirements returned by `merge_requirements` for the same resources.
+/// Offsets saturate at `u64::MAX` instead of overflowing, and for alignments that are not a
+/// power of two, which `validate_requirements` rejects.
+pub fn layout_requirements(reqs: &[Requirements]) -> Vec<u64> {
+    let mut end = 0;
+    reqs.iter()
+        .map(|reqs| {
+            let offset = align::checked_align_up(end, reqs.alignment).unwrap_or(!0);
+            end = offset.saturating_add(reqs.size);
+            offset
+        })
+        .collect()
+}
+
+/// Merge requirements of several resources so they can be placed in a single block.
+///
+/// The resulting requirements are compatible with every resource. Use `layout_requirements`
+/// to get the offset of each resource inside the block.
+pub fn merge_requirements(reqs: &[Requirements]) -> Requirements {
+    let size = layout_requirements(reqs)
+        .last()
+        .map(|offset| offset.saturating_add(reqs[reqs.len() - 1].size))
+        .unwrap_or(0);
+    Requirements {
+        size,
+        alignment: reqs.iter().map(|reqs| reqs.alignment).max().unwrap_or(1),
+        type_mask: reqs.iter().fold(!0, |mask, reqs| mask & reqs.type_mask),
+    }
+}
+
+#[test]
+fn test_merge_requirements() {
+    let reqs = [
+        Requirements {
+            size: 10,
+            alignment: 4,
+            type_mask: 0b111,
+        },
+        Requirements {
+            size: 100,
+            alignment: 64,
+            type_mask: 0b110,
+        },
+        Requirements {
+            size: 1,
+            alignment: 1,
+            type_mask: 0b011,
+        },
+    ];
+    assert_eq!(layout_requirements(&reqs), vec![0, 64, 164]);
+    let merged = merge_requirements(&reqs);
+    assert_eq!(merged.size, 165);
+    assert_eq!(merged.alignment, 64);
+    assert_eq!(merged.type_mask, 0b010);
+
+    let reqs = [
+        Requirements {
+            size: !0 - 8,
+            alignment: 1,
+            type_mask: 0b1,
+        },
+        Requirements {
+            size: 1,
+            alignment: 256,
+            type_mask: 0b1,
+        },
+    ];
+    assert_eq!(layout_requirements(&reqs), vec![0, !0]);
+    assert_eq!(merge_requirements(&reqs).size, !0);
+}
+
+#[test]
+fn test_validate_requirements() {
+    let reqs = Requirements {
+        size: 1024,
+        alignment: 256,
+        type_mask: 0b11,
+    };
+    assert_eq!(validate_requirements(&reqs), Ok(()));
+    assert_eq!(
+        validate_requirements(&Requirements {
+            alignment: 0,
+            ..reqs
+        }),
+        Err(InvalidRequirements::Alignment(0))
+    );
+    assert_eq!(
+        validate_requirements(&Requirements {
+            alignment: 48,
+            ..reqs
+        }),
+        Err(InvalidRequirements::Alignment(48))
+    );
+    assert_eq!(
+        validate_requirements(&Requirements {
+            type_mask: 0,
+            ..reqs
+        }),
+        Err(InvalidRequirements::EmptyTypeMask)
+    );
+    assert_eq!(
+        validate_requirements(&Requirements { size: !0, ..reqs }),
+        Err(InvalidRequirements::Overflow {
+            size: !0,
+            alignment: 256,
+        })
+    );
+}
+
+#[test]
+fn test_checked_round_up() {
+    assert_eq!(checked_round_up(0, 256), Some(0));
+    assert_eq!(checked_round_up(1, 256), Some(256));
+    assert_eq!(checked_round_up(768, 768), Some(768));
+    assert_eq!(checked_round_up(769, 768), Some(1536));
+    assert_eq!(checked_round_up(!0 - 10, 256), None);
+    assert_eq!(checked_round_up(100, 0), None);
+}
+
+#[test]
+fn test_check_requirements_alignment() {
+    let reqs = Requirements {
+        size: 1024,
+        alignment: 96,
+        type_mask: 0b1,
+    };
+    match check_requirements(&reqs) {
+        Err(MemoryError::InvalidRequirements(InvalidRequirements::Alignment(96))) => {}
+        other => panic!("Unexpected {:?}", other),
+    }
+    assert!(check_requirements(&Requirements {
+        alignment: 128,
+        ..reqs
+    })
+    .is_ok());
+}
+
+#[test]
+fn test_check_requirements_zero_size() {
+    let reqs = Requirements {
+        size: 0,
+        alignment: 256,
+        type_mask: 0b1,
+    };
+    match check_requirements(&reqs) {
+        Err(MemoryError::InvalidRequirements(InvalidRequirements::ZeroSize)) => {}
+        other => panic!("Unexpected {:?}", other),
+    }
+    assert_eq!(
+        validate_requirements(&reqs),
+        Err(InvalidRequirements::ZeroSize)
+    );
+}
+
+#[test]
+#[allow(dead_code)]
+fn test_send_sync() {
+    fn foo<T: Send + Sync>() {}
+    fn bar<B: Backend>() {
+        foo::<FailingAllocator<SmartAllocator<B>>>();
+        foo::<Transaction<B, SmartAllocator<B>>>();
+        foo::<RenderTargetCache<B, SmartAllocator<B>>>();
+        foo::<ShardedAllocator<SmartAllocator<B>>>();
+    }
+    foo::<SmartAllocatorConfig>();
+    foo::<AllocationFailure>();
+    foo::<FrameStats>();
+    foo::<SizeHistogram>();
+    foo::<MemoryTypeInfo>();
+    foo::<BlockMetadata>();
+    foo::<Domain>();
+    foo::<DomainStats>();
+    foo::<InvariantViolation>();
+    foo::<Misuse>();
+}
+
+#[test]
+#[allow(dead_code)]
+fn test_forwarding_impls() {
+    fn foo<B: Backend, A: MemoryAllocator<B>>() {}
+    fn bar<B: Backend, O, S: MemorySubAllocator<B, O>>() {}
+    fn baz<'a, B: Backend>() {
+        foo::<B, &'a mut SmartAllocator<B>>();
+        foo::<B, Box<CombinedAllocator<B>>>();
+        foo::<B, &'a mut &'a mut RootAllocator<B>>();
+        bar::<B, RootAllocator<B>, &'a mut ChunkedAllocator<RawBlock<B::Memory>>>();
+        bar::<B, RootAllocator<B>, Box<ArenaAllocator<RawBlock<B::Memory>>>>();
+    }
+}
+
+#[test]
+fn test_incompatible_display() {
+    let incompatible = IncompatibleMemory {
+        reqs: Requirements {
+            size: 1024,
+            alignment: 256,
+            type_mask: 0b01,
+        },
+        properties: Properties::CPU_VISIBLE,
+        memory_types: vec![Properties::DEVICE_LOCAL, Properties::CPU_VISIBLE],
+    };
+    assert_eq!(
+        incompatible.to_string(),
+        "1024 bytes aligned to 256 with type mask 0b1 and properties CPU_VISIBLE; \
+         type 0 (DEVICE_LOCAL): lacks CPU_VISIBLE; type 1 (CPU_VISIBLE): not in type mask"
+    );
+}
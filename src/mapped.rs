@@ -0,0 +1,231 @@
+use std::cmp::min;
+use std::fmt;
+use std::iter::once;
+use std::ops::Range;
+use std::slice::from_raw_parts_mut;
+
+use gfx_hal::device::OutOfMemory;
+use gfx_hal::mapping;
+use gfx_hal::{Backend, Device};
+
+use align::{align_down, align_up};
+use block::Block;
+use marker::NotSend;
+
+/// Host-visible block mapped into host memory, unmapped when dropped.
+///
+/// Mappings are tied to the thread that created them on some backends, so mapped blocks are
+/// neither `Send` nor `Sync`:
+///
+/// ```compile_fail
+/// # extern crate gfx_hal;
+/// # extern crate gfx_memory;
+/// # use gfx_memory::MappedBlock;
+/// fn send<T: Send>() {}
+/// fn check<B: gfx_hal::Backend>() {
+///     send::<MappedBlock<'static, B>>();
+/// }
+/// # fn main() {}
+/// ```
+///
+/// ### Type parameters:
+///
+/// - `B`: hal `Backend`
+pub struct MappedBlock<'a, B: Backend> {
+    device: &'a B::Device,
+    memory: &'a B::Memory,
+    range: Range<u64>,
+    /// Mapped, flushed and invalidated range, see `atom_range`
+    atoms: AtomRange,
+    /// Pointer to the start of `range`
+    ptr: *mut u8,
+    not_send: NotSend,
+}
+
+impl<'a, B> MappedBlock<'a, B>
+where
+    B: Backend,
+{
+    /// Map the whole range of a block.
+    ///
+    /// ### Safety
+    ///
+    /// `block` must be allocated from `CPU_VISIBLE` memory, and no other range of its memory
+    /// object may be mapped while the block is.
+    pub unsafe fn map<T>(device: &'a B::Device, block: &'a T) -> Result<Self, mapping::Error>
+    where
+        T: Block<Memory = B::Memory>,
+    {
+        Self::map_non_coherent(device, block, 1, None)
+    }
+
+    /// Map a block of memory that isn't `COHERENT`.
+    ///
+    /// Memory is flushed and invalidated in whole atoms, so the mapping is widened from the
+    /// start of the atom the block starts in to the end of the atom it ends in, but not past
+    /// the end of the memory object.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `block`: block to map
+    /// - `atom`: `nonCoherentAtomSize` of the device
+    /// - `memory_size`: size of the memory object of the block, if known. Otherwise blocks
+    ///                  ending inside an atom are mapped up to the end of the memory object.
+    ///
+    /// ### Safety
+    ///
+    /// `block` must be allocated from `CPU_VISIBLE` memory, and no other range of its memory
+    /// object may be mapped while the block is.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `atom` is not a power of two.
+    pub unsafe fn map_non_coherent<T>(
+        device: &'a B::Device,
+        block: &'a T,
+        atom: u64,
+        memory_size: Option<u64>,
+    ) -> Result<Self, mapping::Error>
+    where
+        T: Block<Memory = B::Memory>,
+    {
+        let range = block.range();
+        let atoms = atom_range(range.clone(), atom, memory_size);
+        let ptr = match atoms.end {
+            Some(end) => device.map_memory(block.memory(), atoms.start..end),
+            None => device.map_memory(block.memory(), atoms.start..),
+        }?;
+        Ok(MappedBlock {
+            device,
+            memory: block.memory(),
+            ptr: ptr.offset((range.start - atoms.start) as isize),
+            range,
+            atoms,
+            not_send: NotSend::default(),
+        })
+    }
+
+    /// Get the size of the block in bytes.
+    pub fn size(&self) -> u64 {
+        self.range.end - self.range.start
+    }
+
+    /// Get the pointer to the start of the block.
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    /// Get the mapped range as a slice.
+    ///
+    /// ### Safety
+    ///
+    /// The device must not access the block while the slice is in use.
+    pub unsafe fn as_slice_mut(&mut self) -> &mut [u8] {
+        from_raw_parts_mut(self.ptr, self.size() as usize)
+    }
+
+    /// Make writes by the host visible to the device. Only needed for memory that isn't
+    /// `COHERENT`.
+    pub unsafe fn flush(&self) -> Result<(), OutOfMemory> {
+        self.atoms.flush::<B>(self.device, self.memory)
+    }
+
+    /// Make writes by the device visible to the host. Only needed for memory that isn't
+    /// `COHERENT`.
+    pub unsafe fn invalidate(&self) -> Result<(), OutOfMemory> {
+        self.atoms.invalidate::<B>(self.device, self.memory)
+    }
+}
+
+/// Range of memory in whole non-coherent atoms.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct AtomRange {
+    pub(crate) start: u64,
+    /// End of the range, or `None` for the end of the memory object
+    pub(crate) end: Option<u64>,
+}
+
+impl AtomRange {
+    /// Flush the range of `memory`.
+    pub(crate) unsafe fn flush<B: Backend>(
+        self,
+        device: &B::Device,
+        memory: &B::Memory,
+    ) -> Result<(), OutOfMemory> {
+        match self.end {
+            Some(end) => device.flush_mapped_memory_ranges(once((memory, self.start..end))),
+            None => device.flush_mapped_memory_ranges(once((memory, self.start..))),
+        }
+    }
+
+    /// Invalidate the range of `memory`.
+    pub(crate) unsafe fn invalidate<B: Backend>(
+        self,
+        device: &B::Device,
+        memory: &B::Memory,
+    ) -> Result<(), OutOfMemory> {
+        match self.end {
+            Some(end) => device.invalidate_mapped_memory_ranges(once((memory, self.start..end))),
+            None => device.invalidate_mapped_memory_ranges(once((memory, self.start..))),
+        }
+    }
+}
+
+/// Widen `range` to whole `atom`s, as required to flush or invalidate memory that isn't
+/// `COHERENT`.
+///
+/// The end is clamped to `memory_size`, where the memory object ends inside an atom.
+/// If the size is unknown and `range` ends inside an atom, the range extends to the end of
+/// the memory object.
+///
+/// ### Panics
+///
+/// Panics if `atom` is not a power of two.
+pub(crate) fn atom_range(range: Range<u64>, atom: u64, memory_size: Option<u64>) -> AtomRange {
+    let end = align_up(range.end, atom);
+    AtomRange {
+        start: align_down(range.start, atom),
+        end: match memory_size {
+            Some(size) => Some(min(end, size)),
+            None if end == range.end => Some(end),
+            None => None,
+        },
+    }
+}
+
+impl<'a, B> Drop for MappedBlock<'a, B>
+where
+    B: Backend,
+{
+    fn drop(&mut self) {
+        unsafe { self.device.unmap_memory(self.memory) }
+    }
+}
+
+impl<'a, B> fmt::Debug for MappedBlock<'a, B>
+where
+    B: Backend,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("MappedBlock")
+            .field("memory", self.memory)
+            .field("range", &self.range)
+            .field("atoms", &self.atoms)
+            .field("ptr", &self.ptr)
+            .finish()
+    }
+}
+
+#[test]
+fn test_atom_range() {
+    let range = |start, end| AtomRange { start, end };
+    assert_eq!(atom_range(0..100, 1, None), range(0, Some(100)));
+    assert_eq!(atom_range(100..200, 64, Some(1024)), range(64, Some(256)));
+    assert_eq!(atom_range(128..256, 64, None), range(128, Some(256)));
+    // The last atom of the memory object may be incomplete
+    assert_eq!(
+        atom_range(900..1000, 64, Some(1000)),
+        range(896, Some(1000))
+    );
+    assert_eq!(atom_range(900..1000, 64, None), range(896, None));
+}
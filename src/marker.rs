@@ -0,0 +1,16 @@
+//! Marker types stating the thread-safety of types in this crate.
+//!
+//! Types generic over a backend only use it for associated types, so they are `Send` and
+//! `Sync` whenever their fields are, whatever the backend. Types holding host pointers into
+//! mapped device memory must stay on the thread that mapped it, since some backends tie
+//! mappings to the thread or context they were created on.
+
+use std::marker::PhantomData;
+
+/// Marks a type as generic over backend `B` without owning values of `B`.
+///
+/// Unlike `PhantomData<B>`, this doesn't make the type depend on `B` being `Send` or `Sync`.
+pub(crate) type BackendMarker<B> = PhantomData<fn() -> B>;
+
+/// Marks a type as neither `Send` nor `Sync`, regardless of its other fields.
+pub(crate) type NotSend = PhantomData<*const ()>;
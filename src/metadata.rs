@@ -0,0 +1,161 @@
+use std::mem::size_of;
+use std::time::Instant;
+
+use label::BlockLocation;
+
+/// Metadata of a live block.
+///
+/// Kept by `CombinedAllocator` in a slab next to the allocator rather than in the block, so
+/// blocks stay small. Only recorded for tracked blocks, see `CombinedAllocator::set_tracking`.
+/// The slab reuses slots of freed blocks, but the map of live blocks used to find them still
+/// allocates host memory for each allocated block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockMetadata {
+    /// Name of what the block is used for, e.g. `"shadow map"`.
+    pub tag: Option<&'static str>,
+    /// Time the block was allocated at.
+    pub allocated: Instant,
+    /// Frame the block was allocated in, counted by `SmartAllocator::begin_frame`.
+    /// Only recorded with the `block-age` feature.
+    #[cfg(feature = "block-age")]
+    pub frame: u64,
+    /// Arbitrary value set by the user, e.g. an index into their own resource table.
+    pub user_data: u64,
+}
+
+impl BlockMetadata {
+    /// Create metadata of a block allocated just now.
+    pub fn new() -> Self {
+        BlockMetadata {
+            tag: None,
+            allocated: Instant::now(),
+            #[cfg(feature = "block-age")]
+            frame: 0,
+            user_data: 0,
+        }
+    }
+}
+
+impl Default for BlockMetadata {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Live block found by `SmartAllocator::blocks_older_than`.
+#[derive(Clone, Copy, Debug)]
+pub struct AgedBlock {
+    /// Location of the block.
+    pub location: BlockLocation,
+    /// Number of frames since the block was allocated.
+    pub frames: u64,
+    /// Metadata of the block.
+    pub metadata: BlockMetadata,
+}
+
+/// Id of the slot of a block in a `MetadataSlab`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct BlockId(u32);
+
+#[derive(Debug)]
+enum Slot {
+    Used(BlockMetadata),
+    /// Index of the next free slot
+    Free(Option<u32>),
+}
+
+/// Metadata of live blocks, indexed by block id.
+///
+/// Slots of freed blocks are linked into a free list and reused, so once the slab has grown
+/// to the peak number of live blocks, inserting and removing metadata never allocates.
+#[derive(Debug, Default)]
+pub(crate) struct MetadataSlab {
+    slots: Vec<Slot>,
+    next_free: Option<u32>,
+}
+
+impl MetadataSlab {
+    /// Create an empty slab.
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store metadata of a new block.
+    pub(crate) fn insert(&mut self, metadata: BlockMetadata) -> BlockId {
+        match self.next_free {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                match *slot {
+                    Slot::Free(next) => self.next_free = next,
+                    Slot::Used(_) => unreachable!("Free list points to a used slot"),
+                }
+                *slot = Slot::Used(metadata);
+                BlockId(index)
+            }
+            None => {
+                assert!(self.slots.len() < u32::max_value() as usize);
+                self.slots.push(Slot::Used(metadata));
+                BlockId(self.slots.len() as u32 - 1)
+            }
+        }
+    }
+
+    /// Get metadata of a block.
+    pub(crate) fn get(&self, id: BlockId) -> Option<&BlockMetadata> {
+        match self.slots.get(id.0 as usize) {
+            Some(Slot::Used(metadata)) => Some(metadata),
+            _ => None,
+        }
+    }
+
+    /// Get mutable metadata of a block.
+    pub(crate) fn get_mut(&mut self, id: BlockId) -> Option<&mut BlockMetadata> {
+        match self.slots.get_mut(id.0 as usize) {
+            Some(Slot::Used(metadata)) => Some(metadata),
+            _ => None,
+        }
+    }
+
+    /// Get the number of bytes of host memory used by the slots.
+    pub(crate) fn host_memory(&self) -> usize {
+        self.slots.capacity() * size_of::<Slot>()
+    }
+
+    /// Remove metadata of a freed block, making its slot available again.
+    pub(crate) fn remove(&mut self, id: BlockId) -> Option<BlockMetadata> {
+        let slot = self.slots.get_mut(id.0 as usize)?;
+        match ::std::mem::replace(slot, Slot::Free(self.next_free)) {
+            Slot::Used(metadata) => {
+                self.next_free = Some(id.0);
+                Some(metadata)
+            }
+            free => {
+                *slot = free;
+                None
+            }
+        }
+    }
+}
+
+#[test]
+fn test_metadata_slab() {
+    let mut slab = MetadataSlab::new();
+    let first = slab.insert(BlockMetadata::new());
+    let second = slab.insert(BlockMetadata {
+        tag: Some("second"),
+        ..BlockMetadata::new()
+    });
+    slab.get_mut(first).unwrap().user_data = 7;
+    assert_eq!(slab.get(first).unwrap().user_data, 7);
+
+    assert!(slab.remove(first).is_some());
+    assert!(slab.remove(first).is_none());
+    assert!(slab.get(first).is_none());
+
+    // Freed slots are reused without growing the slab
+    let third = slab.insert(BlockMetadata::new());
+    assert_eq!(third, first);
+    assert_eq!(slab.slots.len(), 2);
+    assert!(slab.host_memory() >= 2 * size_of::<Slot>());
+    assert_eq!(slab.get(second).unwrap().tag, Some("second"));
+}
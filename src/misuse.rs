@@ -0,0 +1,130 @@
+use std::fmt;
+use std::sync::Arc;
+
+use gfx_hal::MemoryTypeId;
+
+/// What allocators do when they are misused, e.g. when a block is freed twice or freed into an
+/// allocator it wasn't allocated from.
+///
+/// Shipped products may prefer leaking a block over crashing, while debug builds should keep
+/// failing hard:
+///
+/// ```rust
+/// # extern crate gfx_memory;
+/// # use gfx_memory::MisusePolicy;
+/// # fn main() {
+/// let policy = if cfg!(debug_assertions) {
+///     MisusePolicy::Panic
+/// } else {
+///     MisusePolicy::Report
+/// };
+/// # let _ = policy;
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MisusePolicy {
+    /// Panic with a description of the misuse. This is the default.
+    Panic,
+
+    /// Leak the misused block, leaving the allocator untouched, and pass a `Misuse` to the
+    /// misuse callback if one is set.
+    Report,
+}
+
+impl Default for MisusePolicy {
+    fn default() -> Self {
+        MisusePolicy::Panic
+    }
+}
+
+/// Misuse of an allocator reported with `MisusePolicy::Report`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Misuse {
+    /// Memory type of the misused allocator.
+    pub memory_type: MemoryTypeId,
+    /// Description of the misuse.
+    pub message: String,
+}
+
+impl fmt::Display for Misuse {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "type {}: {}", self.memory_type.0, self.message)
+    }
+}
+
+type Callback = Arc<dyn Fn(&Misuse) + Send + Sync>;
+
+/// Misuse policy and callback, shared between allocators of all memory types.
+#[derive(Clone, Default)]
+pub(crate) struct MisuseHandler {
+    policy: MisusePolicy,
+    callback: Option<Callback>,
+}
+
+impl MisuseHandler {
+    pub(crate) fn set_policy(&mut self, policy: MisusePolicy) {
+        self.policy = policy;
+    }
+
+    pub(crate) fn policy(&self) -> MisusePolicy {
+        self.policy
+    }
+
+    pub(crate) fn set_callback<F>(&mut self, callback: F)
+    where
+        F: Fn(&Misuse) + Send + Sync + 'static,
+    {
+        self.callback = Some(Arc::new(callback));
+    }
+
+    /// Handle a misuse.
+    ///
+    /// Panics with `MisusePolicy::Panic`. Otherwise reports the misuse and returns, and the
+    /// caller must leak the misused block without touching its own state.
+    pub(crate) fn report(&self, memory_type: MemoryTypeId, message: String) {
+        let misuse = Misuse {
+            memory_type,
+            message,
+        };
+        match self.policy {
+            MisusePolicy::Panic => panic!("{}", misuse),
+            MisusePolicy::Report => {
+                if let Some(ref callback) = self.callback {
+                    callback(&misuse);
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Debug for MisuseHandler {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("MisuseHandler")
+            .field("policy", &self.policy)
+            .field("callback", &self.callback.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+#[test]
+fn test_report() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let reported = Arc::new(AtomicUsize::new(0));
+    let mut handler = MisuseHandler::default();
+    handler.set_policy(MisusePolicy::Report);
+    let counter = reported.clone();
+    handler.set_callback(move |misuse| {
+        assert_eq!(misuse.to_string(), "type 2: freed twice");
+        counter.fetch_add(1, Ordering::Relaxed);
+    });
+    handler.report(MemoryTypeId(2), "freed twice".to_owned());
+    assert_eq!(reported.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+#[should_panic(expected = "type 2: freed twice")]
+fn test_panic() {
+    MisuseHandler::default().report(MemoryTypeId(2), "freed twice".to_owned());
+}
@@ -0,0 +1,76 @@
+//! Ids of allocator instances stamped into blocks.
+//!
+//! With debug assertions or the `checks` feature enabled, every sub-allocator gets a unique id
+//! that is stored in the blocks it allocates, so freeing a block into another instance of the
+//! same allocator type is caught immediately. Otherwise the id is zero-sized and all checks pass.
+
+#[cfg(any(debug_assertions, feature = "checks"))]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(any(debug_assertions, feature = "checks"))]
+static NEXT: AtomicUsize = AtomicUsize::new(1);
+
+/// Id of the allocator instance a block was allocated from.
+#[cfg(any(debug_assertions, feature = "checks"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Owner(usize);
+
+/// Id of the allocator instance a block was allocated from.
+#[cfg(not(any(debug_assertions, feature = "checks")))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Owner;
+
+impl Owner {
+    /// Get a new unique id.
+    #[cfg(any(debug_assertions, feature = "checks"))]
+    pub(crate) fn new() -> Self {
+        Owner(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Get a new unique id.
+    #[cfg(not(any(debug_assertions, feature = "checks")))]
+    pub(crate) fn new() -> Self {
+        Owner
+    }
+
+    /// Panic if a block stamped with `block` is given to the allocator with this id.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `block`: id stamped into the block
+    /// - `allocator`: name of the allocator type for the message
+    pub(crate) fn check(self, block: Owner, allocator: &str) {
+        if let Some(message) = self.mismatch(block, allocator) {
+            panic!("{}", message);
+        }
+    }
+
+    /// Describe the mismatch if a block stamped with `block` is given to the allocator with
+    /// this id.
+    pub(crate) fn mismatch(self, block: Owner, allocator: &str) -> Option<String> {
+        if self != block {
+            Some(format!(
+                "Block allocated by {} allocator {:?} was given to allocator {:?}",
+                allocator, block, self
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+#[test]
+#[cfg(any(debug_assertions, feature = "checks"))]
+fn test_owner() {
+    let first = Owner::new();
+    let second = Owner::new();
+    assert_ne!(first, second);
+    first.check(first, "test");
+}
+
+#[test]
+#[should_panic]
+#[cfg(any(debug_assertions, feature = "checks"))]
+fn test_owner_mismatch() {
+    Owner::new().check(Owner::new(), "test");
+}
@@ -0,0 +1,205 @@
+use std::fmt::{self, Debug};
+use std::ops::Range;
+use std::slice::from_raw_parts;
+
+use gfx_hal::buffer::Usage as BufferUsage;
+use gfx_hal::{Backend, Device};
+
+use block::Block;
+use factory::{Factory, Item};
+use mapped::atom_range;
+use marker::NotSend;
+use upload::UploadError;
+use upload_heap::Ring;
+use MemoryAllocator;
+
+/// Persistently mapped ring buffer the device copies data into for the host to read, like
+/// readback heaps of D3D12.
+///
+/// Ranges are allocated with `alloc` and filled by copy commands recorded by the application,
+/// e.g. for screenshots, timestamp queries or compute results. `map_when_ready` waits for the
+/// fence of the submission, invalidates the memory and returns the data. The space of a frame
+/// is reused once `end_frame` has been called for as many following frames as there are
+/// frames in flight.
+///
+/// The heap must be disposed with `dispose`. Like `MappedBlock` it is neither `Send` nor `Sync`.
+///
+/// ### Type parameters:
+///
+/// - `B`: hal `Backend`
+/// - `A`: allocator the memory of the heap is allocated from
+pub struct ReadbackHeap<B: Backend, A: MemoryAllocator<B>> {
+    buffer: Item<B::Buffer, A::Block>,
+    ptr: *mut u8,
+    ring: Ring,
+    /// `nonCoherentAtomSize` invalidations are rounded to
+    atom: u64,
+    not_send: NotSend,
+}
+
+impl<B, A> Debug for ReadbackHeap<B, A>
+where
+    B: Backend,
+    A: MemoryAllocator<B>,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ReadbackHeap")
+            .field("buffer", &self.buffer)
+            .field("ring", &self.ring)
+            .finish()
+    }
+}
+
+impl<B, A> ReadbackHeap<B, A>
+where
+    B: Backend,
+    A: MemoryAllocator<B>,
+{
+    /// Create a readback heap and map its memory.
+    ///
+    /// The heap stays mapped, so it needs a memory object of its own: no other block of the
+    /// memory object could be mapped while the heap is.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `allocator`: allocator to allocate the memory of the heap from
+    /// - `device`: device to create the buffer of the heap on
+    /// - `request`: request of a dedicated allocation of host-visible memory, preferably
+    ///              `CPU_CACHED`, e.g. `Request::dedicated().cpu_visible().cpu_cached()` with
+    ///              `SmartAllocator`
+    /// - `size`: size of the heap in bytes
+    /// - `frames_in_flight`: number of frames the device may still be executing when a frame ends
+    /// - `atom`: `nonCoherentAtomSize` of the device, or 1 for `COHERENT` memory
+    ///
+    /// ### Errors
+    ///
+    /// Fails with `UploadError::SharedMemory` if the block of the heap doesn't start its memory
+    /// object, which shows it was sub-allocated.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `atom` is not a power of two.
+    pub unsafe fn new(
+        allocator: &mut A,
+        device: &B::Device,
+        request: A::Request,
+        size: u64,
+        frames_in_flight: usize,
+        atom: u64,
+    ) -> Result<Self, UploadError> {
+        assert!(atom.is_power_of_two());
+        let buffer = allocator
+            .create_buffer(device, request, size, BufferUsage::TRANSFER_DST)
+            .map_err(UploadError::Staging)?;
+        if buffer.block().range().start != 0 {
+            allocator.destroy_buffer(device, buffer);
+            return Err(UploadError::SharedMemory);
+        }
+        let ptr = match device.map_memory(buffer.block().memory(), buffer.block().range()) {
+            Ok(ptr) => ptr,
+            Err(error) => {
+                allocator.destroy_buffer(device, buffer);
+                return Err(UploadError::Map(error));
+            }
+        };
+        Ok(ReadbackHeap {
+            buffer,
+            ptr,
+            ring: Ring::new(size, frames_in_flight),
+            atom,
+            not_send: NotSend::default(),
+        })
+    }
+
+    /// Get the buffer of the heap, to record copies into.
+    pub fn buffer(&self) -> &B::Buffer {
+        self.buffer.raw()
+    }
+
+    /// Get the size of the heap in bytes.
+    pub fn size(&self) -> u64 {
+        self.ring.size()
+    }
+
+    /// Get the number of bytes allocated by frames in flight and the current frame, including
+    /// padding.
+    pub fn used(&self) -> u64 {
+        self.ring.used()
+    }
+
+    /// Allocate a range of the buffer of the heap for the device to copy `size` bytes into.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `size`: size of the range in bytes
+    /// - `alignment`: alignment of the offset, e.g. `optimalBufferCopyOffsetAlignment`
+    ///
+    /// ### Returns
+    ///
+    /// Returns the range of the buffer, or `UploadError::Full` if frames in flight leave no
+    /// room for it.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `alignment` is not a power of two.
+    pub fn alloc(&mut self, size: u64, alignment: u64) -> Result<Range<u64>, UploadError> {
+        let offset = self.ring.alloc(size, alignment)?;
+        Ok(offset..offset + size)
+    }
+
+    /// Wait until the device has written `range` and get its data.
+    ///
+    /// Only `range`, rounded to whole atoms, is invalidated.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device the heap was created on
+    /// - `fence`: fence signaled by the submission that copies into `range`
+    /// - `range`: range returned by `alloc` in the current frame or a frame in flight
+    ///
+    /// ### Errors
+    ///
+    /// Fails with `UploadError::Timeout` if the fence isn't signaled after waiting for it.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `range` is not within the heap.
+    pub unsafe fn map_when_ready(
+        &mut self,
+        device: &B::Device,
+        fence: &B::Fence,
+        range: Range<u64>,
+    ) -> Result<&[u8], UploadError> {
+        assert!(range.start <= range.end && range.end <= self.size());
+        if !device
+            .wait_for_fence(fence, !0)
+            .map_err(UploadError::Wait)?
+        {
+            return Err(UploadError::Timeout);
+        }
+        let block = self.buffer.block();
+        // The block is the whole memory object
+        atom_range(range.clone(), self.atom, Some(block.range().end))
+            .invalidate::<B>(device, block.memory())
+            .map_err(UploadError::Invalidate)?;
+        Ok(from_raw_parts(
+            self.ptr.offset(range.start as isize),
+            (range.end - range.start) as usize,
+        ))
+    }
+
+    /// Start the next frame.
+    ///
+    /// The space of the oldest frame is reused if more than `frames_in_flight` frames ended since.
+    pub fn end_frame(&mut self) {
+        self.ring.end_frame();
+    }
+
+    /// Unmap the memory of the heap and destroy its buffer.
+    ///
+    /// The device must have finished all copies into the heap.
+    pub unsafe fn dispose(self, allocator: &mut A, device: &B::Device) {
+        device.unmap_memory(self.buffer.block().memory());
+        allocator.destroy_buffer(device, self.buffer);
+    }
+}
@@ -0,0 +1,250 @@
+use std::fmt::{self, Debug};
+
+use gfx_hal::Backend;
+
+use factory::{Factory, ImageInfo};
+use marker::BackendMarker;
+
+/// Cache of images used as render targets, reused between passes and frames.
+///
+/// Post-processing chains create the same temporary images every frame. Instead of creating
+/// and destroying them, images are acquired from the cache by description and released back
+/// to it. Released images are reused by later acquisitions with the same request and
+/// description, so an image is never handed out for memory it wasn't allocated for. Images not
+/// reused for `max_age` frames are destroyed by `next_frame`.
+///
+/// Images must only be released once the device is done with them, or when reusing them in
+/// the same frame is correctly synchronized.
+///
+/// ### Type parameters:
+///
+/// - `B`: hal `Backend`
+/// - `F`: factory used to create the images
+pub struct RenderTargetCache<B: Backend, F: Factory<B>> {
+    free: Vec<CachedImage<F::ImageRequest, F::Image>>,
+    frame: u64,
+    max_age: u64,
+    pd: BackendMarker<B>,
+}
+
+/// Image released to the cache.
+struct CachedImage<R, I> {
+    request: R,
+    info: ImageInfo,
+    image: I,
+    /// Frame the image was released in
+    frame: u64,
+}
+
+impl<B, F> Debug for RenderTargetCache<B, F>
+where
+    B: Backend,
+    F: Factory<B>,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("RenderTargetCache")
+            .field("free", &self.free.len())
+            .field("frame", &self.frame)
+            .field("max_age", &self.max_age)
+            .finish()
+    }
+}
+
+impl<B, F> RenderTargetCache<B, F>
+where
+    B: Backend,
+    F: Factory<B>,
+    F::ImageRequest: PartialEq,
+{
+    /// Create an empty cache.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `max_age`: number of frames released images are kept for without being reused
+    pub fn new(max_age: u64) -> Self {
+        RenderTargetCache {
+            free: Vec::new(),
+            frame: 0,
+            max_age,
+            pd: BackendMarker::default(),
+        }
+    }
+
+    /// Get the number of released images kept by the cache.
+    pub fn cached(&self) -> usize {
+        self.free.len()
+    }
+
+    /// Get an image matching the description, creating one if none is cached.
+    ///
+    /// The most recently released matching image is reused first.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `factory`: factory used to create images, must always be the same for an instance of
+    ///              the cache
+    /// - `device`: device to create the image on
+    /// - `request`: information needed by the factory to allocate memory for a new image
+    /// - `info`: description of the image
+    pub unsafe fn acquire(
+        &mut self,
+        factory: &mut F,
+        device: &B::Device,
+        request: F::ImageRequest,
+        info: ImageInfo,
+    ) -> Result<F::Image, F::Error> {
+        if let Some(image) = take(&mut self.free, &request, &info) {
+            return Ok(image);
+        }
+        factory.create_image(
+            device,
+            request,
+            info.kind,
+            info.level,
+            info.format,
+            info.tiling,
+            info.usage,
+            info.view_caps,
+        )
+    }
+
+    /// Return an image to the cache.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `request`: request the image was acquired with
+    /// - `info`: description the image was acquired with
+    /// - `image`: image acquired from this cache
+    pub fn release(&mut self, request: F::ImageRequest, info: ImageInfo, image: F::Image) {
+        self.free.push(CachedImage {
+            request,
+            info,
+            image,
+            frame: self.frame,
+        });
+    }
+
+    /// Advance to the next frame, destroying images not reused for `max_age` frames.
+    ///
+    /// ### Returns
+    ///
+    /// Number of destroyed images.
+    pub unsafe fn next_frame(&mut self, factory: &mut F, device: &B::Device) -> usize {
+        self.frame += 1;
+        let oldest = self.frame.saturating_sub(self.max_age);
+        expire(&mut self.free, oldest, |image| {
+            factory.destroy_image(device, image)
+        })
+    }
+
+    /// Destroy all cached images.
+    ///
+    /// Images acquired and not released are not affected.
+    pub unsafe fn clear(&mut self, factory: &mut F, device: &B::Device) {
+        for cached in self.free.drain(..) {
+            factory.destroy_image(device, cached.image);
+        }
+    }
+}
+
+/// Take the most recently released image matching the request and description.
+fn take<R, I>(free: &mut Vec<CachedImage<R, I>>, request: &R, info: &ImageInfo) -> Option<I>
+where
+    R: PartialEq,
+{
+    free.iter()
+        .rposition(|cached| cached.request == *request && cached.info == *info)
+        .map(|index| free.remove(index).image)
+}
+
+/// Destroy images released before the `oldest` frame, returning how many were destroyed.
+fn expire<R, I, D>(free: &mut Vec<CachedImage<R, I>>, oldest: u64, mut destroy: D) -> usize
+where
+    D: FnMut(I),
+{
+    let mut destroyed = 0;
+    let mut index = 0;
+    while index < free.len() {
+        if free[index].frame < oldest {
+            destroy(free.remove(index).image);
+            destroyed += 1;
+        } else {
+            index += 1;
+        }
+    }
+    destroyed
+}
+
+#[test]
+fn test_take() {
+    use gfx_hal::format::Format;
+    use gfx_hal::image::{Kind, Tiling, Usage, ViewCapabilities};
+    use request::Request;
+
+    let info = ImageInfo {
+        kind: Kind::D2(256, 256, 1, 1),
+        level: 1,
+        format: Format::Rgba8Unorm,
+        tiling: Tiling::Optimal,
+        usage: Usage::COLOR_ATTACHMENT | Usage::SAMPLED,
+        view_caps: ViewCapabilities::empty(),
+    };
+    let other = ImageInfo {
+        format: Format::R8Unorm,
+        ..info
+    };
+    let local = Request::general();
+    let visible = Request::general().cpu_visible();
+    let cached = |request, info, image| CachedImage {
+        request,
+        info,
+        image,
+        frame: 0,
+    };
+    let mut free = vec![
+        cached(local, info, 0),
+        cached(visible, info, 1),
+        cached(local, other, 2),
+        cached(local, info, 3),
+    ];
+
+    // Most recently released first, and never an image allocated for another request
+    assert_eq!(take(&mut free, &local, &info), Some(3));
+    assert_eq!(take(&mut free, &local, &info), Some(0));
+    assert_eq!(take(&mut free, &local, &info), None);
+    assert_eq!(take(&mut free, &visible, &other), None);
+    assert_eq!(take(&mut free, &visible, &info), Some(1));
+    assert_eq!(take(&mut free, &local, &other), Some(2));
+    assert!(free.is_empty());
+}
+
+#[test]
+fn test_expire() {
+    use gfx_hal::format::Format;
+    use gfx_hal::image::{Kind, Tiling, Usage, ViewCapabilities};
+
+    let info = ImageInfo {
+        kind: Kind::D1(64, 1),
+        level: 1,
+        format: Format::R8Unorm,
+        tiling: Tiling::Optimal,
+        usage: Usage::SAMPLED,
+        view_caps: ViewCapabilities::empty(),
+    };
+    let mut free: Vec<_> = (0..4)
+        .map(|frame| CachedImage {
+            request: (),
+            info,
+            image: frame as u32,
+            frame,
+        })
+        .collect();
+    let mut destroyed = Vec::new();
+    assert_eq!(expire(&mut free, 2, |image| destroyed.push(image)), 2);
+    assert_eq!(destroyed, vec![0, 1]);
+    assert_eq!(
+        free.iter().map(|cached| cached.image).collect::<Vec<_>>(),
+        vec![2, 3]
+    );
+    assert_eq!(expire(&mut free, 2, |_| unreachable!()), 0);
+}
@@ -0,0 +1,287 @@
+use gfx_hal::memory::Properties;
+use gfx_hal::MemoryTypeId;
+
+use combined::Type;
+use domain::Domain;
+
+/// Information required to allocate a block from `CombinedAllocator` or `SmartAllocator`.
+///
+/// Starts from a placement and adds required memory properties:
+///
+/// ```rust
+/// # extern crate gfx_hal;
+/// # extern crate gfx_memory;
+/// # use gfx_hal::memory::Properties;
+/// # use gfx_memory::{Request, Type};
+/// # fn main() {
+/// let request = Request::general().device_local();
+/// assert_eq!(request.ty(), Type::General);
+/// assert_eq!(request.properties(), Properties::DEVICE_LOCAL);
+/// # }
+/// ```
+///
+/// Tuples of `(Type, Properties)` convert into requests.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Request {
+    ty: Type,
+    properties: Properties,
+    lifetime: Lifetime,
+    domain: Domain,
+    group: Option<Group>,
+    readback: bool,
+    memory_type: Option<MemoryTypeId>,
+}
+
+/// Group of blocks placed together, see `Request::in_group`.
+///
+/// Applications define their own groups, e.g. one for all buffers of a bindless renderer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Group(pub u32);
+
+/// Expected lifetime of a block.
+///
+/// `CombinedAllocator` uses it to place `Type::General` blocks: blocks that live for a few
+/// frames at most are allocated from arenas, and static blocks are kept in chunks of their
+/// own, so they don't pin chunks shared with blocks that come and go.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Lifetime {
+    /// Nothing is known, placement depends on the `Type` only.
+    Unknown,
+
+    /// Freed by the end of a render pass.
+    PerPass,
+
+    /// Freed by the end of the frame.
+    PerFrame,
+
+    /// Freed after about this many frames.
+    Frames(u32),
+
+    /// Lives until the application shuts down or the level is unloaded.
+    Static,
+}
+
+impl Lifetime {
+    /// Longest lifetime in frames considered short enough for arenas.
+    pub const SHORT_FRAMES: u32 = 3;
+
+    /// Check if blocks with this lifetime are short-lived.
+    pub fn is_short(&self) -> bool {
+        match *self {
+            Lifetime::PerPass | Lifetime::PerFrame => true,
+            Lifetime::Frames(frames) => frames <= Self::SHORT_FRAMES,
+            Lifetime::Unknown | Lifetime::Static => false,
+        }
+    }
+}
+
+impl Default for Lifetime {
+    fn default() -> Self {
+        Lifetime::Unknown
+    }
+}
+
+impl Request {
+    /// Create a request for a block of type `ty` without required properties.
+    pub fn new(ty: Type) -> Self {
+        Request {
+            ty,
+            properties: Properties::empty(),
+            lifetime: Lifetime::Unknown,
+            domain: Domain::DEFAULT,
+            group: None,
+            readback: false,
+            memory_type: None,
+        }
+    }
+
+    /// Create a request for a general purpose block.
+    pub fn general() -> Self {
+        Self::new(Type::General)
+    }
+
+    /// Create a request for a block of a short-lived object.
+    pub fn short_lived() -> Self {
+        Self::new(Type::ShortLived)
+    }
+
+    /// Create a request for a block with its own memory object.
+    pub fn dedicated() -> Self {
+        Self::new(Type::Dedicated)
+    }
+
+    /// Create a request for a block that never gets its own memory object.
+    pub fn sub_allocated() -> Self {
+        Self::new(Type::SubAllocated)
+    }
+
+    /// Require additional memory properties.
+    pub fn with(self, properties: Properties) -> Self {
+        Request {
+            properties: self.properties | properties,
+            ..self
+        }
+    }
+
+    /// Require device local memory.
+    pub fn device_local(self) -> Self {
+        self.with(Properties::DEVICE_LOCAL)
+    }
+
+    /// Require memory that can be mapped.
+    pub fn cpu_visible(self) -> Self {
+        self.with(Properties::CPU_VISIBLE)
+    }
+
+    /// Require memory that is coherent between host and device.
+    pub fn coherent(self) -> Self {
+        self.with(Properties::COHERENT)
+    }
+
+    /// Require memory that is cached on the host.
+    pub fn cpu_cached(self) -> Self {
+        self.with(Properties::CPU_CACHED)
+    }
+
+    /// Hint the expected lifetime of the block.
+    pub fn lifetime(self, lifetime: Lifetime) -> Self {
+        Request { lifetime, ..self }
+    }
+
+    /// Account the block to a domain. Defaults to `Domain::DEFAULT`.
+    pub fn in_domain(self, domain: Domain) -> Self {
+        Request { domain, ..self }
+    }
+
+    /// Place the block in chunks shared only with blocks of the same group.
+    ///
+    /// `CombinedAllocator` keeps chunks of its own for each group, so the buffers of a group
+    /// are covered by few memory ranges, e.g. for bindless renderers. Blocks that get their own
+    /// memory object and `Type::ShortLived` blocks are not grouped.
+    pub fn in_group(self, group: Group) -> Self {
+        Request {
+            group: Some(group),
+            ..self
+        }
+    }
+
+    /// Mark the block as written by the device and read by the host.
+    ///
+    /// `CombinedAllocator` keeps such blocks in chunks of their own when readback segregation
+    /// is enabled, see `CombinedAllocator::set_segregate_readback`.
+    pub fn for_readback(self) -> Self {
+        Request {
+            readback: true,
+            ..self
+        }
+    }
+
+    /// Allocate the block from exactly this memory type.
+    ///
+    /// `SmartAllocator` skips choosing a memory type, but still checks that it is allowed by
+    /// the requirements and has the required properties, and accounts the block in its heap
+    /// and domain as usual. Useful when the ideal type was already queried, or to match the
+    /// memory type of an imported resource.
+    pub fn in_memory_type(self, memory_type: MemoryTypeId) -> Self {
+        Request {
+            memory_type: Some(memory_type),
+            ..self
+        }
+    }
+
+    /// Get the type of the block.
+    pub fn ty(&self) -> Type {
+        self.ty
+    }
+
+    /// Get the expected lifetime of the block.
+    pub fn expected_lifetime(&self) -> Lifetime {
+        self.lifetime
+    }
+
+    /// Get the domain the block is accounted to.
+    pub fn domain(&self) -> Domain {
+        self.domain
+    }
+
+    /// Get the group the block is placed with.
+    pub fn group(&self) -> Option<Group> {
+        self.group
+    }
+
+    /// Check if the block is read by the host, see `for_readback`.
+    pub fn is_readback(&self) -> bool {
+        self.readback
+    }
+
+    /// Get the memory type the block is pinned to, see `in_memory_type`.
+    pub fn memory_type(&self) -> Option<MemoryTypeId> {
+        self.memory_type
+    }
+
+    /// Get the required memory properties.
+    pub fn properties(&self) -> Properties {
+        self.properties
+    }
+}
+
+impl Default for Request {
+    fn default() -> Self {
+        Self::general()
+    }
+}
+
+impl From<Type> for Request {
+    fn from(ty: Type) -> Self {
+        Self::new(ty)
+    }
+}
+
+impl From<(Type, Properties)> for Request {
+    fn from((ty, properties): (Type, Properties)) -> Self {
+        Self::new(ty).with(properties)
+    }
+}
+
+impl From<Request> for (Type, Properties) {
+    fn from(request: Request) -> Self {
+        (request.ty, request.properties)
+    }
+}
+
+#[test]
+fn test_lifetime() {
+    assert!(Lifetime::PerFrame.is_short());
+    assert!(Lifetime::Frames(Lifetime::SHORT_FRAMES).is_short());
+    assert!(!Lifetime::Frames(Lifetime::SHORT_FRAMES + 1).is_short());
+    assert!(!Lifetime::Static.is_short());
+
+    let request = Request::general().lifetime(Lifetime::Static);
+    assert_eq!(request.expected_lifetime(), Lifetime::Static);
+    assert_eq!(Request::general().expected_lifetime(), Lifetime::Unknown);
+}
+
+#[test]
+fn test_group() {
+    let request = Request::general().in_group(Group(3)).device_local();
+    assert_eq!(request.group(), Some(Group(3)));
+    assert_eq!(Request::general().group(), None);
+}
+
+#[test]
+fn test_readback() {
+    let request = Request::short_lived().cpu_visible().for_readback();
+    assert!(request.is_readback());
+    assert_eq!(request.ty(), Type::ShortLived);
+    assert!(!Request::short_lived().cpu_visible().is_readback());
+}
+
+#[test]
+fn test_memory_type() {
+    let request = Request::general()
+        .device_local()
+        .in_memory_type(MemoryTypeId(2));
+    assert_eq!(request.memory_type(), Some(MemoryTypeId(2)));
+    assert_eq!(request.properties(), Properties::DEVICE_LOCAL);
+    assert_eq!(Request::general().memory_type(), None);
+}
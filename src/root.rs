@@ -1,11 +1,15 @@
-use std::marker::PhantomData;
+use std::collections::HashMap;
+use std::mem::size_of;
 
 use gfx_hal::memory::Requirements;
 use gfx_hal::{Backend, Device, MemoryTypeId};
 
 use block::{Block, RawBlock};
-use relevant::Relevant;
-use {MemoryAllocator, MemoryError};
+use guard::Relevant;
+use label::{Labeler, MemoryLabel, Pool};
+use marker::BackendMarker;
+use misuse::{MisuseHandler, MisusePolicy};
+use {check_requirements, MemoryAllocator, MemoryError};
 
 /// Allocator that allocates memory directly from device.
 ///
@@ -13,14 +17,26 @@ use {MemoryAllocator, MemoryError};
 ///
 /// - `B`: hal `Backend`
 #[derive(Debug)]
-pub struct RootAllocator<B> {
+pub struct RootAllocator<B: Backend> {
     relevant: Relevant,
     id: MemoryTypeId,
     used: u64,
-    pd: PhantomData<fn() -> B>,
+    /// Labels of boxed memory objects allocated and not yet freed, keyed by address
+    memories: HashMap<usize, MemoryLabel>,
+    /// Number of memory objects allocated so far
+    count: u64,
+    /// Kind of allocations currently made
+    pool: Pool,
+    max_allocation: Option<u64>,
+    labeler: Option<Labeler<B::Memory>>,
+    misuse: MisuseHandler,
+    pd: BackendMarker<B>,
 }
 
-impl<B> RootAllocator<B> {
+impl<B> RootAllocator<B>
+where
+    B: Backend,
+{
     /// Create new allocator that will allocate memory of specified type.
     ///
     /// ### Parameters:
@@ -31,10 +47,81 @@ impl<B> RootAllocator<B> {
             relevant: Relevant,
             id,
             used: 0,
-            pd: PhantomData,
+            memories: HashMap::new(),
+            count: 0,
+            pool: Pool::Dedicated,
+            max_allocation: None,
+            labeler: None,
+            misuse: MisuseHandler::default(),
+            pd: BackendMarker::default(),
         }
     }
 
+    /// Set a callback to label memory objects right after they are allocated.
+    ///
+    /// The callback can give memory objects debug names, e.g. with a debug utils extension,
+    /// so that debuggers and validation layers show meaningful names.
+    pub fn set_labeler<F>(&mut self, callback: F)
+    where
+        F: Fn(&B::Memory, &MemoryLabel) + Send + Sync + 'static,
+    {
+        self.labeler = Some(Labeler::new(callback));
+    }
+
+    pub(crate) fn set_shared_labeler(&mut self, labeler: Labeler<B::Memory>) {
+        self.labeler = Some(labeler);
+    }
+
+    /// Set what happens when a block is freed that wasn't allocated by this allocator or was
+    /// already freed.
+    ///
+    /// Defaults to `MisusePolicy::Panic`.
+    pub fn set_misuse_policy(&mut self, policy: MisusePolicy) {
+        self.misuse.set_policy(policy);
+    }
+
+    pub(crate) fn set_misuse_handler(&mut self, handler: MisuseHandler) {
+        self.misuse = handler;
+    }
+
+    /// Set kind of allocations made until the next call.
+    pub(crate) fn set_pool(&mut self, pool: Pool) {
+        self.pool = pool;
+    }
+
+    /// Get the label of a memory object allocated by this allocator.
+    ///
+    /// Returns `None` if the memory object wasn't allocated by this allocator or was freed.
+    pub fn label(&self, memory: &B::Memory) -> Option<MemoryLabel> {
+        self.label_at(memory as *const B::Memory as usize)
+    }
+
+    /// Count memory objects in use allocated for a pool.
+    pub(crate) fn count_pool(&self, pool: Pool) -> usize {
+        self.memories
+            .values()
+            .filter(|label| label.pool == pool)
+            .count()
+    }
+
+    pub(crate) fn label_at(&self, memory: usize) -> Option<MemoryLabel> {
+        self.memories.get(&memory).cloned()
+    }
+
+    /// Limit the size of single memory objects.
+    ///
+    /// Some drivers fail allocations above a fraction of the heap size even when there is
+    /// enough memory available. Bigger allocations fail early with `MemoryError::TooLarge`
+    /// instead. `None` removes the limit, which is the default.
+    pub fn set_max_allocation(&mut self, max: Option<u64>) {
+        self.max_allocation = max;
+    }
+
+    /// Get the maximum size of single memory objects.
+    pub fn max_allocation(&self) -> Option<u64> {
+        self.max_allocation
+    }
+
     /// Get memory type this allocator allocates.
     pub fn memory_type(&self) -> MemoryTypeId {
         self.id
@@ -44,6 +131,41 @@ impl<B> RootAllocator<B> {
     pub fn used(&self) -> u64 {
         self.used
     }
+
+    /// Get the number of memory objects allocated from the device so far, including freed ones.
+    pub fn device_allocations(&self) -> u64 {
+        self.count
+    }
+
+    /// Get the number of bytes of host memory used by the bookkeeping of this allocator.
+    ///
+    /// Counts the boxed memory objects and their labels, not the allocator itself. The size of
+    /// the label map is estimated from its capacity.
+    pub fn host_memory(&self) -> usize {
+        self.memories.len() * size_of::<B::Memory>()
+            + self.memories.capacity() * size_of::<(usize, MemoryLabel)>()
+    }
+}
+
+impl<B> RootAllocator<B>
+where
+    B: Backend,
+{
+    /// Dispose of this allocator, freeing all memory even if blocks are still in use.
+    ///
+    /// This is intended for shutdown paths. Blocks that are still in use must never be accessed
+    /// or returned to the allocator afterwards, but leaked (e.g. with `std::mem::forget`).
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: must be the same device all allocations have been made against.
+    ///             The device must be idle.
+    pub unsafe fn dispose_forced(mut self, device: &B::Device) {
+        for (memory, _) in self.memories.drain() {
+            device.free_memory(*Box::from_raw(memory as *mut B::Memory));
+        }
+        self.relevant.dispose();
+    }
 }
 
 impl<B> MemoryAllocator<B> for RootAllocator<B>
@@ -59,16 +181,48 @@ where
         _: (),
         reqs: Requirements,
     ) -> Result<RawBlock<B::Memory>, MemoryError> {
+        check_requirements(&reqs)?;
+        match self.max_allocation {
+            Some(max) if reqs.size > max => {
+                return Err(MemoryError::TooLarge {
+                    size: reqs.size,
+                    max,
+                })
+            }
+            _ => {}
+        }
         let memory = device.allocate_memory(self.id, reqs.size)?;
+        let label = MemoryLabel {
+            pool: self.pool,
+            memory_type: self.id,
+            index: self.count,
+        };
+        self.count += 1;
+        if let Some(ref labeler) = self.labeler {
+            labeler.label(&memory, &label);
+        }
         let memory = Box::into_raw(Box::new(memory)); // Suboptimal
-        self.used += reqs.size;
+        self.memories.insert(memory as usize, label);
+        self.used = self.used.saturating_add(reqs.size);
         Ok(RawBlock::new(memory, 0..reqs.size))
     }
 
     unsafe fn free(&mut self, device: &B::Device, block: RawBlock<B::Memory>) {
         let size = block.size();
-        assert_eq!(block.range().start, 0);
-        device.free_memory(*Box::from_raw(block.memory() as *const _ as *mut _));
+        let memory = block.memory() as *const B::Memory as *mut B::Memory;
+        if block.range().start != 0 || !self.memories.contains_key(&(memory as usize)) {
+            self.misuse.report(
+                self.id,
+                format!(
+                    "Block {:?} wasn't allocated by this root allocator or was already freed",
+                    block.range()
+                ),
+            );
+            block.dispose();
+            return;
+        }
+        self.memories.remove(&(memory as usize));
+        device.free_memory(*Box::from_raw(memory));
         block.dispose();
         self.used -= size;
     }
@@ -0,0 +1,281 @@
+use std::fmt::{self, Debug};
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use gfx_hal::memory::Requirements;
+use gfx_hal::Backend;
+
+use block::Block;
+use {MemoryAllocator, MemoryError};
+
+/// Allocator split into shards that are locked independently, for allocating from many threads.
+///
+/// Every handle of a sharded allocator prefers its own home shard, and handles are given homes
+/// in turn, so threads using their own handles don't contend for a lock unless there are more
+/// threads than shards. Each shard owns its memory objects, so memory of blocks allocated from
+/// different shards can be mapped concurrently, while mapping memory of blocks of the same shard
+/// must happen with the shard locked. Blocks remember their shard and are freed into it.
+///
+/// ### Type parameters:
+///
+/// - `A`: allocator of each shard
+pub struct ShardedAllocator<A> {
+    shards: Arc<Shards<A>>,
+    home: usize,
+}
+
+struct Shards<A> {
+    shards: Vec<Mutex<A>>,
+    /// Home shard of the next handle
+    next: AtomicUsize,
+}
+
+impl<A> Debug for ShardedAllocator<A> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("ShardedAllocator")
+            .field("shards", &self.shards.shards.len())
+            .field("home", &self.home)
+            .finish()
+    }
+}
+
+impl<A> Clone for ShardedAllocator<A> {
+    fn clone(&self) -> Self {
+        self.handle()
+    }
+}
+
+impl<A> ShardedAllocator<A> {
+    /// Create a sharded allocator, returning its first handle.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `shards` is empty.
+    pub fn new<I>(shards: I) -> Self
+    where
+        I: IntoIterator<Item = A>,
+    {
+        let shards: Vec<_> = shards.into_iter().map(Mutex::new).collect();
+        assert!(
+            !shards.is_empty(),
+            "Sharded allocator needs at least one shard"
+        );
+        ShardedAllocator {
+            shards: Arc::new(Shards {
+                shards,
+                next: AtomicUsize::new(1),
+            }),
+            home: 0,
+        }
+    }
+
+    /// Create another handle, e.g. for another thread, with the next home shard.
+    pub fn handle(&self) -> Self {
+        let next = self.shards.next.fetch_add(1, Ordering::Relaxed);
+        ShardedAllocator {
+            shards: self.shards.clone(),
+            home: next % self.shards.shards.len(),
+        }
+    }
+
+    /// Get the number of shards.
+    pub fn shard_count(&self) -> usize {
+        self.shards.shards.len()
+    }
+
+    /// Get the shard this handle prefers.
+    pub fn home(&self) -> usize {
+        self.home
+    }
+
+    /// Lock a shard to allocate from.
+    ///
+    /// The home shard is preferred, then any other shard that isn't locked. If all are locked,
+    /// waits for the home shard.
+    ///
+    /// ### Returns
+    ///
+    /// Index of the locked shard and its allocator.
+    pub fn lock(&self) -> (usize, MutexGuard<A>) {
+        let count = self.shard_count();
+        for shard in (self.home..count).chain(0..self.home) {
+            if let Ok(guard) = self.shards.shards[shard].try_lock() {
+                return (shard, guard);
+            }
+        }
+        (self.home, self.lock_shard(self.home))
+    }
+
+    /// Lock a shard, e.g. to free blocks allocated from it.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `shard` is out of range.
+    pub fn lock_shard(&self, shard: usize) -> MutexGuard<A> {
+        self.shards.shards[shard].lock().unwrap()
+    }
+
+    /// Take the allocators of all shards, if this is the last handle.
+    pub fn into_shards(self) -> Result<Vec<A>, Self> {
+        let home = self.home;
+        match Arc::try_unwrap(self.shards) {
+            Ok(shards) => Ok(shards
+                .shards
+                .into_iter()
+                .map(|shard| shard.into_inner().unwrap())
+                .collect()),
+            Err(shards) => Err(ShardedAllocator { shards, home }),
+        }
+    }
+}
+
+impl<B, A> MemoryAllocator<B> for ShardedAllocator<A>
+where
+    B: Backend,
+    A: MemoryAllocator<B>,
+{
+    type Request = A::Request;
+    type Block = ShardedBlock<A::Block>;
+
+    unsafe fn alloc(
+        &mut self,
+        device: &B::Device,
+        request: A::Request,
+        reqs: Requirements,
+    ) -> Result<ShardedBlock<A::Block>, MemoryError> {
+        let (shard, mut allocator) = self.lock();
+        allocator
+            .alloc(device, request, reqs)
+            .map(|block| ShardedBlock { block, shard })
+    }
+
+    unsafe fn free(&mut self, device: &B::Device, block: ShardedBlock<A::Block>) {
+        self.lock_shard(block.shard).free(device, block.block)
+    }
+
+    fn is_used(&self) -> bool {
+        (0..self.shard_count()).any(|shard| self.lock_shard(shard).is_used())
+    }
+
+    /// Dispose of all shards.
+    ///
+    /// Fails if other handles are left or any shard is used.
+    unsafe fn dispose(self, device: &B::Device) -> Result<(), Self> {
+        if MemoryAllocator::<B>::is_used(&self) {
+            return Err(self);
+        }
+        for shard in self.into_shards()? {
+            if let Err(_shard) = shard.dispose(device) {
+                panic!("Shard failed to dispose although it isn't used");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Block allocated from a shard of a `ShardedAllocator`.
+#[derive(Debug)]
+pub struct ShardedBlock<T> {
+    block: T,
+    shard: usize,
+}
+
+impl<T> ShardedBlock<T> {
+    /// Wrap a block allocated from a locked shard.
+    pub(crate) fn new(block: T, shard: usize) -> Self {
+        ShardedBlock { block, shard }
+    }
+
+    /// Get the shard the block was allocated from.
+    pub fn shard(&self) -> usize {
+        self.shard
+    }
+
+    /// Get the block allocated by the shard.
+    pub fn inner(&self) -> &T {
+        &self.block
+    }
+
+    /// Unwrap the block, to be freed into the shard it was allocated from.
+    pub(crate) fn into_inner(self) -> T {
+        self.block
+    }
+}
+
+impl<T> Block for ShardedBlock<T>
+where
+    T: Block,
+{
+    type Memory = T::Memory;
+
+    #[inline]
+    fn memory(&self) -> &T::Memory {
+        self.block.memory()
+    }
+
+    #[inline]
+    fn range(&self) -> Range<u64> {
+        self.block.range()
+    }
+}
+
+#[test]
+fn test_lock() {
+    let allocator = ShardedAllocator::new(vec![0u32; 3]);
+    let handles: Vec<_> = (0..4).map(|_| allocator.handle()).collect();
+    assert_eq!(allocator.home(), 0);
+    assert_eq!(
+        handles
+            .iter()
+            .map(|handle| handle.home())
+            .collect::<Vec<_>>(),
+        vec![1, 2, 0, 1]
+    );
+
+    {
+        // Locked home shards are skipped
+        let (shard, _first) = allocator.lock();
+        assert_eq!(shard, 0);
+        let (shard, _second) = handles[2].lock();
+        assert_eq!(shard, 1);
+        let (shard, _third) = handles[3].lock();
+        assert_eq!(shard, 2);
+    }
+
+    let allocator = allocator.into_shards().unwrap_err();
+    drop(handles);
+    assert_eq!(allocator.into_shards().unwrap(), vec![0, 0, 0]);
+}
+
+#[test]
+fn test_concurrent_lock() {
+    use std::sync::Barrier;
+    use std::thread;
+
+    const THREADS: usize = 4;
+
+    let allocator = ShardedAllocator::new(vec![0u32; THREADS]);
+    let barrier = Arc::new(Barrier::new(THREADS));
+    let threads: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let handle = allocator.handle();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                for _ in 0..1000 {
+                    *handle.lock().1 += 1;
+                }
+                // Every thread holds its own shard at the same time
+                let (shard, mut guard) = handle.lock();
+                assert_eq!(shard, handle.home());
+                *guard += 1;
+                barrier.wait();
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    let shards = allocator.into_shards().unwrap();
+    assert_eq!(shards.iter().sum::<u32>(), THREADS as u32 * 1001);
+}
@@ -0,0 +1,449 @@
+//! Replay of allocation traces against pool configurations without a device.
+//!
+//! Traces are recorded from a real allocator with `TraceRecorder` or built by hand, and
+//! `compare_pools` runs them against the chunked allocators of several configurations on a
+//! simulated heap, so chunk sizes and strategies can be compared by measurement.
+
+use std::cmp::{max, min};
+use std::collections::HashMap;
+use std::fmt;
+
+use gfx_hal::memory::Requirements;
+use gfx_hal::{Backend, MemoryTypeId};
+
+use block::{Block, RawBlock};
+use chunked::{ChunkedAllocator, ChunkedBlock};
+use tuning::TuningProfile;
+use {check_requirements, MemoryAllocator, MemoryError};
+
+/// Operation of an `AllocationTrace`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceOp {
+    /// A block was allocated. Blocks are numbered by their allocation in the trace.
+    Alloc {
+        /// Requested size in bytes.
+        size: u64,
+        /// Requested alignment in bytes.
+        alignment: u64,
+    },
+
+    /// The block with this number was freed.
+    Free(usize),
+}
+
+/// Sequence of allocations and frees to replay with `compare_pools`.
+#[derive(Clone, Debug, Default)]
+pub struct AllocationTrace {
+    ops: Vec<TraceOp>,
+    allocations: usize,
+}
+
+impl AllocationTrace {
+    /// Create an empty trace.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an allocation and get the number of the block.
+    pub fn alloc(&mut self, size: u64, alignment: u64) -> usize {
+        self.ops.push(TraceOp::Alloc { size, alignment });
+        self.allocations += 1;
+        self.allocations - 1
+    }
+
+    /// Add a free of the block with number `block`.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if no block with this number was allocated yet.
+    pub fn free(&mut self, block: usize) {
+        assert!(block < self.allocations);
+        self.ops.push(TraceOp::Free(block));
+    }
+
+    /// Get the operations of the trace in order.
+    pub fn ops(&self) -> &[TraceOp] {
+        &self.ops
+    }
+}
+
+/// Allocator wrapper that records an `AllocationTrace` of all allocations and frees.
+///
+/// Failed allocations are not recorded.
+///
+/// ### Type parameters:
+///
+/// - `A`: wrapped allocator
+#[derive(Debug)]
+pub struct TraceRecorder<A> {
+    allocator: A,
+    trace: AllocationTrace,
+    /// Numbers of live blocks keyed by memory object address and offset
+    live: HashMap<(usize, u64), usize>,
+}
+
+impl<A> TraceRecorder<A> {
+    /// Wrap an allocator, starting with an empty trace.
+    pub fn new(allocator: A) -> Self {
+        TraceRecorder {
+            allocator,
+            trace: AllocationTrace::new(),
+            live: HashMap::new(),
+        }
+    }
+
+    /// Get the trace recorded so far.
+    pub fn trace(&self) -> &AllocationTrace {
+        &self.trace
+    }
+
+    /// Get the wrapped allocator.
+    pub fn inner(&self) -> &A {
+        &self.allocator
+    }
+
+    /// Get the wrapped allocator mutably.
+    ///
+    /// Blocks allocated from it directly are not recorded.
+    pub fn inner_mut(&mut self) -> &mut A {
+        &mut self.allocator
+    }
+
+    /// Unwrap the allocator and the recorded trace.
+    pub fn into_inner(self) -> (A, AllocationTrace) {
+        (self.allocator, self.trace)
+    }
+}
+
+/// Key of a block in `TraceRecorder::live`.
+fn block_key<T: Block>(block: &T) -> (usize, u64) {
+    (
+        block.memory() as *const T::Memory as usize,
+        block.range().start,
+    )
+}
+
+impl<B, A> MemoryAllocator<B> for TraceRecorder<A>
+where
+    B: Backend,
+    A: MemoryAllocator<B>,
+{
+    type Request = A::Request;
+    type Block = A::Block;
+
+    unsafe fn alloc(
+        &mut self,
+        device: &B::Device,
+        request: A::Request,
+        reqs: Requirements,
+    ) -> Result<A::Block, MemoryError> {
+        let block = self.allocator.alloc(device, request, reqs)?;
+        let number = self.trace.alloc(reqs.size, reqs.alignment);
+        self.live.insert(block_key(&block), number);
+        Ok(block)
+    }
+
+    unsafe fn free(&mut self, device: &B::Device, block: A::Block) {
+        if let Some(number) = self.live.remove(&block_key(&block)) {
+            self.trace.free(number);
+        }
+        self.allocator.free(device, block)
+    }
+
+    fn is_used(&self) -> bool {
+        self.allocator.is_used()
+    }
+
+    unsafe fn dispose(self, device: &B::Device) -> Result<(), Self> {
+        let TraceRecorder {
+            allocator,
+            trace,
+            live,
+        } = self;
+        allocator
+            .dispose(device)
+            .map_err(|allocator| TraceRecorder {
+                allocator,
+                trace,
+                live,
+            })
+    }
+}
+
+/// Pool configuration to replay a trace against, see `compare_pools`.
+#[derive(Clone, Debug)]
+pub struct SimulatedPool {
+    /// Name of the configuration in the comparison.
+    pub name: String,
+    /// Parameters of the chunked allocator and the dedicated threshold. Arena parameters are
+    /// not used, all blocks are simulated as `Type::General`.
+    pub profile: TuningProfile,
+    /// See `ChunkedAllocator::set_recycle`.
+    pub recycle: bool,
+}
+
+impl SimulatedPool {
+    /// Create a configuration from a profile, without recycling.
+    pub fn new<S: Into<String>>(name: S, profile: TuningProfile) -> Self {
+        SimulatedPool {
+            name: name.into(),
+            profile,
+            recycle: false,
+        }
+    }
+}
+
+/// Outcome of replaying a trace against a `SimulatedPool`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimulationResult {
+    /// Name of the configuration.
+    pub name: String,
+    /// Highest total size of live blocks, as requested.
+    pub peak_used: u64,
+    /// Highest total size of memory objects held, chunks and dedicated blocks.
+    pub peak_allocated: u64,
+    /// Fraction of the memory held at `peak_allocated` that was not used by live blocks.
+    pub fragmentation: f32,
+    /// Number of memory objects allocated from the simulated device.
+    pub device_allocations: u64,
+    /// Number of allocations that failed, e.g. because of invalid requirements.
+    pub failures: usize,
+}
+
+/// Results of `compare_pools`, one per configuration in order.
+///
+/// Printing it with `Display` gives a table with a row per configuration.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PoolComparison {
+    /// Results per configuration.
+    pub results: Vec<SimulationResult>,
+}
+
+impl PoolComparison {
+    /// Get the result with the lowest `peak_allocated`, if any.
+    pub fn best(&self) -> Option<&SimulationResult> {
+        self.results
+            .iter()
+            .min_by_key(|result| result.peak_allocated)
+    }
+}
+
+impl fmt::Display for PoolComparison {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let width = self
+            .results
+            .iter()
+            .map(|result| result.name.len())
+            .fold("pool".len(), max);
+        writeln!(
+            fmt,
+            "{:<width$} {:>14} {:>14} {:>13} {:>13} {:>8}",
+            "pool",
+            "peak used",
+            "peak held",
+            "fragmentation",
+            "device allocs",
+            "failures",
+            width = width
+        )?;
+        for result in &self.results {
+            writeln!(
+                fmt,
+                "{:<width$} {:>14} {:>14} {:>12.1}% {:>13} {:>8}",
+                result.name,
+                result.peak_used,
+                result.peak_allocated,
+                result.fragmentation * 100.0,
+                result.device_allocations,
+                result.failures,
+                width = width
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Memory object of the simulated heap, boxed so that each has an address of its own.
+type SimulatedMemory = u8;
+
+/// Live block of a simulation.
+enum SimulatedBlock {
+    Chunked(ChunkedBlock<SimulatedMemory>),
+    Dedicated(u64),
+}
+
+/// Replay `trace` against each pool configuration on a simulated heap.
+///
+/// Blocks bigger than the dedicated threshold or the maximum chunk size of a configuration get
+/// their own memory object, all others come from a `ChunkedAllocator` with its parameters.
+/// Chunks are kept once allocated, as without `trim`.
+///
+/// ### Panics
+///
+/// Panics if a configuration is invalid, see `TuningProfile::validate`.
+pub fn compare_pools(trace: &AllocationTrace, pools: &[SimulatedPool]) -> PoolComparison {
+    PoolComparison {
+        results: pools.iter().map(|pool| simulate(trace, pool)).collect(),
+    }
+}
+
+fn simulate(trace: &AllocationTrace, pool: &SimulatedPool) -> SimulationResult {
+    let profile = &pool.profile;
+    if let Err(err) = profile.validate() {
+        panic!("Invalid tuning profile of {}: {}", pool.name, err);
+    }
+    let mut chunks = ChunkedAllocator::<RawBlock<SimulatedMemory>>::new(
+        MemoryTypeId(0),
+        profile.blocks_per_chunk,
+        profile.min_block_size,
+        profile.max_chunk_size,
+    );
+    chunks.set_recycle(pool.recycle);
+    let threshold = min(profile.dedicated_threshold, profile.max_chunk_size);
+    let mut memories = Vec::new();
+    let mut live = HashMap::new();
+    let mut used = 0;
+    let mut dedicated = 0;
+    let mut result = SimulationResult {
+        name: pool.name.clone(),
+        peak_used: 0,
+        peak_allocated: 0,
+        fragmentation: 0.0,
+        device_allocations: 0,
+        failures: 0,
+    };
+
+    let mut number = 0;
+    for op in trace.ops() {
+        match *op {
+            TraceOp::Alloc { size, alignment } => {
+                let reqs = Requirements {
+                    size,
+                    alignment,
+                    type_mask: 1,
+                };
+                let block = if let Err(err) = check_requirements(&reqs) {
+                    Err(err)
+                } else if size > threshold {
+                    dedicated += size;
+                    result.device_allocations += 1;
+                    Ok(SimulatedBlock::Dedicated(size))
+                } else {
+                    let device_allocations = &mut result.device_allocations;
+                    chunks
+                        .alloc_with(reqs, |size| {
+                            let memory = Box::<SimulatedMemory>::new(0);
+                            let chunk = RawBlock::new(&*memory, 0..size);
+                            memories.push(memory);
+                            *device_allocations += 1;
+                            chunk
+                        })
+                        .map(SimulatedBlock::Chunked)
+                };
+                match block {
+                    Ok(block) => {
+                        used += size;
+                        live.insert(number, (block, size));
+                    }
+                    Err(_) => result.failures += 1,
+                }
+                number += 1;
+            }
+            TraceOp::Free(block) => match live.remove(&block) {
+                Some((SimulatedBlock::Chunked(block), size)) => {
+                    used -= size;
+                    chunks.release(block);
+                }
+                Some((SimulatedBlock::Dedicated(size), _)) => {
+                    used -= size;
+                    dedicated -= size;
+                }
+                None => {}
+            },
+        }
+        result.peak_used = max(result.peak_used, used);
+        let allocated = chunks.allocated() + dedicated;
+        if allocated > result.peak_allocated {
+            result.peak_allocated = allocated;
+            result.fragmentation = 1.0 - used as f32 / allocated as f32;
+        }
+    }
+
+    for (_, (block, _)) in live {
+        if let SimulatedBlock::Chunked(block) = block {
+            chunks.release(block);
+        }
+    }
+    for chunk in chunks.into_chunks() {
+        unsafe { chunk.dispose() }
+    }
+    drop(memories);
+    result
+}
+
+#[test]
+fn test_trace() {
+    let mut trace = AllocationTrace::new();
+    let first = trace.alloc(256, 16);
+    let second = trace.alloc(1024, 256);
+    trace.free(first);
+    assert_eq!((first, second), (0, 1));
+    assert_eq!(
+        trace.ops(),
+        &[
+            TraceOp::Alloc {
+                size: 256,
+                alignment: 16
+            },
+            TraceOp::Alloc {
+                size: 1024,
+                alignment: 256
+            },
+            TraceOp::Free(0),
+        ]
+    );
+}
+
+#[test]
+fn test_compare_pools() {
+    let mut trace = AllocationTrace::new();
+    let blocks: Vec<_> = (0..32).map(|_| trace.alloc(1000, 256)).collect();
+    let big = trace.alloc(64 << 20, 256);
+    for &block in blocks.iter().step_by(2) {
+        trace.free(block);
+    }
+    trace.free(big);
+
+    let small = TuningProfile {
+        blocks_per_chunk: 8,
+        min_block_size: 256,
+        max_chunk_size: 1 << 20,
+        dedicated_threshold: 1 << 19,
+        ..TuningProfile::default()
+    };
+    let large = TuningProfile {
+        blocks_per_chunk: 64,
+        max_chunk_size: 128 << 20,
+        dedicated_threshold: 128 << 20,
+        ..small
+    };
+    let comparison = compare_pools(
+        &trace,
+        &[
+            SimulatedPool::new("small", small),
+            SimulatedPool::new("large", large),
+        ],
+    );
+    let small = &comparison.results[0];
+    assert_eq!(small.peak_used, 32 * 1000 + (64 << 20));
+    // 4 chunks of 8 blocks of 1 KiB and one dedicated block
+    assert_eq!(small.device_allocations, 5);
+    assert_eq!(small.peak_allocated, 32 * 1024 + (64 << 20));
+    assert_eq!(small.failures, 0);
+    let large = &comparison.results[1];
+    assert_eq!(large.peak_used, small.peak_used);
+    assert_eq!(large.device_allocations, 2);
+    assert!(large.peak_allocated > small.peak_allocated);
+    assert_eq!(comparison.best().unwrap().name, "small");
+    assert_eq!(comparison.to_string().lines().count(), 3);
+}
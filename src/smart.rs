@@ -1,13 +1,36 @@
 use std::any::Any;
-use std::fmt::Debug;
+use std::cmp::{max, min, Ordering};
+use std::collections::VecDeque;
+use std::fmt::{self, Debug};
+use std::mem::{forget, size_of};
 use std::ops::Range;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 
 use gfx_hal::memory::{Properties, Requirements};
-use gfx_hal::{Backend, MemoryProperties, MemoryType, MemoryTypeId};
+use gfx_hal::{Backend, Limits, MemoryProperties, MemoryType, MemoryTypeId};
 
-use block::Block;
-use combined::{CombinedAllocator, CombinedBlock, Type};
-use {MemoryAllocator, MemoryError};
+use arena::{ArenaGrowth, ArenaRounding};
+use block::{Block, RawBlock};
+use class::{MemoryClass, MemoryTypeInfo};
+use combined::{CombinedAllocator, CombinedBlock, CombinedTag, Type};
+use config::SmartAllocatorConfig;
+use domain::{Domain, DomainStats, Domains};
+use event::{Event, Listener};
+use guard::Relevant;
+use invariant::InvariantViolation;
+use label::{BlockLocation, Labeler, MemoryLabel};
+#[cfg(feature = "block-age")]
+use metadata::AgedBlock;
+use metadata::BlockMetadata;
+use misuse::{Misuse, MisuseHandler, MisusePolicy};
+use request::Request;
+use stats::{
+    AllocationFailure, AllocatorConfig, ChunkInfo, ChunkStats, FrameStats, HeapUsage, Leak,
+    LeakReport, SizeHistogram,
+};
+use tuning::{DedicatedThreshold, Slack, TuningProfile};
+use {check_requirements, IncompatibleMemory, MemoryAllocator, MemoryError};
 
 /// Allocator that can choose memory type based on requirements, and keeps track of allocators
 /// for all given memory types.
@@ -17,6 +40,26 @@ use {MemoryAllocator, MemoryError};
 pub struct SmartAllocator<B: Backend> {
     allocators: Vec<(MemoryType, CombinedAllocator<B>)>,
     heaps: Vec<Heap>,
+    spill: Option<SpillPolicy>,
+    heap_selection: HeapSelection,
+    next_heap: usize,
+    trim: Option<TrimPolicy>,
+    retry: Option<RetryPolicy>,
+    backoff: Backoff,
+    frame_budget: Option<u64>,
+    frame_allocations: u64,
+    frame_allocs: u64,
+    frame_frees: u64,
+    /// Highest total size of live blocks
+    peak: u64,
+    /// Most recent failed allocations, oldest first
+    failures: VecDeque<AllocationFailure>,
+    failure_history: usize,
+    domains: Domains,
+    /// Bit mask of memory types never chosen
+    disabled: u64,
+    listener: Listener,
+    misuse: MisuseHandler,
 }
 
 impl<B> SmartAllocator<B>
@@ -25,114 +68,1620 @@ where
 {
     /// Create a new smart allocator from `MemoryProperties` given by a device.
     ///
+    /// Without device limits, readback blocks in memory types that aren't `COHERENT` are padded
+    /// to `MAX_NON_COHERENT_ATOM_SIZE`, see `CombinedAllocator::set_readback_atom`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `memory_properties`: memory properties describing the memory available on a device
+    /// - `arena_chunk_size`: see `ArenaAllocator`
+    /// - `blocks_per_chunk`: see `ChunkedAllocator`
+    /// - `min_block_size`: see `ChunkedAllocator`
+    /// - `max_chunk_size`: see `ChunkedAllocator`
+    ///
+    /// ### Panics
+    ///
+    /// Panics if the parameters are invalid, see `TuningProfile::validate`.
+    pub fn new(
+        memory_properties: MemoryProperties,
+        arena_chunk_size: u64,
+        blocks_per_chunk: usize,
+        min_block_size: u64,
+        max_chunk_size: u64,
+    ) -> Self {
+        Self::from_allocators(memory_properties, |id| {
+            CombinedAllocator::new(
+                id,
+                arena_chunk_size,
+                blocks_per_chunk,
+                min_block_size,
+                max_chunk_size,
+            )
+        })
+    }
+
+    /// Create a new smart allocator with chunk sizes and alignment adjusted to device limits.
+    ///
+    /// See `CombinedAllocator::with_limits`. Readback blocks are only padded to
+    /// `nonCoherentAtomSize` in memory types that aren't `COHERENT`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `memory_properties`: memory properties describing the memory available on a device
+    /// - `limits`: limits of the device
+    /// - `arena_chunk_size`: see `ArenaAllocator`
+    /// - `blocks_per_chunk`: see `ChunkedAllocator`
+    /// - `min_block_size`: see `ChunkedAllocator`
+    /// - `max_chunk_size`: see `ChunkedAllocator`
+    pub fn with_limits(
+        memory_properties: MemoryProperties,
+        limits: &Limits,
+        arena_chunk_size: u64,
+        blocks_per_chunk: usize,
+        min_block_size: u64,
+        max_chunk_size: u64,
+    ) -> Self {
+        Self::from_allocators(memory_properties, |id| {
+            CombinedAllocator::with_limits(
+                id,
+                limits,
+                arena_chunk_size,
+                blocks_per_chunk,
+                min_block_size,
+                max_chunk_size,
+            )
+        })
+    }
+
+    /// Create a new smart allocator tuned by a profile.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `memory_properties`: memory properties describing the memory available on a device
+    /// - `limits`: limits of the device, see `with_limits`
+    /// - `profile`: parameters of the allocators, e.g. from `TuningProfile::for_adapter`
+    ///
+    /// ### Panics
+    ///
+    /// Panics if the profile is invalid, see `TuningProfile::validate`.
+    pub fn with_profile(
+        memory_properties: MemoryProperties,
+        limits: &Limits,
+        profile: &TuningProfile,
+    ) -> Self {
+        if let Err(err) = profile.validate() {
+            panic!("Invalid tuning profile: {}", err);
+        }
+        let mut allocator = Self::with_limits(
+            memory_properties,
+            limits,
+            profile.arena_chunk_size,
+            profile.blocks_per_chunk,
+            profile.min_block_size,
+            profile.max_chunk_size,
+        );
+        allocator.set_dedicated_threshold(profile.dedicated_threshold);
+        allocator.set_arena_rounding(profile.arena_rounding);
+        for heap_index in 0..allocator.heaps.len() {
+            let size = allocator.heaps[heap_index].size();
+            allocator.set_emergency_reserve(
+                heap_index,
+                (size as f64 * profile.heap_headroom as f64) as u64,
+            );
+        }
+        allocator
+    }
+
+    /// Create a new smart allocator from a configuration.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `memory_properties`: memory properties describing the memory available on a device
+    /// - `limits`: limits of the device, see `with_limits`
+    /// - `config`: configuration of the allocator, can be shared between devices
+    ///
+    /// ### Panics
+    ///
+    /// Panics if the configuration is invalid, see `SmartAllocatorConfig::validate_for`.
+    pub fn from_config(
+        memory_properties: MemoryProperties,
+        limits: &Limits,
+        config: &SmartAllocatorConfig,
+    ) -> Self {
+        if let Err(err) = config.validate_for(&memory_properties) {
+            panic!("Invalid allocator configuration: {}", err);
+        }
+        let mut allocator = Self::with_profile(memory_properties, limits, &config.profile);
+        for (memory_type, combined) in &mut allocator.allocators {
+            if let Some(alignment) = config.min_alignment {
+                if alignment > combined.min_alignment() {
+                    combined.set_min_alignment(alignment);
+                }
+            }
+            combined.set_arena_growth(config.arena_growth);
+            if let Some(&(_, size)) = config
+                .large_pages
+                .iter()
+                .find(|&&(heap_index, _)| heap_index == memory_type.heap_index)
+            {
+                combined.set_large_page(Some(size));
+            }
+            if let Some(&(_, max)) = config
+                .max_allocations
+                .iter()
+                .find(|&&(heap_index, _)| heap_index == memory_type.heap_index)
+            {
+                combined.set_max_allocation(Some(max));
+            }
+            combined.set_partial_chunks(config.partial_chunks);
+            combined.set_recycle(config.recycle);
+            combined.set_slack(config.slack);
+            combined.set_tracking(config.tracking);
+            combined.set_segregate_readback(config.segregate_readback);
+        }
+        for &(heap_index, size) in &config.heap_caps {
+            allocator.set_heap_cap(heap_index, Some(size));
+        }
+        if let Some(rule) = config.dedicated_threshold {
+            allocator.set_dedicated_threshold_rule(rule);
+        }
+        for &(domain, budget) in &config.domain_budgets {
+            allocator.set_domain_budget(domain, Some(budget));
+        }
+        allocator.spill = config.spill;
+        allocator.heap_selection = config.heap_selection;
+        allocator.trim = config.trim;
+        allocator.retry = config.retry;
+        allocator.frame_budget = config.frame_budget;
+        allocator.set_misuse_policy(config.misuse);
+        allocator
+    }
+
+    /// Create a new smart allocator for compute workloads.
+    ///
+    /// Uses `TuningProfile::compute`, which suits big long-lived storage buffers. Blocks read
+    /// back by the host are best allocated with `alloc_readback`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `memory_properties`: memory properties describing the memory available on a device
+    /// - `limits`: limits of the device, see `with_limits`
+    pub fn compute(memory_properties: MemoryProperties, limits: &Limits) -> Self {
+        Self::with_profile(memory_properties, limits, &TuningProfile::compute())
+    }
+
+    /// Readback blocks of `COHERENT` memory types are never padded, as they aren't invalidated.
+    fn from_allocators<F>(memory_properties: MemoryProperties, mut allocator: F) -> Self
+    where
+        F: FnMut(MemoryTypeId) -> CombinedAllocator<B>,
+    {
+        SmartAllocator {
+            allocators: memory_properties
+                .memory_types
+                .into_iter()
+                .enumerate()
+                .map(|(index, memory_type)| {
+                    let mut combined = allocator(MemoryTypeId(index));
+                    if memory_type.properties.contains(Properties::COHERENT) {
+                        combined.set_readback_atom(None);
+                    }
+                    (memory_type, combined)
+                })
+                .collect(),
+            heaps: memory_properties
+                .memory_heaps
+                .into_iter()
+                .map(|size| Heap {
+                    size,
+                    used: 0,
+                    reserved: 0,
+                    planned: 0,
+                    high: false,
+                    cap: None,
+                })
+                .collect(),
+            spill: None,
+            heap_selection: HeapSelection::LeastUsed,
+            next_heap: 0,
+            trim: None,
+            retry: None,
+            backoff: Backoff::default(),
+            frame_budget: None,
+            frame_allocations: 0,
+            frame_allocs: 0,
+            frame_frees: 0,
+            peak: 0,
+            failures: VecDeque::new(),
+            failure_history: 16,
+            domains: Domains::default(),
+            disabled: 0,
+            listener: Listener::default(),
+            misuse: MisuseHandler::default(),
+        }
+    }
+
+    /// Get properties of the block
+    pub fn properties(&self, block: &SmartBlock<B::Memory>) -> Properties {
+        self.allocators[block.memory_type as usize].0.properties
+    }
+
+    /// Get the class of a memory type, or `None` if there is no such memory type.
+    pub fn memory_class(&self, memory_type: MemoryTypeId) -> Option<MemoryClass> {
+        self.allocators
+            .get(memory_type.0)
+            .map(|&(ref memory_type, _)| MemoryClass::of(memory_type.properties))
+    }
+
+    /// Get descriptions of all memory types of the device, e.g. for a device info panel.
+    pub fn memory_types<'a>(&'a self) -> impl Iterator<Item = MemoryTypeInfo> + 'a {
+        self.allocators
+            .iter()
+            .enumerate()
+            .map(move |(index, &(ref memory_type, _))| MemoryTypeInfo {
+                id: MemoryTypeId(index),
+                properties: memory_type.properties,
+                heap_index: memory_type.heap_index,
+                heap_size: self.heaps[memory_type.heap_index].size,
+                class: MemoryClass::of(memory_type.properties),
+            })
+    }
+
+    /// Get memory types of a class.
+    pub fn memory_types_of<'a>(
+        &'a self,
+        class: MemoryClass,
+    ) -> impl Iterator<Item = MemoryTypeId> + 'a {
+        self.allocators
+            .iter()
+            .enumerate()
+            .filter(move |&(_, &(ref memory_type, _))| {
+                MemoryClass::of(memory_type.properties) == class
+            })
+            .map(|(index, _)| MemoryTypeId(index))
+    }
+
+    /// Never choose a memory type for new blocks, e.g. to work around a vendor-specific issue.
+    ///
+    /// Blocks already allocated from the memory type are not affected.
+    pub fn disable_memory_type(&mut self, memory_type: MemoryTypeId) {
+        self.disabled |= 1 << memory_type.0;
+    }
+
+    /// Allow choosing a memory type disabled with `disable_memory_type` again.
+    pub fn enable_memory_type(&mut self, memory_type: MemoryTypeId) {
+        self.disabled &= !(1 << memory_type.0);
+    }
+
+    /// Check if a memory type can be chosen for new blocks.
+    pub fn is_memory_type_enabled(&self, memory_type: MemoryTypeId) -> bool {
+        self.disabled & (1 << memory_type.0) == 0
+    }
+
+    /// Check if only one memory type can be chosen for new blocks.
+    ///
+    /// Devices of headless or software backends often expose a single memory type. All blocks
+    /// are then allocated from it directly, skipping preferences, spilling and the comparison
+    /// of heaps. Heap usage is still tracked for statistics and budgets.
+    pub fn is_single_type(&self) -> bool {
+        self.single_type().is_some()
+    }
+
+    /// Get the only memory type that can be chosen, if there is one.
+    fn single_type(&self) -> Option<usize> {
+        let enabled = !self.disabled & ((1u64 << self.allocators.len()) - 1);
+        if enabled.count_ones() == 1 {
+            Some(enabled.trailing_zeros() as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Set the size above which `Type::General` blocks get their own memory object for all
+    /// memory types.
+    ///
+    /// See `CombinedAllocator::set_dedicated_threshold`.
+    pub fn set_dedicated_threshold(&mut self, threshold: u64) {
+        for (_, allocator) in &mut self.allocators {
+            allocator.set_dedicated_threshold(threshold);
+        }
+    }
+
+    /// Set the size above which `Type::General` blocks get their own memory object, in bytes
+    /// or as a fraction of the heap of each memory type.
+    ///
+    /// Heap caps set by `set_heap_cap` are taken into account, but the threshold is not
+    /// updated when they change later. See `CombinedAllocator::set_dedicated_threshold`.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if the fraction is not in `(0, 1]`.
+    pub fn set_dedicated_threshold_rule(&mut self, rule: DedicatedThreshold) {
+        if let Err(err) = rule.validate() {
+            panic!("Invalid dedicated threshold: {}", err);
+        }
+        for (memory_type, allocator) in &mut self.allocators {
+            allocator
+                .set_dedicated_threshold(rule.for_heap(self.heaps[memory_type.heap_index].size()));
+        }
+    }
+
+    /// Set the minimum alignment of blocks allocated from all memory types.
+    ///
+    /// See `CombinedAllocator::set_min_alignment`.
+    pub fn set_min_alignment(&mut self, alignment: u64) {
+        for (_, allocator) in &mut self.allocators {
+            allocator.set_min_alignment(alignment);
+        }
+    }
+
+    /// Allow chunks smaller than the chunk size when a heap is nearly full, for all memory types.
+    ///
+    /// See `ChunkedAllocator::set_partial_chunks`.
+    pub fn set_partial_chunks(&mut self, partial: bool) {
+        for (_, allocator) in &mut self.allocators {
+            allocator.set_partial_chunks(partial);
+        }
+    }
+
+    /// Prefer recently freed blocks and partially full chunks for all memory types.
+    ///
+    /// See `ChunkedAllocator::set_recycle`.
+    pub fn set_recycle(&mut self, recycle: bool) {
+        for (_, allocator) in &mut self.allocators {
+            allocator.set_recycle(recycle);
+        }
+    }
+
+    /// Set geometric growth of arena chunk sizes for all memory types.
+    ///
+    /// See `ArenaAllocator::set_growth`.
+    pub fn set_arena_growth(&mut self, growth: Option<ArenaGrowth>) {
+        for (_, allocator) in &mut self.allocators {
+            allocator.set_arena_growth(growth);
+        }
+    }
+
+    /// Set how arena chunks are sized for big blocks for all memory types.
+    ///
+    /// See `ArenaAllocator::set_rounding`.
+    pub fn set_arena_rounding(&mut self, rounding: ArenaRounding) {
+        for (_, allocator) in &mut self.allocators {
+            allocator.set_arena_rounding(rounding);
+        }
+    }
+
+    /// Reserve extra space behind blocks of all memory types so they can grow in place.
+    ///
+    /// See `CombinedAllocator::set_slack`.
+    pub fn set_slack(&mut self, slack: Option<Slack>) {
+        for (_, allocator) in &mut self.allocators {
+            allocator.set_slack(slack);
+        }
+    }
+
+    /// Track live blocks of all memory types with their metadata.
+    ///
+    /// See `CombinedAllocator::set_tracking`.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if blocks are allocated.
+    pub fn set_tracking(&mut self, tracking: bool) {
+        for (_, allocator) in &mut self.allocators {
+            allocator.set_tracking(tracking);
+        }
+    }
+
+    /// Keep blocks read by the host in chunks of their own for all memory types.
+    ///
+    /// See `CombinedAllocator::set_segregate_readback`.
+    pub fn set_segregate_readback(&mut self, segregate: bool) {
+        for (_, allocator) in &mut self.allocators {
+            allocator.set_segregate_readback(segregate);
+        }
+    }
+
+    /// Align memory objects of all memory types of a heap to large pages of `size` bytes.
+    ///
+    /// See `CombinedAllocator::set_large_page`.
+    pub fn set_large_page(&mut self, heap_index: usize, size: Option<u64>) {
+        for (memory_type, allocator) in &mut self.allocators {
+            if memory_type.heap_index == heap_index {
+                allocator.set_large_page(size);
+            }
+        }
+    }
+
+    /// Limit the size of single memory objects of all memory types of a heap.
+    ///
+    /// See `CombinedAllocator::set_max_allocation`.
+    pub fn set_max_allocation(&mut self, heap_index: usize, max: Option<u64>) {
+        for (memory_type, allocator) in &mut self.allocators {
+            if memory_type.heap_index == heap_index {
+                allocator.set_max_allocation(max);
+            }
+        }
+    }
+
+    /// Get the total size of all blocks allocated by this allocator.
+    pub fn used(&self) -> u64 {
+        self.allocators.iter().map(|alloc| alloc.1.used()).sum()
+    }
+
+    /// Get the total size of all chunks allocated by this allocator.
+    pub fn allocated(&self) -> u64 {
+        self.allocators
+            .iter()
+            .map(|alloc| alloc.1.allocated())
+            .sum()
+    }
+
+    /// Get the number of bytes lost to alignment and rounding by this allocator.
+    pub fn wasted(&self) -> u64 {
+        self.allocators.iter().map(|alloc| alloc.1.wasted()).sum()
+    }
+
+    /// Get the number of bytes of host memory used by the bookkeeping of this allocator.
+    ///
+    /// Includes `CombinedAllocator::host_memory` of all memory types, the heaps, domains and
+    /// recorded failures. Useful to budget the overhead of the allocator on platforms with
+    /// little host memory. The allocator itself is not counted.
+    pub fn host_memory(&self) -> usize {
+        self.allocators.capacity() * size_of::<(MemoryType, CombinedAllocator<B>)>()
+            + self
+                .allocators
+                .iter()
+                .map(|alloc| alloc.1.host_memory())
+                .sum::<usize>()
+            + self.heaps.capacity() * size_of::<Heap>()
+            + self.failures.capacity() * size_of::<AllocationFailure>()
+            + self.domains.host_memory()
+    }
+
+    /// Set the policy for spilling allocations out of device local memory.
+    /// `None` disables spilling, which is the default.
+    pub fn set_spill_policy(&mut self, policy: Option<SpillPolicy>) {
+        self.spill = policy;
+    }
+
+    /// Get the policy for spilling allocations out of device local memory.
+    pub fn spill_policy(&self) -> Option<SpillPolicy> {
+        self.spill
+    }
+
+    /// Set the policy for choosing between heaps of device local memory.
+    pub fn set_heap_selection(&mut self, selection: HeapSelection) {
+        self.heap_selection = selection;
+    }
+
+    /// Get the policy for choosing between heaps of device local memory.
+    pub fn heap_selection(&self) -> HeapSelection {
+        self.heap_selection
+    }
+
+    /// Reserve memory of a heap for emergencies.
+    ///
+    /// Allocations can't use the last `size` bytes of the heap until the reserve is released
+    /// with `use_emergency_reserve`. This keeps some memory available for e.g. an error screen
+    /// when the heap is otherwise exhausted.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `size` is larger than the heap.
+    pub fn set_emergency_reserve(&mut self, heap_index: usize, size: u64) {
+        assert!(size <= self.heaps[heap_index].size());
+        self.heaps[heap_index].reserved = size;
+    }
+
+    /// Pretend a heap is smaller than it is, down to `size` bytes. `None` removes the cap.
+    ///
+    /// Allocations fail with `MemoryError::OutOfMemory` or spill to other heaps as if the heap
+    /// had the capped size, so behavior on devices with less memory can be tested on
+    /// developer machines. Emergency reserves are not adjusted to the cap. A cap of zero makes
+    /// the heap appear full.
+    pub fn set_heap_cap(&mut self, heap_index: usize, size: Option<u64>) {
+        self.heaps[heap_index].cap = size;
+    }
+
+    /// Get the cap of a heap set by `set_heap_cap`.
+    pub fn heap_cap(&self, heap_index: usize) -> Option<u64> {
+        self.heaps[heap_index].cap
+    }
+
+    /// Get the size of the emergency reserve of a heap.
+    pub fn emergency_reserve(&self, heap_index: usize) -> u64 {
+        self.heaps[heap_index].reserved
+    }
+
+    /// Release emergency reserves of all heaps, making them available for allocations.
+    ///
+    /// ### Returns
+    ///
+    /// `true` if any memory was reserved.
+    pub fn use_emergency_reserve(&mut self) -> bool {
+        let mut released = false;
+        for heap in &mut self.heaps {
+            released |= heap.reserved != 0;
+            heap.reserved = 0;
+        }
+        released
+    }
+
+    /// Set the policy for trimming cached chunks automatically.
+    /// `None` disables automatic trimming, which is the default.
+    pub fn set_trim_policy(&mut self, policy: Option<TrimPolicy>) {
+        self.trim = policy;
+    }
+
+    /// Get the policy for trimming cached chunks automatically.
+    pub fn trim_policy(&self) -> Option<TrimPolicy> {
+        self.trim
+    }
+
+    /// Set the policy for retrying allocations the device failed transiently.
+    /// `None` disables retrying, which is the default.
+    pub fn set_retry_policy(&mut self, policy: Option<RetryPolicy>) {
+        self.retry = policy;
+    }
+
+    /// Get the policy for retrying allocations the device failed transiently.
+    pub fn retry_policy(&self) -> Option<RetryPolicy> {
+        self.retry
+    }
+
+    /// Set the maximum number of memory objects allocated from the device per frame.
+    /// `None` removes the limit, which is the default.
+    ///
+    /// Allocating memory from the device can take milliseconds. Once the budget is spent,
+    /// allocations that can't be served from memory already allocated fail with
+    /// `MemoryError::WouldGrow` until the next `begin_frame`, so they can be deferred.
+    pub fn set_frame_budget(&mut self, budget: Option<u64>) {
+        self.frame_budget = budget;
+    }
+
+    /// Get the maximum number of memory objects allocated from the device per frame.
+    pub fn frame_budget(&self) -> Option<u64> {
+        self.frame_budget
+    }
+
+    /// Start a new frame, resetting the numbers of memory objects and blocks allocated and
+    /// freed in the frame. With the `block-age` feature, live blocks become one frame older.
+    pub fn begin_frame(&mut self) {
+        self.frame_allocations = 0;
+        self.frame_allocs = 0;
+        self.frame_frees = 0;
+        #[cfg(feature = "block-age")]
+        for (_, allocator) in &mut self.allocators {
+            allocator.begin_frame();
+        }
+    }
+
+    /// Get live blocks allocated at least `frames` frames ago, optionally only those tagged
+    /// with `tag`.
+    ///
+    /// See `CombinedAllocator::blocks_older_than`.
+    #[cfg(feature = "block-age")]
+    pub fn blocks_older_than<'a>(
+        &'a self,
+        frames: u64,
+        tag: Option<&'a str>,
+    ) -> impl Iterator<Item = AgedBlock> + 'a {
+        self.allocators
+            .iter()
+            .flat_map(move |(_, allocator)| allocator.blocks_older_than(frames, tag))
+    }
+
+    /// Get the number of memory objects allocated from the device since `begin_frame`.
+    pub fn frame_allocations(&self) -> u64 {
+        self.frame_allocations
+    }
+
+    /// Limit the total size of live blocks of a domain. `None` removes the limit.
+    ///
+    /// Allocations that don't fit fail with `MemoryError::OverBudget`. Blocks already allocated
+    /// are kept when the budget is lowered below the usage of the domain.
+    pub fn set_domain_budget(&mut self, domain: Domain, budget: Option<u64>) {
+        self.domains.set_budget(domain, budget);
+    }
+
+    /// Get the budget and usage of a domain.
+    pub fn domain_stats(&self, domain: Domain) -> DomainStats {
+        self.domains.get(domain)
+    }
+
+    /// Get the budgets and usage of all domains used so far or given a budget.
+    pub fn domains<'a>(&'a self) -> impl Iterator<Item = (Domain, DomainStats)> + 'a {
+        self.domains.iter()
+    }
+
+    /// Free all blocks of a domain at once, e.g. when a level is unloaded.
+    ///
+    /// Heaps are trimmed once after all blocks are freed, rather than after each block.
+    ///
+    /// ### Returns
+    ///
+    /// Budget and usage of the domain after freeing the blocks. Blocks of the domain that were
+    /// not passed are still counted, which shows them as leaks.
+    ///
+    /// If any block belongs to another domain, no block is freed and all are returned back.
+    pub unsafe fn release_domain<I>(
+        &mut self,
+        device: &B::Device,
+        domain: Domain,
+        blocks: I,
+    ) -> Result<DomainStats, Vec<SmartBlock<B::Memory>>>
+    where
+        I: IntoIterator<Item = SmartBlock<B::Memory>>,
+    {
+        let blocks: Vec<_> = blocks.into_iter().collect();
+        if blocks.iter().any(|block| block.domain() != domain) {
+            return Err(blocks);
+        }
+        let trim = self.trim.take();
+        for block in blocks {
+            self.free(device, block);
+        }
+        self.trim = trim;
+        if let Some(policy) = self.trim {
+            for heap_index in 0..self.heaps.len() {
+                if self.heaps[heap_index].high && self.heaps[heap_index].usage() < policy.low {
+                    self.heaps[heap_index].high = false;
+                    self.trim_heap(device, heap_index);
+                }
+            }
+        }
+        Ok(self.domains.get(domain))
+    }
+
+    /// Set the number of failed allocations kept for `recent_failures`. Defaults to 16.
+    ///
+    /// Failures with `MemoryError::WouldGrow` are expected when a frame budget is set, and are
+    /// not kept.
+    pub fn set_failure_history(&mut self, count: usize) {
+        self.failure_history = count;
+        while self.failures.len() > count {
+            self.failures.pop_front();
+        }
+    }
+
+    /// Get the number of failed allocations kept for `recent_failures`.
+    pub fn failure_history(&self) -> usize {
+        self.failure_history
+    }
+
+    /// Get the most recent failed allocations, oldest first.
+    pub fn recent_failures<'a>(&'a self) -> impl Iterator<Item = &'a AllocationFailure> + 'a {
+        self.failures.iter()
+    }
+
+    /// Forget the failed allocations kept so far.
+    pub fn clear_failures(&mut self) {
+        self.failures.clear();
+    }
+
+    /// Get a summary of the allocator cheap enough to poll every frame.
+    pub fn frame_stats(&self) -> FrameStats {
+        FrameStats {
+            allocated: self.allocated(),
+            used: self.used(),
+            live_blocks: self
+                .allocators
+                .iter()
+                .map(|alloc| alloc.1.allocations() as u64)
+                .sum(),
+            allocs_this_frame: self.frame_allocs,
+            frees_this_frame: self.frame_frees,
+            peak: self.peak,
+        }
+    }
+
+    /// Set a callback waiting between retries of failed allocations instead of sleeping.
+    ///
+    /// The callback receives the delay for the attempt and may e.g. wait for in-flight frames
+    /// to finish so that their memory is released.
+    pub fn set_backoff<F>(&mut self, callback: F)
+    where
+        F: Fn(Duration) + Send + Sync + 'static,
+    {
+        self.backoff.0 = Some(Box::new(callback));
+    }
+
+    /// Get statistics of all chunks sub-allocated by this allocator.
+    pub fn chunk_stats<'a>(&'a self) -> impl Iterator<Item = ChunkStats> + 'a {
+        self.allocators
+            .iter()
+            .flat_map(|(_, allocator)| allocator.chunk_stats())
+    }
+
+    /// Get the current state of all chunks sub-allocated from all memory types.
+    ///
+    /// Building block for exporting memory layouts, see `ChunkInfo`.
+    pub fn chunks<'a>(&'a self) -> impl Iterator<Item = ChunkInfo> + 'a {
+        self.allocators
+            .iter()
+            .flat_map(|(_, allocator)| allocator.chunks())
+    }
+
+    /// Find resources to move out of sparsely used chunks, so the chunks can be freed.
+    ///
+    /// Reports the keys of items whose blocks lie in chunks with live blocks taking less than
+    /// `max_usage` of their size. Each reported item needs its block moved with `migrate`, its
+    /// resource recreated in the new block with `RecreateFactory::rebind_buffer` or
+    /// `RecreateFactory::rebind_image`, its contents copied, and the migration committed.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `items`: keys and blocks, e.g. `Item`s created by this allocator, of resources that
+    ///            may be moved
+    /// - `max_usage`: fraction of a chunk in use below which its blocks are moved
+    pub fn defrag_candidates<'a, K, T, I>(&self, items: I, max_usage: f32) -> Vec<K>
+    where
+        I: IntoIterator<Item = (K, &'a T)>,
+        T: Block<Memory = B::Memory> + 'a,
+    {
+        let sparse: Vec<_> = self
+            .chunks()
+            .filter(|chunk| is_sparse(chunk, max_usage))
+            .collect();
+        items
+            .into_iter()
+            .filter(|&(_, block)| in_chunks(&sparse, block))
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    /// Get the histograms of requested sizes and alignments of blocks per memory type.
+    ///
+    /// See `CombinedAllocator::size_histogram`.
+    pub fn size_histograms<'a>(
+        &'a self,
+    ) -> impl Iterator<Item = (MemoryTypeId, &'a SizeHistogram)> + 'a {
+        self.allocators
+            .iter()
+            .enumerate()
+            .map(|(index, (_, allocator))| (MemoryTypeId(index), allocator.size_histogram()))
+    }
+
+    /// Forget the blocks counted in the size histograms of all memory types.
+    pub fn clear_size_histograms(&mut self) {
+        for (_, allocator) in &mut self.allocators {
+            allocator.clear_size_histogram();
+        }
+    }
+
+    /// Get the location of a block allocated by this allocator.
+    ///
+    /// Combined with labels set by `set_labeler`, this maps the block to a memory object and
+    /// offset as shown in graphics debugger captures.
+    pub fn locate(&self, block: &SmartBlock<B::Memory>) -> Option<BlockLocation> {
+        self.allocators
+            .get(block.memory_type as usize)
+            .and_then(|(_, allocator)| allocator.locate(block))
+    }
+
+    /// Get the metadata of a live block allocated by this allocator.
+    ///
+    /// See `CombinedAllocator::metadata`.
+    pub fn metadata(&self, block: &SmartBlock<B::Memory>) -> Option<&BlockMetadata> {
+        self.allocators
+            .get(block.memory_type as usize)
+            .and_then(|(_, allocator)| allocator.metadata(block))
+    }
+
+    /// Get the size a live block can grow to in place.
+    ///
+    /// See `CombinedAllocator::capacity`.
+    pub fn capacity(&self, block: &SmartBlock<B::Memory>) -> Option<u64> {
+        self.allocators
+            .get(block.memory_type as usize)
+            .and_then(|(_, allocator)| allocator.capacity_of(block.size(), &block.tag))
+    }
+
+    /// Change the size of a live block without moving it, if it has the capacity and growing
+    /// it fits into the budget of its domain.
+    ///
+    /// See `CombinedAllocator::try_grow_in_place`.
+    pub fn try_grow_in_place(&mut self, block: &mut SmartBlock<B::Memory>, size: u64) -> bool {
+        let old = block.size();
+        match self.capacity(block) {
+            Some(capacity) if size <= capacity => {}
+            _ => return false,
+        }
+        if size > old && !self.domains.get(block.domain()).fits(size - old) {
+            return false;
+        }
+        // Heaps account the capacity of blocks, which doesn't change
+        block.raw.resize(size);
+        self.domains.resize(block.domain(), old, size);
+        true
+    }
+
+    /// Name what a live block is used for.
+    ///
+    /// See `CombinedAllocator::set_tag`.
+    pub fn set_tag(&mut self, block: &SmartBlock<B::Memory>, tag: &'static str) {
+        if let Some((_, allocator)) = self.allocators.get_mut(block.memory_type as usize) {
+            allocator.set_tag(block, tag);
+        }
+    }
+
+    /// Attach an arbitrary value to a live block.
+    ///
+    /// See `CombinedAllocator::set_user_data`.
+    pub fn set_user_data(&mut self, block: &SmartBlock<B::Memory>, user_data: u64) {
+        if let Some((_, allocator)) = self.allocators.get_mut(block.memory_type as usize) {
+            allocator.set_user_data(block, user_data);
+        }
+    }
+
+    /// Find the live block covering `offset` in a memory object allocated by this allocator.
+    ///
+    /// See `CombinedAllocator::find_block_containing`.
+    pub fn find_block_containing(&self, memory: &B::Memory, offset: u64) -> Option<BlockLocation> {
+        self.allocators
+            .iter()
+            .find_map(|(_, allocator)| allocator.find_block_containing(memory, offset))
+    }
+
+    /// Free all cached chunks with no blocks in use.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device the memory was allocated from
+    pub unsafe fn trim(&mut self, device: &B::Device) {
+        for (_, allocator) in &mut self.allocators {
+            allocator.trim(device);
+        }
+    }
+
+    /// Audit internal consistency of this allocator.
+    ///
+    /// Checks every memory type with `CombinedAllocator::check_invariants` and that the usage
+    /// accounted for each heap matches the live blocks of its memory types.
+    ///
+    /// Returns the first violation found, checking memory types in order.
+    pub fn check_invariants(&self) -> Result<(), InvariantViolation> {
+        let mut used = vec![0u64; self.heaps.len()];
+        for (memory_type, allocator) in &self.allocators {
+            allocator.check_invariants()?;
+            used[memory_type.heap_index] += allocator.live_size();
+        }
+        for (index, (heap, &used)) in self.heaps.iter().zip(&used).enumerate() {
+            if heap.used != used {
+                return Err(InvariantViolation::new(
+                    format!("heap {}", index),
+                    format!(
+                        "{} bytes are accounted but live blocks sum to {}",
+                        heap.used, used
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the parameters this allocator actually uses.
+    ///
+    /// Includes defaults picked by constructors and adjustments to device limits, so it can be
+    /// attached to bug reports instead of the arguments the allocator was created with.
+    pub fn config(&self) -> AllocatorConfig {
+        AllocatorConfig {
+            memory_types: self
+                .allocators
+                .iter()
+                .map(|(_, allocator)| allocator.config())
+                .collect(),
+            emergency_reserves: self.heaps.iter().map(|heap| heap.reserved).collect(),
+        }
+    }
+
+    /// Report blocks that are still in use, along with the configuration of the allocator.
+    pub fn leak_report(&self) -> LeakReport {
+        LeakReport {
+            leaks: self
+                .allocators
+                .iter()
+                .filter(|(_, allocator)| allocator.allocations() != 0)
+                .map(|(_, allocator)| Leak {
+                    memory_type: allocator.memory_type(),
+                    blocks: allocator.allocations(),
+                    used: allocator.used(),
+                })
+                .collect(),
+            config: self.config(),
+        }
+    }
+
+    /// Attempt to dispose of this allocator, trimming all cached chunks first.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: must be the same device all allocations have been made against
+    ///
+    /// ### Returns
+    ///
+    /// If the allocator contains memory blocks that are still in use, this will return the
+    /// allocator along with a report of blocks in use.
+    #[allow(clippy::result_large_err)]
+    pub unsafe fn dispose_or_report(
+        mut self,
+        device: &B::Device,
+    ) -> Result<(), (Self, LeakReport)> {
+        self.trim(device);
+        self.dispose(device).map_err(|allocator| {
+            let report = allocator.leak_report();
+            (allocator, report)
+        })
+    }
+
+    /// Attempt to dispose of this allocator without panicking.
+    ///
+    /// See `CombinedAllocator::try_dispose`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: must be the same device all allocations have been made against
+    ///
+    /// ### Returns
+    ///
+    /// If the allocator still holds any memory, this will return `Err(self)`.
+    #[allow(clippy::result_large_err)]
+    pub unsafe fn try_dispose(mut self, device: &B::Device) -> Result<(), Self> {
+        if self.is_used() {
+            return Err(self);
+        }
+        self.trim(device);
+        if self
+            .allocators
+            .iter()
+            .any(|(_, allocator)| allocator.holds_memory())
+        {
+            return Err(self);
+        }
+        for (_, allocator) in self.allocators.drain(..) {
+            if let Err(allocator) = allocator.try_dispose(device) {
+                // Already checked, but leaking is better than panicking here.
+                forget(allocator);
+            }
+        }
+        Ok(())
+    }
+
+    /// Dispose of this allocator, freeing all memory even if blocks are still in use.
+    ///
+    /// This is intended for shutdown paths, where returning every block is impractical.
+    /// Blocks that are still in use must never be accessed or returned to the allocator
+    /// afterwards, but leaked (e.g. with `std::mem::forget`).
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: must be the same device all allocations have been made against.
+    ///             The device must be idle.
+    pub unsafe fn dispose_forced(mut self, device: &B::Device) {
+        for (_, allocator) in self.allocators.drain(..) {
+            allocator.dispose_forced(device);
+        }
+    }
+
+    /// Free cached chunks of memory types using the heap.
+    unsafe fn trim_heap(&mut self, device: &B::Device, heap_index: usize) {
+        for (memory_type, allocator) in &mut self.allocators {
+            if memory_type.heap_index == heap_index {
+                allocator.trim(device);
+            }
+        }
+    }
+
+    /// Set a callback to label memory objects of all memory types right after they are
+    /// allocated.
+    ///
+    /// See `RootAllocator::set_labeler`.
+    pub fn set_labeler<F>(&mut self, callback: F)
+    where
+        F: Fn(&B::Memory, &MemoryLabel) + Send + Sync + 'static,
+    {
+        let labeler = Labeler::new(callback);
+        for (_, allocator) in &mut self.allocators {
+            allocator.set_shared_labeler(labeler.clone());
+        }
+    }
+
+    /// Set a callback that receives `Event`s reported by this allocator.
+    pub fn set_listener<F>(&mut self, callback: F)
+    where
+        F: Fn(&Event) + Send + Sync + 'static,
+    {
+        self.listener.set(callback);
+    }
+
+    /// Set what happens when a block is freed that isn't live in this allocator.
+    ///
+    /// See `CombinedAllocator::set_misuse_policy`.
+    pub fn set_misuse_policy(&mut self, policy: MisusePolicy) {
+        self.misuse.set_policy(policy);
+        self.share_misuse_handler();
+    }
+
+    /// Get what happens when this allocator is misused.
+    pub fn misuse_policy(&self) -> MisusePolicy {
+        self.misuse.policy()
+    }
+
+    /// Set a callback receiving misuse reported with `MisusePolicy::Report`, e.g. to log it.
+    pub fn set_misuse_callback<F>(&mut self, callback: F)
+    where
+        F: Fn(&Misuse) + Send + Sync + 'static,
+    {
+        self.misuse.set_callback(callback);
+        self.share_misuse_handler();
+    }
+
+    fn share_misuse_handler(&mut self) {
+        for (_, allocator) in &mut self.allocators {
+            allocator.set_misuse_handler(self.misuse.clone());
+        }
+    }
+
+    /// Get the fraction of device local memory in use.
+    pub fn device_local_usage(&self) -> f32 {
+        let (used, size) = self
+            .heaps
+            .iter()
+            .enumerate()
+            .filter(|&(index, _)| {
+                self.allocators.iter().any(|&(memory_type, _)| {
+                    memory_type.heap_index == index
+                        && MemoryClass::of(memory_type.properties).is_device_local()
+                })
+            })
+            .fold((0, 0), |(used, size), (_, heap)| {
+                (used + heap.used, size + heap.size())
+            });
+        if size == 0 {
+            0.0
+        } else {
+            used as f32 / size as f32
+        }
+    }
+
+    /// Allocate a block of memory, choosing between compatible memory types by preferences.
+    ///
+    /// Among memory types with the requested properties, those satisfying earlier preferences
+    /// are chosen over those satisfying only later ones. Memory types satisfying the same
+    /// preferences are chosen by heap usage like in `alloc`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device to allocate the memory from
+    /// - `request`: type and required properties of the block
+    /// - `preferences`: preferences ordered from the most important one
+    /// - `reqs`: the requirements the memory block must meet
+    pub unsafe fn alloc_preferred(
+        &mut self,
+        device: &B::Device,
+        request: Request,
+        preferences: &[Preference],
+        reqs: Requirements,
+    ) -> Result<SmartBlock<B::Memory>, MemoryError> {
+        let result = match check_requirements(&reqs)
+            .and_then(|()| self.check_compatible(request.properties(), reqs))
+            .and_then(|()| self.choose(request, preferences, reqs))
+        {
+            Ok(chosen) => self.alloc_from(device, chosen, request, reqs),
+            Err(err) => Err(err),
+        };
+        self.record_failure(request, reqs, result)
+    }
+
+    /// Allocate a block of memory for data read back by the host.
+    ///
+    /// The block is allocated from mappable memory, preferring memory cached on the host, which
+    /// is much faster to read, and memory that isn't device local, leaving it for resources
+    /// accessed by the device. The request is marked with `Request::for_readback`, so the block
+    /// is padded to whole atoms and kept apart from other blocks if enabled, see
+    /// `set_segregate_readback`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device to allocate the memory from
+    /// - `reqs`: the requirements the memory block must meet
+    pub unsafe fn alloc_readback(
+        &mut self,
+        device: &B::Device,
+        reqs: Requirements,
+    ) -> Result<SmartBlock<B::Memory>, MemoryError> {
+        self.alloc_preferred(device, readback_request(), &READBACK_PREFERENCES, reqs)
+    }
+
+    /// Allocate a block of memory from one of the listed memory types.
+    ///
+    /// The memory type is chosen among `memory_types` like in `alloc`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device to allocate the memory from
+    /// - `request`: type and required properties of the block
+    /// - `memory_types`: memory types the block may be allocated from
+    /// - `reqs`: the requirements the memory block must meet
+    pub unsafe fn alloc_in(
+        &mut self,
+        device: &B::Device,
+        request: Request,
+        memory_types: &[MemoryTypeId],
+        reqs: Requirements,
+    ) -> Result<SmartBlock<B::Memory>, MemoryError> {
+        let allowed = memory_types
+            .iter()
+            .fold(0, |mask, memory_type| mask | 1 << memory_type.0);
+        if reqs.type_mask & allowed == 0 {
+            return Err(MemoryError::NoCompatibleMemoryType);
+        }
+        let reqs = Requirements {
+            type_mask: reqs.type_mask & allowed,
+            ..reqs
+        };
+        self.alloc_preferred(device, request, &[], reqs)
+    }
+
+    /// Reserve memory for a block without allocating it.
+    ///
+    /// The memory type is chosen like in `alloc`, and the size of the block is accounted as used
+    /// in its heap until the reservation is committed with `commit_reservation` or cancelled with
+    /// `cancel_reservation`. This allows planning allocations of a whole frame against heap
+    /// budgets before touching the device.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `request`: type and required properties of the block
+    /// - `reqs`: the requirements the memory block must meet
+    pub fn reserve(
+        &mut self,
+        request: Request,
+        reqs: Requirements,
+    ) -> Result<Reservation, MemoryError> {
+        check_requirements(&reqs)?;
+        self.check_compatible(request.properties(), reqs)?;
+        let index = self.choose(request, &[], reqs)?;
+        let heap_index = self.allocators[index].0.heap_index;
+        let heap = &mut self.heaps[heap_index];
+        heap.planned = heap.planned.saturating_add(reqs.size);
+        Ok(Reservation {
+            relevant: Relevant,
+            request,
+            reqs,
+            index,
+            heap_index,
+        })
+    }
+
+    /// Reserve memory for several blocks at once.
+    ///
+    /// Either all blocks fit and all reservations are returned, or nothing is reserved.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `requests`: type, required properties and requirements of each block
+    pub fn reserve_all(
+        &mut self,
+        requests: &[(Request, Requirements)],
+    ) -> Result<Vec<Reservation>, MemoryError> {
+        let mut reservations = Vec::with_capacity(requests.len());
+        for &(request, reqs) in requests {
+            match self.reserve(request, reqs) {
+                Ok(reservation) => reservations.push(reservation),
+                Err(err) => {
+                    for reservation in reservations {
+                        self.cancel_reservation(reservation);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        Ok(reservations)
+    }
+
+    /// Allocate the block for a reservation.
+    ///
+    /// Allocation can still fail if the device runs out of memory that isn't tracked by heap
+    /// budgets, in which case the reservation is released.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device to allocate the memory from
+    /// - `reservation`: reservation made by `reserve` on this allocator
+    pub unsafe fn commit_reservation(
+        &mut self,
+        device: &B::Device,
+        reservation: Reservation,
+    ) -> Result<SmartBlock<B::Memory>, MemoryError> {
+        let (request, reqs, index) = (reservation.request, reservation.reqs, reservation.index);
+        self.cancel_reservation(reservation);
+        let result = self.alloc_from(device, index, request, reqs);
+        self.record_failure(request, reqs, result)
+    }
+
+    /// Keep the failure of an allocation for `recent_failures`.
+    fn record_failure<T>(
+        &mut self,
+        request: Request,
+        reqs: Requirements,
+        result: Result<T, MemoryError>,
+    ) -> Result<T, MemoryError> {
+        match result {
+            Err(MemoryError::WouldGrow) => {}
+            Err(ref error) if self.failure_history != 0 => {
+                if self.failures.len() == self.failure_history {
+                    self.failures.pop_front();
+                }
+                self.failures.push_back(AllocationFailure {
+                    time: Instant::now(),
+                    request,
+                    reqs,
+                    error: error.clone(),
+                    heaps: self
+                        .heaps
+                        .iter()
+                        .map(|heap| HeapUsage {
+                            size: heap.size(),
+                            used: heap.used,
+                            reserved: heap.reserved,
+                            planned: heap.planned,
+                        })
+                        .collect(),
+                });
+            }
+            _ => {}
+        }
+        result
+    }
+
+    /// Release a reservation without allocating the block.
+    pub fn cancel_reservation(&mut self, reservation: Reservation) {
+        let Reservation {
+            relevant,
+            reqs,
+            heap_index,
+            ..
+        } = reservation;
+        relevant.dispose();
+        let heap = &mut self.heaps[heap_index];
+        debug_assert!(heap.planned >= reqs.size);
+        heap.planned = heap.planned.saturating_sub(reqs.size);
+    }
+
+    /// Choose memory type for a block, spilling out of device local memory if required.
+    ///
+    /// Memory types pinned with `Request::in_memory_type` are only checked.
+    fn choose(
+        &self,
+        request: Request,
+        preferences: &[Preference],
+        reqs: Requirements,
+    ) -> Result<usize, MemoryError> {
+        let (ty, prop): (Type, Properties) = request.into();
+        if let Some(MemoryTypeId(index)) = request.memory_type() {
+            if index >= self.allocators.len() {
+                return Err(MemoryError::NoCompatibleMemoryType);
+            }
+            return self.check_single(index, prop, reqs);
+        }
+        if let Some(index) = self.single_type() {
+            return self.check_single(index, prop, reqs);
+        }
+        match self.spill_properties(ty, prop) {
+            Some(spill) => match self.pick(spill, preferences, reqs) {
+                Ok(chosen) => {
+                    self.listener.emit(Event::Spilled {
+                        requested: prop,
+                        memory_type: MemoryTypeId(chosen),
+                        size: reqs.size,
+                    });
+                    Ok(chosen)
+                }
+                Err(_) => self.pick(prop, preferences, reqs),
+            },
+            None => self.pick(prop, preferences, reqs),
+        }
+    }
+
+    /// Allocate a block from the chosen memory type and account it in its heap.
+    unsafe fn alloc_from(
+        &mut self,
+        device: &B::Device,
+        chosen: usize,
+        request: Request,
+        reqs: Requirements,
+    ) -> Result<SmartBlock<B::Memory>, MemoryError> {
+        // Blocks are at least as large as requested, so this fails early without allocating
+        if !self.domains.get(request.domain()).fits(reqs.size) {
+            return Err(MemoryError::OverBudget(request.domain()));
+        }
+        let before = self.allocators[chosen].1.device_allocations();
+        let block = if self.frame_budget_spent() {
+            self.allocators[chosen].1.try_alloc(request, reqs)?
+        } else {
+            match self.allocators[chosen].1.alloc(device, request, reqs) {
+                Err(MemoryError::OutOfMemory) => self.retry(device, chosen, request, reqs)?,
+                result => result?,
+            }
+        };
+        self.frame_allocations += self.allocators[chosen].1.device_allocations() - before;
+        // Domains account the size of the block, which may be rounded up
+        if !self.domains.get(request.domain()).fits(block.size()) {
+            self.allocators[chosen].1.free_checked(device, block);
+            return Err(MemoryError::OverBudget(request.domain()));
+        }
+        let heap_index = self.allocators[chosen].0.heap_index;
+        let capacity = self.allocators[chosen]
+            .1
+            .capacity_of(block.size(), &block.1)
+            .expect("Block was just allocated");
+        self.heaps[heap_index].alloc(capacity);
+        self.domains.alloc(request.domain(), block.size());
+        self.frame_allocs += 1;
+        self.peak = max(self.peak, self.heaps.iter().map(|heap| heap.used).sum());
+        if let Some(policy) = self.trim {
+            let heap = &mut self.heaps[heap_index];
+            heap.high |= heap.usage() > policy.high;
+        }
+        if self.round_robin(request.properties()) {
+            self.next_heap = (heap_index + 1) % self.heaps.len();
+        }
+        Ok(SmartBlock::new(block, chosen, heap_index, request.domain()))
+    }
+
+    /// Check if no more memory objects can be allocated in this frame.
+    fn frame_budget_spent(&self) -> bool {
+        match self.frame_budget {
+            Some(budget) => self.frame_allocations >= budget,
+            None => false,
+        }
+    }
+
+    /// Retry an allocation the device failed although the heap should have enough memory.
+    unsafe fn retry(
+        &mut self,
+        device: &B::Device,
+        chosen: usize,
+        request: Request,
+        reqs: Requirements,
+    ) -> Result<CombinedBlock<B::Memory>, MemoryError> {
+        let heap_index = self.allocators[chosen].0.heap_index;
+        let policy = match self.retry {
+            Some(policy) if self.heaps[heap_index].available() >= reqs.size => policy,
+            _ => return Err(MemoryError::OutOfMemory),
+        };
+        let mut delay = policy.backoff;
+        for _ in 0..policy.attempts {
+            if policy.trim {
+                self.trim_heap(device, heap_index);
+            }
+            self.backoff.wait(delay);
+            delay = delay.checked_mul(2).unwrap_or(delay);
+            match self.allocators[chosen].1.alloc(device, request, reqs) {
+                Err(MemoryError::OutOfMemory) => continue,
+                result => return result,
+            }
+        }
+        Err(MemoryError::OutOfMemory)
+    }
+
+    /// Start moving a block into memory with other properties.
+    ///
+    /// Allocates the destination block, so the user can record copying of the data from the
+    /// source block. After the copy is complete the migration must be finished with `commit`,
+    /// which frees the source block, or with `abort`, which frees the destination block.
+    ///
     /// ### Parameters:
     ///
-    /// - `memory_properties`: memory properties describing the memory available on a device
-    /// - `arena_chunk_size`: see `ArenaAllocator`
-    /// - `blocks_per_chunk`: see `ChunkedAllocator`
-    /// - `min_block_size`: see `ChunkedAllocator`
-    /// - `max_chunk_size`: see `ChunkedAllocator`
-    pub fn new(
-        memory_properties: MemoryProperties,
-        arena_chunk_size: u64,
-        blocks_per_chunk: usize,
-        min_block_size: u64,
-        max_chunk_size: u64,
-    ) -> Self {
-        SmartAllocator {
-            allocators: memory_properties
-                .memory_types
-                .into_iter()
-                .enumerate()
-                .map(|(index, memory_type)| {
-                    (
-                        memory_type,
-                        CombinedAllocator::new(
-                            MemoryTypeId(index),
-                            arena_chunk_size,
-                            blocks_per_chunk,
-                            min_block_size,
-                            max_chunk_size,
-                        ),
-                    )
-                })
-                .collect(),
-            heaps: memory_properties
-                .memory_heaps
-                .into_iter()
-                .map(|size| Heap { size, used: 0 })
-                .collect(),
+    /// - `device`: device the block was allocated from
+    /// - `block`: block to move
+    /// - `request`: type and properties of the destination block
+    /// - `reqs`: requirements of the resource bound to the block
+    ///
+    /// ### Returns
+    ///
+    /// If the destination block can't be allocated, the source block is returned along with the
+    /// error.
+    pub unsafe fn migrate(
+        &mut self,
+        device: &B::Device,
+        block: SmartBlock<B::Memory>,
+        request: Request,
+        reqs: Requirements,
+    ) -> MigrateResult<B::Memory> {
+        match self.alloc(device, request, reqs) {
+            Ok(dst) => Ok(Migration { src: block, dst }),
+            Err(err) => Err((block, err)),
         }
     }
 
-    /// Get properties of the block
-    pub fn properties(&self, block: &SmartBlock<B::Memory>) -> Properties {
-        self.allocators[block.1].0.properties
+    /// Finish a migration, freeing the source block.
+    ///
+    /// ### Returns
+    ///
+    /// The destination block.
+    pub unsafe fn commit(
+        &mut self,
+        device: &B::Device,
+        migration: Migration<B::Memory>,
+    ) -> SmartBlock<B::Memory> {
+        self.free(device, migration.src);
+        migration.dst
     }
 
-    /// Get the total size of all blocks allocated by this allocator.
-    pub fn used(&self) -> u64 {
-        self.allocators.iter().map(|alloc| alloc.1.used()).sum()
+    /// Cancel a migration, freeing the destination block.
+    ///
+    /// ### Returns
+    ///
+    /// The source block.
+    pub unsafe fn abort(
+        &mut self,
+        device: &B::Device,
+        migration: Migration<B::Memory>,
+    ) -> SmartBlock<B::Memory> {
+        self.free(device, migration.dst);
+        migration.src
     }
 
-    /// Get the total size of all chunks allocated by this allocator.
-    pub fn allocated(&self) -> u64 {
-        self.allocators
+    /// Start moving a block into a block of another size, memory type or pool.
+    ///
+    /// Like `migrate`, but the tag and user data of the source block are carried over to the
+    /// destination block, so resizing or re-homing a resource is a single call followed by the
+    /// copy and `commit`, or `abort` if the copy can't be done. The number of bytes to copy is
+    /// given by `Migration::copy_size`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device the block was allocated from
+    /// - `block`: block to move
+    /// - `request`: type and properties of the destination block
+    /// - `reqs`: requirements of the resource bound to the destination block
+    ///
+    /// ### Returns
+    ///
+    /// If the destination block can't be allocated, the source block is returned along with the
+    /// error and stays allocated.
+    pub unsafe fn realloc(
+        &mut self,
+        device: &B::Device,
+        block: SmartBlock<B::Memory>,
+        request: Request,
+        reqs: Requirements,
+    ) -> MigrateResult<B::Memory> {
+        let metadata = self.metadata(&block).cloned();
+        let migration = self.migrate(device, block, request, reqs)?;
+        if let Some(metadata) = metadata {
+            if let Some(tag) = metadata.tag {
+                self.set_tag(&migration.dst, tag);
+            }
+            self.set_user_data(&migration.dst, metadata.user_data);
+        }
+        Ok(migration)
+    }
+
+    /// Get properties to use instead of requested ones if the allocation should be spilled.
+    fn spill_properties(&self, ty: Type, prop: Properties) -> Option<Properties> {
+        match (ty, self.spill) {
+            (Type::General, Some(policy)) | (Type::SubAllocated, Some(policy))
+                if prop.contains(Properties::DEVICE_LOCAL)
+                    && self.device_local_usage() > policy.threshold =>
+            {
+                Some((prop - Properties::DEVICE_LOCAL) | policy.fallback)
+            }
+            _ => None,
+        }
+    }
+
+    /// Check if heaps should be chosen in round-robin order for the properties.
+    fn round_robin(&self, prop: Properties) -> bool {
+        self.heap_selection == HeapSelection::RoundRobin && prop.contains(Properties::DEVICE_LOCAL)
+    }
+
+    /// Fail with `MemoryError::Incompatible` if no memory type is allowed by the type mask and
+    /// has the required properties, including for an empty type mask.
+    fn check_compatible(&self, prop: Properties, reqs: Requirements) -> Result<(), MemoryError> {
+        let compatible = self
+            .allocators
             .iter()
-            .map(|alloc| alloc.1.allocated())
-            .sum()
+            .enumerate()
+            .any(|(index, allocator)| {
+                (1u64 << index) & reqs.type_mask != 0 && allocator.0.properties.contains(prop)
+            });
+        if compatible {
+            Ok(())
+        } else {
+            Err(MemoryError::Incompatible(Box::new(IncompatibleMemory {
+                reqs,
+                properties: prop,
+                memory_types: self
+                    .allocators
+                    .iter()
+                    .map(|allocator| allocator.0.properties)
+                    .collect(),
+            })))
+        }
     }
-}
 
-impl<B> MemoryAllocator<B> for SmartAllocator<B>
-where
-    B: Backend,
-{
-    type Request = (Type, Properties);
-    type Block = SmartBlock<B::Memory>;
+    /// Check that the only or pinned memory type is compatible and its heap has enough available
+    /// memory.
+    fn check_single(
+        &self,
+        index: usize,
+        prop: Properties,
+        reqs: Requirements,
+    ) -> Result<usize, MemoryError> {
+        let memory_type = self.allocators[index].0;
+        if (1 << index) & reqs.type_mask == 0 || !memory_type.properties.contains(prop) {
+            Err(MemoryError::NoCompatibleMemoryType)
+        } else if self.heaps[memory_type.heap_index].available()
+            < reqs.size.saturating_add(reqs.alignment)
+        {
+            Err(MemoryError::OutOfMemory)
+        } else {
+            Ok(index)
+        }
+    }
 
-    unsafe fn alloc(
-        &mut self,
-        device: &B::Device,
-        (ty, prop): (Type, Properties),
+    /// Find compatible memory type with enough available memory that satisfies the most important
+    /// preferences and has the least used heap (or the next heap in round-robin order).
+    fn pick(
+        &self,
+        prop: Properties,
+        preferences: &[Preference],
         reqs: Requirements,
-    ) -> Result<SmartBlock<B::Memory>, MemoryError> {
+    ) -> Result<usize, MemoryError> {
         let mut compatible = false;
-        let mut candidate = None;
+        let mut candidate: Option<(usize, f32)> = None;
+        let round_robin = self.round_robin(prop);
 
-        // Find compatible memory type with least used heap with enough available memory
         for index in 0..self.allocators.len() {
             let memory_type = self.allocators[index].0;
             // filter out non-compatible
             if ((1 << index) & reqs.type_mask) != (1 << index)
+                || (1 << index) & self.disabled != 0
                 || !memory_type.properties.contains(prop)
             {
                 continue;
             }
             compatible = true;
             // filter out if heap has not enough memory available
-            if self.heaps[memory_type.heap_index].available() < (reqs.size + reqs.alignment) {
+            if self.heaps[memory_type.heap_index].available()
+                < reqs.size.saturating_add(reqs.alignment)
+            {
                 continue;
             }
-            // Compare with candidate. Replace if this one is less used.
-            let this_usage = self.heaps[memory_type.heap_index].usage();
-            match candidate {
-                Some((ref mut candidate, ref mut usage)) if *usage > this_usage => {
-                    *candidate = index;
-                    *usage = this_usage;
+            // Compare with candidate. Replace if this one is preferred or less used.
+            let this_usage = if round_robin {
+                // Distance from the next heap in order
+                ((memory_type.heap_index + self.heaps.len() - self.next_heap) % self.heaps.len())
+                    as f32
+            } else {
+                self.heaps[memory_type.heap_index].usage()
+            };
+            let better = match candidate {
+                Some((candidate, usage)) => {
+                    let candidate_properties = self.allocators[candidate].0.properties;
+                    let preference = preferences
+                        .iter()
+                        .map(|pref| pref.satisfied_by(memory_type.properties))
+                        .cmp(
+                            preferences
+                                .iter()
+                                .map(|pref| pref.satisfied_by(candidate_properties)),
+                        );
+                    preference == Ordering::Greater
+                        || (preference == Ordering::Equal && usage > this_usage)
                 }
-                ref mut candidate @ None => *candidate = Some((index, this_usage)),
-                _ => {}
+                None => true,
+            };
+            if better {
+                candidate = Some((index, this_usage));
             }
         }
 
         match candidate {
-            Some((chosen, _)) => {
-                // Allocate from final candidate
-                let block = self.allocators[chosen].1.alloc(device, ty, reqs)?;
-                self.heaps[self.allocators[chosen].0.heap_index].alloc(block.size());
-                Ok(SmartBlock(block, chosen))
-            }
+            Some((chosen, _)) => Ok(chosen),
             None => {
                 // No candidates
                 Err(if !compatible {
@@ -143,11 +1692,145 @@ where
             }
         }
     }
+}
+
+/// Policy for choosing between heaps of device local memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum HeapSelection {
+    /// Allocate from the least used heap. This packs allocations into the biggest heaps.
+    LeastUsed,
+
+    /// Allocate from heaps in turn, spreading allocations across all heaps.
+    /// Applies only to requests for `DEVICE_LOCAL` memory.
+    RoundRobin,
+}
+
+/// Policy for trimming cached chunks when heap usage drops.
+///
+/// When usage of a heap drops below `low` after being above `high`, chunks with no blocks in
+/// use are freed for all memory types of the heap. This keeps the memory allocated from the
+/// device proportional to the working set.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TrimPolicy {
+    /// Fraction of a heap in use above which the heap becomes a candidate for trimming.
+    pub high: f32,
+    /// Fraction of a heap in use below which the heap is trimmed.
+    pub low: f32,
+}
+
+/// Policy for retrying allocations the device failed with out of memory although heap
+/// accounting says the memory should be available.
+///
+/// Such failures are often transient, e.g. while the driver is still releasing freed memory.
+/// Before each attempt cached chunks of the heap are optionally trimmed and the allocator waits
+/// with exponential backoff, starting at `backoff` and doubling after each attempt.
+/// See `SmartAllocator::set_backoff` for waiting for something other than time.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RetryPolicy {
+    /// Number of attempts after the first failure.
+    pub attempts: u32,
+    /// Delay before the first attempt.
+    pub backoff: Duration,
+    /// Trim cached chunks of the heap before each attempt.
+    pub trim: bool,
+}
+
+type BackoffCallback = Box<dyn Fn(Duration) + Send + Sync>;
+
+/// Optional callback waiting between retries.
+#[derive(Default)]
+struct Backoff(Option<BackoffCallback>);
+
+impl Backoff {
+    fn wait(&self, delay: Duration) {
+        match self.0 {
+            Some(ref callback) => callback(delay),
+            None => sleep(delay),
+        }
+    }
+}
+
+impl Debug for Backoff {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(_) => fmt.write_str("Backoff(Some(..))"),
+            None => fmt.write_str("Backoff(None)"),
+        }
+    }
+}
+
+/// Preference of memory properties used to choose between compatible memory types.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Preference {
+    /// Prefer memory types with all of these properties, e.g. `CPU_CACHED` for readback.
+    With(Properties),
+
+    /// Prefer memory types with none of these properties, e.g. `CPU_VISIBLE` for memory used
+    /// only by the device.
+    Without(Properties),
+}
+
+impl Preference {
+    /// Check if memory with given properties satisfies this preference.
+    pub fn satisfied_by(&self, properties: Properties) -> bool {
+        match *self {
+            Preference::With(prop) => properties.contains(prop),
+            Preference::Without(prop) => !properties.intersects(prop),
+        }
+    }
+}
+
+/// Policy for spilling general purpose allocations out of device local memory.
+///
+/// When usage of device local heaps exceeds `threshold`, allocations of `Type::General` or
+/// `Type::SubAllocated` requesting `DEVICE_LOCAL` memory are placed into memory with `fallback`
+/// properties instead and an `Event::Spilled` is emitted. If no such memory is available the
+/// allocation is attempted with the requested properties.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SpillPolicy {
+    /// Fraction of device local memory in use above which allocations spill.
+    pub threshold: f32,
+    /// Properties requested instead of `DEVICE_LOCAL`, usually `CPU_VISIBLE`.
+    pub fallback: Properties,
+}
+
+impl<B> MemoryAllocator<B> for SmartAllocator<B>
+where
+    B: Backend,
+{
+    type Request = Request;
+    type Block = SmartBlock<B::Memory>;
+
+    unsafe fn alloc(
+        &mut self,
+        device: &B::Device,
+        request: Request,
+        reqs: Requirements,
+    ) -> Result<SmartBlock<B::Memory>, MemoryError> {
+        self.alloc_preferred(device, request, &[], reqs)
+    }
 
     unsafe fn free(&mut self, device: &B::Device, block: SmartBlock<B::Memory>) {
-        let SmartBlock(block, index) = block;
-        self.heaps[self.allocators[index].0.heap_index].free(block.size());
-        self.allocators[index].1.free(device, block);
+        let (index, heap_index) = (block.memory_type as usize, block.heap_index as usize);
+        let domain = block.domain();
+        let block = block.into_combined();
+        let size = block.size();
+        let capacity = self.allocators[index].1.capacity_of(size, &block.1);
+        if self.allocators[index].1.free_checked(device, block) {
+            self.heaps[heap_index].free(capacity.unwrap_or(size));
+            self.domains.free(domain, size);
+            self.frame_frees += 1;
+        }
+        if let Some(policy) = self.trim {
+            if self.heaps[heap_index].high && self.heaps[heap_index].usage() < policy.low {
+                self.heaps[heap_index].high = false;
+                self.trim_heap(device, heap_index);
+            }
+        }
     }
 
     fn is_used(&self) -> bool {
@@ -171,30 +1854,180 @@ where
 #[derive(Debug)]
 struct Heap {
     size: u64,
+    /// Capacity of live blocks, see `SmartAllocator::capacity`
     used: u64,
+    reserved: u64,
+    /// Size of outstanding `Reservation`s
+    planned: u64,
+    /// Usage was above high watermark since last trim
+    high: bool,
+    /// Size the heap pretends to have, see `SmartAllocator::set_heap_cap`
+    cap: Option<u64>,
 }
 
 impl Heap {
     fn available(&self) -> u64 {
-        self.size - self.used
+        self.size().saturating_sub(
+            self.used
+                .saturating_add(self.reserved)
+                .saturating_add(self.planned),
+        )
     }
 
     fn alloc(&mut self, size: u64) {
-        self.used += size;
+        self.used = self.used.saturating_add(size);
     }
 
     fn free(&mut self, size: u64) {
-        self.used -= size;
+        debug_assert!(self.used >= size);
+        self.used = self.used.saturating_sub(size);
     }
 
+    /// Get the fraction of the heap in use. Heaps capped to zero bytes are fully used.
     fn usage(&self) -> f32 {
-        self.used as f32 / self.size as f32
+        match self.size() {
+            0 => 1.0,
+            size => self.used as f32 / size as f32,
+        }
+    }
+
+    /// Get the size of the heap, limited by its cap.
+    fn size(&self) -> u64 {
+        self.cap.map_or(self.size, |cap| min(cap, self.size))
+    }
+}
+
+/// Memory reserved for a block by `SmartAllocator::reserve`.
+///
+/// A `Reservation` must never be silently dropped, that will result in a panic.
+/// It must be committed with `SmartAllocator::commit_reservation` or cancelled with
+/// `SmartAllocator::cancel_reservation`.
+#[derive(Debug)]
+pub struct Reservation {
+    relevant: Relevant,
+    request: Request,
+    reqs: Requirements,
+    index: usize,
+    heap_index: usize,
+}
+
+impl Reservation {
+    /// Get memory type the block will be allocated from.
+    pub fn memory_type(&self) -> MemoryTypeId {
+        MemoryTypeId(self.index)
+    }
+
+    /// Get the size of the reserved block.
+    pub fn size(&self) -> u64 {
+        self.reqs.size
+    }
+}
+
+type MigrateResult<M> = Result<Migration<M>, (SmartBlock<M>, MemoryError)>;
+
+/// Block being moved into another block by `SmartAllocator::migrate` or `SmartAllocator::realloc`.
+///
+/// A `Migration` must be finished by `SmartAllocator::commit` or `SmartAllocator::abort`.
+#[derive(Debug)]
+pub struct Migration<M> {
+    src: SmartBlock<M>,
+    dst: SmartBlock<M>,
+}
+
+impl<M> Migration<M> {
+    /// Get the block data is moved from.
+    pub fn src(&self) -> &SmartBlock<M> {
+        &self.src
+    }
+
+    /// Get the block data is moved to.
+    pub fn dst(&self) -> &SmartBlock<M> {
+        &self.dst
+    }
+
+    /// Get the number of bytes to copy, which is the size of the smaller block.
+    pub fn copy_size(&self) -> u64
+    where
+        M: Debug + Any,
+    {
+        min(self.src.size(), self.dst.size())
     }
 }
 
+/// Preferences of `SmartAllocator::alloc_readback`.
+const READBACK_PREFERENCES: [Preference; 2] = [
+    Preference::With(Properties::CPU_CACHED),
+    Preference::Without(Properties::DEVICE_LOCAL),
+];
+
+/// Get the request of `SmartAllocator::alloc_readback`.
+fn readback_request() -> Request {
+    Request::general().cpu_visible().for_readback()
+}
+
+/// Check if a chunk holds live blocks taking less than `max_usage` of its size.
+fn is_sparse(chunk: &ChunkInfo, max_usage: f32) -> bool {
+    chunk.blocks != 0 && (chunk.used as f32) < chunk.size as f32 * max_usage
+}
+
+/// Check if a block starts in one of `chunks`.
+fn in_chunks<T: Block>(chunks: &[ChunkInfo], block: &T) -> bool {
+    let memory = block.memory() as *const T::Memory as usize;
+    let start = block.range().start;
+    chunks.iter().any(|chunk| {
+        chunk.memory == memory && chunk.offset <= start && start < chunk.offset + chunk.size
+    })
+}
+
 /// `Block` type returned by `SmartAllocator`.
+///
+/// Stores the raw block and tag of the `CombinedBlock` it was allocated as directly, next to
+/// its memory type, heap and domain, so its memory is reached without going through nested
+/// blocks. Blocks of the other allocators are flat already: they hold a raw block followed by
+/// the words identifying where it came from.
 #[derive(Debug)]
-pub struct SmartBlock<M>(CombinedBlock<M>, usize);
+pub struct SmartBlock<M> {
+    raw: RawBlock<M>,
+    tag: CombinedTag,
+    /// Hal allows at most 32 memory types and 16 heaps
+    memory_type: u8,
+    heap_index: u8,
+    domain: u16,
+}
+
+impl<M> SmartBlock<M> {
+    fn new(block: CombinedBlock<M>, memory_type: usize, heap_index: usize, domain: Domain) -> Self {
+        debug_assert!(memory_type <= u8::max_value() as usize);
+        debug_assert!(heap_index <= u8::max_value() as usize);
+        let CombinedBlock(raw, tag) = block;
+        SmartBlock {
+            raw,
+            tag,
+            memory_type: memory_type as u8,
+            heap_index: heap_index as u8,
+            domain: domain.0,
+        }
+    }
+
+    fn into_combined(self) -> CombinedBlock<M> {
+        CombinedBlock(self.raw, self.tag)
+    }
+
+    /// Get memory type the block was allocated from.
+    pub fn memory_type(&self) -> MemoryTypeId {
+        MemoryTypeId(self.memory_type as usize)
+    }
+
+    /// Get index of the heap the block was allocated from.
+    pub fn heap_index(&self) -> usize {
+        self.heap_index as usize
+    }
+
+    /// Get the domain the block is accounted to.
+    pub fn domain(&self) -> Domain {
+        Domain(self.domain)
+    }
+}
 
 impl<M> Block for SmartBlock<M>
 where
@@ -204,15 +2037,33 @@ where
 
     #[inline(always)]
     fn memory(&self) -> &M {
-        self.0.memory()
+        self.raw.memory()
     }
 
     #[inline(always)]
     fn range(&self) -> Range<u64> {
-        self.0.range()
+        self.raw.range()
     }
 }
 
+#[test]
+fn test_block_size() {
+    use std::mem::size_of;
+    assert_eq!(
+        size_of::<SmartBlock<()>>(),
+        size_of::<CombinedBlock<()>>() + 8
+    );
+}
+
+#[test]
+fn test_preference() {
+    let host = Properties::CPU_VISIBLE | Properties::COHERENT;
+    assert!(Preference::With(Properties::CPU_VISIBLE).satisfied_by(host));
+    assert!(!Preference::With(Properties::CPU_CACHED).satisfied_by(host));
+    assert!(Preference::Without(Properties::DEVICE_LOCAL).satisfied_by(host));
+    assert!(!Preference::Without(Properties::CPU_VISIBLE).satisfied_by(host));
+}
+
 #[test]
 #[allow(dead_code)]
 fn test_send_sync() {
@@ -221,3 +2072,325 @@ fn test_send_sync() {
         foo::<SmartAllocator<B>>()
     }
 }
+
+#[cfg(test)]
+fn test_allocator() -> SmartAllocator<::gfx_backend_empty::Backend> {
+    SmartAllocator::new(
+        MemoryProperties {
+            memory_types: vec![
+                MemoryType {
+                    properties: Properties::DEVICE_LOCAL,
+                    heap_index: 0,
+                },
+                MemoryType {
+                    properties: Properties::CPU_VISIBLE | Properties::COHERENT,
+                    heap_index: 1,
+                },
+                MemoryType {
+                    properties: Properties::CPU_VISIBLE | Properties::CPU_CACHED,
+                    heap_index: 1,
+                },
+            ],
+            memory_heaps: vec![1 << 30, 1 << 28],
+        },
+        1 << 20,
+        32,
+        256,
+        1 << 26,
+    )
+}
+
+#[cfg(test)]
+fn test_block(memory: &(), range: Range<u64>, memory_type: usize) -> SmartBlock<()> {
+    use owner::Owner;
+    let size = range.end - range.start;
+    SmartBlock::new(
+        CombinedBlock(
+            RawBlock::new(memory, range),
+            CombinedTag::Root(size, 0, Owner::new()),
+        ),
+        memory_type,
+        0,
+        Domain::DEFAULT,
+    )
+}
+
+#[test]
+fn test_realloc() {
+    use gfx_backend_empty::Device;
+    let mut allocator = test_allocator();
+    let memory = ();
+
+    // Only the device local memory type is allowed, so the block can't move into host memory
+    let reqs = Requirements {
+        size: 512,
+        alignment: 16,
+        type_mask: 0b1,
+    };
+    let block = test_block(&memory, 0..256, 0);
+    let request = Request::general().cpu_visible();
+    let (block, err) = unsafe { allocator.realloc(&Device, block, request, reqs) }.unwrap_err();
+    match err {
+        MemoryError::Incompatible(_) => {}
+        err => panic!("Unexpected error {:?}", err),
+    }
+    assert_eq!(block.range(), 0..256);
+    assert_eq!(allocator.recent_failures().count(), 1);
+    assert_eq!(allocator.frame_stats().allocs_this_frame, 0);
+
+    // Only what fits into both blocks is copied
+    let migration = Migration {
+        src: block,
+        dst: test_block(&memory, 1024..1536, 0),
+    };
+    assert_eq!(migration.copy_size(), 256);
+    unsafe {
+        migration.src.raw.dispose();
+        migration.dst.raw.dispose();
+    }
+}
+
+#[test]
+fn test_frame_stats() {
+    use gfx_backend_empty::Device;
+    let mut allocator = test_allocator();
+    allocator.set_misuse_policy(MisusePolicy::Report);
+    assert_eq!(allocator.frame_stats(), FrameStats::default());
+    let reqs = Requirements {
+        size: 256,
+        alignment: 16,
+        type_mask: !0,
+    };
+
+    // Allocations failing before or instead of allocating device memory are not counted
+    allocator.set_domain_budget(Domain::DEFAULT, Some(128));
+    match unsafe { allocator.alloc(&Device, Request::general(), reqs) } {
+        Err(MemoryError::OverBudget(Domain::DEFAULT)) => {}
+        r => panic!("Unexpected result {:?}", r),
+    }
+    allocator.set_domain_budget(Domain::DEFAULT, None);
+    allocator.set_frame_budget(Some(0));
+    match unsafe { allocator.alloc(&Device, Request::general(), reqs) } {
+        Err(MemoryError::WouldGrow) => {}
+        r => panic!("Unexpected result {:?}", r),
+    }
+    assert_eq!(allocator.frame_stats(), FrameStats::default());
+
+    // Neither are blocks of another allocator that are rejected when freed
+    if cfg!(any(debug_assertions, feature = "checks")) {
+        let other = test_allocator();
+        let memory = ();
+        let block = SmartBlock::new(
+            CombinedBlock(
+                RawBlock::new(&memory, 0..256),
+                CombinedTag::Chunked(0, 256, other.allocators[0].1.chunks().owner()),
+            ),
+            0,
+            0,
+            Domain::DEFAULT,
+        );
+        unsafe { allocator.free(&Device, block) };
+        assert_eq!(allocator.frame_stats(), FrameStats::default());
+        assert!(unsafe { other.dispose(&Device) }.is_ok());
+    }
+
+    allocator.begin_frame();
+    assert_eq!(allocator.frame_stats(), FrameStats::default());
+    assert!(unsafe { allocator.dispose(&Device) }.is_ok());
+}
+
+#[test]
+fn test_zero_heap_cap() {
+    let mut allocator = test_allocator();
+    allocator.set_heap_cap(0, Some(0));
+    assert_eq!(allocator.heap_cap(0), Some(0));
+    assert_eq!(allocator.heaps[0].usage(), 1.0);
+
+    let reqs = Requirements {
+        size: 256,
+        alignment: 16,
+        type_mask: !0,
+    };
+    match allocator.reserve(Request::general().device_local(), reqs) {
+        Err(MemoryError::OutOfMemory) => {}
+        result => panic!("Unexpected result {:?}", result),
+    }
+
+    allocator.set_heap_cap(0, None);
+    assert_eq!(allocator.heaps[0].usage(), 0.0);
+}
+
+#[test]
+fn test_release_domain() {
+    use gfx_backend_empty::Device;
+    let mut allocator = test_allocator();
+    let memory = ();
+
+    // Nothing is freed if any block belongs to another domain
+    let blocks = vec![
+        test_block(&memory, 0..256, 0),
+        SmartBlock {
+            domain: 1,
+            ..test_block(&memory, 256..512, 0)
+        },
+    ];
+    let blocks = unsafe { allocator.release_domain(&Device, Domain::DEFAULT, blocks) }.unwrap_err();
+    assert_eq!(blocks.len(), 2);
+    assert_eq!(allocator.domain_stats(Domain::DEFAULT).blocks, 0);
+    for block in blocks {
+        unsafe { block.raw.dispose() }
+    }
+
+    let stats = unsafe { allocator.release_domain(&Device, Domain::DEFAULT, None) }.unwrap();
+    assert_eq!(stats.used, 0);
+}
+
+#[test]
+fn test_defrag_candidates() {
+    use label::Pool;
+    let (first, second) = (0u32, 0u32);
+    let chunk = |memory: &u32, offset, used, blocks| ChunkInfo {
+        memory_type: MemoryTypeId(0),
+        pool: Pool::Chunked,
+        size: 1024,
+        used,
+        blocks,
+        group: None,
+        label: None,
+        offset,
+        memory: memory as *const u32 as usize,
+    };
+    assert!(is_sparse(&chunk(&first, 0, 256, 1), 0.5));
+    assert!(!is_sparse(&chunk(&first, 0, 512, 2), 0.5));
+    assert!(!is_sparse(&chunk(&first, 0, 0, 0), 0.5));
+
+    let chunks = [chunk(&first, 1024, 256, 1)];
+    let blocks = vec![
+        RawBlock::new(&first, 1024..1280),
+        RawBlock::new(&first, 0..256),
+        RawBlock::new(&first, 2048..2304),
+        RawBlock::new(&second, 1024..1280),
+    ];
+    let found: Vec<_> = blocks
+        .iter()
+        .map(|block| in_chunks(&chunks, block))
+        .collect();
+    assert_eq!(found, vec![true, false, false, false]);
+    for block in blocks {
+        unsafe { block.dispose() }
+    }
+
+    // Nothing is sparse without chunks
+    let allocator = test_allocator();
+    let items: Vec<(usize, &SmartBlock<()>)> = Vec::new();
+    assert!(allocator.defrag_candidates(items, 1.0).is_empty());
+}
+
+#[test]
+fn test_grow_in_place() {
+    let mut allocator = test_allocator();
+    let memory = ();
+    let owner = allocator.allocators[0].1.chunks().owner();
+    let mut block = SmartBlock::new(
+        CombinedBlock(
+            RawBlock::new(&memory, 0..200),
+            CombinedTag::Chunked(0, 200, owner),
+        ),
+        0,
+        0,
+        Domain::DEFAULT,
+    );
+    allocator.heaps[0].alloc(256);
+    allocator.domains.alloc(Domain::DEFAULT, 200);
+
+    assert_eq!(allocator.capacity(&block), Some(256));
+    assert!(allocator.try_grow_in_place(&mut block, 256));
+    assert_eq!(block.size(), 256);
+    assert!(!allocator.try_grow_in_place(&mut block, 257));
+    // Heaps account the capacity, domains the size of blocks
+    assert_eq!(allocator.heaps[0].used, 256);
+    assert_eq!(allocator.domains.get(Domain::DEFAULT).used, 256);
+
+    // The capacity of arena blocks stays put however they are resized
+    let owner = allocator.allocators[0].1.arenas().owner();
+    let mut arena = SmartBlock::new(
+        CombinedBlock(
+            RawBlock::new(&memory, 256..356),
+            CombinedTag::Arena(0, 125, owner),
+        ),
+        0,
+        0,
+        Domain::DEFAULT,
+    );
+    allocator.heaps[0].alloc(125);
+    allocator.domains.alloc(Domain::DEFAULT, 100);
+    assert_eq!(allocator.capacity(&arena), Some(125));
+    assert!(allocator.try_grow_in_place(&mut arena, 125));
+    assert_eq!(allocator.capacity(&arena), Some(125));
+    assert!(allocator.try_grow_in_place(&mut arena, 50));
+    assert_eq!(arena.size(), 50);
+    assert_eq!(allocator.capacity(&arena), Some(125));
+    assert!(!allocator.try_grow_in_place(&mut arena, 126));
+    assert!(allocator.try_grow_in_place(&mut arena, 125));
+    assert_eq!(allocator.heaps[0].used, 381);
+    assert_eq!(allocator.domains.get(Domain::DEFAULT).used, 381);
+    unsafe { arena.raw.dispose() };
+    unsafe { block.raw.dispose() };
+}
+
+#[test]
+fn test_readback_request() {
+    use tuning::MAX_NON_COHERENT_ATOM_SIZE;
+    let mut allocator = test_allocator();
+    allocator.set_segregate_readback(true);
+    let reqs = Requirements {
+        size: 256,
+        alignment: 16,
+        type_mask: !0,
+    };
+    let request = readback_request();
+    assert!(request.is_readback());
+    // Host cached memory is preferred, and the block lands in its readback chunks
+    let chosen = allocator
+        .choose(request, &READBACK_PREFERENCES, reqs)
+        .unwrap();
+    assert_eq!(chosen, 2);
+    assert!(allocator.allocators[chosen].1.segregates(request));
+    // Only memory that isn't coherent needs padding
+    assert_eq!(allocator.allocators[1].1.readback_atom(), None);
+    assert_eq!(
+        allocator.allocators[chosen].1.readback_atom(),
+        Some(MAX_NON_COHERENT_ATOM_SIZE)
+    );
+}
+
+#[test]
+fn test_invalid_requirements() {
+    use gfx_backend_empty::Device;
+    use InvalidRequirements;
+    let mut allocator = test_allocator();
+    // Invalid requirements are reported before the missing properties
+    let reqs = Requirements {
+        size: 0,
+        alignment: 16,
+        type_mask: 0b1,
+    };
+    let request = Request::general().cpu_visible();
+    match allocator.reserve(request, reqs) {
+        Err(MemoryError::InvalidRequirements(InvalidRequirements::ZeroSize)) => {}
+        r => panic!("Unexpected result {:?}", r),
+    }
+    match unsafe { allocator.alloc(&Device, request, reqs) } {
+        Err(MemoryError::InvalidRequirements(InvalidRequirements::ZeroSize)) => {}
+        r => panic!("Unexpected result {:?}", r),
+    }
+
+    let reqs = Requirements { size: 256, ..reqs };
+    match unsafe { allocator.alloc(&Device, request, reqs) } {
+        Err(MemoryError::Incompatible(ref incompatible)) => {
+            assert_eq!(incompatible.properties, Properties::CPU_VISIBLE);
+        }
+        r => panic!("Unexpected result {:?}", r),
+    }
+    assert_eq!(allocator.recent_failures().count(), 2);
+}
@@ -0,0 +1,540 @@
+use std::fmt;
+use std::mem::size_of;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use gfx_hal::memory::Requirements;
+use gfx_hal::MemoryTypeId;
+
+use arena::{ArenaGrowth, ArenaRounding};
+use label::{MemoryLabel, Pool};
+use request::{Group, Request};
+use MemoryError;
+
+/// Statistics of a single chunk of memory sub-allocated by an allocator.
+///
+/// Chunks that live long, but never become empty, are a symptom of slow fragmentation.
+#[derive(Clone, Copy, Debug)]
+pub struct ChunkStats {
+    /// Memory type of the chunk.
+    pub memory_type: MemoryTypeId,
+    /// Size of the chunk in bytes.
+    pub size: u64,
+    /// Time the chunk was allocated.
+    pub created: Instant,
+    /// Number of blocks allocated from the chunk.
+    pub allocations: u64,
+    /// Number of blocks returned to the chunk.
+    pub frees: u64,
+    /// Number of times all blocks of the chunk were returned.
+    pub emptied: u64,
+    /// Label of the memory object the chunk was allocated from.
+    ///
+    /// Only known when collected through an allocator that owns a `RootAllocator`,
+    /// e.g. `CombinedAllocator` or `SmartAllocator`.
+    pub label: Option<MemoryLabel>,
+    /// Offset of the chunk in the memory object.
+    pub offset: u64,
+    /// Address of the memory object, used to look up its label.
+    pub(crate) memory: usize,
+}
+
+impl ChunkStats {
+    pub(crate) fn new<M>(memory_type: MemoryTypeId, memory: &M, range: Range<u64>) -> Self {
+        ChunkStats {
+            memory_type,
+            size: range.end - range.start,
+            created: Instant::now(),
+            allocations: 0,
+            frees: 0,
+            emptied: 0,
+            label: None,
+            offset: range.start,
+            memory: memory as *const M as usize,
+        }
+    }
+
+    pub(crate) fn alloc(&mut self) {
+        self.allocations += 1;
+    }
+
+    pub(crate) fn free(&mut self) {
+        self.frees += 1;
+        if self.frees == self.allocations {
+            self.emptied += 1;
+        }
+    }
+
+    /// Get time since the chunk was allocated.
+    pub fn age(&self) -> Duration {
+        self.created.elapsed()
+    }
+
+    /// Get number of blocks currently allocated from the chunk.
+    pub fn live(&self) -> u64 {
+        self.allocations - self.frees
+    }
+
+    /// Get the average number of blocks allocated from the chunk between becoming empty.
+    pub fn recycle_rate(&self) -> f32 {
+        self.allocations as f32 / (self.emptied + 1) as f32
+    }
+}
+
+/// Current state of a single chunk of memory sub-allocated by an allocator.
+///
+/// Collected with `SmartAllocator::chunks`, to export memory layouts in any format, e.g. to
+/// custom dashboards, or to assert on them in tests.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkInfo {
+    /// Memory type of the chunk.
+    pub memory_type: MemoryTypeId,
+    /// Kind of allocator the chunk belongs to, `Pool::Arena` or `Pool::Chunked`.
+    pub pool: Pool,
+    /// Size of the chunk in bytes.
+    pub size: u64,
+    /// Total size of live blocks in the chunk, excluding alignment padding of arena blocks.
+    pub used: u64,
+    /// Number of live blocks in the chunk.
+    pub blocks: u64,
+    /// Group of the blocks in the chunk, see `Request::in_group`.
+    pub group: Option<Group>,
+    /// Label of the memory object the chunk was allocated from, see `ChunkStats::label`.
+    pub label: Option<MemoryLabel>,
+    /// Offset of the chunk in the memory object.
+    pub offset: u64,
+    /// Address of the memory object, used to look up its label.
+    pub(crate) memory: usize,
+}
+
+impl ChunkInfo {
+    pub(crate) fn new(pool: Pool, stats: &ChunkStats, used: u64) -> Self {
+        ChunkInfo {
+            memory_type: stats.memory_type,
+            pool,
+            size: stats.size,
+            used,
+            blocks: stats.live(),
+            group: None,
+            label: stats.label,
+            offset: stats.offset,
+            memory: stats.memory,
+        }
+    }
+}
+
+/// Histogram of requested sizes and alignments of blocks, in power of two buckets.
+///
+/// Shows what `min_block_size` and `blocks_per_chunk` should be for the allocation patterns
+/// of an application. Bucket `i` counts values in `2^(i-1)+1 ..= 2^i`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SizeHistogram {
+    sizes: Vec<u64>,
+    alignments: Vec<u64>,
+}
+
+impl SizeHistogram {
+    /// Count the requirements of an allocated block.
+    pub(crate) fn record(&mut self, reqs: &Requirements) {
+        Self::count(&mut self.sizes, reqs.size);
+        Self::count(&mut self.alignments, reqs.alignment);
+    }
+
+    fn count(buckets: &mut Vec<u64>, value: u64) {
+        let bucket = (64 - value.saturating_sub(1).leading_zeros()) as usize;
+        if bucket >= buckets.len() {
+            buckets.resize(bucket + 1, 0);
+        }
+        buckets[bucket] += 1;
+    }
+
+    /// Get the number of blocks counted.
+    pub fn total(&self) -> u64 {
+        self.sizes.iter().sum()
+    }
+
+    /// Get the number of blocks per size bucket, as `(largest size, count)` pairs.
+    /// Empty buckets are skipped.
+    pub fn sizes<'a>(&'a self) -> impl Iterator<Item = (u64, u64)> + 'a {
+        Self::buckets(&self.sizes)
+    }
+
+    /// Get the number of blocks per alignment, as `(alignment, count)` pairs.
+    /// Empty buckets are skipped.
+    pub fn alignments<'a>(&'a self) -> impl Iterator<Item = (u64, u64)> + 'a {
+        Self::buckets(&self.alignments)
+    }
+
+    fn buckets<'a>(buckets: &'a [u64]) -> impl Iterator<Item = (u64, u64)> + 'a {
+        buckets
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count != 0)
+            .map(|(bucket, &count)| (1 << bucket, count))
+    }
+
+    /// Add the counts of another histogram, e.g. to sum up memory types.
+    pub fn merge(&mut self, other: &SizeHistogram) {
+        Self::merge_buckets(&mut self.sizes, &other.sizes);
+        Self::merge_buckets(&mut self.alignments, &other.alignments);
+    }
+
+    fn merge_buckets(buckets: &mut Vec<u64>, other: &[u64]) {
+        if buckets.len() < other.len() {
+            buckets.resize(other.len(), 0);
+        }
+        for (count, other) in buckets.iter_mut().zip(other) {
+            *count += *other;
+        }
+    }
+
+    /// Forget all counted blocks.
+    pub fn clear(&mut self) {
+        self.sizes.clear();
+        self.alignments.clear();
+    }
+
+    /// Get the number of bytes of host memory used by the buckets.
+    pub fn host_memory(&self) -> usize {
+        (self.sizes.capacity() + self.alignments.capacity()) * size_of::<u64>()
+    }
+}
+
+/// Summary of an allocator cheap enough to poll every frame, see `SmartAllocator::frame_stats`.
+///
+/// Meant for on-screen debug displays. Collecting it allocates nothing on the heap.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    /// Total size of all chunks allocated from the device.
+    pub allocated: u64,
+    /// Total size of all live blocks.
+    pub used: u64,
+    /// Number of live blocks.
+    pub live_blocks: u64,
+    /// Number of blocks allocated since `SmartAllocator::begin_frame`.
+    pub allocs_this_frame: u64,
+    /// Number of blocks freed since `SmartAllocator::begin_frame`.
+    pub frees_this_frame: u64,
+    /// Highest total size of live blocks so far.
+    pub peak: u64,
+}
+
+/// Parameters actually used by the allocator of a single memory type.
+///
+/// Includes defaults and adjustments to device limits, see `CombinedAllocator::config`.
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryTypeConfig {
+    /// Memory type of the allocator.
+    pub memory_type: MemoryTypeId,
+    /// See `ArenaAllocator`.
+    pub arena_chunk_size: u64,
+    /// See `ArenaAllocator::set_growth`.
+    pub arena_growth: Option<ArenaGrowth>,
+    /// See `ArenaAllocator::set_rounding`.
+    pub arena_rounding: ArenaRounding,
+    /// See `ChunkedAllocator`.
+    pub blocks_per_chunk: usize,
+    /// See `ChunkedAllocator`.
+    pub min_block_size: u64,
+    /// See `ChunkedAllocator`.
+    pub max_chunk_size: u64,
+    /// See `CombinedAllocator::set_dedicated_threshold`.
+    pub dedicated_threshold: u64,
+    /// See `CombinedAllocator::set_min_alignment`.
+    pub min_alignment: u64,
+    /// See `ChunkedAllocator::set_partial_chunks`.
+    pub partial_chunks: bool,
+    /// See `ChunkedAllocator::set_recycle`.
+    pub recycle: bool,
+    /// See `CombinedAllocator::set_large_page`.
+    pub large_page: Option<u64>,
+    /// See `CombinedAllocator::set_max_allocation`.
+    pub max_allocation: Option<u64>,
+}
+
+impl fmt::Display for MemoryTypeConfig {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "type {}: arena chunks of {} bytes, {} blocks per chunk, blocks of {}..{} bytes, \
+             dedicated above {} bytes, alignment {}",
+            self.memory_type.0,
+            self.arena_chunk_size,
+            self.blocks_per_chunk,
+            self.min_block_size,
+            self.max_chunk_size,
+            self.dedicated_threshold,
+            self.min_alignment,
+        )?;
+        if let Some(growth) = self.arena_growth {
+            write!(
+                fmt,
+                ", arena growth x{} up to {} bytes",
+                growth.factor, growth.max_chunk_size
+            )?;
+        }
+        match self.arena_rounding {
+            ArenaRounding::ChunkSize => {}
+            ArenaRounding::PowerOfTwo => fmt.write_str(", big arena chunks of powers of two")?,
+            ArenaRounding::Exact => fmt.write_str(", big arena chunks of exact size")?,
+        }
+        if self.partial_chunks {
+            fmt.write_str(", partial chunks")?;
+        }
+        if self.recycle {
+            fmt.write_str(", recycling")?;
+        }
+        if let Some(size) = self.large_page {
+            write!(fmt, ", large pages of {} bytes", size)?;
+        }
+        if let Some(max) = self.max_allocation {
+            write!(fmt, ", memory objects up to {} bytes", max)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parameters actually used by an allocator, see `SmartAllocator::config`.
+///
+/// Printing it with `Display` gives a compact summary suited for bug reports.
+#[derive(Clone, Debug, Default)]
+pub struct AllocatorConfig {
+    /// Parameters per memory type.
+    pub memory_types: Vec<MemoryTypeConfig>,
+    /// Size of the emergency reserve of each heap.
+    pub emergency_reserves: Vec<u64>,
+}
+
+impl fmt::Display for AllocatorConfig {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+        for config in &self.memory_types {
+            if !first {
+                fmt.write_str("; ")?;
+            }
+            first = false;
+            write!(fmt, "{}", config)?;
+        }
+        for (index, reserve) in self.emergency_reserves.iter().enumerate() {
+            if *reserve != 0 {
+                write!(fmt, "; heap {}: {} bytes reserved", index, reserve)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Blocks still in use when an allocator was disposed.
+#[derive(Clone, Debug, Default)]
+pub struct LeakReport {
+    /// Leaked blocks per memory type. Memory types without leaks are omitted.
+    pub leaks: Vec<Leak>,
+    /// Configuration of the allocator.
+    pub config: AllocatorConfig,
+}
+
+/// Blocks of a single memory type still in use.
+#[derive(Clone, Copy, Debug)]
+pub struct Leak {
+    /// Memory type of the blocks.
+    pub memory_type: MemoryTypeId,
+    /// Number of blocks in use.
+    pub blocks: usize,
+    /// Total size of blocks in use.
+    pub used: u64,
+}
+
+impl LeakReport {
+    /// Get the total number of leaked blocks.
+    pub fn blocks(&self) -> usize {
+        self.leaks.iter().map(|leak| leak.blocks).sum()
+    }
+
+    /// Get the total size of leaked blocks.
+    pub fn used(&self) -> u64 {
+        self.leaks.iter().map(|leak| leak.used).sum()
+    }
+}
+
+impl fmt::Display for LeakReport {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "{} blocks ({} bytes) in use",
+            self.blocks(),
+            self.used()
+        )?;
+        for leak in &self.leaks {
+            write!(
+                fmt,
+                "; type {}: {} blocks ({} bytes)",
+                leak.memory_type.0, leak.blocks, leak.used
+            )?;
+        }
+        if !self.config.memory_types.is_empty() {
+            write!(fmt, " (config: {})", self.config)?;
+        }
+        Ok(())
+    }
+}
+
+/// Usage of a memory heap at some point in time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeapUsage {
+    /// Size of the heap.
+    pub size: u64,
+    /// Total size of blocks allocated from the heap.
+    pub used: u64,
+    /// Size held back as emergency reserve.
+    pub reserved: u64,
+    /// Size of outstanding reservations.
+    pub planned: u64,
+}
+
+/// Allocation failed by `SmartAllocator`, see `SmartAllocator::recent_failures`.
+#[derive(Clone, Debug)]
+pub struct AllocationFailure {
+    /// Time the allocation failed at.
+    pub time: Instant,
+    /// Type and properties of the requested block.
+    pub request: Request,
+    /// Requirements of the requested block.
+    pub reqs: Requirements,
+    /// Error the allocation failed with.
+    pub error: MemoryError,
+    /// Usage of each heap when the allocation failed.
+    pub heaps: Vec<HeapUsage>,
+}
+
+impl fmt::Display for AllocationFailure {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "{} bytes aligned to {} with type mask {:#b} ({:?}, {:?}): {}",
+            self.reqs.size,
+            self.reqs.alignment,
+            self.reqs.type_mask,
+            self.request.ty(),
+            self.request.properties(),
+            self.error
+        )?;
+        for (index, heap) in self.heaps.iter().enumerate() {
+            write!(
+                fmt,
+                "; heap {}: {} of {} bytes used",
+                index, heap.used, heap.size
+            )?;
+            if heap.reserved != 0 {
+                write!(fmt, ", {} reserved", heap.reserved)?;
+            }
+            if heap.planned != 0 {
+                write!(fmt, ", {} planned", heap.planned)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_chunk_stats() {
+    let mut stats = ChunkStats::new(MemoryTypeId(0), &(), 0..1024);
+    stats.alloc();
+    stats.alloc();
+    stats.free();
+    assert_eq!(stats.live(), 1);
+    assert_eq!(stats.emptied, 0);
+    stats.free();
+    stats.alloc();
+    stats.free();
+    assert_eq!(stats.emptied, 2);
+    assert_eq!(stats.recycle_rate(), 1.0);
+}
+
+#[test]
+fn test_config_display() {
+    let memory_type = MemoryTypeConfig {
+        memory_type: MemoryTypeId(1),
+        arena_chunk_size: 1024,
+        arena_growth: None,
+        arena_rounding: ArenaRounding::ChunkSize,
+        blocks_per_chunk: 64,
+        min_block_size: 256,
+        max_chunk_size: 4096,
+        dedicated_threshold: 2048,
+        min_alignment: 256,
+        partial_chunks: false,
+        recycle: true,
+        large_page: None,
+        max_allocation: None,
+    };
+    let config = AllocatorConfig {
+        memory_types: vec![memory_type],
+        emergency_reserves: vec![0, 512],
+    };
+    assert_eq!(
+        config.to_string(),
+        "type 1: arena chunks of 1024 bytes, 64 blocks per chunk, blocks of 256..4096 bytes, \
+         dedicated above 2048 bytes, alignment 256, recycling; heap 1: 512 bytes reserved"
+    );
+}
+
+#[test]
+fn test_failure_display() {
+    let failure = AllocationFailure {
+        time: Instant::now(),
+        request: Request::general().device_local(),
+        reqs: Requirements {
+            size: 4096,
+            alignment: 256,
+            type_mask: 0b11,
+        },
+        error: MemoryError::OutOfMemory,
+        heaps: vec![
+            HeapUsage {
+                size: 8192,
+                used: 6144,
+                reserved: 1024,
+                planned: 0,
+            },
+            HeapUsage {
+                size: 8192,
+                used: 0,
+                reserved: 0,
+                planned: 0,
+            },
+        ],
+    };
+    assert_eq!(
+        failure.to_string(),
+        "4096 bytes aligned to 256 with type mask 0b11 (General, DEVICE_LOCAL): Out of memory; \
+         heap 0: 6144 of 8192 bytes used, 1024 reserved; heap 1: 0 of 8192 bytes used"
+    );
+}
+
+#[test]
+fn test_size_histogram() {
+    let mut histogram = SizeHistogram::default();
+    for &(size, alignment) in &[(1, 1), (256, 256), (257, 256), (512, 4096)] {
+        histogram.record(&Requirements {
+            size,
+            alignment,
+            type_mask: 0b1,
+        });
+    }
+    assert_eq!(histogram.total(), 4);
+    assert_eq!(
+        histogram.sizes().collect::<Vec<_>>(),
+        vec![(1, 1), (256, 1), (512, 2)]
+    );
+    assert_eq!(
+        histogram.alignments().collect::<Vec<_>>(),
+        vec![(1, 1), (256, 2), (4096, 1)]
+    );
+
+    let mut merged = SizeHistogram::default();
+    merged.merge(&histogram);
+    merged.merge(&histogram);
+    assert_eq!(merged.total(), 8);
+    merged.clear();
+    assert_eq!(merged.total(), 0);
+}
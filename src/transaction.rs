@@ -0,0 +1,388 @@
+use std::collections::VecDeque;
+use std::fmt::{self, Debug};
+
+use gfx_hal::buffer::Usage as BufferUsage;
+use gfx_hal::memory::Requirements;
+use gfx_hal::{Backend, Device};
+
+use factory::{bind_buffer, bind_image, Factory, FactoryError, ImageInfo, Item, ResourceInfo};
+use marker::BackendMarker;
+use request::Request;
+use smart::{Reservation, SmartAllocator, SmartBlock};
+use MemoryAllocator;
+
+/// Group of buffers and images created together by a `Factory`.
+///
+/// Creation requests are accumulated and executed by `commit`. Either all resources are created,
+/// or the ones created before a failure are destroyed and nothing is returned.
+/// With a `SmartAllocator`, `commit_reserved` also makes sure memory for all resources fits
+/// into the heaps before any of it is allocated.
+///
+/// ### Type parameters:
+///
+/// - `B`: hal `Backend`
+/// - `F`: `Factory` creating the resources
+pub struct Transaction<B: Backend, F: Factory<B>> {
+    buffers: Vec<(F::BufferRequest, u64, BufferUsage)>,
+    images: Vec<(F::ImageRequest, ImageInfo)>,
+    pd: BackendMarker<B>,
+}
+
+impl<B, F> Debug for Transaction<B, F>
+where
+    B: Backend,
+    F: Factory<B>,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Transaction")
+            .field("buffers", &self.buffers.len())
+            .field("images", &self.images.len())
+            .finish()
+    }
+}
+
+impl<B, F> Transaction<B, F>
+where
+    B: Backend,
+    F: Factory<B>,
+{
+    /// Create an empty transaction.
+    pub fn new() -> Self {
+        Transaction {
+            buffers: Vec::new(),
+            images: Vec::new(),
+            pd: BackendMarker::default(),
+        }
+    }
+
+    /// Add a buffer to create.
+    ///
+    /// ### Returns
+    ///
+    /// Index of the buffer in `Resources::buffers`.
+    pub fn buffer(&mut self, request: F::BufferRequest, size: u64, usage: BufferUsage) -> usize {
+        self.buffers.push((request, size, usage));
+        self.buffers.len() - 1
+    }
+
+    /// Add an image to create.
+    ///
+    /// ### Returns
+    ///
+    /// Index of the image in `Resources::images`.
+    pub fn image(&mut self, request: F::ImageRequest, info: ImageInfo) -> usize {
+        self.images.push((request, info));
+        self.images.len() - 1
+    }
+
+    /// Create all resources of the transaction.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `factory`: factory to create the resources with
+    /// - `device`: device to create the resources on
+    ///
+    /// ### Returns
+    ///
+    /// If creating any of the resources fails, already created resources are destroyed and the
+    /// error is returned.
+    pub unsafe fn commit(
+        self,
+        factory: &mut F,
+        device: &B::Device,
+    ) -> Result<Resources<F::Buffer, F::Image>, F::Error> {
+        let mut resources = Resources {
+            buffers: Vec::with_capacity(self.buffers.len()),
+            images: Vec::with_capacity(self.images.len()),
+        };
+        let result = self.create(factory, device, &mut resources);
+        match result {
+            Ok(()) => Ok(resources),
+            Err(err) => {
+                for buffer in resources.buffers {
+                    factory.destroy_buffer(device, buffer);
+                }
+                for image in resources.images {
+                    factory.destroy_image(device, image);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    unsafe fn create(
+        self,
+        factory: &mut F,
+        device: &B::Device,
+        resources: &mut Resources<F::Buffer, F::Image>,
+    ) -> Result<(), F::Error> {
+        for (request, size, usage) in self.buffers {
+            resources
+                .buffers
+                .push(factory.create_buffer(device, request, size, usage)?);
+        }
+        for (request, info) in self.images {
+            resources.images.push(factory.create_image(
+                device,
+                request,
+                info.kind,
+                info.level,
+                info.format,
+                info.tiling,
+                info.usage,
+                info.view_caps,
+            )?);
+        }
+        Ok(())
+    }
+}
+
+/// Buffer or image created without memory, waiting for its reservation to be committed.
+struct Unbound<I> {
+    raw: I,
+    request: Request,
+    reqs: Requirements,
+    info: ResourceInfo,
+}
+
+/// Resources created by `Transaction::commit_reserved`.
+pub type SmartResources<B> = Resources<
+    Item<<B as Backend>::Buffer, SmartBlock<<B as Backend>::Memory>>,
+    Item<<B as Backend>::Image, SmartBlock<<B as Backend>::Memory>>,
+>;
+
+impl<B> Transaction<B, SmartAllocator<B>>
+where
+    B: Backend,
+{
+    /// Create all resources of the transaction, reserving memory for all of them first.
+    ///
+    /// Resources are created without memory to learn their requirements, then memory for all
+    /// of them is reserved with `SmartAllocator::reserve`. Nothing is allocated unless all
+    /// blocks fit into their heaps, so a transaction that doesn't fit leaves the heaps untouched.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `allocator`: allocator to allocate the memory from
+    /// - `device`: device to create the resources on
+    ///
+    /// ### Returns
+    ///
+    /// If creating any of the resources fails, already created resources are destroyed,
+    /// reservations are cancelled and the error is returned.
+    pub unsafe fn commit_reserved(
+        self,
+        allocator: &mut SmartAllocator<B>,
+        device: &B::Device,
+    ) -> Result<SmartResources<B>, FactoryError> {
+        let mut buffers = VecDeque::with_capacity(self.buffers.len());
+        let mut images = VecDeque::with_capacity(self.images.len());
+        let mut resources = Resources {
+            buffers: Vec::with_capacity(self.buffers.len()),
+            images: Vec::with_capacity(self.images.len()),
+        };
+        let result = self
+            .create_unbound(device, &mut buffers, &mut images)
+            .and_then(|()| reserve(allocator, &buffers, &images))
+            .and_then(|reservations| {
+                bind(
+                    allocator,
+                    device,
+                    &mut buffers,
+                    &mut images,
+                    reservations,
+                    &mut resources,
+                )
+            });
+        match result {
+            Ok(()) => Ok(resources),
+            Err(err) => {
+                for buffer in buffers {
+                    device.destroy_buffer(buffer.raw);
+                }
+                for image in images {
+                    device.destroy_image(image.raw);
+                }
+                for buffer in resources.buffers {
+                    allocator.destroy_buffer(device, buffer);
+                }
+                for image in resources.images {
+                    allocator.destroy_image(device, image);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    unsafe fn create_unbound(
+        self,
+        device: &B::Device,
+        buffers: &mut VecDeque<Unbound<B::Buffer>>,
+        images: &mut VecDeque<Unbound<B::Image>>,
+    ) -> Result<(), FactoryError> {
+        for (request, size, usage) in self.buffers {
+            let info = ResourceInfo::Buffer { size, usage };
+            let raw = device.create_buffer(size, usage).map_err(|error| {
+                FactoryError::BufferCreationError {
+                    error,
+                    resource: info.clone(),
+                }
+            })?;
+            buffers.push_back(Unbound {
+                reqs: device.get_buffer_requirements(&raw),
+                raw,
+                request,
+                info,
+            });
+        }
+        for (request, image) in self.images {
+            let info = ResourceInfo::Image(image);
+            let raw = device
+                .create_image(
+                    image.kind,
+                    image.level,
+                    image.format,
+                    image.tiling,
+                    image.usage,
+                    image.view_caps,
+                )
+                .map_err(|error| FactoryError::ImageCreationError {
+                    error,
+                    resource: info.clone(),
+                })?;
+            images.push_back(Unbound {
+                reqs: device.get_image_requirements(&raw),
+                raw,
+                request,
+                info,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Reserve memory for all resources, buffers first, or nothing at all.
+fn reserve<B>(
+    allocator: &mut SmartAllocator<B>,
+    buffers: &VecDeque<Unbound<B::Buffer>>,
+    images: &VecDeque<Unbound<B::Image>>,
+) -> Result<Vec<Reservation>, FactoryError>
+where
+    B: Backend,
+{
+    let mut reservations = Vec::with_capacity(buffers.len() + images.len());
+    let unbound = buffers
+        .iter()
+        .map(|buffer| (buffer.request, buffer.reqs, &buffer.info))
+        .chain(
+            images
+                .iter()
+                .map(|image| (image.request, image.reqs, &image.info)),
+        );
+    for (request, reqs, info) in unbound {
+        match allocator.reserve(request, reqs) {
+            Ok(reservation) => reservations.push(reservation),
+            Err(error) => {
+                for reservation in reservations {
+                    allocator.cancel_reservation(reservation);
+                }
+                return Err(FactoryError::MemoryError {
+                    error,
+                    resource: info.clone(),
+                });
+            }
+        }
+    }
+    Ok(reservations)
+}
+
+/// Allocate reserved memory and bind it to the resources, moving them into `resources`.
+///
+/// Resources left in `buffers` and `images` on failure are not bound yet, and their
+/// reservations are cancelled.
+unsafe fn bind<B>(
+    allocator: &mut SmartAllocator<B>,
+    device: &B::Device,
+    buffers: &mut VecDeque<Unbound<B::Buffer>>,
+    images: &mut VecDeque<Unbound<B::Image>>,
+    reservations: Vec<Reservation>,
+    resources: &mut SmartResources<B>,
+) -> Result<(), FactoryError>
+where
+    B: Backend,
+{
+    let mut reservations = reservations.into_iter();
+    let mut result = Ok(());
+    while result.is_ok() {
+        let reservation = match reservations.next() {
+            Some(reservation) => reservation,
+            None => break,
+        };
+        let block = allocator.commit_reservation(device, reservation);
+        result = if let Some(buffer) = buffers.pop_front() {
+            match block {
+                Ok(block) => match bind_buffer(device, buffer.raw, block, buffer.info) {
+                    Ok(buffer) => {
+                        resources.buffers.push(buffer);
+                        Ok(())
+                    }
+                    Err((block, error)) => {
+                        allocator.free(device, block);
+                        Err(error)
+                    }
+                },
+                Err(error) => {
+                    device.destroy_buffer(buffer.raw);
+                    Err(FactoryError::MemoryError {
+                        error,
+                        resource: buffer.info,
+                    })
+                }
+            }
+        } else {
+            let image = images.pop_front().expect("Reserved for each resource");
+            match block {
+                Ok(block) => match bind_image(device, image.raw, block, image.info) {
+                    Ok(image) => {
+                        resources.images.push(image);
+                        Ok(())
+                    }
+                    Err((block, error)) => {
+                        allocator.free(device, block);
+                        Err(error)
+                    }
+                },
+                Err(error) => {
+                    device.destroy_image(image.raw);
+                    Err(FactoryError::MemoryError {
+                        error,
+                        resource: image.info,
+                    })
+                }
+            }
+        };
+    }
+    for reservation in reservations {
+        allocator.cancel_reservation(reservation);
+    }
+    result
+}
+
+impl<B, F> Default for Transaction<B, F>
+where
+    B: Backend,
+    F: Factory<B>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Resources created by a committed `Transaction`, in the order they were added.
+#[derive(Debug)]
+pub struct Resources<Buf, Img> {
+    /// Created buffers
+    pub buffers: Vec<Buf>,
+    /// Created images
+    pub images: Vec<Img>,
+}
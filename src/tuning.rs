@@ -0,0 +1,435 @@
+use std::error::Error;
+use std::fmt;
+
+use gfx_hal::adapter::{AdapterInfo, DeviceType};
+
+use arena::ArenaRounding;
+
+const MB: u64 = 1024 * 1024;
+
+/// Divisor of `max_chunk_size` giving the dedicated threshold of `CombinedAllocator::new`.
+///
+/// A block bigger than half of the largest chunk would leave most of its chunk unusable, so it
+/// gets its own memory object instead. Profiles set `dedicated_threshold` explicitly.
+pub const DEDICATED_THRESHOLD_DIVISOR: u64 = 2;
+
+/// Atom size readback blocks of `CombinedAllocator::new` are padded to.
+///
+/// Vulkan limits `nonCoherentAtomSize` to at most 256 bytes, so padding to it is safe on any
+/// device when its limits are not known.
+pub const MAX_NON_COHERENT_ATOM_SIZE: u64 = 256;
+
+// PCI vendor ids of GPU vendors with presets
+const NVIDIA: usize = 0x10DE;
+const AMD: usize = 0x1002;
+const INTEL: usize = 0x8086;
+const ARM: usize = 0x13B5;
+const QUALCOMM: usize = 0x5143;
+const IMGTEC: usize = 0x1010;
+const APPLE: usize = 0x106B;
+
+/// Allocator parameters tuned for a family of GPUs.
+///
+/// Use `TuningProfile::for_adapter` to pick a built-in preset and
+/// `SmartAllocator::with_profile` to create an allocator from it.
+/// Custom profiles can be checked with `validate`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TuningProfile {
+    /// See `ArenaAllocator`.
+    pub arena_chunk_size: u64,
+    /// See `ChunkedAllocator`.
+    pub blocks_per_chunk: usize,
+    /// See `ChunkedAllocator`.
+    pub min_block_size: u64,
+    /// See `ChunkedAllocator`.
+    pub max_chunk_size: u64,
+    /// See `CombinedAllocator::set_dedicated_threshold`.
+    pub dedicated_threshold: u64,
+    /// See `ArenaAllocator::set_rounding`.
+    pub arena_rounding: ArenaRounding,
+    /// Fraction of each heap kept free as emergency reserve.
+    /// See `SmartAllocator::set_emergency_reserve`.
+    pub heap_headroom: f32,
+}
+
+impl TuningProfile {
+    /// Preset for discrete desktop GPUs of unknown vendors.
+    pub fn desktop() -> Self {
+        TuningProfile {
+            arena_chunk_size: 16 * MB,
+            blocks_per_chunk: 64,
+            min_block_size: 256,
+            max_chunk_size: 64 * MB,
+            dedicated_threshold: 32 * MB,
+            arena_rounding: ArenaRounding::ChunkSize,
+            heap_headroom: 0.05,
+        }
+    }
+
+    /// Preset for NVIDIA GPUs.
+    ///
+    /// Drivers handle many memory objects well but allocation itself is slow.
+    pub fn nvidia() -> Self {
+        TuningProfile {
+            max_chunk_size: 128 * MB,
+            dedicated_threshold: 64 * MB,
+            ..Self::desktop()
+        }
+    }
+
+    /// Preset for AMD GPUs.
+    ///
+    /// Large resources benefit from dedicated allocations.
+    pub fn amd() -> Self {
+        TuningProfile {
+            dedicated_threshold: 16 * MB,
+            ..Self::desktop()
+        }
+    }
+
+    /// Preset for integrated GPUs sharing memory with the host, such as Intel's.
+    pub fn integrated() -> Self {
+        TuningProfile {
+            arena_chunk_size: 8 * MB,
+            max_chunk_size: 32 * MB,
+            dedicated_threshold: 16 * MB,
+            heap_headroom: 0.1,
+            ..Self::desktop()
+        }
+    }
+
+    /// Preset for mobile GPUs with little memory and a low limit on memory objects.
+    pub fn mobile() -> Self {
+        TuningProfile {
+            arena_chunk_size: 4 * MB,
+            blocks_per_chunk: 32,
+            min_block_size: 256,
+            max_chunk_size: 16 * MB,
+            dedicated_threshold: 8 * MB,
+            arena_rounding: ArenaRounding::ChunkSize,
+            heap_headroom: 0.2,
+        }
+    }
+
+    /// Preset for compute workloads on any GPU.
+    ///
+    /// Storage buffers are big and long-lived, so chunks and blocks are large and few.
+    /// Short-lived allocations are rare, so arena chunks are kept small.
+    pub fn compute() -> Self {
+        TuningProfile {
+            arena_chunk_size: 4 * MB,
+            blocks_per_chunk: 16,
+            min_block_size: 64 * 1024,
+            max_chunk_size: 256 * MB,
+            dedicated_threshold: 128 * MB,
+            arena_rounding: ArenaRounding::Exact,
+            heap_headroom: 0.05,
+        }
+    }
+
+    /// Choose a preset by PCI vendor id and device type.
+    pub fn for_vendor(vendor: usize, device_type: &DeviceType) -> Self {
+        match vendor {
+            NVIDIA => Self::nvidia(),
+            AMD => Self::amd(),
+            INTEL => Self::integrated(),
+            ARM | QUALCOMM | IMGTEC | APPLE => Self::mobile(),
+            _ => match *device_type {
+                DeviceType::IntegratedGpu => Self::integrated(),
+                _ => Self::desktop(),
+            },
+        }
+    }
+
+    /// Check that allocators can be created from this profile.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        validate_parameters(
+            self.arena_chunk_size,
+            self.blocks_per_chunk,
+            self.min_block_size,
+            self.max_chunk_size,
+        )?;
+        if !(0.0..1.0).contains(&self.heap_headroom) {
+            return Err(ConfigError::HeapHeadroom(self.heap_headroom));
+        }
+        Ok(())
+    }
+
+    /// Choose a preset for an adapter.
+    pub fn for_adapter(info: &AdapterInfo) -> Self {
+        Self::for_vendor(info.vendor, &info.device_type)
+    }
+}
+
+impl Default for TuningProfile {
+    fn default() -> Self {
+        Self::desktop()
+    }
+}
+
+/// Size above which `Type::General` blocks get their own memory object, see
+/// `SmartAllocator::set_dedicated_threshold_rule`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum DedicatedThreshold {
+    /// Size in bytes, the same for all memory types.
+    Size(u64),
+
+    /// Fraction of the size of the heap of each memory type, e.g. `0.125` makes blocks larger
+    /// than an eighth of the heap dedicated.
+    HeapFraction(f32),
+}
+
+impl DedicatedThreshold {
+    /// Get the threshold in bytes for a heap of `heap_size` bytes.
+    pub fn for_heap(&self, heap_size: u64) -> u64 {
+        match *self {
+            DedicatedThreshold::Size(size) => size,
+            DedicatedThreshold::HeapFraction(fraction) => {
+                (heap_size as f64 * fraction as f64) as u64
+            }
+        }
+    }
+
+    /// Check that the fraction of the heap is in `(0, 1]`.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        match *self {
+            DedicatedThreshold::HeapFraction(fraction) if !(fraction > 0.0 && fraction <= 1.0) => {
+                Err(ConfigError::DedicatedFraction(fraction))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Extra space reserved behind blocks so they can grow in place, see
+/// `CombinedAllocator::set_slack`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Slack {
+    /// Fraction of the requested size, e.g. `0.25` reserves a quarter more.
+    Fraction(f32),
+
+    /// Round sizes up to the next power of two.
+    PowerOfTwo,
+}
+
+impl Slack {
+    /// Get the size reserved for a block of `size` bytes.
+    pub fn apply(&self, size: u64) -> u64 {
+        match *self {
+            Slack::Fraction(fraction) => {
+                size.saturating_add((size as f64 * fraction as f64).ceil() as u64)
+            }
+            Slack::PowerOfTwo => size.checked_next_power_of_two().unwrap_or(size),
+        }
+    }
+
+    /// Check that the fraction is not negative.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        match *self {
+            Slack::Fraction(fraction) if !(fraction >= 0.0) => {
+                Err(ConfigError::SlackFraction(fraction))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Reasons for allocator parameters to be rejected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConfigError {
+    /// Arena chunk size is zero.
+    ZeroArenaChunkSize,
+
+    /// Number of blocks per chunk is zero.
+    ZeroBlocksPerChunk,
+
+    /// Minimum block size is not a power of two.
+    MinBlockSize(u64),
+
+    /// Maximum chunk size is not a power of two.
+    MaxChunkSize(u64),
+
+    /// Minimum block size is larger than the maximum chunk size.
+    BlockLargerThanChunk {
+        /// Minimum block size
+        min_block_size: u64,
+        /// Maximum chunk size
+        max_chunk_size: u64,
+    },
+
+    /// Heap headroom is not in `[0, 1)`.
+    HeapHeadroom(f32),
+
+    /// Fraction of the heap for the dedicated threshold is not in `(0, 1]`.
+    DedicatedFraction(f32),
+
+    /// Fraction of slack is negative.
+    SlackFraction(f32),
+
+    /// Minimum alignment is not a power of two.
+    MinAlignment(u64),
+
+    /// Large page size is not a power of two.
+    LargePage(u64),
+
+    /// Heap index is out of range for the memory properties of the device.
+    HeapIndex(usize),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::ZeroArenaChunkSize => fmt.write_str("arena chunk size is zero"),
+            ConfigError::ZeroBlocksPerChunk => fmt.write_str("blocks per chunk is zero"),
+            ConfigError::MinBlockSize(size) => {
+                write!(fmt, "minimum block size {} is not a power of two", size)
+            }
+            ConfigError::MaxChunkSize(size) => {
+                write!(fmt, "maximum chunk size {} is not a power of two", size)
+            }
+            ConfigError::BlockLargerThanChunk {
+                min_block_size,
+                max_chunk_size,
+            } => write!(
+                fmt,
+                "minimum block size {} is larger than maximum chunk size {}",
+                min_block_size, max_chunk_size
+            ),
+            ConfigError::HeapHeadroom(headroom) => {
+                write!(fmt, "heap headroom {} is not in [0, 1)", headroom)
+            }
+            ConfigError::DedicatedFraction(fraction) => write!(
+                fmt,
+                "dedicated threshold fraction {} is not in (0, 1]",
+                fraction
+            ),
+            ConfigError::SlackFraction(fraction) => {
+                write!(fmt, "slack fraction {} is negative", fraction)
+            }
+            ConfigError::MinAlignment(alignment) => {
+                write!(fmt, "minimum alignment {} is not a power of two", alignment)
+            }
+            ConfigError::LargePage(size) => {
+                write!(fmt, "large page size {} is not a power of two", size)
+            }
+            ConfigError::HeapIndex(index) => write!(fmt, "heap index {} is out of range", index),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+/// Check parameters of `CombinedAllocator::new`.
+pub(crate) fn validate_parameters(
+    arena_chunk_size: u64,
+    blocks_per_chunk: usize,
+    min_block_size: u64,
+    max_chunk_size: u64,
+) -> Result<(), ConfigError> {
+    if arena_chunk_size == 0 {
+        Err(ConfigError::ZeroArenaChunkSize)
+    } else if blocks_per_chunk == 0 {
+        Err(ConfigError::ZeroBlocksPerChunk)
+    } else if !min_block_size.is_power_of_two() {
+        Err(ConfigError::MinBlockSize(min_block_size))
+    } else if !max_chunk_size.is_power_of_two() {
+        Err(ConfigError::MaxChunkSize(max_chunk_size))
+    } else if min_block_size > max_chunk_size {
+        Err(ConfigError::BlockLargerThanChunk {
+            min_block_size,
+            max_chunk_size,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_presets() {
+    assert_eq!(
+        TuningProfile::for_vendor(ARM, &DeviceType::IntegratedGpu),
+        TuningProfile::mobile()
+    );
+    assert_eq!(
+        TuningProfile::for_vendor(0, &DeviceType::IntegratedGpu),
+        TuningProfile::integrated()
+    );
+    for profile in &[
+        TuningProfile::desktop(),
+        TuningProfile::nvidia(),
+        TuningProfile::amd(),
+        TuningProfile::integrated(),
+        TuningProfile::mobile(),
+        TuningProfile::compute(),
+    ] {
+        assert_eq!(profile.validate(), Ok(()));
+        assert!(profile.dedicated_threshold <= profile.max_chunk_size);
+    }
+}
+
+#[test]
+fn test_validate() {
+    let profile = TuningProfile::desktop();
+    assert_eq!(
+        TuningProfile {
+            arena_chunk_size: 0,
+            ..profile
+        }
+        .validate(),
+        Err(ConfigError::ZeroArenaChunkSize)
+    );
+    assert_eq!(
+        TuningProfile {
+            min_block_size: 96,
+            ..profile
+        }
+        .validate(),
+        Err(ConfigError::MinBlockSize(96))
+    );
+    assert_eq!(
+        TuningProfile {
+            min_block_size: 2 * profile.max_chunk_size,
+            ..profile
+        }
+        .validate(),
+        Err(ConfigError::BlockLargerThanChunk {
+            min_block_size: 2 * profile.max_chunk_size,
+            max_chunk_size: profile.max_chunk_size,
+        })
+    );
+    assert_eq!(
+        TuningProfile {
+            heap_headroom: 1.0,
+            ..profile
+        }
+        .validate(),
+        Err(ConfigError::HeapHeadroom(1.0))
+    );
+}
+
+#[test]
+fn test_dedicated_threshold() {
+    const GB: u64 = 1024 * MB;
+    assert_eq!(DedicatedThreshold::Size(MB).for_heap(8 * GB), MB);
+    assert_eq!(DedicatedThreshold::HeapFraction(0.125).for_heap(8 * GB), GB);
+    assert!(DedicatedThreshold::HeapFraction(1.0).validate().is_ok());
+    assert_eq!(
+        DedicatedThreshold::HeapFraction(0.0).validate(),
+        Err(ConfigError::DedicatedFraction(0.0))
+    );
+}
+
+#[test]
+fn test_slack() {
+    assert_eq!(Slack::Fraction(0.25).apply(100), 125);
+    assert_eq!(Slack::Fraction(0.0).apply(100), 100);
+    assert_eq!(Slack::PowerOfTwo.apply(100), 128);
+    assert_eq!(Slack::PowerOfTwo.apply(!0), !0);
+    assert_eq!(
+        Slack::Fraction(-1.0).validate(),
+        Err(ConfigError::SlackFraction(-1.0))
+    );
+}
@@ -0,0 +1,1204 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt::{self, Debug};
+use std::io::{self, Read};
+use std::iter::once;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use gfx_hal::buffer::Usage as BufferUsage;
+use gfx_hal::command::{BufferCopy, BufferImageCopy, CommandBuffer, OneShot};
+use gfx_hal::device::{DeviceLost, OomOrDeviceLost, OutOfMemory};
+use gfx_hal::image::{
+    Access as ImageAccess, Layout, Offset, SubresourceLayers, SubresourceRange, Usage as ImageUsage,
+};
+use gfx_hal::mapping;
+use gfx_hal::memory::{Barrier, Dependencies};
+use gfx_hal::pool::CommandPool;
+use gfx_hal::pso::PipelineStage;
+use gfx_hal::queue::{Capability, CommandQueue, QueueFamilyId, Submission, Supports, Transfer};
+use gfx_hal::{Backend, Device};
+
+use block::Block;
+use event::{Event, Listener};
+use factory::{Factory, FactoryError, HalError, ImageInfo, Item};
+use mapped::MappedBlock;
+use sharded::{ShardedAllocator, ShardedBlock};
+use MemoryAllocator;
+
+/// Number of staging buffers `Uploader::upload_stream` alternates between.
+const STREAM_SLOTS: usize = 2;
+
+/// Possible errors that may be returned from `Uploader`, `UploadHeap` and `ReadbackHeap`.
+#[derive(Debug, Clone)]
+pub enum UploadError {
+    /// Staging buffer creation failed.
+    Staging(FactoryError),
+
+    /// Staging buffer memory couldn't be mapped.
+    Map(mapping::Error),
+
+    /// Flushing staging buffer memory failed.
+    Flush(OutOfMemory),
+
+    /// Invalidating readback buffer memory failed.
+    Invalidate(OutOfMemory),
+
+    /// Image creation failed.
+    Image(FactoryError),
+
+    /// Submission failed.
+    Submit(OutOfMemory),
+
+    /// Waiting for the upload to complete failed.
+    Wait(OomOrDeviceLost),
+
+    /// Reading or producing data to upload failed.
+    Read(io::ErrorKind),
+
+    /// The `UploadCoordinator` was dropped.
+    Disconnected,
+
+    /// An `UploadHeap` or `ReadbackHeap` has no room for this many bytes.
+    Full(u64),
+
+    /// Data doesn't match the size of the region it is uploaded to.
+    DataSize {
+        /// Size of the region in bytes
+        expected: u64,
+        /// Size of the data in bytes
+        actual: u64,
+    },
+
+    /// The memory of an `UploadHeap` or `ReadbackHeap` is shared with other blocks.
+    SharedMemory,
+
+    /// Waiting for the device timed out.
+    Timeout,
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UploadError::Staging(_) => fmt.write_str("Failed to create staging buffer"),
+            UploadError::Map(_) => fmt.write_str("Failed to map staging buffer"),
+            UploadError::Flush(_) => fmt.write_str("Failed to flush staging buffer"),
+            UploadError::Invalidate(_) => fmt.write_str("Failed to invalidate readback buffer"),
+            UploadError::Image(_) => fmt.write_str("Failed to create image"),
+            UploadError::Submit(_) => fmt.write_str("Failed to submit upload"),
+            UploadError::Wait(_) => fmt.write_str("Failed to wait for upload"),
+            UploadError::Read(kind) => write!(fmt, "Failed to read data: {:?}", kind),
+            UploadError::Disconnected => fmt.write_str("Upload coordinator is gone"),
+            UploadError::Full(size) => write!(fmt, "Upload heap has no room for {} bytes", size),
+            UploadError::DataSize { expected, actual } => {
+                write!(fmt, "Expected {} bytes of data, got {}", expected, actual)
+            }
+            UploadError::SharedMemory => fmt.write_str("Heap memory is shared with other blocks"),
+            UploadError::Timeout => fmt.write_str("Timed out waiting for the device"),
+        }
+    }
+}
+
+impl Error for UploadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            UploadError::Staging(ref error) => Some(error),
+            UploadError::Map(ref error) => Some(HalError::wrap(error)),
+            UploadError::Flush(ref error) => Some(HalError::wrap(error)),
+            UploadError::Invalidate(ref error) => Some(HalError::wrap(error)),
+            UploadError::Image(ref error) => Some(error),
+            UploadError::Submit(ref error) => Some(HalError::wrap(error)),
+            UploadError::Wait(ref error) => Some(HalError::wrap(error)),
+            UploadError::Read(_)
+            | UploadError::Disconnected
+            | UploadError::Full(_)
+            | UploadError::DataSize { .. }
+            | UploadError::SharedMemory
+            | UploadError::Timeout => None,
+        }
+    }
+}
+
+/// Copies recorded into one command buffer and the staging buffers they read from.
+struct Batch<B: Backend, T> {
+    command: CommandBuffer<B, Transfer, OneShot>,
+    staging: Vec<Item<B::Buffer, T>>,
+    fence: Option<B::Fence>,
+    /// Number of cleanups that found the batch pending
+    pending: u32,
+}
+
+/// Uploads data to device-local resources through a dedicated transfer queue.
+///
+/// Data is written to host-visible staging buffers and copy commands are recorded into a
+/// command buffer. `submit` sends all copies recorded since the previous submission as one
+/// batch, and `cleanup` frees the staging buffers of batches the device has finished executing.
+///
+/// Resources written by the uploader are owned by its queue family after the batch completes.
+/// Use `Item::transfer_buffer` and `Item::transfer_image` to release them to another family.
+///
+/// ### Type parameters:
+///
+/// - `B`: hal `Backend`
+/// - `A`: allocator staging memory is allocated from
+pub struct Uploader<B: Backend, A: MemoryAllocator<B>> {
+    request: A::Request,
+    /// `nonCoherentAtomSize` staging memory is flushed in
+    atom: u64,
+    family: QueueFamilyId,
+    pool: CommandPool<B, Transfer>,
+    recording: Option<Batch<B, A::Block>>,
+    in_flight: VecDeque<Batch<B, A::Block>>,
+    fences: Vec<B::Fence>,
+    watchdog: Option<u32>,
+    listener: Listener,
+}
+
+impl<B, A> Debug for Uploader<B, A>
+where
+    B: Backend,
+    A: MemoryAllocator<B>,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Uploader")
+            .field("family", &self.family)
+            .field(
+                "recording",
+                &self.recording.as_ref().map(|b| b.staging.len()),
+            )
+            .field("in_flight", &self.in_flight.len())
+            .field("fences", &self.fences.len())
+            .field("watchdog", &self.watchdog)
+            .finish()
+    }
+}
+
+impl<B, A> Uploader<B, A>
+where
+    B: Backend,
+    A: MemoryAllocator<B>,
+    A::Request: Clone,
+{
+    /// Create an uploader recording into command buffers from `pool`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `request`: request used to allocate host-visible staging memory
+    /// - `family`: queue family of the transfer queue `pool` was created for
+    /// - `pool`: command pool of `family`
+    pub fn new(request: A::Request, family: QueueFamilyId, pool: CommandPool<B, Transfer>) -> Self {
+        Uploader {
+            request,
+            atom: 1,
+            family,
+            pool,
+            recording: None,
+            in_flight: VecDeque::new(),
+            fences: Vec::new(),
+            watchdog: None,
+            listener: Listener::default(),
+        }
+    }
+
+    /// Get queue family of the transfer queue.
+    pub fn family(&self) -> QueueFamilyId {
+        self.family
+    }
+
+    /// Flush staging memory in whole atoms of `atom` bytes.
+    ///
+    /// Must be set to `nonCoherentAtomSize` if the request of staging memory allows memory that
+    /// isn't `COHERENT`. Staging blocks ending inside an atom are flushed up to the end of their
+    /// memory object. Defaults to 1.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `atom` is not a power of two.
+    pub fn set_atom_size(&mut self, atom: u64) {
+        assert!(atom.is_power_of_two());
+        self.atom = atom;
+    }
+
+    /// Report batches still pending after `cleanups` calls to `cleanup` with
+    /// `Event::StuckFrees`, or never if `None`, which is the default.
+    ///
+    /// `cleanup` is usually called once per frame, so this is the number of frames staging
+    /// buffers may wait for their fence before something is considered wrong.
+    pub fn set_watchdog(&mut self, cleanups: Option<u32>) {
+        self.watchdog = cleanups;
+    }
+
+    /// Set a callback that receives `Event`s reported by this uploader.
+    pub fn set_listener<F>(&mut self, callback: F)
+    where
+        F: Fn(&Event) + Send + Sync + 'static,
+    {
+        self.listener.set(callback);
+    }
+
+    /// Get number of submitted batches the device may not have finished yet.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Copy `data` into `dst` starting at `offset`.
+    ///
+    /// The copy is executed by the next `submit`.
+    ///
+    /// ### Safety
+    ///
+    /// `dst` must have `TRANSFER_DST` usage and must not be destroyed before the batch completes.
+    pub unsafe fn upload_buffer(
+        &mut self,
+        allocator: &mut A,
+        device: &B::Device,
+        dst: &B::Buffer,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(), UploadError> {
+        let staging = self.stage(allocator, device, data)?;
+        let batch = self.batch();
+        batch.command.copy_buffer(
+            staging.raw(),
+            dst,
+            once(BufferCopy {
+                src: 0,
+                dst: offset,
+                size: data.len() as u64,
+            }),
+        );
+        batch.staging.push(staging);
+        Ok(())
+    }
+
+    /// Copy `data` into the region of `dst` described by `copy`.
+    ///
+    /// `copy.buffer_offset` is relative to `data`.
+    /// The copy is executed by the next `submit`.
+    ///
+    /// ### Safety
+    ///
+    /// `dst` must have `TRANSFER_DST` usage, be in `layout` when the batch executes
+    /// and must not be destroyed before the batch completes.
+    pub unsafe fn upload_image(
+        &mut self,
+        allocator: &mut A,
+        device: &B::Device,
+        dst: &B::Image,
+        layout: Layout,
+        copy: BufferImageCopy,
+        data: &[u8],
+    ) -> Result<(), UploadError> {
+        let staging = self.stage(allocator, device, data)?;
+        let batch = self.batch();
+        batch
+            .command
+            .copy_buffer_to_image(staging.raw(), dst, layout, once(copy));
+        batch.staging.push(staging);
+        Ok(())
+    }
+
+    /// Create an image, fill its first mip level with `data` and wait until the upload completes.
+    ///
+    /// Blocking convenience for tools and offline processing that don't have a frame loop.
+    /// `data` holds all array layers of the first level tightly packed. The image gets
+    /// `TRANSFER_DST` usage in addition to `info.usage` and is left in `ShaderReadOnlyOptimal`
+    /// layout, owned by the uploader's queue family. Copies recorded before are submitted and
+    /// waited for as well. If the submission fails, they are discarded with the image.
+    ///
+    /// ### Errors
+    ///
+    /// Returns `UploadError::DataSize` without creating the image if the length of `data`
+    /// doesn't match the size of the first level.
+    ///
+    /// ### Safety
+    ///
+    /// `queue` must belong to the uploader's queue family.
+    pub unsafe fn upload_image_sync<C>(
+        &mut self,
+        allocator: &mut A,
+        device: &B::Device,
+        queue: &mut CommandQueue<B, C>,
+        request: A::Request,
+        info: ImageInfo,
+        data: &[u8],
+    ) -> Result<Item<B::Image, A::Block>, UploadError>
+    where
+        C: Capability + Supports<Transfer>,
+    {
+        let expected = image_data_size(&info);
+        if data.len() as u64 != expected {
+            return Err(UploadError::DataSize {
+                expected,
+                actual: data.len() as u64,
+            });
+        }
+        // Make sure submission can't fail once the copy is recorded.
+        if self.fences.is_empty() {
+            let fence = device.create_fence(false).map_err(UploadError::Submit)?;
+            self.fences.push(fence);
+        }
+        let mut image = allocator
+            .create_image(
+                device,
+                request,
+                info.kind,
+                info.level,
+                info.format,
+                info.tiling,
+                info.usage | ImageUsage::TRANSFER_DST,
+                info.view_caps,
+            )
+            .map_err(UploadError::Image)?;
+        let staging = match self.stage(allocator, device, data) {
+            Ok(staging) => staging,
+            Err(error) => {
+                allocator.destroy_image(device, image);
+                return Err(error);
+            }
+        };
+
+        let aspects = info.format.surface_desc().aspects;
+        let layers = 0..info.kind.num_layers();
+        let range = SubresourceRange {
+            aspects,
+            levels: 0..info.level,
+            layers: layers.clone(),
+        };
+        {
+            let batch = self.batch();
+            batch.command.pipeline_barrier(
+                PipelineStage::TOP_OF_PIPE..PipelineStage::TRANSFER,
+                Dependencies::empty(),
+                once(Barrier::Image {
+                    states: (ImageAccess::empty(), Layout::Undefined)
+                        ..(ImageAccess::TRANSFER_WRITE, Layout::TransferDstOptimal),
+                    target: image.raw(),
+                    families: None,
+                    range: range.clone(),
+                }),
+            );
+            batch.command.copy_buffer_to_image(
+                staging.raw(),
+                image.raw(),
+                Layout::TransferDstOptimal,
+                once(BufferImageCopy {
+                    buffer_offset: 0,
+                    buffer_width: 0,
+                    buffer_height: 0,
+                    image_layers: SubresourceLayers {
+                        aspects,
+                        level: 0,
+                        layers,
+                    },
+                    image_offset: Offset::ZERO,
+                    image_extent: info.kind.extent(),
+                }),
+            );
+            batch.command.pipeline_barrier(
+                PipelineStage::TRANSFER..PipelineStage::BOTTOM_OF_PIPE,
+                Dependencies::empty(),
+                once(Barrier::Image {
+                    states: (ImageAccess::TRANSFER_WRITE, Layout::TransferDstOptimal)
+                        ..(ImageAccess::SHADER_READ, Layout::ShaderReadOnlyOptimal),
+                    target: image.raw(),
+                    families: None,
+                    range,
+                }),
+            );
+            batch.staging.push(staging);
+        }
+        image.set_family(Some(self.family));
+
+        if let Err(error) = self.submit(device, queue, None) {
+            // Nothing recorded since the last submission reached the device.
+            if let Some(batch) = self.recording.take() {
+                for staging in batch.staging {
+                    allocator.destroy_buffer(device, staging);
+                }
+                self.pool.free(once(batch.command));
+            }
+            allocator.destroy_image(device, image);
+            return Err(UploadError::Submit(error));
+        }
+        match self.wait(allocator, device) {
+            Ok(()) => Ok(image),
+            Err(error) => {
+                allocator.destroy_image(device, image);
+                Err(error)
+            }
+        }
+    }
+
+    /// Stream everything `reader` yields into `dst` starting at `offset`.
+    ///
+    /// Data is read in `chunk_size` slices directly into mapped staging memory. A couple of
+    /// staging buffers are reused for all slices, and each slice is submitted as its own batch,
+    /// so files much larger than the host memory can be streamed to device-local buffers.
+    /// Blocks while the device catches up with the reader.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `queue`: transfer queue of the uploader's family
+    /// - `dst`: buffer and offset to write the data at, and size of staging buffers
+    /// - `reader`: source of the data, e.g. an asset file
+    ///
+    /// ### Safety
+    ///
+    /// `queue` must belong to the uploader's queue family.
+    /// `dst.buffer` must be large enough to hold the data and must not be destroyed before the
+    /// last batch completes.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `dst.chunk_size` is zero.
+    ///
+    /// ### Returns
+    ///
+    /// Number of bytes streamed.
+    pub unsafe fn upload_stream<R, C>(
+        &mut self,
+        allocator: &mut A,
+        device: &B::Device,
+        queue: &mut CommandQueue<B, C>,
+        dst: StreamTarget<B>,
+        reader: &mut R,
+    ) -> Result<u64, UploadError>
+    where
+        R: Read,
+        C: Capability + Supports<Transfer>,
+    {
+        self.upload_chunks(allocator, device, queue, dst, |buf| fill(reader, buf))
+    }
+
+    /// Fill staging memory chunk by chunk with `write` and copy the chunks into `dst`.
+    ///
+    /// `write` is called with mapped staging memory of `dst.chunk_size` bytes and returns the
+    /// number of bytes it wrote, which must not exceed the size of the memory it was given.
+    /// Uploading stops when it writes nothing. This allows e.g.
+    /// decompressing an LZ4 stream directly into staging memory without an intermediate
+    /// host buffer. Staging buffers are reused as in `upload_stream`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `queue`: transfer queue of the uploader's family
+    /// - `dst`: buffer and offset to write the data at, and size of staging buffers
+    /// - `write`: callback producing the data
+    ///
+    /// ### Safety
+    ///
+    /// `queue` must belong to the uploader's queue family.
+    /// `dst.buffer` must be large enough to hold the data and must not be destroyed before the
+    /// last batch completes.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `dst.chunk_size` is zero.
+    ///
+    /// ### Errors
+    ///
+    /// Returns `UploadError::Read` with `InvalidData` if `write` claims to have written more
+    /// than `dst.chunk_size` bytes, without copying that chunk.
+    ///
+    /// ### Returns
+    ///
+    /// Number of bytes uploaded.
+    pub unsafe fn upload_chunks<F, C>(
+        &mut self,
+        allocator: &mut A,
+        device: &B::Device,
+        queue: &mut CommandQueue<B, C>,
+        dst: StreamTarget<B>,
+        mut write: F,
+    ) -> Result<u64, UploadError>
+    where
+        F: FnMut(&mut [u8]) -> io::Result<usize>,
+        C: Capability + Supports<Transfer>,
+    {
+        assert_ne!(dst.chunk_size, 0, "Chunk size must not be zero");
+        let mut staging = Vec::with_capacity(STREAM_SLOTS);
+        let mut result = Ok(0);
+        for _ in 0..STREAM_SLOTS {
+            match allocator.create_buffer(
+                device,
+                self.request.clone(),
+                dst.chunk_size,
+                BufferUsage::TRANSFER_SRC,
+            ) {
+                Ok(buffer) => staging.push(buffer),
+                Err(error) => {
+                    result = Err(UploadError::Staging(error));
+                    break;
+                }
+            }
+        }
+        if result.is_ok() {
+            result = self.stream(allocator, device, queue, &staging, dst, &mut write);
+        }
+
+        // Staging buffers are freed along with the last batch that may use them.
+        let in_flight = &mut self.in_flight;
+        match self.recording.as_mut().or_else(|| in_flight.back_mut()) {
+            Some(batch) => batch.staging.extend(staging),
+            None => {
+                for buffer in staging {
+                    allocator.destroy_buffer(device, buffer);
+                }
+            }
+        }
+        result
+    }
+
+    unsafe fn stream<F, C>(
+        &mut self,
+        allocator: &mut A,
+        device: &B::Device,
+        queue: &mut CommandQueue<B, C>,
+        staging: &[Item<B::Buffer, A::Block>],
+        dst: StreamTarget<B>,
+        write: &mut F,
+    ) -> Result<u64, UploadError>
+    where
+        F: FnMut(&mut [u8]) -> io::Result<usize>,
+        C: Capability + Supports<Transfer>,
+    {
+        let mut streamed = 0;
+        for slot in staging.iter().cycle() {
+            // Batches complete in order, so the previous user of this slot is done once
+            // only the batches of the other slots are in flight.
+            self.wait_until(allocator, device, STREAM_SLOTS - 1)?;
+            let size =
+                write_with::<B, _, _>(device, slot.block(), self.atom, dst.chunk_size, write)?;
+            if size == 0 {
+                break;
+            }
+            self.batch().command.copy_buffer(
+                slot.raw(),
+                dst.buffer,
+                once(BufferCopy {
+                    src: 0,
+                    dst: dst.offset + streamed,
+                    size,
+                }),
+            );
+            self.submit(device, queue, None)
+                .map_err(UploadError::Submit)?;
+            streamed += size;
+        }
+        Ok(streamed)
+    }
+
+    /// Wait for all batches in flight and free their staging buffers.
+    unsafe fn wait(&mut self, allocator: &mut A, device: &B::Device) -> Result<(), UploadError> {
+        self.wait_until(allocator, device, 0)
+    }
+
+    /// Wait until no more than `pending` batches are in flight.
+    unsafe fn wait_until(
+        &mut self,
+        allocator: &mut A,
+        device: &B::Device,
+        pending: usize,
+    ) -> Result<(), UploadError> {
+        if self.in_flight.len() > pending {
+            let batch = &self.in_flight[self.in_flight.len() - 1 - pending];
+            device
+                .wait_for_fence(batch.fence.as_ref().unwrap(), !0)
+                .map_err(UploadError::Wait)?;
+        }
+        self.cleanup(allocator, device)
+            .map_err(|error| UploadError::Wait(OomOrDeviceLost::DeviceLost(error)))
+    }
+
+    /// Submit copies recorded since the last submission to `queue`.
+    ///
+    /// `signal` is signaled when the batch completes, so that other queues can wait for it.
+    /// Does nothing if no copies were recorded.
+    ///
+    /// ### Safety
+    ///
+    /// `queue` must belong to the uploader's queue family.
+    pub unsafe fn submit<C>(
+        &mut self,
+        device: &B::Device,
+        queue: &mut CommandQueue<B, C>,
+        signal: Option<&B::Semaphore>,
+    ) -> Result<(), OutOfMemory>
+    where
+        C: Capability + Supports<Transfer>,
+    {
+        let mut batch = match self.recording.take() {
+            Some(batch) => batch,
+            None => return Ok(()),
+        };
+        let fence = match self.fences.pop() {
+            Some(fence) => fence,
+            None => match device.create_fence(false) {
+                Ok(fence) => fence,
+                Err(error) => {
+                    self.recording = Some(batch);
+                    return Err(error);
+                }
+            },
+        };
+        batch.command.finish();
+        queue.submit(
+            Submission {
+                command_buffers: once(&batch.command),
+                wait_semaphores: None::<(&B::Semaphore, PipelineStage)>,
+                signal_semaphores: signal,
+            },
+            Some(&fence),
+        );
+        batch.fence = Some(fence);
+        self.in_flight.push_back(batch);
+        Ok(())
+    }
+
+    /// Free staging buffers of batches the device has finished executing.
+    ///
+    /// Batches complete in submission order, so polling stops at the first pending batch.
+    /// Batches pending for longer than allowed by `set_watchdog` are reported once.
+    pub unsafe fn cleanup(
+        &mut self,
+        allocator: &mut A,
+        device: &B::Device,
+    ) -> Result<(), DeviceLost> {
+        while self.in_flight.front().map_or(Ok(false), |batch| {
+            device.get_fence_status(batch.fence.as_ref().unwrap())
+        })? {
+            let batch = self.in_flight.pop_front().unwrap();
+            self.recycle(allocator, device, batch);
+        }
+        for batch in &mut self.in_flight {
+            batch.pending = batch.pending.saturating_add(1);
+            if overdue(batch.pending, self.watchdog) {
+                self.listener.emit(Event::StuckFrees {
+                    buffers: batch.staging.len(),
+                    size: batch
+                        .staging
+                        .iter()
+                        .map(|staging| staging.block().size())
+                        .sum(),
+                    cleanups: batch.pending,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Dispose of the uploader, returning its command pool.
+    ///
+    /// Fails and returns the uploader back if any batch is still recording or in flight.
+    pub unsafe fn dispose(self, device: &B::Device) -> Result<CommandPool<B, Transfer>, Self> {
+        if self.recording.is_some() || !self.in_flight.is_empty() {
+            return Err(self);
+        }
+        for fence in self.fences {
+            device.destroy_fence(fence);
+        }
+        Ok(self.pool)
+    }
+
+    unsafe fn stage(
+        &mut self,
+        allocator: &mut A,
+        device: &B::Device,
+        data: &[u8],
+    ) -> Result<Item<B::Buffer, A::Block>, UploadError> {
+        let staging = allocator
+            .create_buffer(
+                device,
+                self.request.clone(),
+                data.len() as u64,
+                BufferUsage::TRANSFER_SRC,
+            )
+            .map_err(UploadError::Staging)?;
+        if let Err(error) = write::<B, _>(device, staging.block(), self.atom, data) {
+            allocator.destroy_buffer(device, staging);
+            return Err(error);
+        }
+        Ok(staging)
+    }
+
+    fn batch(&mut self) -> &mut Batch<B, A::Block> {
+        let pool = &mut self.pool;
+        self.recording.get_or_insert_with(|| {
+            let mut command = pool.acquire_command_buffer::<OneShot>();
+            unsafe {
+                command.begin();
+            }
+            Batch {
+                command,
+                staging: Vec::new(),
+                fence: None,
+                pending: 0,
+            }
+        })
+    }
+
+    unsafe fn recycle(&mut self, allocator: &mut A, device: &B::Device, batch: Batch<B, A::Block>) {
+        for staging in batch.staging {
+            allocator.destroy_buffer(device, staging);
+        }
+        self.pool.free(once(batch.command));
+        let fence = batch.fence.unwrap();
+        if device.reset_fence(&fence).is_ok() {
+            self.fences.push(fence);
+        } else {
+            device.destroy_fence(fence);
+        }
+    }
+}
+
+/// Get the size of tightly packed data for all layers of the first level of an image.
+fn image_data_size(info: &ImageInfo) -> u64 {
+    let desc = info.format.surface_desc();
+    let extent = info.kind.extent();
+    let blocks = |size: u32, dim: u8| (size as u64 + dim as u64 - 1) / dim as u64;
+    blocks(extent.width, desc.dim.0)
+        * blocks(extent.height, desc.dim.1)
+        * extent.depth as u64
+        * info.kind.num_layers() as u64
+        * (desc.bits as u64 / 8)
+}
+
+/// Destination of data staged by an `UploadLoader`.
+#[derive(Debug)]
+pub enum UploadTarget<'a, B: Backend> {
+    /// Region of a buffer.
+    Buffer {
+        /// Buffer with `TRANSFER_DST` usage.
+        buffer: &'a B::Buffer,
+        /// Offset in `buffer` to write the data at.
+        offset: u64,
+    },
+
+    /// Region of an image.
+    Image {
+        /// Image with `TRANSFER_DST` usage.
+        image: &'a B::Image,
+        /// Layout of the image when the batch executes.
+        layout: Layout,
+        /// Region to write, `copy.buffer_offset` is relative to the staged data.
+        copy: BufferImageCopy,
+    },
+}
+
+/// Destination of data uploaded chunk by chunk, see `Uploader::upload_chunks`.
+#[derive(Debug)]
+pub struct StreamTarget<'a, B: Backend> {
+    /// Buffer with `TRANSFER_DST` usage.
+    pub buffer: &'a B::Buffer,
+    /// Offset in `buffer` to write the data at.
+    pub offset: u64,
+    /// Size of staging buffers the data is written to before it is copied.
+    pub chunk_size: u64,
+}
+
+/// Data written to staging memory by a loader thread.
+struct Staged<B: Backend, T, K> {
+    staging: Item<B::Buffer, T>,
+    size: u64,
+    key: K,
+}
+
+/// Coordinates uploads of assets loaded by multiple threads.
+///
+/// Loader threads use `UploadLoader` handles to allocate staging buffers and write data into
+/// them concurrently, each through its own handle of a `ShardedAllocator`. The staged data is
+/// handed over to the thread owning the coordinator, which records copies to the destinations
+/// resolved from the keys staged with the data and submits them through its `Uploader`.
+///
+/// ### Type parameters:
+///
+/// - `B`: hal `Backend`
+/// - `A`: allocator of the shards staging memory is allocated from
+/// - `K`: key identifying the destination of staged data
+pub struct UploadCoordinator<B: Backend, A: MemoryAllocator<B>, K> {
+    uploader: Uploader<B, ShardedAllocator<A>>,
+    allocator: ShardedAllocator<A>,
+    sender: Sender<Staged<B, ShardedBlock<A::Block>, K>>,
+    receiver: Receiver<Staged<B, ShardedBlock<A::Block>, K>>,
+}
+
+impl<B, A, K> Debug for UploadCoordinator<B, A, K>
+where
+    B: Backend,
+    A: MemoryAllocator<B>,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("UploadCoordinator")
+            .field("uploader", &self.uploader)
+            .finish()
+    }
+}
+
+impl<B, A, K> UploadCoordinator<B, A, K>
+where
+    B: Backend,
+    A: MemoryAllocator<B>,
+    A::Request: Clone,
+{
+    /// Create a coordinator submitting through `uploader`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `uploader`: uploader recording and submitting the copies
+    /// - `allocator`: handle of the allocator staging memory is allocated from, loaders get
+    ///                handles with the following home shards
+    pub fn new(uploader: Uploader<B, ShardedAllocator<A>>, allocator: ShardedAllocator<A>) -> Self {
+        let (sender, receiver) = channel();
+        UploadCoordinator {
+            uploader,
+            allocator,
+            sender,
+            receiver,
+        }
+    }
+
+    /// Get the allocator staging memory is allocated from.
+    pub fn allocator(&self) -> &ShardedAllocator<A> {
+        &self.allocator
+    }
+
+    /// Get number of submitted batches the device may not have finished yet.
+    pub fn in_flight(&self) -> usize {
+        self.uploader.in_flight()
+    }
+
+    /// Create a handle for a loader thread, allocating from the next home shard.
+    pub fn loader(&self) -> UploadLoader<B, A, K> {
+        UploadLoader {
+            allocator: self.allocator.handle(),
+            request: self.uploader.request.clone(),
+            atom: self.uploader.atom,
+            sender: self.sender.clone(),
+        }
+    }
+
+    /// Record copies of all data staged since the last call.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `resolve`: function returning the destination of data staged with a key
+    ///
+    /// ### Safety
+    ///
+    /// Destinations must not be destroyed before the batch completes.
+    ///
+    /// ### Returns
+    ///
+    /// Number of recorded copies.
+    pub unsafe fn record<'a, F>(&mut self, mut resolve: F) -> usize
+    where
+        F: FnMut(&K) -> UploadTarget<'a, B>,
+        B::Buffer: 'a,
+        B::Image: 'a,
+    {
+        let mut count = 0;
+        for staged in self.receiver.try_iter() {
+            let batch = self.uploader.batch();
+            match resolve(&staged.key) {
+                UploadTarget::Buffer { buffer, offset } => batch.command.copy_buffer(
+                    staged.staging.raw(),
+                    buffer,
+                    once(BufferCopy {
+                        src: 0,
+                        dst: offset,
+                        size: staged.size,
+                    }),
+                ),
+                UploadTarget::Image {
+                    image,
+                    layout,
+                    copy,
+                } => batch.command.copy_buffer_to_image(
+                    staged.staging.raw(),
+                    image,
+                    layout,
+                    once(copy),
+                ),
+            }
+            batch.staging.push(staged.staging);
+            count += 1;
+        }
+        count
+    }
+
+    /// Submit recorded copies to `queue`.
+    ///
+    /// See `Uploader::submit`.
+    pub unsafe fn submit<C>(
+        &mut self,
+        device: &B::Device,
+        queue: &mut CommandQueue<B, C>,
+        signal: Option<&B::Semaphore>,
+    ) -> Result<(), OutOfMemory>
+    where
+        C: Capability + Supports<Transfer>,
+    {
+        self.uploader.submit(device, queue, signal)
+    }
+
+    /// Free staging buffers of batches the device has finished executing.
+    pub unsafe fn cleanup(&mut self, device: &B::Device) -> Result<(), DeviceLost> {
+        self.uploader.cleanup(&mut self.allocator, device)
+    }
+
+    /// Dispose of the coordinator, returning the command pool of its uploader.
+    ///
+    /// Data staged but not recorded is discarded.
+    /// Fails and returns the coordinator back if any batch is still recording or in flight.
+    pub unsafe fn dispose(self, device: &B::Device) -> Result<CommandPool<B, Transfer>, Self> {
+        let UploadCoordinator {
+            uploader,
+            mut allocator,
+            sender,
+            receiver,
+        } = self;
+        match uploader.dispose(device) {
+            Ok(pool) => {
+                for staged in receiver.try_iter() {
+                    allocator.destroy_buffer(device, staged.staging);
+                }
+                Ok(pool)
+            }
+            Err(uploader) => Err(UploadCoordinator {
+                uploader,
+                allocator,
+                sender,
+                receiver,
+            }),
+        }
+    }
+}
+
+/// Handle used by loader threads to stage data for an `UploadCoordinator`.
+pub struct UploadLoader<B: Backend, A: MemoryAllocator<B>, K> {
+    allocator: ShardedAllocator<A>,
+    request: A::Request,
+    atom: u64,
+    sender: Sender<Staged<B, ShardedBlock<A::Block>, K>>,
+}
+
+impl<B, A, K> Clone for UploadLoader<B, A, K>
+where
+    B: Backend,
+    A: MemoryAllocator<B>,
+    A::Request: Clone,
+{
+    fn clone(&self) -> Self {
+        UploadLoader {
+            allocator: self.allocator.handle(),
+            request: self.request.clone(),
+            atom: self.atom,
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<B, A, K> Debug for UploadLoader<B, A, K>
+where
+    B: Backend,
+    A: MemoryAllocator<B>,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str("UploadLoader")
+    }
+}
+
+impl<B, A, K> UploadLoader<B, A, K>
+where
+    B: Backend,
+    A: MemoryAllocator<B>,
+    A::Request: Clone,
+{
+    /// Write `data` to a new staging buffer and hand it over to the coordinator.
+    ///
+    /// The staging buffer is allocated from the loader's home shard, or any other shard that
+    /// isn't locked. The shard stays locked while the data is written, since its memory objects
+    /// may be mapped by other threads allocating from it, while loaders writing to other shards
+    /// proceed concurrently.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: device to allocate staging memory from
+    /// - `key`: key the coordinator resolves the destination from
+    /// - `data`: data to upload
+    pub unsafe fn stage(&self, device: &B::Device, key: K, data: &[u8]) -> Result<(), UploadError> {
+        let (shard, mut allocator) = self.allocator.lock();
+        let staging = allocator
+            .create_buffer(
+                device,
+                self.request.clone(),
+                data.len() as u64,
+                BufferUsage::TRANSFER_SRC,
+            )
+            .map_err(UploadError::Staging)?;
+        if let Err(error) = write::<B, _>(device, staging.block(), self.atom, data) {
+            allocator.destroy_buffer(device, staging);
+            return Err(error);
+        }
+        drop(allocator);
+        let staged = Staged {
+            staging: staging.map_block(|block| ShardedBlock::new(block, shard)),
+            size: data.len() as u64,
+            key,
+        };
+        if let Err(error) = self.sender.send(staged) {
+            let staging = (error.0).staging.map_block(ShardedBlock::into_inner);
+            self.allocator
+                .lock_shard(shard)
+                .destroy_buffer(device, staging);
+            return Err(UploadError::Disconnected);
+        }
+        Ok(())
+    }
+}
+
+/// Write `data` to the beginning of host-visible `block` and flush it in whole `atom`s.
+unsafe fn write<B, T>(
+    device: &B::Device,
+    block: &T,
+    atom: u64,
+    data: &[u8],
+) -> Result<(), UploadError>
+where
+    B: Backend,
+    T: Block<Memory = B::Memory>,
+{
+    let mut mapped =
+        MappedBlock::<B>::map_non_coherent(device, block, atom, None).map_err(UploadError::Map)?;
+    mapped.as_slice_mut()[..data.len()].copy_from_slice(data);
+    mapped.flush().map_err(UploadError::Flush)
+}
+
+/// Let `write` fill up to `size` bytes at the beginning of host-visible `block` and flush it
+/// in whole `atom`s.
+unsafe fn write_with<B, T, F>(
+    device: &B::Device,
+    block: &T,
+    atom: u64,
+    size: u64,
+    write: &mut F,
+) -> Result<u64, UploadError>
+where
+    B: Backend,
+    T: Block<Memory = B::Memory>,
+    F: FnMut(&mut [u8]) -> io::Result<usize>,
+{
+    let mut mapped =
+        MappedBlock::<B>::map_non_coherent(device, block, atom, None).map_err(UploadError::Map)?;
+    let written = write(&mut mapped.as_slice_mut()[..size as usize])
+        .map_err(|error| UploadError::Read(error.kind()))?;
+    let written = check_written(written, size)?;
+    mapped.flush().map_err(UploadError::Flush)?;
+    Ok(written)
+}
+
+/// Check the number of bytes a callback reports to have written into `size` bytes.
+fn check_written(written: usize, size: u64) -> Result<u64, UploadError> {
+    if written as u64 > size {
+        Err(UploadError::Read(io::ErrorKind::InvalidData))
+    } else {
+        Ok(written as u64)
+    }
+}
+
+/// Check if a batch pending for `pending` cleanups was just caught by the `watchdog`.
+///
+/// Batches are reported only once, on the first cleanup over the limit.
+fn overdue(pending: u32, watchdog: Option<u32>) -> bool {
+    watchdog.map_or(false, |cleanups| pending == cleanups.saturating_add(1))
+}
+
+/// Read from `reader` until `buf` is full or `reader` is exhausted.
+fn fill<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(filled)
+}
+
+#[test]
+fn test_fill() {
+    let data = (0..10).collect::<Vec<u8>>();
+    let mut reader = io::Read::chain(&data[..4], &data[4..]);
+    let mut buf = [0; 6];
+    assert_eq!(fill(&mut reader, &mut buf).unwrap(), 6);
+    assert_eq!(buf, [0, 1, 2, 3, 4, 5]);
+    assert_eq!(fill(&mut reader, &mut buf).unwrap(), 4);
+    assert_eq!(&buf[..4], &[6, 7, 8, 9]);
+    assert_eq!(fill(&mut reader, &mut buf).unwrap(), 0);
+}
+
+#[test]
+fn test_error_source() {
+    let error = UploadError::Map(mapping::Error::OutOfBounds);
+    assert_eq!(error.to_string(), "Failed to map staging buffer");
+    assert!(error.source().is_some());
+    let error = UploadError::Timeout;
+    assert_eq!(error.to_string(), "Timed out waiting for the device");
+    assert!(error.source().is_none());
+}
+
+#[test]
+fn test_image_data_size() {
+    use gfx_hal::format::Format;
+    use gfx_hal::image::{Kind, Tiling, ViewCapabilities};
+
+    let info = |kind, format| ImageInfo {
+        kind,
+        level: 1,
+        format,
+        tiling: Tiling::Optimal,
+        usage: ImageUsage::SAMPLED,
+        view_caps: ViewCapabilities::empty(),
+    };
+    assert_eq!(
+        image_data_size(&info(Kind::D2(100, 50, 2, 1), Format::Rgba8Unorm)),
+        40_000
+    );
+    assert_eq!(
+        image_data_size(&info(Kind::D3(4, 4, 4), Format::R8Unorm)),
+        64
+    );
+    // Partial blocks of compressed formats take a whole block
+    assert_eq!(
+        image_data_size(&info(Kind::D2(10, 10, 1, 1), Format::Bc1RgbaUnorm)),
+        72
+    );
+
+    let error = UploadError::DataSize {
+        expected: 64,
+        actual: 63,
+    };
+    assert_eq!(error.to_string(), "Expected 64 bytes of data, got 63");
+}
+
+#[test]
+fn test_check_written() {
+    assert_eq!(check_written(0, 256).unwrap(), 0);
+    assert_eq!(check_written(256, 256).unwrap(), 256);
+    match check_written(257, 256) {
+        Err(UploadError::Read(io::ErrorKind::InvalidData)) => {}
+        other => panic!("Unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn test_overdue() {
+    assert!(!overdue(1, None));
+    assert!(!overdue(2, Some(2)));
+    assert!(overdue(3, Some(2)));
+    assert!(!overdue(4, Some(2)));
+    assert!(overdue(1, Some(0)));
+    // Doesn't overflow with the largest limit
+    assert!(!overdue(u32::max_value() - 1, Some(u32::max_value())));
+}
@@ -0,0 +1,310 @@
+use std::collections::VecDeque;
+use std::fmt::{self, Debug};
+use std::ops::Range;
+use std::ptr::copy_nonoverlapping;
+
+use gfx_hal::buffer::Usage as BufferUsage;
+use gfx_hal::{Backend, Device};
+
+use align::align_up;
+use block::Block;
+use factory::{Factory, Item};
+use mapped::atom_range;
+use marker::NotSend;
+use upload::UploadError;
+use MemoryAllocator;
+
+/// Persistently mapped ring buffer that data is written to every frame, like upload heaps of
+/// D3D12.
+///
+/// Data written with `write` is read by the device directly from the returned buffer and
+/// offset, e.g. as uniforms or vertices, or copied to device-local resources. The space of a
+/// frame is reused once `end_frame` has been called for as many following frames as there are
+/// frames in flight, so the device must have finished them by then.
+///
+/// The heap must be disposed with `dispose`. Like `MappedBlock` it is neither `Send` nor `Sync`.
+///
+/// ### Type parameters:
+///
+/// - `B`: hal `Backend`
+/// - `A`: allocator the memory of the heap is allocated from
+pub struct UploadHeap<B: Backend, A: MemoryAllocator<B>> {
+    buffer: Item<B::Buffer, A::Block>,
+    ptr: *mut u8,
+    ring: Ring,
+    /// Position of the ring head at the last flush
+    flushed: u64,
+    /// `nonCoherentAtomSize` flushes are rounded to
+    atom: u64,
+    not_send: NotSend,
+}
+
+impl<B, A> Debug for UploadHeap<B, A>
+where
+    B: Backend,
+    A: MemoryAllocator<B>,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("UploadHeap")
+            .field("buffer", &self.buffer)
+            .field("ring", &self.ring)
+            .finish()
+    }
+}
+
+impl<B, A> UploadHeap<B, A>
+where
+    B: Backend,
+    A: MemoryAllocator<B>,
+{
+    /// Create an upload heap and map its memory.
+    ///
+    /// The heap stays mapped, so it needs a memory object of its own: no other block of the
+    /// memory object could be mapped while the heap is.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `allocator`: allocator to allocate the memory of the heap from
+    /// - `device`: device to create the buffer of the heap on
+    /// - `request`: request of a dedicated allocation of host-visible memory, e.g.
+    ///              `Request::dedicated().cpu_visible()` with `SmartAllocator`
+    /// - `size`: size of the heap in bytes
+    /// - `usage`: usage of the buffer, `TRANSFER_SRC` is always added
+    /// - `frames_in_flight`: number of frames the device may still be executing when a frame ends
+    /// - `atom`: `nonCoherentAtomSize` of the device, or 1 for `COHERENT` memory
+    ///
+    /// ### Errors
+    ///
+    /// Fails with `UploadError::SharedMemory` if the block of the heap doesn't start its memory
+    /// object, which shows it was sub-allocated.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `atom` is not a power of two.
+    pub unsafe fn new(
+        allocator: &mut A,
+        device: &B::Device,
+        request: A::Request,
+        size: u64,
+        usage: BufferUsage,
+        frames_in_flight: usize,
+        atom: u64,
+    ) -> Result<Self, UploadError> {
+        assert!(atom.is_power_of_two());
+        let buffer = allocator
+            .create_buffer(device, request, size, usage | BufferUsage::TRANSFER_SRC)
+            .map_err(UploadError::Staging)?;
+        if buffer.block().range().start != 0 {
+            allocator.destroy_buffer(device, buffer);
+            return Err(UploadError::SharedMemory);
+        }
+        let ptr = match device.map_memory(buffer.block().memory(), buffer.block().range()) {
+            Ok(ptr) => ptr,
+            Err(error) => {
+                allocator.destroy_buffer(device, buffer);
+                return Err(UploadError::Map(error));
+            }
+        };
+        Ok(UploadHeap {
+            buffer,
+            ptr,
+            ring: Ring::new(size, frames_in_flight),
+            flushed: 0,
+            atom,
+            not_send: NotSend::default(),
+        })
+    }
+
+    /// Get the buffer of the heap.
+    pub fn buffer(&self) -> &B::Buffer {
+        self.buffer.raw()
+    }
+
+    /// Get the size of the heap in bytes.
+    pub fn size(&self) -> u64 {
+        self.ring.size()
+    }
+
+    /// Get the number of bytes written by frames in flight and the current frame, including
+    /// padding.
+    pub fn used(&self) -> u64 {
+        self.ring.used()
+    }
+
+    /// Write `data` to the heap.
+    ///
+    /// Writes are made visible to the device by `flush` or `end_frame`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `data`: data to write
+    /// - `alignment`: alignment of the offset, e.g. `minUniformBufferOffsetAlignment`
+    ///
+    /// ### Returns
+    ///
+    /// Returns the buffer of the heap and the offset `data` was written at, or
+    /// `UploadError::Full` if frames in flight leave no room for it.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if `alignment` is not a power of two.
+    pub fn write(&mut self, data: &[u8], alignment: u64) -> Result<(&B::Buffer, u64), UploadError> {
+        let offset = self.ring.alloc(data.len() as u64, alignment)?;
+        unsafe {
+            copy_nonoverlapping(data.as_ptr(), self.ptr.offset(offset as isize), data.len());
+        }
+        Ok((self.buffer.raw(), offset))
+    }
+
+    /// Make data written since the last flush visible to the device.
+    ///
+    /// Only needed for memory that isn't `COHERENT`, and done by `end_frame` anyway. The range
+    /// written since the last flush is rounded to whole atoms.
+    pub unsafe fn flush(&mut self, device: &B::Device) -> Result<(), UploadError> {
+        if self.flushed != self.ring.head {
+            let block = self.buffer.block();
+            // The block is the whole memory object
+            let written = written(self.flushed, self.ring.head, self.ring.size());
+            atom_range(written, self.atom, Some(block.range().end))
+                .flush::<B>(device, block.memory())
+                .map_err(UploadError::Flush)?;
+            self.flushed = self.ring.head;
+        }
+        Ok(())
+    }
+
+    /// Flush the data written in the current frame and start the next one.
+    ///
+    /// The space of the oldest frame is reused if more than `frames_in_flight` frames ended since.
+    pub unsafe fn end_frame(&mut self, device: &B::Device) -> Result<(), UploadError> {
+        self.flush(device)?;
+        self.ring.end_frame();
+        Ok(())
+    }
+
+    /// Unmap the memory of the heap and destroy its buffer.
+    ///
+    /// The device must have finished all frames that read from the heap.
+    pub unsafe fn dispose(self, allocator: &mut A, device: &B::Device) {
+        device.unmap_memory(self.buffer.block().memory());
+        allocator.destroy_buffer(device, self.buffer);
+    }
+}
+
+/// Space of a ring buffer reused after a number of frames, shared by `UploadHeap` and
+/// `ReadbackHeap`.
+#[derive(Debug)]
+pub(crate) struct Ring {
+    size: u64,
+    /// Total bytes allocated so far, including padding
+    head: u64,
+    /// Position before which space can be allocated again
+    tail: u64,
+    /// Position of `head` at the end of frames in flight, oldest first
+    frames: VecDeque<u64>,
+    frames_in_flight: usize,
+}
+
+impl Ring {
+    pub(crate) fn new(size: u64, frames_in_flight: usize) -> Self {
+        Ring {
+            size,
+            head: 0,
+            tail: 0,
+            frames: VecDeque::new(),
+            frames_in_flight,
+        }
+    }
+
+    pub(crate) fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub(crate) fn used(&self) -> u64 {
+        self.head - self.tail
+    }
+
+    /// Allocate `size` bytes aligned to `alignment` and return their offset in the ring.
+    pub(crate) fn alloc(&mut self, size: u64, alignment: u64) -> Result<u64, UploadError> {
+        let (start, offset) = place(self.head, self.tail, self.size, size, alignment)
+            .ok_or(UploadError::Full(size))?;
+        self.head = start + size;
+        Ok(offset)
+    }
+
+    /// End the current frame, reusing the space of the oldest frame if more than
+    /// `frames_in_flight` frames ended since.
+    pub(crate) fn end_frame(&mut self) {
+        self.frames.push_back(self.head);
+        while self.frames.len() > self.frames_in_flight {
+            self.tail = self.frames.pop_front().unwrap();
+        }
+    }
+}
+
+/// Find where `size` bytes aligned to `alignment` fit into a ring of `capacity` bytes.
+///
+/// Returns the position the data starts at and its offset in the ring. Data is never split at
+/// the end of the ring, the rest of the ring is skipped instead.
+fn place(head: u64, tail: u64, capacity: u64, size: u64, alignment: u64) -> Option<(u64, u64)> {
+    let wrap = head - head % capacity;
+    let mut offset = align_up(head - wrap, alignment);
+    let mut start = wrap + offset;
+    if offset + size > capacity {
+        offset = 0;
+        start = wrap + capacity;
+    }
+    if size > capacity || start + size - tail > capacity {
+        None
+    } else {
+        Some((start, offset))
+    }
+}
+
+/// Get the range of a ring of `size` bytes written between positions `from` and `to`, or
+/// the whole ring if the range wraps around.
+fn written(from: u64, to: u64, size: u64) -> Range<u64> {
+    let start = from % size;
+    if start + (to - from) > size {
+        0..size
+    } else {
+        start..start + (to - from)
+    }
+}
+
+#[test]
+fn test_place() {
+    assert_eq!(place(0, 0, 1024, 100, 1), Some((0, 0)));
+    assert_eq!(place(100, 0, 1024, 100, 256), Some((256, 256)));
+    // Data doesn't fit at the end of the ring
+    assert_eq!(place(1000, 0, 1024, 100, 1), None);
+    assert_eq!(place(1000, 200, 1024, 100, 1), Some((1024, 0)));
+    assert_eq!(place(1100, 400, 1024, 100, 256), Some((1280, 256)));
+    assert_eq!(place(0, 0, 1024, 2048, 1), None);
+}
+
+#[test]
+fn test_ring() {
+    let mut ring = Ring::new(1024, 1);
+    assert_eq!(ring.alloc(600, 1).unwrap(), 0);
+    ring.end_frame();
+    match ring.alloc(600, 1) {
+        Err(UploadError::Full(600)) => {}
+        other => panic!("Unexpected {:?}", other),
+    }
+    ring.end_frame();
+    assert_eq!(ring.used(), 0);
+    assert_eq!(ring.alloc(600, 1).unwrap(), 0);
+    // The end of the ring is skipped
+    assert_eq!(ring.used(), 1024);
+}
+
+#[test]
+fn test_written() {
+    assert_eq!(written(0, 100, 1024), 0..100);
+    assert_eq!(written(1100, 1300, 1024), 76..276);
+    assert_eq!(written(1000, 1024, 1024), 1000..1024);
+    // Data skipping or wrapping around the end of the ring
+    assert_eq!(written(1000, 1124, 1024), 0..1024);
+    assert_eq!(written(0, 2048, 1024), 0..1024);
+}